@@ -0,0 +1,9 @@
+#!/bin/sh
+# A stand-in for an external prover, for testing `mm0-rs tptp --prover`.
+# It claims to prove any problem, and reports every axiom and hypothesis
+# of the problem as used, in the format of a TSTP derivation.
+file="$1"
+echo "% SZS status Theorem for $file"
+echo "% SZS output start CNFRefutation for $file"
+sed -n "s#^\(fof\|thf\)(\([^,]*\), *\(axiom\|hypothesis\),.*#\1(\2, \3, \$true, file('$file', \2)).#p" "$file"
+echo "% SZS output end CNFRefutation for $file"
@@ -8,7 +8,7 @@
 //!
 //! [`mm0_rs::server`]: crate::server
 //! [`mm0-c`]: https://github.com/digama0/mm0/tree/master/mm0-c
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
 use std::collections::{HashMap, hash_map::Entry};
 use std::{io, fs};
 use futures::{FutureExt, future::BoxFuture};
@@ -20,7 +20,8 @@ use annotate_snippets::{
   display_list::{DisplayList, FormatOptions}};
 use typed_arena::Arena;
 use clap::ArgMatches;
-use crate::elab::{ElabError, ElabErrorKind, ElaborateBuilder, ElabResult, FrozenEnv};
+use crate::elab::{ElabError, ElabErrorKind, ElaborateBuilder, ElabResult, Environment, FrozenEnv,
+  minimize::minimize_proofs};
 use crate::parser::{parse, ParseError, ErrorLevel};
 use crate::lined_string::LinedString;
 use crate::mmb::import::elab as mmb_elab;
@@ -324,7 +325,18 @@ fn log_msg(#[allow(unused_mut)] mut s: String) {
       write!(s, ", memory = {}M", n >> 20).expect("writing to a string");
     }
   }
-  println!("{}", s)
+  emit(&s)
+}
+
+/// If true, messages and errors from elaboration are printed to stderr instead of stdout.
+static LOG_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Print messages and errors to stderr instead of stdout, for subcommands that use
+/// stdout for their output.
+pub(crate) fn log_to_stderr() { LOG_TO_STDERR.store(true, Ordering::Relaxed) }
+
+fn emit(s: &str) {
+  if LOG_TO_STDERR.load(Ordering::Relaxed) { eprintln!("{}", s) } else { println!("{}", s) }
 }
 
 /// Elaborate a file for an [`Environment`](crate::elab::Environment) result.
@@ -372,7 +384,7 @@ async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult
     if !ast.errors.is_empty() {
       for e in &ast.errors {
         e.to_snippet(&path, &ast.source,
-          |s| println!("{}", DisplayList::from(s).to_string()))
+          |s| emit(&DisplayList::from(s).to_string()))
       }
     }
     let ast = Arc::new(ast);
@@ -400,13 +412,15 @@ async fn elaborate(path: FileRef, rd: ArcList<FileRef>) -> io::Result<ElabResult
           Ok(recv)
         },
         recv_goal: None,
+        recv_progress: None,
+        read_file: None,
       }.elab();
     let (cyc, _, errors, env) = fut.await;
     (cyc, errors, env)
   };
   log_msg(format!("elabbed {}", path));
   let errors: Option<Arc<[_]>> = if errors.is_empty() { None } else {
-    fn print(s: Snippet<'_>) { println!("{}\n", DisplayList::from(s).to_string()) }
+    fn print(s: Snippet<'_>) { emit(&format!("{}\n", DisplayList::from(s))) }
     let mut to_range = mk_to_range();
     if let FileContents::Ascii(text) = &file.text {
       for e in &errors { e.to_snippet(&path, text, &mut to_range, print) }
@@ -447,8 +461,9 @@ fn elaborate_and_send(path: FileRef, send: FSender<ElabResult<()>>, rd: ArcList<
 }
 
 /// Elaborate a file, and return the completed [`FrozenEnv`] result, along with the
-/// file contents.
+/// file contents. The directory of `path` is used as the project root.
 pub(crate) fn elab_for_result(path: FileRef) -> io::Result<(FileContents, Option<FrozenEnv>)> {
+  crate::set_default_project_root(path.path());
   let (path, file) = VFS_.get_or_insert(path)?;
   let env = match block_on(elaborate(path, Default::default()))? {
     ElabResult::Ok(_, _, env) => Some(env),
@@ -461,17 +476,20 @@ pub(crate) fn elab_for_result(path: FileRef) -> io::Result<(FileContents, Option
 ///
 /// # Arguments
 ///
-/// `mm0-rs compile <in.mm1> [out.mmb]`, where:
+/// `mm0-rs compile <in.mm1> [out.mmb] [--minimize]`, where:
 ///
 /// - `in.mm1` is the MM1 (or MM0) file to elaborate
 /// - `out.mmb` (or `out.mmu`) is the MMB file to generate, if the elaboration is
 ///   successful. The file extension is used to determine if we are outputting
 ///   binary. If this argument is omitted, the input is only elaborated.
+/// - `--minimize`: Minimize all proofs before generating the output, and report
+///   the savings for each theorem.
 pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
   let path = args.value_of("INPUT").expect("required arg");
   let path: FileRef = fs::canonicalize(path)?.into();
   let (file, env) = elab_for_result(path.clone())?;
-  let env = env.unwrap_or_else(|| std::process::exit(1));
+  let mut env = env.unwrap_or_else(|| std::process::exit(1));
+  if args.is_present("minimize") { env = minimize(&env, file.ascii()) }
   if let Some(s) = args.value_of_os("output") {
     if let Err((fsp, e)) =
       if s == "-" { env.run_output(io::stdout()) }
@@ -484,16 +502,38 @@ pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
       std::process::exit(1);
     }
   }
-  if let Some(out) = args.value_of("OUTPUT") {
-    use {fs::File, io::BufWriter};
-    let w = BufWriter::new(File::create(out)?);
-    if out.ends_with(".mmu") {
-      env.export_mmu(w)?;
-    } else {
-      let mut ex = MMBExporter::new(path, file.try_ascii().map(|fc| &**fc), &env, w);
-      ex.run(true)?;
-      ex.finish()?;
-    }
+  if let Some(out) = args.value_of("OUTPUT") { export(path, &file, &env, out)? }
+  Ok(())
+}
+
+/// Minimize all the proofs in `old` (see [`elab::minimize`](crate::elab::minimize)),
+/// printing the old and new proof sizes of each theorem that got smaller to stderr.
+fn minimize(old: &FrozenEnv, source: &LinedString) -> FrozenEnv {
+  let mut env = Environment::new();
+  env.merge(old, None, (0..0).into(), &mut vec![]).expect("can't fail");
+  let thms = env.thms.enum_iter().map(|(t, _)| t).collect::<Vec<_>>();
+  let res = minimize_proofs(&mut env, source, thms);
+  let (mut total_old, mut total_new) = (0, 0);
+  for &(t, old, new) in &res {
+    eprintln!("{}: {} -> {}", env.data[env.thms[t].atom].name, old, new);
+    total_old += old;
+    total_new += new;
+  }
+  eprintln!("minimized {} proofs, saving {} of {} nodes", res.len(), total_old - total_new, total_old);
+  FrozenEnv::new(env)
+}
+
+/// Export an elaborated file to `out`, which is an MMU file if it has the `.mmu` extension
+/// and an MMB file otherwise.
+pub(crate) fn export(path: FileRef, file: &FileContents, env: &FrozenEnv, out: &str) -> io::Result<()> {
+  use {fs::File, io::BufWriter};
+  let w = BufWriter::new(File::create(out)?);
+  if out.ends_with(".mmu") {
+    env.export_mmu(w)?;
+  } else {
+    let mut ex = MMBExporter::new(path, file.try_ascii().map(|fc| &**fc), env, w);
+    ex.run(true)?;
+    ex.finish()?;
   }
   Ok(())
 }
\ No newline at end of file
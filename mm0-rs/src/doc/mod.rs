@@ -1,16 +1,17 @@
 //! Build documentation pages for MM1/MM0 files
 use std::{collections::{hash_map::Entry, HashMap}, hash::Hash, path::PathBuf};
-use std::convert::{TryFrom, TryInto};
+use std::convert::TryInto;
 use std::mem;
 use std::fs::{self, File};
 use std::io::{self, BufWriter, Write};
-use bit_set::BitSet;
 use clap::ArgMatches;
 use lsp_types::Url;
 use pulldown_cmark::escape::WriteWrapper;
-use crate::{elab::environment::{AtomData, DocComment}, lined_string::LinedString, util::{ArcString, FileRef, SliceUninit}};
-use crate::elab::{Environment, lisp::{LispVal, print::FormatEnv, pretty::Annot},
-  environment::{DeclKey, Proof, ProofNode, StmtTrace, AtomID, TermID, ThmID, ThmKind, Thm,
+use crate::elab::proof_layout::ProofOrder;
+use crate::{elab::environment::{AtomData, DocComment}, lined_string::LinedString, util::{ArcString, FileRef}};
+use crate::elab::{Environment, FrozenEnv, lisp::{LispVal, print::FormatEnv, pretty::Annot},
+  proof_stats::AxiomUse, proof_layout::{layout_proof, Line, LineKind},
+  environment::{DeclKey, Proof, StmtTrace, AtomID, ThmID, ThmKind, Thm,
     ExprNode, Type}};
 
 const PP_WIDTH: usize = 160;
@@ -86,199 +87,6 @@ impl<'a, 'b, W: Write> pretty::RenderAnnotated<'b, Annot> for HtmlPrinter<'a, W>
   }
 }
 
-struct AxiomUse(HashMap<ThmID, BitSet>);
-
-impl AxiomUse {
-  fn new(env: &Environment) -> (Vec<ThmID>, Self) {
-    let mut axuse = HashMap::new();
-    let mut to_tid = vec![ThmID(u32::MAX)];
-    for (tid, td) in env.thms.enum_iter() {
-      if let ThmKind::Axiom = td.kind {
-        let axid = to_tid.len();
-        to_tid.push(tid);
-        let mut bs = BitSet::new();
-        bs.insert(axid);
-        axuse.insert(tid, bs);
-      }
-    }
-    (to_tid, AxiomUse(axuse))
-  }
-
-  fn accumulate(&mut self, env: &Environment, bs: &mut BitSet, node: &ProofNode) {
-    match node {
-      ProofNode::Ref(_) |
-      ProofNode::Dummy(_, _) |
-      ProofNode::Term {..} |
-      ProofNode::Hyp(_, _) |
-      ProofNode::Refl(_) |
-      ProofNode::Sym(_) |
-      ProofNode::Cong {..} |
-      ProofNode::Unfold {..} => {}
-      ProofNode::Conv(p) => self.accumulate(env, bs, &p.2),
-      &ProofNode::Thm {thm: tid, ref args, ..} => {
-        bs.union_with(self.get(env, tid));
-        for p in &**args { self.accumulate(env, bs, p) }
-      }
-    }
-  }
-
-  fn get<'a, 'b>(&'a mut self, env: &'b Environment, tid: ThmID) -> &'a BitSet {
-    if let Some(bs) = self.0.get(&tid) {
-      // Safety: This is the same issue that comes up in Spans::insert.
-      // We are performing a lifetime cast here because rust can't see that
-      // in the None case it is safe to drop the borrow of `self.axuse`.
-      #[allow(clippy::useless_transmute, clippy::transmute_ptr_to_ptr)]
-      return unsafe { std::mem::transmute(bs) }
-    }
-    let mut bs = BitSet::new();
-    let td = &env.thms[tid];
-    match &td.kind {
-      ThmKind::Axiom => unreachable!(),
-      ThmKind::Thm(None) => {bs.insert(0);}
-      ThmKind::Thm(Some(Proof {heap, head, ..})) => {
-        for p in &heap[td.args.len()..] { self.accumulate(env, &mut bs, p) }
-        self.accumulate(env, &mut bs, head)
-      }
-    }
-    self.0.entry(tid).or_insert(bs)
-  }
-}
-
-#[derive(Debug, Clone)]
-enum LineKind {
-  Hyp(Option<AtomID>),
-  Thm(ThmID),
-  Conv(Box<[TermID]>),
-}
-struct Line {
-  hyps: Box<[u32]>,
-  kind: LineKind,
-  expr: LispVal,
-}
-
-struct LayoutProof<'a> {
-  env: &'a mut Environment,
-  lines: Vec<Line>,
-  rev: bool,
-  args: &'a [(Option<AtomID>, Type)],
-  heap: &'a [ProofNode],
-  hyps: &'a [(Option<AtomID>, ExprNode)],
-  heap_lines: Box<[Option<LayoutResult>]>,
-}
-
-#[derive(Clone)]
-enum LayoutResult {
-  Expr(LispVal),
-  Proof(u32),
-  Conv(Box<[TermID]>),
-}
-
-impl LayoutResult {
-  fn into_expr(self) -> LispVal {
-    if let LayoutResult::Expr(e) = self {e} else {panic!("bad proof")}
-  }
-  fn into_proof(self) -> u32 {
-    if let LayoutResult::Proof(e) = self {e} else {panic!("bad proof")}
-  }
-  fn into_conv(self) -> Box<[TermID]> {
-    if let LayoutResult::Conv(e) = self {e} else {panic!("bad proof")}
-  }
-  fn as_conv(&self) -> &[TermID] {
-    if let LayoutResult::Conv(e) = self {e} else {panic!("bad proof")}
-  }
-}
-
-impl<'a> LayoutProof<'a> {
-  fn push_line(&mut self, hyps: Box<[u32]>, kind: LineKind, expr: LispVal) -> u32 {
-    let line = self.lines.len().try_into().expect("lines are u32");
-    self.lines.push(Line {hyps, kind, expr});
-    line
-  }
-
-  fn layout_conv(&mut self, defs: &mut Vec<TermID>, p: &ProofNode) {
-    match p {
-      &ProofNode::Ref(i) => {
-        for &d in
-          if let Some(h) = &self.heap_lines[i] {h} else {
-            let h = self.layout(&self.heap[i]);
-            self.heap_lines[i].get_or_insert(h)
-          }.as_conv()
-        { if !defs.contains(&d) {defs.push(d)} }
-      },
-      ProofNode::Dummy(_, _) |
-      ProofNode::Term {..} |
-      ProofNode::Hyp(_, _) |
-      ProofNode::Thm {..} |
-      ProofNode::Conv(_) => unreachable!(),
-      ProofNode::Refl(_) => {}
-      ProofNode::Sym(c) => self.layout_conv(defs, c),
-      ProofNode::Cong {args, ..} => for c in &**args { self.layout_conv(defs, c) },
-      &ProofNode::Unfold {term, ref res, ..} => {
-        if !defs.contains(&term) {defs.push(term)}
-        self.layout_conv(defs, &res.2)
-      }
-    }
-  }
-
-  fn layout(&mut self, p: &ProofNode) -> LayoutResult {
-    match *p {
-      ProofNode::Ref(i) =>
-        if let Some(n) = &self.heap_lines[i] {n.clone()}
-        else {
-          let res = if let Some((a, _)) = self.args.get(i) {
-            let a = a.unwrap_or_else(|| self.env.get_atom(format!("v{}", i+1).as_bytes()));
-            LayoutResult::Expr(LispVal::atom(a))
-          } else { self.layout(&self.heap[i]) };
-          self.heap_lines[i] = Some(res.clone());
-          res
-        },
-      ProofNode::Dummy(a, _) => LayoutResult::Expr(LispVal::atom(a)),
-      ProofNode::Term {term, ref args} => {
-        let mut out = Vec::with_capacity(args.len()+1);
-        out.push(LispVal::atom(self.env.terms[term].atom));
-        for e in &**args {
-          out.push(self.layout(e).into_expr());
-        }
-        LayoutResult::Expr(LispVal::list(out))
-      }
-      ProofNode::Hyp(i, ref e) => {
-        let e = self.layout(e).into_expr();
-        LayoutResult::Proof(self.push_line(Box::new([]), LineKind::Hyp(self.hyps[i].0), e))
-      }
-      ProofNode::Thm {thm, ref args, ref res} => {
-        let td = &self.env.thms[thm];
-        let mut hyps = SliceUninit::new(td.hyps.len());
-        if self.rev {
-          for (i, e) in args[td.args.len()..].iter().enumerate().rev() {
-            hyps.set(i, self.layout(e).into_proof())
-          }
-        } else {
-          for (i, e) in args[td.args.len()..].iter().enumerate() {
-            hyps.set(i, self.layout(e).into_proof())
-          }
-        }
-        let res = self.layout(res).into_expr();
-        LayoutResult::Proof(self.push_line(unsafe {hyps.assume_init()}, LineKind::Thm(thm), res))
-      }
-      ProofNode::Conv(ref p) => {
-        let n = self.layout(&p.2).into_proof();
-        let mut defs = self.layout(&p.1).into_conv();
-        let tgt = self.layout(&p.0).into_expr();
-        defs.sort_by_key(|&t| self.env.data[self.env.terms[t].atom].name.as_str());
-        LayoutResult::Proof(self.push_line(Box::new([n]), LineKind::Conv(defs), tgt))
-      }
-      ProofNode::Refl(_) |
-      ProofNode::Sym(_) |
-      ProofNode::Cong {..} |
-      ProofNode::Unfold {..} => {
-        let mut defs = vec![];
-        self.layout_conv(&mut defs, p);
-        LayoutResult::Conv(defs.into_boxed_slice())
-      }
-    }
-  }
-}
-
 fn render_line<'a>(fe: FormatEnv<'_>, mangler: &'a mut Mangler, w: &mut impl Write,
   line: u32, hyps: &[u32], kind: LineKind, e: &LispVal) -> io::Result<()> {
   let kind_class = match kind {
@@ -332,27 +140,11 @@ fn render_proof<'a>(
   hyps: &'a [(Option<AtomID>, ExprNode)],
   pf: &'a Proof,
 ) -> io::Result<()> {
-  let mut layout = LayoutProof {
-    env, args, heap: &pf.heap, hyps,
-    rev: matches!(order, ProofOrder::Pre), lines: vec![],
-    heap_lines: vec![None; pf.heap.len()].into_boxed_slice()
-  };
-  layout.layout(&pf.head).into_proof();
-  let lines = layout.lines;
+  let lines = layout_proof(env, order, args, hyps, pf);
   let fe = FormatEnv {source, env};
-  match order {
-    ProofOrder::Post =>
-      for (line, Line {mut hyps, kind, expr}) in lines.into_iter().enumerate() {
-        for i in &mut *hyps { *i += 1 }
-        render_line(fe, mangler, w, (line + 1).try_into().expect("lines are u32"), &hyps, kind, &expr)?;
-      }
-    ProofOrder::Pre => {
-      let len = lines.len();
-      for (line, Line {mut hyps, kind, expr}) in lines.into_iter().rev().enumerate() {
-        for i in &mut *hyps { *i = u32::try_from(len).expect("lines are u32") - *i }
-        render_line(fe, mangler, w, (line + 1).try_into().expect("lines are u32"), &hyps, kind, &expr)?;
-      }
-    }
+  for (line, Line {mut hyps, kind, expr}) in lines.into_iter().enumerate() {
+    for i in &mut *hyps { *i += 1 }
+    render_line(fe, mangler, w, (line + 1).try_into().expect("lines are u32"), &hyps, kind, &expr)?;
   }
   Ok(())
 }
@@ -381,9 +173,6 @@ impl PartialEq for CaseInsensitiveName {
 }
 impl Eq for CaseInsensitiveName {}
 
-#[derive(Clone, Copy)]
-enum ProofOrder { Pre, Post }
-
 struct BuildDoc<'a, W> {
   thm_folder: PathBuf,
   source: &'a LinedString,
@@ -541,7 +330,7 @@ impl<'a, W: Write> BuildDoc<'a, W> {
     if let ThmKind::Thm(_) = td.kind {
       writeln!(file, "    <h2 class=\"axioms\">Axiom use</h2>")?;
       let mut first = true;
-      for i in self.axuse.1.get(&self.env, tid) {
+      for i in self.axuse.1.get(&self.env.thms, tid) {
         if !mem::take(&mut first) { writeln!(file, ",")? }
         if i == 0 {
           write!(file, "<i>sorry</i>")?
@@ -635,9 +424,29 @@ pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
   let (fc, old) = crate::compiler::elab_for_result(path.clone())?;
   let old = old.unwrap_or_else(|| std::process::exit(1));
   println!("writing docs");
+  let dir = PathBuf::from(args.value_of("OUTPUT").unwrap_or("doc"));
+  let order = match args.value_of("order") {
+    Some("pre") => ProofOrder::Pre,
+    Some("post") => ProofOrder::Post,
+    _ => unreachable!(),
+  };
+  let base_url = match args.value_of("src") {
+    Some("-") => None,
+    src => Some(Url::parse(src.unwrap_or("https://github.com/digama0/mm0/blob/master/examples/"))
+      .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?),
+  };
+  build_doc(&path, fc.ascii(), &old, dir, order, args.value_of("only"), base_url)
+}
+
+/// Write the documentation for an elaborated file into the folder `dir`.
+/// If `only` is set to a comma-separated list of theorems, then only the pages for these
+/// theorems are generated (and the index is not written). `base_url` is used to generate
+/// links to the source.
+pub(crate) fn build_doc(path: &FileRef, source: &LinedString, old: &FrozenEnv, mut dir: PathBuf,
+  order: ProofOrder, only: Option<&str>, base_url: Option<Url>
+) -> io::Result<()> {
   let mut env = Environment::new();
-  env.merge(&old, (0..0).into(), &mut vec![]).expect("can't fail");
-  let mut dir = PathBuf::from(args.value_of("OUTPUT").unwrap_or("doc"));
+  env.merge(old, None, (0..0).into(), &mut vec![]).expect("can't fail");
   fs::create_dir_all(&dir)?;
   macro_rules! import {($($str:expr),*) => {$({
     let mut file = dir.to_owned();
@@ -647,12 +456,6 @@ pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
     }
   })*}}
   import!("stylesheet.css", "proof.js");
-  let order = match args.value_of("order") {
-    Some("pre") => ProofOrder::Pre,
-    Some("post") => ProofOrder::Post,
-    _ => unreachable!(),
-  };
-  let only = args.value_of("only");
   let index = if only.is_some() {None} else {
     let mut file = dir.to_owned();
     file.push("index.html");
@@ -660,15 +463,10 @@ pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
   };
   dir.push("thms");
   fs::create_dir_all(&dir)?;
-  let base_url = match args.value_of("src") {
-    Some("-") => None,
-    src => Some(Url::parse(src.unwrap_or("https://github.com/digama0/mm0/blob/master/examples/"))
-      .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?),
-  };
   let mut bd = BuildDoc {
-    source: fc.ascii(),
+    source,
     base_url, order,
-    axuse: AxiomUse::new(&env),
+    axuse: AxiomUse::new(&env.thms),
     thm_folder: dir, env, index,
     mangler: Mangler::default(),
   };
@@ -687,7 +485,7 @@ pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
       bd.thm_doc(i.checked_sub(1).map(|j| thms[j]), tid, thms.get(i+1).copied())?;
     }
   } else {
-    bd.write_all(&path, old.stmts())?;
+    bd.write_all(path, old.stmts())?;
   }
   Ok(())
 }
\ No newline at end of file
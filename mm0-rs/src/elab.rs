@@ -13,7 +13,19 @@ pub mod lisp;
 pub mod math_parser;
 pub mod local_context;
 pub mod refine;
+pub mod expr_table;
+pub mod rewrite;
+pub mod tauto;
+pub mod norm_num;
+pub mod auto;
+pub mod disc_tree;
+pub mod tptp;
+pub mod lrat;
 pub mod proof;
+pub mod proof_stats;
+pub mod proof_layout;
+pub mod minimize;
+pub mod unfold;
 pub mod inout;
 
 use std::ops::{Deref, DerefMut};
@@ -140,6 +152,11 @@ impl ElabError {
     ElabError::new(pos, ElabErrorKind::Boxed(msg, Some(v)))
   }
 
+  /// Make an elaboration warning from a position, a message, and a list of related info
+  pub fn warn_with_info(pos: impl Into<Span>, msg: BoxError, v: Vec<(FileSpan, BoxError)>) -> ElabError {
+    ElabError { pos: pos.into(), level: ErrorLevel::Warning, kind: ElabErrorKind::Boxed(msg, Some(v))}
+  }
+
   /// Make an elaboration warning from a position and a message.
   pub fn warn(pos: impl Into<Span>, e: impl Into<BoxError>) -> ElabError {
     ElabError { pos: pos.into(), level: ErrorLevel::Warning, kind: ElabErrorKind::Boxed(e.into(), None)}
@@ -159,10 +176,12 @@ impl ElabError {
       range: file.to_range(self.pos),
       severity: Some(self.level.to_diag_severity()),
       code: None,
+      code_description: None,
       source: Some("mm0-rs".to_owned()),
       message: self.kind.msg(),
       related_information: self.kind.to_related_info(to_loc),
       tags: None,
+      data: None,
     }
   }
 }
@@ -202,11 +221,28 @@ impl ReportMode {
 
 /// A function that gets called on goal view events.
 #[allow(clippy::type_complexity)]
-pub struct GoalListener(Box<dyn for<'a> FnMut(&'a Elaborator, &'a str)>);
+pub struct GoalListener {
+  /// The callback, which receives the elaborator and the pretty printed goal state.
+  f: Box<dyn for<'a> FnMut(&'a Elaborator, &'a str)>,
+  /// The cursor position, if the listener wants the goals at this point of a tactic script
+  /// instead of the goals left unsolved at the end of the proof.
+  cursor: Option<usize>,
+  /// Set once the listener has been called at the cursor, after which it is not called again.
+  done: bool,
+}
 
 impl GoalListener {
   /// Creates a new [`GoalListener`] from a callback.
-  pub fn new(f: impl for<'a> FnMut(&'a Elaborator, &'a str) + 'static) -> Self { Self(Box::new(f)) }
+  pub fn new(f: impl for<'a> FnMut(&'a Elaborator, &'a str) + 'static) -> Self {
+    Self {f: Box::new(f), cursor: None, done: false}
+  }
+
+  /// Creates a new [`GoalListener`] from a callback, which is called with the goals before
+  /// the first tactic after the cursor position `pos` in a tactic script containing it.
+  /// Proofs that are not tactic scripts still report the goals left at the end.
+  pub fn at(pos: usize, f: impl for<'a> FnMut(&'a Elaborator, &'a str) + 'static) -> Self {
+    Self {f: Box::new(f), cursor: Some(pos), done: false}
+  }
 }
 
 impl std::fmt::Debug for GoalListener {
@@ -215,6 +251,42 @@ impl std::fmt::Debug for GoalListener {
   }
 }
 
+/// A function that gets called before each statement is elaborated, with the [`AST`]
+/// and the index of the statement in [`AST::stmts`].
+#[allow(clippy::type_complexity)]
+pub struct ProgressListener(Box<dyn for<'a> FnMut(&'a AST, usize) + Send>);
+
+impl ProgressListener {
+  /// Creates a new [`ProgressListener`] from a callback.
+  pub fn new(f: impl for<'a> FnMut(&'a AST, usize) + Send + 'static) -> Self { Self(Box::new(f)) }
+}
+
+impl std::fmt::Debug for ProgressListener {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    "ProgressListener".fmt(f)
+  }
+}
+
+/// A callback for reading data files from lisp (using `read-file` and `read-sexprs`).
+///
+/// It is given the canonical path of the file, and returns its contents. It can be used
+/// to read the contents from an editor buffer rather than the disk, and to record the
+/// file as a dependency of the current file.
+pub struct FileReader(Box<dyn FnMut(FileRef) -> StdResult<Arc<LinedString>, BoxError> + Send>);
+
+impl FileReader {
+  /// Creates a new [`FileReader`] from a callback.
+  pub fn new(f: impl FnMut(FileRef) -> StdResult<Arc<LinedString>, BoxError> + Send + 'static) -> Self {
+    Self(Box::new(f))
+  }
+}
+
+impl std::fmt::Debug for FileReader {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    "FileReader".fmt(f)
+  }
+}
+
 /// The [`Elaborator`] struct contains the working data for elaboration, and is the
 /// main interface to MM1 operations (along with [`Evaluator`](lisp::eval::Evaluator),
 /// which a lisp execution context).
@@ -243,6 +315,8 @@ pub struct Elaborator {
   pub(crate) spans: Spans<ObjectKind>,
   /// True if we are currently elaborating an MM0 file
   mm0_mode: bool,
+  /// True if we are inside a `local do` block, whose global definitions are not exported
+  lisp_local: bool,
   /// True if we are checking proofs (otherwise we pretend every proof says `theorem foo = '?;`)
   check_proofs: bool,
   /// The current reporting mode, whether we will report each severity of error
@@ -255,6 +329,8 @@ pub struct Elaborator {
   arena: lisp::LispArena,
   /// A listener for goal view events.
   recv_goal: Option<GoalListener>,
+  /// The reader for data files, or `None` to read them directly from disk.
+  read_file: Option<FileReader>,
 }
 
 impl Deref for Elaborator {
@@ -294,12 +370,14 @@ impl Elaborator {
       lc: LocalContext::new(),
       spans: Spans::new(),
       mm0_mode,
+      lisp_local: false,
       check_proofs,
       backtrace: ReportMode {error: true, warn: false, info: false},
       inout: InoutHandlers::default(),
       reporting: ReportMode::new(),
       arena: Default::default(),
       recv_goal,
+      read_file: None,
     }
   }
 
@@ -319,7 +397,23 @@ impl Elaborator {
 
   fn call_goal_listener(&mut self, stat: &str) {
     if let Some(mut listener) = self.recv_goal.take() {
-      listener.0(self, stat);
+      if !listener.done { (listener.f)(self, stat) }
+      self.recv_goal = Some(listener);
+    }
+  }
+
+  /// Called before running the next tactic of the tactic script at `sp`, which starts at
+  /// `next` (or `None` at the end of the script). If the goal listener is waiting for a
+  /// cursor in the script that is before this tactic, it is called with the current goals.
+  fn call_goal_listener_at(&mut self, sp: Span, next: Option<usize>) {
+    if let Some(mut listener) = self.recv_goal.take() {
+      if let (Some(pos), false) = (listener.cursor, listener.done) {
+        if sp.start <= pos && pos <= sp.end && !matches!(next, Some(n) if n <= pos) {
+          let stat = self.stat();
+          (listener.f)(self, &stat);
+          listener.done = true;
+        }
+      }
       self.recv_goal = Some(listener);
     }
   }
@@ -566,11 +660,14 @@ impl Elaborator {
       &StmtKind::Coercion {id, from, to} => self.elab_coe(id, from, to)?,
       StmtKind::Notation(n) => self.elab_gen_nota(n)?,
       &StmtKind::Import(sp, _) => return Ok(ElabStmt::Import(sp)),
-      StmtKind::Do(es) => {
+      StmtKind::Do(m, es) => {
         if self.mm0_mode {
           self.report(ElabError::warn(span, "(MM0 mode) do blocks not allowed"))
         }
-        for e in es { self.parse_and_print(e, mem::take(&mut doc))? }
+        self.lisp_local = m.contains(Modifiers::LOCAL);
+        let r = es.iter().try_for_each(|e| self.parse_and_print(e, mem::take(&mut doc)));
+        self.lisp_local = false;
+        r?
       }
       StmtKind::Annot(e, s) => {
         let v = self.eval_lisp(e)?;
@@ -594,6 +691,80 @@ impl Elaborator {
     }
     Ok(ElabStmt::Ok)
   }
+
+  /// Creates a new [`Elaborator`] for the file `path`, whose environment is a copy of the
+  /// previously elaborated environment `env`. This is used to evaluate additional
+  /// statements in the context of a file (see [`elab_more`](Self::elab_more)) without
+  /// modifying the original environment.
+  ///
+  /// The `ast` contains the statements to evaluate (it can be replaced later by
+  /// [`elab_more`](Self::elab_more)). Any errors during the import of `env` are reported
+  /// in the returned elaborator.
+  #[must_use] pub fn from_env(ast: Arc<AST>, path: FileRef, env: &FrozenEnv) -> Elaborator {
+    let mut elab = Elaborator::new(ast, path, false, crate::get_check_proofs(), Arc::default(), None);
+    elab.arena.install_thread_local();
+    let r = elab.env.merge(env, None, (0..0).into(), &mut elab.errors);
+    elab.catch(r);
+    lisp::LispArena::uninstall_thread_local();
+    elab
+  }
+
+  /// Elaborates all statements in `ast`, on top of the current environment, and returns
+  /// the errors and messages produced (including the results of `do` blocks, which are
+  /// reported as info messages). The spans in the returned errors refer to `ast`, which
+  /// becomes the current AST of the elaborator.
+  ///
+  /// This is used for incremental evaluation, where statements are read one at a time.
+  /// `import` statements are not supported.
+  pub fn elab_more(&mut self, ast: Arc<AST>) -> Vec<ElabError> {
+    self.ast = ast;
+    self.arena.install_thread_local();
+    let ast = self.ast.clone();
+    for s in &ast.stmts {
+      if self.cancel.load(Ordering::Relaxed) {break}
+      match self.elab_stmt(String::new(), s, s.span) {
+        Ok(ElabStmt::Ok) => {}
+        Ok(ElabStmt::Import(sp)) => self.report(ElabError::new_e(sp, "import is not supported here")),
+        Err(e) => self.report(e)
+      }
+      self.push_spans();
+    }
+    lisp::LispArena::uninstall_thread_local();
+    mem::take(&mut self.errors)
+  }
+
+  /// Evaluates the lisp expression `e`, which is part of `ast`, in the current environment.
+  /// Like [`elab_more`](Self::elab_more), `ast` becomes the current AST of the elaborator.
+  pub fn eval_more(&mut self, ast: Arc<AST>, e: &SExpr) -> Result<LispVal> {
+    self.ast = ast;
+    self.cur_timeout = self.timeout.and_then(|d| Instant::now().checked_add(d));
+    self.arena.install_thread_local();
+    let r = self.eval_lisp(e);
+    lisp::LispArena::uninstall_thread_local();
+    r
+  }
+
+  /// Returns the statement of the proof term `p`, which is elaborated as in `(have 'x p)`,
+  /// so implicit arguments can be omitted. The local context is left unchanged, except
+  /// that the result may contain new metavariables.
+  pub fn infer_proof_type(&mut self, sp: Span, p: LispVal) -> Result<LispVal> {
+    let x = self.get_atom(b":type");
+    let (old, len) = (self.lc.proofs.get(&x).copied(), self.lc.proof_order.len());
+    let goals = self.lc.goals.clone();
+    self.arena.install_thread_local();
+    let have = LispVal::proc(lisp::Proc::Builtin(lisp::BuiltinProc::Have));
+    let r = self.call_func(sp, have, vec![LispVal::atom(x), p]);
+    lisp::LispArena::uninstall_thread_local();
+    let ty = self.lc.proof_order.get(len).map(|h| h.1.clone());
+    self.lc.proof_order.truncate(len);
+    match old {
+      Some(i) => {self.lc.proofs.insert(x, i);}
+      None => {self.lc.proofs.remove(&x);}
+    }
+    self.lc.goals = goals;
+    r?;
+    ty.ok_or_else(|| ElabError::new_e(sp, "failed to elaborate proof"))
+  }
 }
 
 /// The result of elaboration of a dependent file.
@@ -642,6 +813,10 @@ pub struct ElaborateBuilder<'a, F> {
   /// to transfer an [`Environment`] containing the elaborated theorems, as well as any
   /// extra data `T`, which is collected and passed through the function.
   pub recv_goal: Option<GoalListener>,
+  /// A listener which is notified as each statement is elaborated, used for progress reporting.
+  pub recv_progress: Option<ProgressListener>,
+  /// The reader for data files loaded by lisp code, or `None` to read them from disk.
+  pub read_file: Option<FileReader>,
 }
 
 impl<'a, T: Send, F> ElaborateBuilder<'a, F>
@@ -679,7 +854,8 @@ where F: FnMut(FileRef) -> StdResult<Receiver<ElabResult<T>>, BoxError> {
       cyc: Option<ArcList<FileRef>>,
       recv: ImportMap<Receiver<ElabResult<T>>>,
       idx: usize,
-      progress: UnfinishedStmt<T>
+      progress: UnfinishedStmt<T>,
+      recv_progress: Option<ProgressListener>,
     }
 
     struct ElabFuture<T>(Option<ElabFutureInner<T>>);
@@ -690,7 +866,7 @@ where F: FnMut(FileRef) -> StdResult<Receiver<ElabResult<T>>, BoxError> {
         let this = &mut unsafe { self.get_unchecked_mut() }.0;
         let ElabFutureInner {
           elab: FrozenElaborator(elab),
-          cyc, toks, recv, idx, progress, report_upstream_errors
+          cyc, toks, recv, idx, progress, report_upstream_errors, recv_progress
         } = this.as_mut().expect("poll called after Ready");
         elab.arena.install_thread_local();
         'l: loop {
@@ -714,7 +890,7 @@ where F: FnMut(FileRef) -> StdResult<Receiver<ElabResult<T>>, BoxError> {
                       }
                     }
                   }
-                  let r = elab.env.merge(&env, *sp, &mut elab.errors);
+                  let r = elab.env.merge(&env, Some(p), *sp, &mut elab.errors);
                   elab.catch(r);
                 }
                 Ok(ElabResult::Canceled) => {
@@ -736,6 +912,7 @@ where F: FnMut(FileRef) -> StdResult<Receiver<ElabResult<T>>, BoxError> {
           let ast = elab.ast.clone();
           while let Some(s) = ast.stmts.get(*idx) {
             if elab.cancel.load(Ordering::Relaxed) {break}
+            if let Some(f) = recv_progress { (f.0)(&ast, *idx) }
             match elab.elab_stmt(String::new(), s, s.span) {
               Ok(ElabStmt::Ok) => {}
               Ok(ElabStmt::Import(sp)) => {
@@ -765,6 +942,7 @@ where F: FnMut(FileRef) -> StdResult<Receiver<ElabResult<T>>, BoxError> {
     let mut recv = HashMap::new();
     let mut elab = Elaborator::new(self.ast.clone(),
       self.path, self.mm0_mode, self.check_proofs, self.cancel, self.recv_goal);
+    elab.read_file = self.read_file;
     elab.arena.install_thread_local();
     for &(sp, ref f) in &self.ast.imports {
       (|| -> Result<_> {
@@ -785,6 +963,7 @@ where F: FnMut(FileRef) -> StdResult<Receiver<ElabResult<T>>, BoxError> {
       recv,
       idx: 0,
       progress: UnfinishedStmt::None,
+      recv_progress: self.recv_progress,
     }))
   }
 }
\ No newline at end of file
@@ -0,0 +1,387 @@
+//! Bounded backward-chaining proof search, used by the `auto` builtin.
+//!
+//! The search uses the hypotheses in the local context together with a list of theorems
+//! supplied by the caller. A search state is a list of open goals and a substitution for
+//! the unification variables ("holes") standing for theorem arguments that are not
+//! determined by the conclusion, like the `a` in `mp: a -> b, a |- b`. A step picks an
+//! open goal, unifies it with a hypothesis or with the conclusion of a theorem, and
+//! replaces it with the instantiated hypotheses of the theorem. States are explored
+//! best-first, ordered by the number of steps taken plus the number of open goals, with a
+//! limit on the depth of the proof tree and on the total number of states. The search also
+//! stops at the elaborator's timeout (see `set-timeout`) or when elaboration is cancelled.
+//! Only the frontier of the search is kept; each state refers to the steps taken so far
+//! through a shared linked list, from which the proof is reconstructed.
+//!
+//! The result is a proof term in the explicit `(! thm args hyps)` form accepted by `refine`.
+//! If the search fails, the error lists the partial proofs that came closest to success.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::time::Instant;
+use super::Elaborator;
+use super::environment::{AtomID, ExprNode, SortID, ThmID, Type};
+use super::lisp::{LispVal, Uncons, eval::SResult};
+use super::local_context::InferSort;
+use super::expr_table::{ExprTable, Node};
+
+/// The default maximum depth of the proof tree found by `auto`.
+pub const DEFAULT_DEPTH: usize = 5;
+
+/// The maximum number of search states that `auto` will expand before giving up.
+const MAX_STATES: usize = 20000;
+
+/// The number of partial proofs reported when the search fails.
+const REPORT_ATTEMPTS: usize = 3;
+
+/// A fact that can be used to close a goal.
+#[derive(Copy, Clone)]
+enum Rule {
+  /// A hypothesis in the local context, as an index into [`Auto::hyps`].
+  Hyp(usize),
+  /// A theorem, whose arguments are the holes starting at the given index.
+  Thm(ThmID, usize),
+}
+
+/// A step in a partial proof: the goal `goal` was closed using `rule`,
+/// leaving the goals `subgoals` for the hypotheses of the rule.
+struct Step {
+  goal: usize,
+  rule: Rule,
+  subgoals: Box<[usize]>,
+  prev: Option<Rc<Step>>,
+}
+
+/// An open goal in a search state.
+#[derive(Copy, Clone)]
+struct Goal {
+  /// The identifier of the goal, used to assemble the proof.
+  id: usize,
+  /// The statement to prove, which can contain holes.
+  stmt: usize,
+  /// The depth of the goal in the proof tree.
+  depth: usize,
+}
+
+/// A partial proof.
+#[derive(Clone)]
+struct State {
+  /// The open goals.
+  goals: Vec<Goal>,
+  /// The assignment of holes.
+  subst: HashMap<usize, usize>,
+  /// The number of goals created so far.
+  next_goal: usize,
+  /// The number of holes created so far.
+  next_hole: usize,
+  /// The steps taken so far, most recent first.
+  steps: Option<Rc<Step>>,
+  /// The number of steps taken so far.
+  len: usize,
+}
+
+impl State {
+  fn steps(&self) -> impl Iterator<Item=&Step> {
+    let mut s = self.steps.as_deref();
+    std::iter::from_fn(move || { let step = s?; s = step.prev.as_deref(); Some(step) })
+  }
+}
+
+/// A state in the search queue, ordered by priority (lower is better) and then by
+/// insertion order, so that the search is deterministic.
+struct Queued {
+  key: (usize, usize),
+  st: State,
+}
+
+impl PartialEq for Queued {
+  fn eq(&self, other: &Self) -> bool { self.key == other.key }
+}
+impl Eq for Queued {}
+impl PartialOrd for Queued {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+impl Ord for Queued {
+  fn cmp(&self, other: &Self) -> Ordering { self.key.cmp(&other.key) }
+}
+
+/// The state of an `auto` run.
+struct Auto<'a> {
+  tbl: ExprTable<'a>,
+  /// The hypotheses in the local context, and their statements.
+  hyps: Vec<(AtomID, usize)>,
+  /// The candidate theorems.
+  thms: Vec<ThmID>,
+  /// The dependencies of the regular variables in the local context.
+  deps: HashMap<AtomID, Box<[AtomID]>>,
+  /// The variables in the local context and their sorts, in declaration order.
+  vars: Vec<(AtomID, SortID)>,
+  /// The maximum depth of the proof tree.
+  depth: usize,
+  /// The time at which the search gives up, if any.
+  deadline: Option<Instant>,
+  /// Set when elaboration is cancelled.
+  cancel: &'a AtomicBool,
+}
+
+impl Auto<'_> {
+  /// Returns false if the head of the theorem's conclusion cannot match `goal`.
+  fn head_matches(&self, thm: ThmID, goal: usize) -> bool {
+    let td = &self.tbl.fe.env.thms[thm];
+    let mut e = &td.ret;
+    loop {
+      match *e {
+        ExprNode::Ref(j) if j < td.args.len() => return true,
+        ExprNode::Ref(j) => e = &td.heap[j],
+        ExprNode::Dummy(_, _) => return false,
+        ExprNode::App(t, _) => return matches!(self.tbl.nodes[goal], Node::App(t2, _) if t == t2),
+      }
+    }
+  }
+
+  /// The successors of a state, obtained by closing one of its goals in all possible ways.
+  fn expand(&mut self, st: &State) -> Vec<State> {
+    // Prefer a goal whose head is known, since any theorem applies to a hole.
+    let gi = st.goals.iter()
+      .position(|g| !matches!(self.tbl.nodes[self.tbl.walk(g.stmt, &st.subst)], Node::Hole(_)))
+      .unwrap_or(0);
+    let goal = st.goals[gi];
+    let stmt = self.tbl.walk(goal.stmt, &st.subst);
+    let mut out = vec![];
+    let mut push = |st: &State, subst, rule, new_goals: Vec<Goal>, next_hole| {
+      let subgoals = new_goals.iter().map(|g| g.id).collect();
+      let next_goal = st.next_goal + new_goals.len();
+      let mut goals = new_goals;
+      goals.extend(st.goals.iter().enumerate().filter(|&(i, _)| i != gi).map(|(_, g)| *g));
+      out.push(State {
+        goals, subst, next_goal, next_hole,
+        steps: Some(Rc::new(Step {goal: goal.id, rule, subgoals, prev: st.steps.clone()})),
+        len: st.len + 1,
+      })
+    };
+    for (i, &(_, h)) in self.hyps.iter().enumerate() {
+      let mut subst = st.subst.clone();
+      if self.tbl.unify(stmt, h, &mut subst) {
+        push(st, subst, Rule::Hyp(i), vec![], st.next_hole)
+      }
+    }
+    if matches!(self.tbl.nodes[stmt], Node::Hole(_)) { return out }
+    for k in 0..self.thms.len() {
+      let thm = self.thms[k];
+      if !self.head_matches(thm, stmt) { continue }
+      let td = &self.tbl.fe.env.thms[thm];
+      if !td.hyps.is_empty() && goal.depth + 1 >= self.depth { continue }
+      let (nargs, base) = (td.args.len(), st.next_hole);
+      let concl = self.tbl.inst_holes(&td.heap, nargs, &td.ret, base);
+      let mut subst = st.subst.clone();
+      if !self.tbl.unify(stmt, concl, &mut subst) { continue }
+      let td = &self.tbl.fe.env.thms[thm];
+      let mut new_goals = vec![];
+      for (j, (_, h)) in td.hyps.iter().enumerate() {
+        let stmt = self.tbl.inst_holes(&td.heap, nargs, h, base);
+        new_goals.push(Goal {id: st.next_goal + j, stmt, depth: goal.depth + 1});
+      }
+      push(st, subst, Rule::Thm(thm, base), new_goals, base + nargs)
+    }
+    out
+  }
+
+  /// The free variables of `i`, including the dependencies of regular variables.
+  fn free_vars(&self, i: usize, out: &mut HashSet<AtomID>) {
+    match self.tbl.nodes[i] {
+      Node::Var(a) => {
+        out.insert(a);
+        if let Some(ds) = self.deps.get(&a) { out.extend(ds.iter().copied()) }
+      }
+      Node::App(_, ref args) => for &a in &**args { self.free_vars(a, out) },
+      _ => {}
+    }
+  }
+
+  /// The theorems applied in a partial proof, and the index of their first hole.
+  fn thm_steps(st: &State) -> Vec<(ThmID, usize)> {
+    st.steps().filter_map(|s| match s.rule {
+      Rule::Thm(thm, base) => Some((thm, base)),
+      Rule::Hyp(_) => None,
+    }).collect()
+  }
+
+  /// Assign the holes that are still undetermined in a complete proof. This happens for
+  /// arguments that only appear in hypotheses which are proved by theorems that are more
+  /// general than needed, like `b` in `ax_1: a -> b -> a`, and any local variable of the
+  /// right sort will do.
+  fn fill_holes(&mut self, st: &mut State) {
+    for (thm, base) in Self::thm_steps(st) {
+      let nargs = self.tbl.fe.env.thms[thm].args.len();
+      for j in 0..nargs {
+        if let Type::Reg(s, _) = self.tbl.fe.env.thms[thm].args[j].1 {
+          let h = self.tbl.intern(Node::Hole(base + j));
+          if let Node::Hole(k) = self.tbl.nodes[self.tbl.walk(h, &st.subst)] {
+            if let Some(&(a, _)) = self.vars.iter().find(|v| v.1 == s) {
+              let v = self.tbl.intern(Node::Var(a));
+              st.subst.insert(k, v);
+            }
+          }
+        }
+      }
+    }
+  }
+
+  /// Check that the theorem applications in a complete proof respect the bound variables
+  /// and disjointness conditions of the theorems.
+  fn valid(&mut self, st: &State) -> bool {
+    for (thm, base) in Self::thm_steps(st) {
+      let nargs = self.tbl.fe.env.thms[thm].args.len();
+      let vals = (0..nargs).map(|j| {
+        let h = self.tbl.intern(Node::Hole(base + j));
+        self.tbl.zonk(h, &st.subst)
+      }).collect::<Vec<_>>();
+      let td = &self.tbl.fe.env.thms[thm];
+      let mut bvs = vec![];
+      for (&(_, ty), &v) in td.args.iter().zip(&vals) {
+        if let Type::Bound(_) = ty {
+          match self.tbl.nodes[v] {
+            Node::Var(a) if !bvs.contains(&a) => bvs.push(a),
+            _ => return false,
+          }
+        }
+      }
+      for (&(_, ty), &v) in td.args.iter().zip(&vals) {
+        if let Type::Reg(_, deps) = ty {
+          let mut fvs = HashSet::new();
+          self.free_vars(v, &mut fvs);
+          if bvs.iter().enumerate().any(|(k, a)| deps & (1 << k) == 0 && fvs.contains(a)) {
+            return false
+          }
+        }
+      }
+    }
+    true
+  }
+
+  /// Assemble the proof of goal `id` from a complete state.
+  fn proof(&mut self, st: &State, steps: &HashMap<usize, &Step>, id: usize) -> LispVal {
+    let step = steps[&id];
+    match step.rule {
+      Rule::Hyp(i) => LispVal::atom(self.hyps[i].0),
+      Rule::Thm(thm, base) => {
+        let td = &self.tbl.fe.env.thms[thm];
+        let mut args = vec![LispVal::atom(AtomID::BANG), LispVal::atom(td.atom)];
+        for j in 0..td.args.len() {
+          let h = self.tbl.intern(Node::Hole(base + j));
+          let v = self.tbl.zonk(h, &st.subst);
+          args.push(self.tbl.expr(v))
+        }
+        for &g in &*step.subgoals { args.push(self.proof(st, steps, g)) }
+        LispVal::list(args)
+      }
+    }
+  }
+
+  fn print(&mut self, i: usize, subst: &HashMap<usize, usize>) -> String {
+    let i = self.tbl.zonk(i, subst);
+    let e = self.tbl.expr(i);
+    format!("{}", self.tbl.fe.pp(&e, 80))
+  }
+
+  /// Describe a partial proof, for the failure report.
+  fn describe(&mut self, st: &State) -> String {
+    let mut used = st.steps().map(|s| match s.rule {
+      Rule::Hyp(i) => self.hyps[i].0,
+      Rule::Thm(thm, _) => self.tbl.fe.env.thms[thm].atom,
+    }).collect::<Vec<_>>();
+    used.reverse();
+    let used = used.iter().map(|&a| format!("{}", self.tbl.fe.data[a].name)).collect::<Vec<_>>();
+    let goals = st.goals.iter().map(|g| self.print(g.stmt, &st.subst)).collect::<Vec<_>>();
+    format!("\n  using {}, remaining: {}", used.join(" "), goals.join(", "))
+  }
+
+  /// Run the search for a proof of `goal`.
+  fn search(&mut self, goal: usize) -> SResult<LispVal> {
+    let mut queue = BinaryHeap::new();
+    queue.push(Reverse(Queued {key: (1, 0), st: State {
+      goals: vec![Goal {id: 0, stmt: goal, depth: 0}],
+      subst: HashMap::new(), next_goal: 1, next_hole: 0, steps: None, len: 0,
+    }}));
+    let mut queued = 1;
+    let mut closest: Vec<State> = vec![];
+    let mut explored = 0;
+    let mut stopped = None;
+    while let Some(Reverse(Queued {mut st, ..})) = queue.pop() {
+      if st.goals.is_empty() {
+        self.fill_holes(&mut st);
+        if !self.valid(&st) { continue }
+        let steps = st.steps().map(|s| (s.goal, s)).collect();
+        return Ok(self.proof(&st, &steps, 0))
+      }
+      explored += 1;
+      if explored > MAX_STATES {
+        stopped = Some(("gave up", MAX_STATES));
+        break
+      }
+      if self.cancel.load(AtomicOrdering::Relaxed) { return Err("cancelled".into()) }
+      if matches!(self.deadline, Some(t) if t < Instant::now()) {
+        stopped = Some(("timeout", explored));
+        break
+      }
+      if st.len > 0 {
+        let key = |st: &State| (st.goals.len(), Reverse(st.len));
+        let pos = closest.iter().position(|s| key(&st) < key(s)).unwrap_or(closest.len());
+        if pos < REPORT_ATTEMPTS { closest.insert(pos, st.clone()); closest.truncate(REPORT_ATTEMPTS) }
+      }
+      for st2 in self.expand(&st) {
+        queue.push(Reverse(Queued {key: (st2.len + st2.goals.len(), queued), st: st2}));
+        queued += 1;
+      }
+    }
+    let mut msg = format!("auto failed to prove {}", self.print(goal, &HashMap::new()));
+    if let Some((reason, n)) = stopped {
+      use std::fmt::Write;
+      write!(msg, " ({} after {} states)", reason, n).expect("impossible");
+    }
+    if closest.is_empty() {
+      msg += ": no hypothesis or theorem applies"
+    } else {
+      msg += "; closest attempts:";
+      for st in &closest { msg += &self.describe(st) }
+    }
+    Err(msg)
+  }
+}
+
+impl Elaborator {
+  /// Implementation of `(auto thms [goal] [depth])`, which searches for a proof of `goal`
+  /// (or the type of the first goal if `goal` is not given) using the hypotheses in the
+  /// local context and the theorems in the list `thms`.
+  pub(crate) fn auto(&self, thms: &LispVal, goal: Option<&LispVal>, depth: usize) -> SResult<LispVal> {
+    let thms = Uncons::from(thms.clone()).map(|e| {
+      let a = e.as_atom().ok_or_else(|| format!("expected a theorem name, got {}", self.print(&e)))?;
+      self.thm(a).ok_or_else(|| format!("unknown theorem '{}'", self.data[a].name))
+    }).collect::<SResult<Vec<_>>>()?;
+    let goal = match goal {
+      Some(t) => t.clone(),
+      None => self.lc.goals.first().and_then(|g| g.goal_type()).ok_or("no goals")?,
+    };
+    let mut tbl = ExprTable::new(self.format_env());
+    let mut hyps = vec![];
+    for (i, (a, ty, _)) in self.lc.proof_order.iter().enumerate() {
+      // Skip shadowed hypotheses, since they cannot be referred to by name.
+      if self.lc.proofs.get(a) == Some(&i) { hyps.push((*a, tbl.node(ty)?)) }
+    }
+    let deps = self.lc.vars.iter().filter_map(|(&a, (_, is))| match is {
+      InferSort::Reg(_, ds) => Some((a, ds.clone())),
+      _ => None,
+    }).collect();
+    let vars = self.lc.var_order.iter().filter_map(|&(_, a, ref is)| {
+      let a = a?;
+      match is.as_ref().or_else(|| self.lc.vars.get(&a).map(|v| &v.1))? {
+        InferSort::Bound(s) | InferSort::Reg(s, _) => Some((a, *s)),
+        InferSort::Unknown {..} => None,
+      }
+    }).collect();
+    let goal = tbl.node(&goal)?;
+    let (deadline, cancel) = (self.cur_timeout, &*self.cancel);
+    Auto {tbl, hyps, thms, deps, vars, depth, deadline, cancel}.search(goal)
+  }
+}
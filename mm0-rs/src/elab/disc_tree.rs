@@ -0,0 +1,200 @@
+//! A discrimination tree index of theorem conclusions, used by the `find-thms` builtin
+//! and the `mm0-rs/findTheorems` server request.
+//!
+//! Each conclusion is flattened to the preorder list of its head symbols, with every
+//! theorem variable replaced by a wildcard of the same sort, and stored in a trie keyed on
+//! these symbols. The index is maintained incrementally by [`Environment::try_add_thm`],
+//! so it also covers imported theorems. A lookup walks the trie along the flattened query,
+//! where a wildcard in the trie matches any query subterm of its sort (only bound variables
+//! if the theorem variable is bound) and a hole in the query skips any stored subterm.
+//! This gives a superset of the theorems whose conclusion unifies with the query, which is
+//! then filtered by actual unification.
+//!
+//! [`Environment::try_add_thm`]: super::Environment::try_add_thm
+
+use std::collections::HashMap;
+use super::Elaborator;
+use super::environment::{AtomID, ExprNode, SortID, Term, TermID, TermVec, Thm, ThmID};
+use super::lisp::{LispKind, LispVal, Uncons, eval::SResult};
+use super::local_context::InferSort;
+use super::expr_table::{ExprTable, Node};
+
+/// A key in the discrimination tree.
+#[allow(variant_size_differences)]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+enum Key {
+  /// A theorem variable of the given sort, which is bound if the flag is set. It matches
+  /// any subterm of the same sort, or only bound variables if it is bound.
+  Var(SortID, bool),
+  /// An application of a term constructor, followed by the keys of its arguments.
+  Term(TermID),
+}
+crate::deep_size_0!(Key);
+
+/// A discrimination tree of theorem conclusions, stored in the
+/// [`Environment`](super::Environment).
+#[derive(Default, Clone, Debug, DeepSizeOf)]
+pub struct ThmIndex {
+  /// The subtrees for each possible next key.
+  children: HashMap<Key, ThmIndex>,
+  /// The theorems whose conclusion ends at this node.
+  thms: Vec<ThmID>,
+}
+
+impl ThmIndex {
+  /// Add a theorem to the index, keyed on its conclusion.
+  pub fn insert(&mut self, id: ThmID, thm: &Thm) {
+    fn keys(thm: &Thm, e: &ExprNode, out: &mut Vec<Key>) {
+      match *e {
+        ExprNode::Ref(i) if i < thm.args.len() => {
+          let ty = thm.args[i].1;
+          out.push(Key::Var(ty.sort(), ty.bound()));
+        }
+        ExprNode::Ref(i) => keys(thm, &thm.heap[i], out),
+        // Dummies do not appear in theorem statements.
+        ExprNode::Dummy(_, s) => out.push(Key::Var(s, true)),
+        ExprNode::App(t, ref es) => {
+          out.push(Key::Term(t));
+          for e in &**es { keys(thm, e, out) }
+        }
+      }
+    }
+    let mut ks = vec![];
+    keys(thm, &thm.ret, &mut ks);
+    let mut node = self;
+    for k in ks { node = node.children.entry(k).or_default() }
+    node.thms.push(id)
+  }
+
+  /// Collect the subtrees reached after skipping `n` complete subterms.
+  fn skip<'a>(&'a self, terms: &TermVec<Term>, n: usize, out: &mut Vec<&'a ThmIndex>) {
+    if n == 0 { return out.push(self) }
+    for (k, child) in &self.children {
+      match *k {
+        Key::Var(..) => child.skip(terms, n - 1, out),
+        Key::Term(t) => child.skip(terms, n - 1 + terms[t].args.len(), out),
+      }
+    }
+  }
+
+  /// Collect the theorems whose conclusion may match the query subterms in `stack`
+  /// (which are processed from the end), from the expression table `tbl`. The sorts of
+  /// local variables in the query are looked up in `vars`.
+  pub(crate) fn find(&self, tbl: &ExprTable<'_>, vars: &HashMap<AtomID, (bool, InferSort)>,
+      stack: &mut Vec<usize>, out: &mut Vec<ThmID>) {
+    let i = match stack.pop() {
+      None => return out.extend_from_slice(&self.thms),
+      Some(i) => i,
+    };
+    match tbl.nodes[i] {
+      Node::Hole(_) | Node::MVar(_) => {
+        let mut next = vec![];
+        self.skip(&tbl.fe.env.terms, 1, &mut next);
+        for node in next { node.find(tbl, vars, stack, out) }
+      }
+      Node::Var(_) | Node::App(..) => {
+        match query_sort(tbl, vars, i) {
+          Some((s, bound)) => {
+            if let Some(node) = self.children.get(&Key::Var(s, false)) { node.find(tbl, vars, stack, out) }
+            if bound {
+              if let Some(node) = self.children.get(&Key::Var(s, true)) { node.find(tbl, vars, stack, out) }
+            }
+          }
+          None => for (k, node) in &self.children {
+            if let Key::Var(..) = k { node.find(tbl, vars, stack, out) }
+          }
+        }
+        if let Node::App(t, ref args) = tbl.nodes[i] {
+          if let Some(node) = self.children.get(&Key::Term(t)) {
+            let n = stack.len();
+            stack.extend(args.iter().rev());
+            node.find(tbl, vars, stack, out);
+            stack.truncate(n);
+          }
+        }
+      }
+    }
+    stack.push(i)
+  }
+}
+
+/// The sort of the query subterm `i`, and whether it is a bound variable, or `None` if
+/// it is not known (a hole, or a variable with no declared type).
+fn query_sort(tbl: &ExprTable<'_>, vars: &HashMap<AtomID, (bool, InferSort)>, i: usize) -> Option<(SortID, bool)> {
+  match tbl.nodes[i] {
+    Node::App(t, _) => Some((tbl.fe.env.terms[t].ret.0, false)),
+    Node::Var(a) => match vars.get(&a)?.1 {
+      InferSort::Bound(s) => Some((s, true)),
+      InferSort::Reg(s, _) => Some((s, false)),
+      InferSort::Unknown {..} => None,
+    },
+    Node::MVar(_) | Node::Hole(_) => None,
+  }
+}
+
+impl Elaborator {
+  /// Convert a query expression to a node of `tbl`. Metavariables and the atom `_`
+  /// are wildcards (each becoming a distinct hole), and an atom naming a nullary term
+  /// constructor that is not shadowed by a local variable is the application of the term,
+  /// since such atoms are left bare in unelaborated math quotations.
+  fn find_thms_query(&self, tbl: &mut ExprTable<'_>, e: &LispVal, holes: &mut usize) -> SResult<usize> {
+    let n = e.unwrapped(|k| match *k {
+      LispKind::Atom(AtomID::UNDER) | LispKind::MVar(_, _) => Ok(None),
+      LispKind::Atom(a) => Ok(Some(match self.term(a) {
+        Some(t) if !self.lc.vars.contains_key(&a) && self.terms[t].args.is_empty() =>
+          Err((t, Uncons::nil())),
+        _ => Ok(a),
+      })),
+      LispKind::List(_) | LispKind::DottedList(_, _) => {
+        let mut u = Uncons::from(e.clone());
+        let t = u.next().and_then(|h| h.as_atom()).and_then(|a| self.term(a))
+          .ok_or_else(|| format!("not a term: {}", self.print(e)))?;
+        Ok(Some(Err((t, u))))
+      }
+      _ => Err(format!("not a term: {}", self.print(e)))
+    })?;
+    match n {
+      None => { *holes += 1; Ok(tbl.intern(Node::Hole(*holes - 1))) }
+      Some(Ok(a)) => Ok(tbl.intern(Node::Var(a))),
+      Some(Err((t, u))) => {
+        let mut args = vec![];
+        for e in u { args.push(self.find_thms_query(tbl, &e, holes)?) }
+        if args.len() != self.terms[t].args.len() {
+          return Err(format!("incorrect number of arguments: {}", self.print(e)))
+        }
+        Ok(tbl.app(t, &args))
+      }
+    }
+  }
+
+  /// Implementation of `(find-thms e)`, which returns the theorems whose conclusion
+  /// unifies with `e` (respecting the sorts of the theorem variables), in declaration order.
+  pub(crate) fn find_thms(&self, e: &LispVal) -> SResult<Vec<ThmID>> {
+    let mut tbl = ExprTable::new(self.format_env());
+    let mut holes = 0;
+    let query = self.find_thms_query(&mut tbl, e, &mut holes)?;
+    let mut cands = vec![];
+    self.thm_index.find(&tbl, &self.lc.vars, &mut vec![query], &mut cands);
+    cands.sort();
+    Ok(cands.into_iter().filter(|&thm| {
+      let td = &self.thms[thm];
+      let concl = tbl.inst_holes(&td.heap, td.args.len(), &td.ret, holes);
+      let mut subst = HashMap::new();
+      if !tbl.unify(query, concl, &mut subst) { return false }
+      // The unifier does not know about sorts, so check the assignment of each variable.
+      td.args.iter().enumerate().all(|(j, &(_, ty))| {
+        let i = { let h = tbl.intern(Node::Hole(holes + j)); tbl.walk(h, &subst) };
+        let sort = match tbl.nodes[i] {
+          Node::Hole(h) if h >= holes => { let ty2 = td.args[h - holes].1; Some((ty2.sort(), ty2.bound())) }
+          _ => query_sort(&tbl, &self.lc.vars, i),
+        };
+        match sort { None => true, Some((s, bound)) => s == ty.sort() && (bound || !ty.bound()) }
+      })
+    }).collect())
+  }
+
+  /// Find the theorems whose conclusion unifies with the first goal, if there is one.
+  pub(crate) fn find_thms_goal(&self) -> Option<SResult<Vec<ThmID>>> {
+    Some(self.find_thms(&self.lc.goals.first()?.goal_type()?))
+  }
+}
@@ -13,7 +13,13 @@ use std::collections::HashMap;
 use super::{ElabError, BoxError, spans::Spans, FrozenEnv, FrozenLispVal};
 use crate::util::{ArcString, FileRef, FileSpan, HashMapExt, Span};
 use super::lisp::{LispVal, RefineSyntax, Syntax};
-use super::frozen::{FrozenLispKind, FrozenLispRef};
+use super::frozen::{FrozenLispData, FrozenLispKind, FrozenLispRef};
+use super::rewrite::RewriteEnv;
+use super::tauto::TautoEnv;
+use super::norm_num::NormNumEnv;
+use super::disc_tree::ThmIndex;
+use super::tptp::TptpEnv;
+use super::lrat::LratEnv;
 pub use crate::parser::ast::{Modifiers, Prec};
 
 macro_rules! id_wrapper {
@@ -365,7 +371,7 @@ pub enum StmtTrace {
 /// A declaration is either a [`Term`] or a [`Thm`]. This is done because in MM1
 /// Terms and Thms share a namespace (although they are put in separate number-spaces
 /// for compilation to MM0).
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum DeclKey {
   /// A term or def, with its ID
   Term(TermID),
@@ -475,6 +481,9 @@ pub struct LispData {
   pub src: Option<(FileSpan, Span)>,
   /// The documentation on the declaration of the item.
   pub doc: Option<DocComment>,
+  /// True if this definition was made in a `local do` block, in which case it
+  /// is not exported to files that import this one.
+  pub local: bool,
   /// The value associated to the name.
   pub val: LispVal
 }
@@ -576,6 +585,18 @@ pub struct Environment {
   pub stmts: Vec<StmtTrace>,
   /// The list of spans that have been collected in the current statement.
   pub spans: Vec<Spans<ObjectKind>>,
+  /// The equality relations and congruence lemmas registered for the rewriter.
+  pub rewrite: RewriteEnv,
+  /// The connectives and lemmas registered for the `tauto` tactic.
+  pub tauto: Option<TautoEnv>,
+  /// The numeral encoding and lemmas registered for the `norm-num` builtin.
+  pub norm_num: Option<NormNumEnv>,
+  /// The mapping of terms to TPTP connectives registered for `tptp-export`.
+  pub tptp: Option<TptpEnv>,
+  /// The connectives and lemmas registered for the `lrat` builtin.
+  pub lrat: Option<LratEnv>,
+  /// The index of theorem conclusions, used by `find-thms`.
+  pub thm_index: ThmIndex,
 }
 
 macro_rules! make_atoms {
@@ -611,6 +632,12 @@ macro_rules! make_atoms {
           thms: Default::default(),
           stmts: Default::default(),
           spans: Default::default(),
+          rewrite: Default::default(),
+          tauto: None,
+          norm_num: None,
+          tptp: None,
+          lrat: None,
+          thm_index: Default::default(),
         }
       }
     }
@@ -707,6 +734,10 @@ pub struct Remapper {
   thm: ThmVec<ThmID>,
   /// A mapping of foreign atoms into local atom IDs
   pub(crate) atom: AtomVec<AtomID>,
+  /// Overrides for the atoms of global variable references in lisp code. This is used
+  /// to redirect references to [`local`](LispData::local) definitions in an imported file
+  /// to their hidden names.
+  pub(crate) global: HashMap<AtomID, AtomID>,
   /// A mapping of foreign [`FrozenLispVal`]s into local [`LispVal`]s.
   /// It uses a pointer to the underlying allocation as an identifier so that
   /// we don't remap the same lisp values many times.
@@ -1230,7 +1261,9 @@ impl Environment {
       }))
     } else {
       data.decl = Some(DeclKey::Thm(new_id));
-      self.thms.push(t());
+      let t = t();
+      self.thm_index.insert(new_id, &t);
+      self.thms.push(t);
       self.stmts.push(StmtTrace::Decl(a));
       Ok(new_id)
     }
@@ -1270,18 +1303,76 @@ impl Environment {
 
   /// Merge `other` into this environment. This merges definitions with the same name and type,
   /// and relabels lisp objects with the new [`AtomID`] mapping.
-  pub fn merge(&mut self, other: &FrozenEnv, sp: Span, errors: &mut Vec<ElabError>) -> Result<(), ElabError> {
+  ///
+  /// If `import` is `Some(path)`, then `other` is the environment of the file `path` being
+  /// imported, rather than a copy of this file's own environment. In this case lisp
+  /// definitions marked [`local`](LispData::local) are not visible by name, each lisp definition made
+  /// in `path` itself is also made available under the qualified name `module::name` (where
+  /// `module` is the file name of `path` without the extension), and a warning is reported
+  /// if a lisp definition replaces one from an unrelated file.
+  ///
+  /// Local lisp definitions are still needed by the exported functions that use them, so they
+  /// are copied to the hidden name `module::#name`, which cannot be written in lisp source,
+  /// and references to them in imported code are redirected accordingly.
+  pub fn merge(&mut self, other: &FrozenEnv, import: Option<&FileRef>,
+    sp: Span, errors: &mut Vec<ElabError>
+  ) -> Result<(), ElabError> {
     let remap = &mut Remapper {
       atom: other.data().iter().map(|d| self.get_atom_arc(d.name().clone())).collect(),
       ..Default::default()
     };
+    let module = import.and_then(|p| p.path().file_stem()).map(|s| s.to_string_lossy());
+    if let Some(m) = &module {
+      #[allow(clippy::cast_possible_truncation)]
+      for (i, d) in other.data().iter().enumerate() {
+        if d.lisp().as_ref().map_or(false, FrozenLispData::local) {
+          let hidden = self.get_atom(format!("{}::#{}", m, d.name()).as_bytes());
+          remap.global.insert(AtomID(i as u32), hidden);
+        }
+      }
+    }
+    let mut known_files = None;
     #[allow(clippy::cast_possible_truncation)]
     for (i, d) in other.data().iter().enumerate() {
-      let data = &mut self.data[remap.atom[AtomID(i as u32)]];
-      data.lisp = d.lisp().as_ref().map(|v| v.remap(remap));
-      if data.lisp.is_none() {
-        data.graveyard = d.graveyard().clone();
+      let a = remap.atom[AtomID(i as u32)];
+      let v = match d.lisp() {
+        // An atom that is merely mentioned in `other` does not erase an existing
+        // definition, but one that was explicitly undefined does.
+        None => {
+          if d.graveyard().is_some() || import.is_none() {
+            let data = &mut self.data[a];
+            data.lisp = None;
+            data.graveyard = d.graveyard().clone();
+          }
+          continue
+        }
+        Some(v) => v,
+      };
+      if let Some(path) = import {
+        if v.local() {
+          let hidden = remap.global[&AtomID(i as u32)];
+          self.data[hidden].lisp = Some(LispData {local: false, ..v.remap(remap)});
+          continue
+        }
+        if let (Some(LispData {src: Some((fsp, _)), ..}), Some((ofsp, _))) = (&self.data[a].lisp, v.src()) {
+          if fsp.file != ofsp.file && !known_files.get_or_insert_with(|| other.files())
+              .contains(&fsp.file) {
+            errors.push(ElabError::warn_with_info(sp,
+              format!("imported global '{}' replaces a definition from another file", d.name()).into(),
+              vec![(ofsp.clone(), "defined here".into()), (fsp.clone(), "previously defined here".into())]));
+          }
+        }
+        if let (Some(m), Some((ofsp, _))) = (&module, v.src()) {
+          if ofsp.file == *path && !d.name().windows(2).any(|s| s == b"::") {
+            let data = v.remap(remap);
+            let q = self.get_atom(format!("{}::{}", m, d.name()).as_bytes());
+            self.data[q].lisp = Some(data.clone());
+            self.data[a].lisp = Some(data);
+            continue
+          }
+        }
       }
+      self.data[a].lisp = Some(v.remap(remap));
     }
     for s in other.stmts() {
       match *s {
@@ -1341,6 +1432,11 @@ impl Environment {
       }
     }
     self.pe.merge(other.pe(), remap, sp, &self.sorts, errors);
+    self.rewrite.merge(other.rewrite(), remap, errors, sp);
+    TautoEnv::merge(&mut self.tauto, other.tauto(), remap, errors, sp);
+    NormNumEnv::merge(&mut self.norm_num, other.norm_num(), remap, errors, sp);
+    TptpEnv::merge(&mut self.tptp, other.tptp(), remap, errors, sp);
+    LratEnv::merge(&mut self.lrat, other.lrat(), remap, errors, sp);
     Ok(())
   }
 
@@ -0,0 +1,262 @@
+//! A hash-consed table of expressions, used by the tactics that are implemented natively
+//! in Rust, such as `rewrite`.
+//!
+//! Expressions are converted from lisp values to [`Node`]s, which are interned so that
+//! equal expressions get the same index. The table supports matching theorem statements
+//! against expressions, and converting expressions back to lisp values for use in the
+//! proof terms produced by the tactics.
+
+use std::collections::{HashMap, HashSet};
+use super::environment::{AtomID, ExprNode, TermID, Thm, ThmID};
+use super::lisp::{LispKind, LispVal, Uncons, print::FormatEnv, eval::SResult};
+
+/// An expression node in a hash-consed [`ExprTable`].
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Node {
+  /// A variable in the local context.
+  Var(AtomID),
+  /// A metavariable, which is treated as an opaque constant.
+  MVar(usize),
+  /// An application of a term constructor.
+  App(TermID, Box<[usize]>),
+  /// A unification variable, used by `auto` for theorem arguments that are not
+  /// determined by the conclusion.
+  Hole(usize),
+}
+
+/// A hash-consed table of expressions, used for matching theorem statements against
+/// expressions and constructing explicit proof terms.
+pub(crate) struct ExprTable<'a> {
+  pub(crate) fe: FormatEnv<'a>,
+  /// The expression nodes.
+  pub(crate) nodes: Vec<Node>,
+  /// The index of each node in `nodes`.
+  ids: HashMap<Node, usize>,
+  /// The lisp expression corresponding to each node, if it has been computed.
+  /// Leaves are always populated, so that metavariables are preserved.
+  lisp: HashMap<usize, LispVal>,
+}
+
+impl<'a> ExprTable<'a> {
+  pub(crate) fn new(fe: FormatEnv<'a>) -> Self {
+    ExprTable {fe, nodes: vec![], ids: HashMap::new(), lisp: HashMap::new()}
+  }
+
+  pub(crate) fn intern(&mut self, n: Node) -> usize {
+    let Self {nodes, ids, ..} = self;
+    *ids.entry(n).or_insert_with_key(|n| { nodes.push(n.clone()); nodes.len() - 1 })
+  }
+
+  /// Intern the application `t args`.
+  pub(crate) fn app(&mut self, t: TermID, args: &[usize]) -> usize {
+    self.intern(Node::App(t, args.into()))
+  }
+
+  /// Convert a lisp expression into a node.
+  pub(crate) fn node(&mut self, e: &LispVal) -> SResult<usize> {
+    let fe = self.fe;
+    let n = e.unwrapped(|k| match *k {
+      LispKind::Atom(a) => Ok(Ok(Node::Var(a))),
+      LispKind::MVar(n, _) => Ok(Ok(Node::MVar(n))),
+      LispKind::List(_) | LispKind::DottedList(_, _) => {
+        let mut u = Uncons::from(e.clone());
+        let t = u.next().and_then(|h| h.as_atom()).and_then(|a| fe.term(a))
+          .ok_or_else(|| format!("not a term: {}", fe.to(e)))?;
+        Ok(Err((t, u)))
+      }
+      _ => Err(format!("not a term: {}", fe.to(e)))
+    })?;
+    let n = match n {
+      Ok(n) => { let i = self.intern(n); self.lisp.entry(i).or_insert_with(|| e.clone()); return Ok(i) }
+      Err((t, u)) => {
+        let mut args = vec![];
+        for e in u { args.push(self.node(&e)?) }
+        if args.len() != fe.terms[t].args.len() {
+          return Err(format!("incorrect number of arguments: {}", fe.to(e)))
+        }
+        Node::App(t, args.into())
+      }
+    };
+    Ok(self.intern(n))
+  }
+
+  /// Convert a node into a lisp expression.
+  pub(crate) fn expr(&mut self, i: usize) -> LispVal {
+    if let Some(e) = self.lisp.get(&i) { return e.clone() }
+    let e = match self.nodes[i].clone() {
+      Node::Var(a) => LispVal::atom(a),
+      Node::MVar(_) => unreachable!("metavariables are always populated"),
+      Node::Hole(_) => LispVal::atom(AtomID::UNDER),
+      Node::App(t, args) => {
+        let mut es = vec![LispVal::atom(self.fe.terms[t].atom)];
+        es.extend(args.iter().map(|&a| self.expr(a)));
+        LispVal::list(es)
+      }
+    };
+    self.lisp.insert(i, e.clone());
+    e
+  }
+
+  /// Match the pattern `pat` from a theorem statement against node `i`.
+  pub(crate) fn matches(&self, heap: &[ExprNode], nargs: usize, pat: &ExprNode, i: usize,
+      subst: &mut [Option<usize>]) -> bool {
+    match *pat {
+      ExprNode::Ref(j) if j < nargs =>
+        if let Some(k) = subst[j] { k == i } else { subst[j] = Some(i); true },
+      ExprNode::Ref(j) => self.matches(heap, nargs, &heap[j], i, subst),
+      ExprNode::Dummy(_, _) => false,
+      ExprNode::App(t, ref ps) => match &self.nodes[i] {
+        Node::App(t2, args) if t == *t2 && ps.len() == args.len() =>
+          ps.iter().zip(args.iter()).all(|(p, &a)| self.matches(heap, nargs, p, a, subst)),
+        _ => false
+      }
+    }
+  }
+
+  /// Substitute into the pattern `e` from a theorem statement.
+  pub(crate) fn subst(&mut self, heap: &[ExprNode], nargs: usize, e: &ExprNode, subst: &[Option<usize>]) -> usize {
+    match *e {
+      ExprNode::Ref(j) if j < nargs => subst[j].expect("variable not in the pattern"),
+      ExprNode::Ref(j) => self.subst(heap, nargs, &heap[j], subst),
+      ExprNode::Dummy(_, _) => unreachable!("dummy in theorem statement"),
+      ExprNode::App(t, ref es) => {
+        let args = es.iter().map(|e| self.subst(heap, nargs, e, subst)).collect();
+        self.intern(Node::App(t, args))
+      }
+    }
+  }
+
+  /// Find a substitution for the variables of `thm` such that the conclusion is `concl`
+  /// and the hypotheses are `hyps` in some order. Returns the substitution and,
+  /// for each hypothesis of `thm`, the index in `hyps` of the corresponding statement.
+  pub(crate) fn unify_thm(&self, thm: ThmID, concl: usize, hyps: &[usize]) -> Option<(Vec<usize>, Vec<usize>)> {
+    fn rec(tbl: &ExprTable<'_>, td: &Thm, k: usize, hyps: &[usize], used: &mut Vec<usize>,
+        subst: &mut Vec<Option<usize>>) -> bool {
+      let h = if let Some((_, h)) = td.hyps.get(k) { h } else { return true };
+      for (i, &e) in hyps.iter().enumerate() {
+        if used.contains(&i) { continue }
+        let old = subst.clone();
+        if tbl.matches(&td.heap, td.args.len(), h, e, subst) {
+          used.push(i);
+          if rec(tbl, td, k + 1, hyps, used, subst) { return true }
+          used.pop();
+        }
+        *subst = old;
+      }
+      false
+    }
+    let td = &self.fe.env.thms[thm];
+    if td.hyps.len() != hyps.len() { return None }
+    let mut subst = vec![None; td.args.len()];
+    if !self.matches(&td.heap, td.args.len(), &td.ret, concl, &mut subst) { return None }
+    let mut used = vec![];
+    if !rec(self, td, 0, hyps, &mut used, &mut subst) { return None }
+    Some((subst.into_iter().collect::<Option<_>>()?, used))
+  }
+
+  /// Create a fresh opaque expression, for use in checking the form of a theorem.
+  pub(crate) fn fresh(&mut self) -> usize {
+    self.intern(Node::MVar(usize::MAX - self.nodes.len()))
+  }
+
+  /// Check that `thm` has exactly the form `hyps -> concl` (up to the order of the
+  /// hypotheses), where `concl` and `hyps` are built from [`fresh`](Self::fresh) variables,
+  /// and return an error message mentioning `form` if not.
+  pub(crate) fn check_thm(&self, thm: ThmID, concl: usize, hyps: &[usize], form: &str) -> SResult<()> {
+    let name = || &self.fe.data[self.fe.env.thms[thm].atom].name;
+    let (subst, _) = self.unify_thm(thm, concl, hyps)
+      .ok_or_else(|| format!("'{}' does not have the form '{}'", name(), form))?;
+    // Distinct variables of the theorem must map to distinct fresh variables, to ensure
+    // that the theorem is not a special case of the required form.
+    let mut seen = HashSet::new();
+    if !subst.into_iter().all(|i| matches!(self.nodes[i], Node::MVar(_)) && seen.insert(i)) {
+      return Err(format!("'{}' is more specific than '{}'", name(), form))
+    }
+    Ok(())
+  }
+
+  /// Construct the proof `(! thm args hyps)`, where the arguments are determined by
+  /// matching the conclusion and hypotheses of `thm` against `concl` and `hyps`.
+  /// The hypotheses can be given in any order.
+  pub(crate) fn apply(&mut self, thm: ThmID, concl: usize, hyps: Vec<(usize, LispVal)>) -> LispVal {
+    let mut args = vec![LispVal::atom(AtomID::BANG)];
+    self.apply_args(thm, concl, hyps, &mut args);
+    LispVal::list(args)
+  }
+
+  /// Construct the elaborated proof `(thm args hyps)`, like [`apply`](Self::apply).
+  /// This is only valid inside `(:verb p)`, but it is not elaborated again by `refine`,
+  /// so subproofs can be shared.
+  pub(crate) fn apply_verb(&mut self, thm: ThmID, concl: usize, hyps: Vec<(usize, LispVal)>) -> LispVal {
+    let mut args = vec![];
+    self.apply_args(thm, concl, hyps, &mut args);
+    LispVal::list(args)
+  }
+
+  fn apply_args(&mut self, thm: ThmID, concl: usize, hyps: Vec<(usize, LispVal)>, args: &mut Vec<LispVal>) {
+    let stmts = hyps.iter().map(|h| h.0).collect::<Vec<_>>();
+    let (subst, order) = self.unify_thm(thm, concl, &stmts).expect("registered theorem does not match");
+    args.push(LispVal::atom(self.fe.env.thms[thm].atom));
+    for i in subst { args.push(self.expr(i)) }
+    let mut hyps = hyps.into_iter().map(|h| Some(h.1)).collect::<Vec<_>>();
+    args.extend(order.into_iter().map(|i| hyps[i].take().expect("distinct")));
+  }
+}
+
+/// Unification with [`Hole`](Node::Hole)s, used by `auto` and `find-thms`.
+impl ExprTable<'_> {
+  /// Follow the substitution at the root of `i`.
+  pub(crate) fn walk(&self, mut i: usize, subst: &HashMap<usize, usize>) -> usize {
+    while let Node::Hole(h) = self.nodes[i] {
+      match subst.get(&h) { Some(&j) => i = j, None => break }
+    }
+    i
+  }
+
+  /// Returns true if the hole `h` occurs in `i`.
+  pub(crate) fn occurs(&self, h: usize, i: usize, subst: &HashMap<usize, usize>) -> bool {
+    match self.nodes[self.walk(i, subst)] {
+      Node::Hole(h2) => h == h2,
+      Node::App(_, ref args) => args.iter().any(|&a| self.occurs(h, a, subst)),
+      _ => false,
+    }
+  }
+
+  /// Unify `a` and `b`, extending the substitution.
+  pub(crate) fn unify(&self, a: usize, b: usize, subst: &mut HashMap<usize, usize>) -> bool {
+    let (a, b) = (self.walk(a, subst), self.walk(b, subst));
+    if a == b { return true }
+    match (&self.nodes[a], &self.nodes[b]) {
+      (&Node::Hole(h), _) if !self.occurs(h, b, subst) => { subst.insert(h, b); true }
+      (_, &Node::Hole(h)) if !self.occurs(h, a, subst) => { subst.insert(h, a); true }
+      (Node::App(t1, args1), Node::App(t2, args2)) if t1 == t2 =>
+        args1.iter().zip(&**args2).all(|(&a, &b)| self.unify(a, b, subst)),
+      _ => false,
+    }
+  }
+
+  /// Apply the substitution everywhere in `i`.
+  pub(crate) fn zonk(&mut self, i: usize, subst: &HashMap<usize, usize>) -> usize {
+    let i = self.walk(i, subst);
+    match self.nodes[i].clone() {
+      Node::App(t, args) => {
+        let args2 = args.iter().map(|&a| self.zonk(a, subst)).collect::<Vec<_>>();
+        if *args2 == *args { i } else { self.app(t, &args2) }
+      }
+      _ => i,
+    }
+  }
+
+  /// Instantiate a pattern from a theorem statement, where variable `j` is the hole `base + j`.
+  pub(crate) fn inst_holes(&mut self, heap: &[ExprNode], nargs: usize, e: &ExprNode, base: usize) -> usize {
+    match *e {
+      ExprNode::Ref(j) if j < nargs => self.intern(Node::Hole(base + j)),
+      ExprNode::Ref(j) => self.inst_holes(heap, nargs, &heap[j], base),
+      ExprNode::Dummy(_, _) => unreachable!("dummy in theorem statement"),
+      ExprNode::App(t, ref es) => {
+        let args = es.iter().map(|e| self.inst_holes(heap, nargs, e, base)).collect::<Vec<_>>();
+        self.app(t, &args)
+      }
+    }
+  }
+}
@@ -35,15 +35,20 @@ use std::cell::{Cell, RefCell};
 use std::ops::Deref;
 use std::sync::Arc;
 use std::rc::Rc;
-use std::collections::{HashMap, hash_map::Entry};
+use std::collections::{HashMap, HashSet, hash_map::Entry};
 use num::BigInt;
+use super::rewrite::RewriteEnv;
+use super::tauto::TautoEnv;
+use super::norm_num::NormNumEnv;
+use super::tptp::TptpEnv;
+use super::lrat::LratEnv;
 use super::{Spans, ObjectKind, Remap, Remapper,
   environment::{Environment, ParserEnv,
     AtomVec, TermVec, ThmVec, SortVec, DeclKey, StmtTrace, DocComment, LispData,
     SortID, TermID, ThmID, AtomID, Sort, Term, Thm, AtomData},
   lisp::{LispVal, LispKind, LispRef, LispWeak,
     InferTarget, Proc, Annot, Syntax, print::FormatEnv}};
-use crate::util::{ArcString, FileSpan, Span};
+use crate::util::{ArcString, FileRef, FileSpan, Span};
 use crate::{lined_string::LinedString, __mk_lisp_kind};
 
 /// A "frozen" environment, which is a thread-safe read only
@@ -99,10 +104,33 @@ impl FrozenEnv {
   #[must_use] pub fn thm(&self, t: ThmID) -> &Thm { &self.thms()[t] }
   /// Accessor for [`Environment::stmts`]
   #[must_use] pub fn stmts(&self) -> &[StmtTrace] { &unsafe { self.thaw() }.stmts }
+  /// Get the set of files that contributed declarations or lisp definitions to the environment.
+  #[must_use] pub fn files(&self) -> HashSet<FileRef> {
+    let mut files = HashSet::new();
+    for s in self.sorts().iter() { files.insert(s.span.file.clone()); }
+    for t in self.terms().iter() { files.insert(t.span.file.clone()); }
+    for t in self.thms().iter() { files.insert(t.span.file.clone()); }
+    for d in self.data().iter() {
+      if let Some((fsp, _)) = d.lisp().as_ref().and_then(|v| v.src().as_ref()) {
+        files.insert(fsp.file.clone());
+      }
+    }
+    files
+  }
   /// Parse a string into an atom.
   #[must_use] pub fn get_atom(&self, s: &[u8]) -> Option<AtomID> { unsafe { self.thaw() }.atoms.get(s).copied() }
   /// Accessor for [`Environment::pe`]
   #[must_use] pub fn pe(&self) -> &ParserEnv { &unsafe { self.thaw() }.pe }
+  /// Accessor for [`Environment::rewrite`]
+  #[must_use] pub fn rewrite(&self) -> &RewriteEnv { &unsafe { self.thaw() }.rewrite }
+  /// Accessor for [`Environment::tauto`]
+  #[must_use] pub fn tauto(&self) -> &Option<TautoEnv> { &unsafe { self.thaw() }.tauto }
+  /// Accessor for [`Environment::norm_num`]
+  #[must_use] pub fn norm_num(&self) -> &Option<NormNumEnv> { &unsafe { self.thaw() }.norm_num }
+  /// Accessor for [`Environment::tptp`]
+  #[must_use] pub fn tptp(&self) -> &Option<TptpEnv> { &unsafe { self.thaw() }.tptp }
+  /// Accessor for [`Environment::lrat`]
+  #[must_use] pub fn lrat(&self) -> &Option<LratEnv> { &unsafe { self.thaw() }.lrat }
 }
 
 /// A wrapper around an [`AtomData`] that is frozen.
@@ -135,6 +163,8 @@ impl FrozenLispData {
   #[must_use] pub fn src(&self) -> &Option<(FileSpan, Span)> { &self.0.src }
   /// Accessor for [`LispData::doc`]
   #[must_use] pub fn doc(&self) -> &Option<DocComment> { &self.0.doc }
+  /// Accessor for [`LispData::local`]
+  #[must_use] pub fn local(&self) -> bool { self.0.local }
 }
 impl Deref for FrozenLispData {
   type Target = FrozenLispVal;
@@ -305,6 +335,7 @@ impl Remap for FrozenLispData {
     LispData {
       src: self.src().clone(),
       doc: self.doc().clone(),
+      local: self.local(),
       val: (**self).remap(r)
     }
   }
@@ -327,6 +358,9 @@ impl Remap for FrozenLispKind {
       FrozenLispKind::Annot(sp, m) => LispVal::new(LispKind::Annot(sp.clone(), m.remap(r))),
       FrozenLispKind::Proc(f) => LispVal::proc(f.remap(r)),
       FrozenLispKind::AtomMap(m) => LispVal::new(LispKind::AtomMap(m.remap(r))),
+      FrozenLispKind::Vector(es) => LispVal::new(LispKind::Vector(es.remap(r))),
+      FrozenLispKind::HashMap(m) => LispVal::new(LispKind::HashMap(
+        m.iter().map(|(k, v)| (k.remap(r), v.remap(r))).collect())),
       FrozenLispKind::Ref(m) => match r.refs.entry(m as *const _) {
         Entry::Occupied(e) => e.get().clone(),
         Entry::Vacant(e) => {
@@ -373,6 +407,7 @@ impl Remap for FrozenProc {
         }
       )),
       Proc::MMCCompiler(c) => Proc::MMCCompiler(c.remap(r)),
+      Proc::Macro(f) => Proc::Macro(f.remap(r)),
     }
   }
 }
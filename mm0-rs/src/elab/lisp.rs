@@ -111,6 +111,9 @@ str_enum! {
     MatchFn: "match-fn",
     /// `match-fn*`: a lambda taking any number of arguments that pattern matches on the list of arguments.
     MatchFns: "match-fn*",
+    /// `defmacro`: define a global macro, a function from s-expressions to s-expressions
+    /// that is called on the unevaluated arguments when the code is parsed.
+    DefMacro: "defmacro",
   }
 }
 
@@ -118,7 +121,7 @@ impl Syntax {
   /// Parse a string and atom type pair into a [`Syntax`].
   pub fn parse(s: &[u8], a: Atom) -> Result<Syntax, &[u8]> {
     match a {
-      Atom::Ident => Syntax::from_bytes(s).ok_or(s),
+      Atom::Ident | Atom::Gen(_) => Syntax::from_bytes(s).ok_or(s),
       Atom::Quote => Ok(Syntax::Quote),
       Atom::Unquote => Ok(Syntax::Unquote),
       Atom::Nfx => Err(b":nfx"),
@@ -265,6 +268,12 @@ macro_rules! __mk_lisp_kind {
       /// A map from atoms to values. This can be used as a mutable map if it is behind a
       /// [`Ref`](Self::Ref).
       AtomMap(HashMap<AtomID, $val>),
+      /// A vector of values, which supports constant time indexing. This can be used as a
+      /// mutable (growable) vector if it is behind a [`Ref`](Self::Ref).
+      Vector(Vec<$val>),
+      /// A map from hashable values (see [`LispKey`]) to values. This can be used as a
+      /// mutable map if it is behind a [`Ref`](Self::Ref).
+      HashMap(HashMap<$crate::elab::lisp::LispKey, $val>),
       /// A mutable reference. This is the only way to have mutable values in
       /// client code.
       Ref($ref_),
@@ -648,6 +657,31 @@ impl LispKind {
   pub fn is_map(&self) -> bool {
     self.unwrapped(|e| matches!(e, LispKind::AtomMap(_)))
   }
+  /// Returns true if this value is a vector.
+  pub fn is_vec(&self) -> bool {
+    self.unwrapped(|e| matches!(e, LispKind::Vector(_)))
+  }
+  /// Returns true if this value is a hash map.
+  pub fn is_hash_map(&self) -> bool {
+    self.unwrapped(|e| matches!(e, LispKind::HashMap(_)))
+  }
+  /// Get the [`LispKey`] corresponding to this value, if it can be used as a key in a
+  /// hash map.
+  pub fn as_key(&self) -> Option<LispKey> {
+    self.unwrapped(|e| match e {
+      &LispKind::Atom(a) => Some(LispKey::Atom(a)),
+      LispKind::Number(n) => Some(LispKey::Number(n.clone())),
+      LispKind::String(s) => Some(LispKey::String(s.clone())),
+      &LispKind::Bool(b) => Some(LispKey::Bool(b)),
+      LispKind::List(es) => Some(LispKey::List(es.iter().map(|e| e.as_key()).collect::<Option<_>>()?)),
+      LispKind::DottedList(es, r) => {
+        let mut ks = es.iter().map(|e| e.as_key()).collect::<Option<Vec<_>>>()?;
+        if let LispKey::List(ks2) = r.as_key()? {ks.extend(ks2.into_vec())} else {return None}
+        Some(LispKey::List(ks.into()))
+      }
+      _ => None
+    })
+  }
   /// Returns true if this value is not `#undef` or a reference to `#undef`.
   pub fn is_def(&self) -> bool {
     self.unwrapped(|e| !matches!(e, LispKind::Undef))
@@ -794,6 +828,7 @@ impl PartialEq<LispKind> for LispKind {
       (LispKind::Syntax(a), LispKind::Syntax(b)) => a == b,
       (LispKind::Undef, LispKind::Undef) => true,
       (LispKind::List(a), LispKind::List(b)) => a == b,
+      (LispKind::Vector(a), LispKind::Vector(b)) => a == b,
       (LispKind::List(a), _) => other.eq_list(a.iter()),
       (_, LispKind::List(b)) => self.eq_list(b.iter()),
       (LispKind::DottedList(es1, r1), LispKind::DottedList(es2, r2)) => {
@@ -808,12 +843,44 @@ impl PartialEq<LispKind> for LispKind {
           }
         }
       }
-      _ => false // Goal, Proc, MVar, AtomMap all have only reference equality
+      _ => false // Goal, Proc, MVar, AtomMap, HashMap all have only reference equality
     }))
   }
 }
 impl Eq for LispKind {}
 
+/// A lisp value that can be used as a key in a [`LispKind::HashMap`].
+///
+/// These are the values with structural equality and no mutable parts: atoms, numbers,
+/// strings, booleans and proper lists of keys. (Note that the atom `'foo` and the string
+/// `"foo"` are different keys.)
+#[derive(Clone, Debug, PartialEq, Eq, Hash, EnvDebug, DeepSizeOf)]
+pub enum LispKey {
+  /// An atom key.
+  Atom(AtomID),
+  /// A number key.
+  Number(BigInt),
+  /// A string key.
+  String(ArcString),
+  /// A boolean key.
+  Bool(bool),
+  /// A list of keys.
+  List(Box<[LispKey]>),
+}
+
+impl LispKey {
+  /// Convert a [`LispKey`] back into the [`LispVal`] it represents.
+  #[must_use] pub fn to_lisp(&self) -> LispVal {
+    match self {
+      &LispKey::Atom(a) => LispVal::atom(a),
+      LispKey::Number(n) => LispVal::number(n.clone()),
+      LispKey::String(s) => LispVal::string(s.clone()),
+      &LispKey::Bool(b) => LispVal::bool(b),
+      LispKey::List(ks) => LispVal::list(ks.iter().map(LispKey::to_lisp).collect::<Vec<_>>()),
+    }
+  }
+}
+
 /// An annotation, which is a tag placed on lisp values that is ignored by all
 /// the basic functions.
 #[derive(Clone, Debug, EnvDebug, DeepSizeOf)]
@@ -897,7 +964,12 @@ pub enum Proc {
   /// internal state here. See [`Compiler::call`].
   ///
   /// [`Compiler::call`]: crate::mmc::Compiler::call
-  MMCCompiler(RefCell<crate::mmc::Compiler>) // TODO: use extern instead
+  MMCCompiler(RefCell<crate::mmc::Compiler>), // TODO: use extern instead
+  /// A macro, created by `defmacro`. Uses of the macro are expanded when the code is
+  /// parsed, by calling the contained procedure on the (quoted) arguments and parsing
+  /// the result in place of the call. Applying a macro at run time calls the procedure,
+  /// so it returns the expansion.
+  Macro(LispVal),
 }
 
 /// A procedure specification, which defines the number of arguments expected
@@ -930,7 +1002,8 @@ impl Proc {
       Proc::Builtin(p) => p.spec(),
       &Proc::Lambda {spec, ..} => spec,
       Proc::MatchCont(_) |
-      Proc::ProofThunk(_, _) => ProcSpec::AtLeast(0),
+      Proc::ProofThunk(_, _) |
+      Proc::Macro(_) => ProcSpec::AtLeast(0),
       Proc::RefineCallback |
       Proc::MMCCompiler(_) => ProcSpec::AtLeast(1),
     }
@@ -1012,6 +1085,71 @@ str_enum! {
     BXor: "bxor",
     /// `(bnot a)` performs a bitwise NOT of the argument; additional arguments act like NAND.
     BNot: "bnot",
+    /// `(gcd a b ...)` computes the (nonnegative) greatest common divisor of the arguments.
+    /// `(gcd)` is `0`.
+    Gcd: "gcd",
+    /// `(lcm a b ...)` computes the (nonnegative) least common multiple of the arguments.
+    /// `(lcm)` is `1`.
+    Lcm: "lcm",
+    /// `(modpow b e m)` computes `b ^ e % m`, with a result in the range `[0, |m|)`.
+    /// The exponent must be nonnegative.
+    ModPow: "modpow",
+    /// `(modinv a m)` computes the inverse of `a` modulo `m`, in the range `[0, |m|)`.
+    /// It is an error if `a` and `m` are not coprime.
+    ModInv: "modinv",
+    /// `(isqrt n)` computes the integer square root of `n`, the largest `k` with `k * k <= n`.
+    ISqrt: "isqrt",
+    /// `(ilog b n)` computes the integer logarithm of `n` in base `b`,
+    /// the largest `k` with `b ^ k <= n`. `b` must be at least 2 and `n` must be positive.
+    ILog: "ilog",
+    /// `(prime? n)` returns true if `n` is a prime number. It uses a Miller-Rabin test
+    /// which is deterministic for `n < 3.3 * 10^24`.
+    IsPrime: "prime?",
+    /// `(rat n d)` constructs the rational number `n / d` in lowest terms.
+    /// Rational numbers are represented as integers when the denominator is 1,
+    /// and otherwise as a pair `(n . d)` with `d > 1` coprime to `n`.
+    /// `n` and `d` may themselves be rational, and `(rat x)` normalizes `x`.
+    Rat: "rat",
+    /// `(rat+ a b ...)` computes the sum of rational numbers.
+    RatAdd: "rat+",
+    /// `(rat- a b ...)` subtracts rational numbers, or negates a single argument.
+    RatSub: "rat-",
+    /// `(rat* a b ...)` computes the product of rational numbers.
+    RatMul: "rat*",
+    /// `(rat/ a b ...)` divides rational numbers, or inverts a single argument.
+    RatDiv: "rat/",
+    /// `(rat< a b ...)` is true if the rational numbers are strictly increasing.
+    RatLt: "rat<",
+    /// `(rat<= a b ...)` is true if the rational numbers are nondecreasing.
+    RatLe: "rat<=",
+    /// `(numer a)` returns the numerator of the rational number `a`, in lowest terms.
+    Numer: "numer",
+    /// `(denom a)` returns the (positive) denominator of the rational number `a`,
+    /// in lowest terms.
+    Denom: "denom",
+    /// `(u64 n)` truncates `n` to an unsigned 64 bit integer, i.e. `n mod 2^64`.
+    U64: "u64",
+    /// `(i64 n)` truncates `n` to a signed 64 bit integer in two's complement.
+    I64: "i64",
+    /// `(u64+ a b ...)` computes the sum of the arguments, wrapping modulo `2^64`.
+    U64Add: "u64+",
+    /// `(u64- a b ...)` subtracts the arguments, wrapping modulo `2^64`.
+    U64Sub: "u64-",
+    /// `(u64* a b ...)` computes the product of the arguments, wrapping modulo `2^64`.
+    U64Mul: "u64*",
+    /// `(i64+ a b ...)` computes the sum of the arguments as signed 64 bit integers,
+    /// wrapping on overflow.
+    I64Add: "i64+",
+    /// `(i64- a b ...)` subtracts the arguments as signed 64 bit integers,
+    /// wrapping on overflow.
+    I64Sub: "i64-",
+    /// `(i64* a b ...)` computes the product of the arguments as signed 64 bit integers,
+    /// wrapping on overflow.
+    I64Mul: "i64*",
+    /// `(bits n lo len)` extracts the `len` bits of `n` starting at bit `lo`,
+    /// as a nonnegative number. Negative `n` are treated as infinite two's complement,
+    /// and in this case `len` must be at most `2^24`.
+    Bits: "bits",
     /// `==`, distinct from `=`, is sometimes called `equal?` in other lisps, and performs
     /// recursive equality comparison.
     ///
@@ -1107,6 +1245,57 @@ str_enum! {
     /// calling `f` on the heads of all the arguments, then the second elements and so on.
     /// All lists must be the same length.
     Map: "map",
+    /// `(filter l f)` returns the list of elements `x` of `l` such that `(f x)` is truthy.
+    /// ```metamath-zero
+    /// (filter '(1 2 3 4) (fn (x) {x > 2}))  -- (3 4)
+    /// ```
+    Filter: "filter",
+    /// `(foldl l z f)` folds the function `f` over the list `l` from the left, starting
+    /// with `z`: `(foldl '(a b c) z f)` is `(f (f (f z a) b) c)`.
+    /// The last call to `f` is a tail call.
+    Foldl: "foldl",
+    /// `(foldr l z f)` folds the function `f` over the list `l` from the right, starting
+    /// with `z`: `(foldr '(a b c) z f)` is `(f a (f b (f c z)))`.
+    /// The last call to `f` is a tail call.
+    Foldr: "foldr",
+    /// `(any l f)` calls `f` on each element of `l` in turn, and returns the first
+    /// truthy result, or `#f` if there is none. The call on the last element is a tail call.
+    Any: "any",
+    /// `(all l f)` calls `f` on each element of `l` in turn, and returns `#f` if any result
+    /// is `#f`, and otherwise the result of the last call (or `#t` if `l` is empty).
+    /// The call on the last element is a tail call.
+    All: "all",
+    /// `(sort l f)` sorts the list `l` using the comparison function `f`, where `(f a b)`
+    /// should be truthy if `a` is strictly less than `b`. The sort is stable.
+    /// ```metamath-zero
+    /// (sort '(3 1 2) <)  -- (1 2 3)
+    /// ```
+    Sort: "sort",
+    /// `(assoc l k)` returns the first element of the list `l` whose head is equal
+    /// (in the sense of `==`) to `k`, or `#undef` if there is none.
+    /// ```metamath-zero
+    /// (assoc '((a 1) (b 2)) 'b)  -- (b 2)
+    /// ```
+    Assoc: "assoc",
+    /// `(append l1 l2 l3)` returns the concatenation of the lists `l1`, `l2` and `l3`.
+    /// The last argument does not have to be a list; `(append '(a) 'b)` is `(a . b)`.
+    Append: "append",
+    /// `(reverse l)` returns the elements of the list `l` in reverse order.
+    Reverse: "reverse",
+    /// * `(range a b)` returns the list of integers `(a a+1 ... b-1)`.
+    /// * `(range b)` is the same as `(range 0 b)`.
+    /// * `(range a b step)` returns `(a a+step a+2*step ...)`, stopping before
+    ///   reaching `b`. `step` may be negative but not zero.
+    /// ```metamath-zero
+    /// (range 2 5)        -- (2 3 4)
+    /// (range 5 0 (- 2))  -- (5 3 1)
+    /// ```
+    Range: "range",
+    /// `(zip l1 l2 l3)` returns the list of lists `((a1 b1 c1) (a2 b2 c2) ...)` of the
+    /// corresponding elements of `l1`, `l2`, `l3`. All lists must be the same length.
+    Zip: "zip",
+    /// `(length l)` returns the number of elements in the list `l`.
+    Length: "length",
     /// `(bool? e)` is true if the argument is a boolean, `#t` or `#f`.
     IsBool: "bool?",
     /// `(atom? e)` is true if the argument is an atom (also known as a symbol), `'x`.
@@ -1151,7 +1340,21 @@ str_enum! {
     IsAtomMap: "atom-map?",
     /// `(atom-map! [k1 v1] [k2 v2] ...)` creates a new mutable atom map, a key-value store.
     NewAtomMap: "atom-map!",
-    /// * `(lookup m k)` gets the value stored in the atom map `m` at `k`, or `#undef` if not present.
+    /// `(hash-map? m)` is true if the argument is a hash map.
+    IsHashMap: "hash-map?",
+    /// `(hash-map! [k1 v1] [k2 v2] ...)` creates a new mutable hash map. Unlike atom maps,
+    /// the keys can be any atoms, numbers, strings, booleans, or lists of these.
+    /// ```metamath-zero
+    /// (def m (hash-map! '[(1 2) a] '["x" b]))
+    /// (lookup m '(1 2))   -- a
+    /// (lookup m 'x)       -- #undef
+    /// ```
+    NewHashMap: "hash-map!",
+    /// `(map->list m)` returns the list of `[k v]` pairs in the atom map or hash map `m`,
+    /// in an unspecified order.
+    MapToList: "map->list",
+    /// * `(lookup m k)` gets the value stored in the atom map or hash map `m` at `k`,
+    ///   or `#undef` if not present.
     /// * `(lookup m k v)` will return `v` instead if the key is not present,
     ///   unless `v` is a procedure, in which case it will be called with no arguments on lookup failure.
     Lookup: "lookup",
@@ -1164,6 +1367,32 @@ str_enum! {
     ///   with the value `v` inserted at key `k`.
     /// * `(insert m k)` returns `k` erased from `m`.
     InsertNew: "insert",
+    /// `(vec? v)` is true if the argument is a vector.
+    IsVec: "vec?",
+    /// `(vec! e1 e2 e3)` creates a new mutable vector with elements `e1`, `e2`, `e3`.
+    /// Vectors support constant time indexing and updates, and amortized constant time
+    /// pushing and popping at the end.
+    NewVec: "vec!",
+    /// `(list->vec l)` creates a new mutable vector with the elements of the list `l`.
+    ListToVec: "list->vec",
+    /// `(vec->list v)` returns the list of elements of the vector `v`.
+    VecToList: "vec->list",
+    /// `(vec-len v)` returns the number of elements in the vector `v`.
+    VecLen: "vec-len",
+    /// `(vec-get v n)` returns the `n`th element (zero-indexed) of the vector `v`,
+    /// or `#undef` if out of range.
+    /// ```metamath-zero
+    /// (vec-get (vec! 'a 'b 'c) 1)  -- b
+    /// ```
+    VecGet: "vec-get",
+    /// `(vec-set! v n e)` sets the `n`th element of the mutable vector `v` to `e`. It is
+    /// an error if `n` is out of range.
+    VecSet: "vec-set!",
+    /// `(vec-push! v e1 e2)` appends `e1` and `e2` to the end of the mutable vector `v`.
+    VecPush: "vec-push!",
+    /// `(vec-pop! v)` removes the last element of the mutable vector `v` and returns it,
+    /// or returns `#undef` if the vector is empty.
+    VecPop: "vec-pop!",
     /// `(set-timeout n)` sets the timeout for running individual theorems and
     /// `do` blocks to `n` milliseconds. The default is 5 seconds.
     SetTimeout: "set-timeout",
@@ -1298,6 +1527,109 @@ str_enum! {
     /// effect as the top level command `output string: e1 e2 ...;` but this command is only
     /// triggered on a compile, while `eval-string` works also in server mode.
     EvalString: "eval-string",
+    /// `(read-file path)` returns the contents of the file at `path` (relative to the
+    /// current file) as a string. The file must be inside the project root, and in server
+    /// mode, editing it causes the current file to be elaborated again.
+    ReadFile: "read-file",
+    /// `(read-sexprs path)` reads the file at `path` like `read-file`, and parses it as a
+    /// sequence of s-expressions, which are returned as a list of quoted values.
+    /// Formulas are not supported in data files.
+    ReadSexprs: "read-sexprs",
+    /// `(register-eq 'eq 'refl 'trans ['mp])` registers the binary term `eq` as the
+    /// equality relation on the sort of its arguments, for use by `rewrite`.
+    /// `refl` must prove `eq a a` and `trans` must prove `eq a b > eq b c > eq a c`
+    /// (that is, `eq a c` from the hypotheses `eq a b` and `eq b c`). The optional `mp`
+    /// proves `b > eq a b > a`, and is needed for `rewrite-goal` on a provable sort.
+    RegisterEq: "register-eq",
+    /// `(register-congr 'thm ...)` registers each theorem `thm`, which should have the form
+    /// `eq a1 b1 > ... > eq an bn > eq (t a1 ... an) (t b1 ... bn)` (one hypothesis for
+    /// each non-bound argument, in any order), as the congruence lemma for the term `t`.
+    /// `rewrite` only rewrites inside terms which have a congruence lemma.
+    RegisterCongr: "register-congr",
+    /// `(rewrite e rules [limit])` rewrites the expression `e` to normal form, using the
+    /// theorems in the list `rules`, which should have no hypotheses and conclude
+    /// `eq lhs rhs` for a registered equality relation. Subterms are rewritten first,
+    /// and a rule is applied whenever its left hand side matches. It returns `(e2 p)`
+    /// where `e2` is the normal form and `p` is a proof of `eq e e2`. It is an error if
+    /// rewriting loops or takes more than `limit` steps (default 10000).
+    Rewrite: "rewrite",
+    /// `(rewrite-goal t rules [p])` rewrites the expression `t` of a provable sort to normal
+    /// form `t2`, like `rewrite`, and returns a proof of `t` using the proof `p` of `t2`
+    /// (default `_`, which becomes a new goal) and the registered `mp` theorem.
+    /// It is meant to be used in tactics, e.g. `(focus (refine (rewrite-goal t '(rule))))`.
+    RewriteGoal: "rewrite-goal",
+    /// `(register-tauto cfg)` configures the `tauto` tactic. `cfg` is a list of pairs
+    /// `(key name)`, where the keys `im`, `not`, `an`, and optionally `or` and `iff`, give the
+    /// propositional connectives, and the remaining keys give the lemmas used to
+    /// reconstruct proofs (see the MM1 documentation for the list).
+    RegisterTauto: "register-tauto",
+    /// `(tauto [t])` decides whether `t` (default: the type of the first goal) is a
+    /// propositional tautology, treating subterms not headed by a registered connective
+    /// as atoms, and returns a proof of `t` suitable for `refine`.
+    Tauto: "tauto",
+    /// `(register-norm-num cfg)` configures the `norm-num` builtin. `cfg` is a list of
+    /// entries `(key value ...)` giving the numeral encoding, the arithmetic terms, the tables
+    /// of single digit facts and the structural lemmas (see the MM1 documentation).
+    RegisterNormNum: "register-norm-num",
+    /// `(norm-num e)` evaluates the numeric expression `e` to a numeral `n` in normal form,
+    /// and returns `(n p)` where `p` proves `e = n`. If `e` is a comparison `a = b`, `a < b`,
+    /// `a <= b` or `a != b` of numeric expressions, it instead returns a proof of `e`.
+    NormNum: "norm-num",
+    /// `(auto thms [goal] [depth])` searches for a proof of `goal` (default: the type of the
+    /// first goal) by backward chaining, using the hypotheses in the local context and the
+    /// theorems in the list `thms`, and returns the proof suitable for `refine`. The proof
+    /// tree has at most `depth` levels (default 5). On failure, the error message lists the
+    /// partial proofs that got closest.
+    Auto: "auto",
+    /// `(find-thms e)` returns the list of theorems whose conclusion unifies with the
+    /// expression `e`, respecting the sorts of the theorem variables, in declaration order.
+    /// Metavariables and `_` in `e` match anything,
+    /// so `(find-thms (goal-type (hd (get-goals))))` finds the theorems that can be
+    /// refined against the current goal, which can be passed as candidates to `auto`.
+    FindThms: "find-thms",
+    /// `(register-tptp cfg)` maps term constructors to TPTP connectives for `tptp-export`.
+    /// `cfg` is a list of entries `(conn t1 t2 ...)`, where `conn` is one of `true`, `false`,
+    /// `not`, `and`, `or`, `imp`, `iff`, `all`, `ex`, `eq`, `ne`, and the terms `t1 t2 ...`
+    /// are translated to it. Repeated registrations extend the mapping.
+    RegisterTptp: "register-tptp",
+    /// `(tptp-export premises [goal] [lang])` returns a string containing the TPTP problem
+    /// of proving `goal` (by default the type of the first goal) from `premises`, a list of
+    /// hypothesis and theorem names. `lang` is `'fof` (the default) or `'thf`.
+    TptpExport: "tptp-export",
+    /// `(tptp-reconstruct used [goal] [depth])` proves `goal` (by default the type of the
+    /// first goal) using `auto` with the theorems in `used`, which is either a list of
+    /// theorem names or the output of a prover on a problem from `tptp-export`, from which
+    /// the premises used in the proof are extracted.
+    TptpReconstruct: "tptp-reconstruct",
+    /// `(register-lrat cfg)` configures the `lrat` builtin. `cfg` is a list of pairs
+    /// `(key name)`, where the keys `im`, `not`, `an`, `or` and `false` give the
+    /// propositional connectives, and the remaining keys give the lemmas used to
+    /// reconstruct proofs (see the MM1 documentation for the list).
+    RegisterLrat: "register-lrat",
+    /// `(lrat cnf proof vars hyps)` checks the LRAT `proof` of unsatisfiability of the
+    /// DIMACS `cnf` (both strings), where the variables stand for the formulas in the list
+    /// `vars`, and returns a proof of the registered false term using the proofs `hyps` of
+    /// the clauses, in the form `(:verb p)` which is accepted by `refine`.
+    Lrat: "lrat",
+    /// `(proof-steps thm [order])` returns the proof of the theorem `thm` as a list of
+    /// steps `(hyps step e)`, where `hyps` are the indexes of the steps it uses, `e` is the
+    /// statement it proves, and `step` is a theorem name, `(:hyp h)` for a hypothesis, or
+    /// `(:conv d1 ... dn)` for a conversion unfolding definitions `d1 ... dn`. `order` is
+    /// `'post` (the default, the conclusion is last) or `'pre` (the conclusion is first).
+    ProofSteps: "proof-steps",
+    /// `(minimize-proof x ...)` replaces the proofs of the theorems `x ...` (or of all
+    /// theorems, if none are given) with smaller proofs, by merging subproofs of the same
+    /// statement, removing redundant conversions, and using earlier theorems to skip steps.
+    /// It returns a list of `(x old new)` with the old and new sizes of the proofs that changed.
+    MinimizeProof: "minimize-proof",
+    /// `(unfold e [d])` unfolds all occurrences of the definition `d` in the expression `e`,
+    /// or all definitions if `d` is omitted. It returns `(e2 c)` where `e2` is the unfolded
+    /// expression and `c` is a conversion proof of `e = e2`, so that `(:conv e c p)` proves
+    /// `e` given a proof `p` of `e2`.
+    Unfold: "unfold",
+    /// `(whnf e)` unfolds the head of the expression `e` until it is not a definition,
+    /// and returns `(e2 c)` in the same format as `unfold`.
+    Whnf: "whnf",
     /// `(mmc-init)` returns a new compiler object, which is itself a procedure that can
     /// be called to compile MMC functions. See [`Compiler::call`].
     ///
@@ -1507,6 +1839,17 @@ impl Remap for LispVal {
   fn remap(&self, r: &mut Remapper) -> Self { unsafe { self.freeze() }.remap(r) }
 }
 
+impl Remap for LispKey {
+  type Target = Self;
+  fn remap(&self, r: &mut Remapper) -> Self {
+    match self {
+      LispKey::Atom(a) => LispKey::Atom(a.remap(r)),
+      LispKey::List(ks) => LispKey::List(ks.remap(r)),
+      _ => self.clone(),
+    }
+  }
+}
+
 impl Remap for InferTarget {
   type Target = Self;
   fn remap(&self, r: &mut Remapper) -> Self {
@@ -10,20 +10,27 @@ use std::time::{Instant, Duration};
 use std::sync::atomic::Ordering;
 use std::collections::HashMap;
 use std::convert::TryInto;
-use num::{BigInt, ToPrimitive};
+use num::{BigInt, Integer, One, Signed, ToPrimitive, Zero, bigint::Sign};
 use crate::util::{ArcString, FileRef, FileSpan, SliceExt, Span};
-use crate::parser::ast::SExpr;
+use std::path::PathBuf;
+use std::result::Result as StdResult;
+use crate::lined_string::LinedString;
+use crate::parser::{parse_sexprs, ast::{Atom, SExpr, SExprKind}};
 use super::super::{Result, Elaborator, LispData,
   AtomID, Environment, AtomData, DeclKey, StmtTrace,
   ElabError, ReportMode, ElabErrorKind, ErrorLevel, BoxError, ObjectKind,
   refine::{RStack, RState, RefineResult}};
-use super::{Arc, BuiltinProc, Cell, InferTarget, LispKind, LispRef, LispVal,
+use super::{Arc, BuiltinProc, Cell, InferTarget, LispKey, LispKind, LispRef, LispVal,
   Modifiers, Proc, ProcPos, ProcSpec, QExpr, Rc, RefCell, ThmID, Uncons};
 use super::parser::{IR, Branch, Pattern, MVarPattern, DefTarget};
 use super::super::local_context::{InferSort, AwaitingProof, try_get_span};
 use super::super::environment::{TermKind, ThmKind, ExprNode, ProofNode};
 use super::print::{FormatEnv, EnvDisplay};
 
+/// The largest `len` accepted by `(bits n lo len)` when the result is not just `n >> lo`,
+/// to avoid allocating an enormous mask.
+const MAX_BITS_LEN: u64 = 1 << 24;
+
 #[derive(Debug)]
 enum Stack<'a> {
   List(Span, Vec<LispVal>, std::slice::Iter<'a, IR>),
@@ -43,6 +50,7 @@ enum Stack<'a> {
   Ret(FileSpan, ProcPos, Vec<LispVal>, Arc<IR>),
   MatchCont(Span, LispVal, std::slice::Iter<'a, Branch>, Rc<Cell<bool>>),
   MapProc(Span, Span, LispVal, Box<[Uncons]>, Vec<LispVal>),
+  ListProc(Span, Span, LispVal, ListProc),
   AddThmProc(FileSpan, Box<AwaitingProof>),
   Refines(Span, Option<Span>, std::slice::Iter<'a, IR>),
   Refine {sp: Span, stack: Vec<RStack>},
@@ -80,6 +88,7 @@ impl<'a> EnvDisplay for Stack<'a> {
         fe.to(e), fe.to(bs.as_slice())),
       Stack::MapProc(_, _, e, us, es) => write!(f, "(map {}\n  {})\n  ->{} _",
         fe.to(e), fe.to(&**us), fe.to(es)),
+      Stack::ListProc(_, _, e, lp) => write!(f, "({} {}\n  {})\n  -> _", lp.name(), fe.to(e), fe.to(lp)),
       Stack::AddThmProc(_, ap) => write!(f, "(add-thm {} _)", fe.to(&ap.atom())),
       Stack::Refines(_, _, irs) => write!(f, "(refine _ {})", fe.to(irs.as_slice())),
       Stack::Refine {..} => write!(f, "(refine _)"),
@@ -102,6 +111,7 @@ enum State<'a> {
   Pattern(Span, LispVal, std::slice::Iter<'a, Branch>,
     &'a Branch, Vec<PatternStack<'a>>, Box<[LispVal]>, PatternState<'a>),
   MapProc(Span, Span, LispVal, Box<[Uncons]>, Vec<LispVal>),
+  ListProc(Span, Span, LispVal, ListProc),
   Refine {sp: Span, stack: Vec<RStack>, state: RState},
 }
 
@@ -125,11 +135,168 @@ impl<'a> EnvDisplay for State<'a> {
         fe.to(e), fe.to(br), fe.to(bs.as_slice()), fe.to(st)),
       State::MapProc(_, _, e, us, es) => write!(f, "(map {}\n  {})\n  ->{}",
         fe.to(e), fe.to(&**us), fe.to(es)),
+      State::ListProc(_, _, e, lp) => write!(f, "({} {}\n  {})", lp.name(), fe.to(e), fe.to(lp)),
       State::Refine {state, ..} => state.fmt(fe, f),
     }
   }
 }
 
+/// The state of one of the higher order list functions, like `filter` or `foldl`, which
+/// call a procedure (the function stored alongside it in [`State::ListProc`]) repeatedly.
+#[derive(Debug)]
+enum ListProc {
+  /// `(filter l f)`: the rest of the list, the elements kept so far,
+  /// and the element currently being tested
+  Filter(Uncons, Vec<LispVal>, Option<LispVal>),
+  /// `(foldl l z f)`: the accumulator and the rest of the list
+  Foldl(LispVal, Uncons),
+  /// `(foldr l z f)`: the accumulator and the rest of the list, in reverse order
+  Foldr(LispVal, Vec<LispVal>),
+  /// `(any l f)` (if `true`) or `(all l f)` (if `false`): the rest of the list
+  AnyAll(bool, Uncons),
+  /// `(sort l f)`
+  Sort(MergeSort),
+}
+
+/// The result of [`ListProc::next`].
+enum ListStep {
+  /// Call the function with these arguments, and pass the result to [`ListProc::ret`]
+  Call(Vec<LispVal>),
+  /// Call the function with these arguments, and return the result
+  TailCall(Vec<LispVal>),
+  /// Return this value
+  Done(LispVal),
+}
+
+impl ListProc {
+  fn name(&self) -> &'static str {
+    match self {
+      ListProc::Filter(..) => "filter",
+      ListProc::Foldl(..) => "foldl",
+      ListProc::Foldr(..) => "foldr",
+      ListProc::AnyAll(true, _) => "any",
+      ListProc::AnyAll(false, _) => "all",
+      ListProc::Sort(_) => "sort",
+    }
+  }
+
+  /// Get the next function call to make, or the final result.
+  fn next(&mut self) -> SResult<ListStep> {
+    fn end(u: &Uncons) -> SResult<()> {
+      if u.exactly(0) {Ok(())} else {Err("expected a list".into())}
+    }
+    Ok(match self {
+      ListProc::Filter(u, keep, cur) => match u.next() {
+        None => {end(u)?; ListStep::Done(LispVal::list(mem::take(keep)))}
+        Some(e) => {*cur = Some(e.clone()); ListStep::Call(vec![e])}
+      },
+      ListProc::Foldl(z, u) => match u.next() {
+        None => {end(u)?; ListStep::Done(z.clone())}
+        Some(e) => {
+          let args = vec![mem::replace(z, LispVal::undef()), e];
+          if u.exactly(0) {ListStep::TailCall(args)} else {ListStep::Call(args)}
+        }
+      },
+      ListProc::Foldr(z, es) => match es.pop() {
+        None => ListStep::Done(z.clone()),
+        Some(e) => {
+          let args = vec![e, mem::replace(z, LispVal::undef())];
+          if es.is_empty() {ListStep::TailCall(args)} else {ListStep::Call(args)}
+        }
+      },
+      &mut ListProc::AnyAll(any, ref mut u) => match u.next() {
+        None => {end(u)?; ListStep::Done(LispVal::bool(!any))}
+        Some(e) => if u.exactly(0) {ListStep::TailCall(vec![e])} else {ListStep::Call(vec![e])}
+      },
+      ListProc::Sort(ms) => match ms.next() {
+        Some((a, b)) => ListStep::Call(vec![a, b]),
+        None => ListStep::Done(LispVal::list(mem::take(&mut ms.src))),
+      },
+    })
+  }
+
+  /// Process the return value of a function call. Returns a value if the
+  /// computation should stop early with this result.
+  fn ret(&mut self, ret: LispVal) -> Option<LispVal> {
+    match self {
+      ListProc::Filter(_, keep, cur) => if ret.truthy() {keep.extend(cur.take())},
+      ListProc::Foldl(z, _) | ListProc::Foldr(z, _) => *z = ret,
+      &mut ListProc::AnyAll(any, _) => if ret.truthy() == any {return Some(ret)},
+      ListProc::Sort(ms) => ms.lt = Some(ret.truthy()),
+    }
+    None
+  }
+}
+
+impl EnvDisplay for ListProc {
+  fn fmt(&self, fe: FormatEnv<'_>, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ListProc::Filter(u, keep, _) => write!(f, "{}\n  ->{}", fe.to(u), fe.to(keep)),
+      ListProc::Foldl(z, u) => write!(f, "{} {}", fe.to(u), fe.to(z)),
+      ListProc::Foldr(z, es) => write!(f, "{} {}", fe.to(es), fe.to(z)),
+      ListProc::AnyAll(_, u) => u.fmt(fe, f),
+      ListProc::Sort(ms) => ms.src.fmt(fe, f),
+    }
+  }
+}
+
+/// A bottom-up merge sort, which is driven by an external comparison function
+/// so that it can call back into lisp code.
+#[derive(Debug)]
+struct MergeSort {
+  /// The runs of length `width` being merged
+  src: Vec<LispVal>,
+  /// The output of the current pass
+  dst: Vec<LispVal>,
+  /// The length of the sorted runs in `src`
+  width: usize,
+  /// The start of the current pair of runs
+  lo: usize,
+  /// The position in the left run
+  i: usize,
+  /// The position in the right run
+  j: usize,
+  /// The result of the last comparison, `(f src[j] src[i])`
+  lt: Option<bool>,
+}
+
+impl MergeSort {
+  fn new(src: Vec<LispVal>) -> Self {
+    let j = src.len().min(1);
+    MergeSort {dst: Vec::with_capacity(src.len()), src, width: 1, lo: 0, i: 0, j, lt: None}
+  }
+
+  /// Get the next pair `(a, b)` to compare (we want to know if `a < b`),
+  /// or `None` if `src` is sorted.
+  fn next(&mut self) -> Option<(LispVal, LispVal)> {
+    match self.lt.take() {
+      Some(true) => {self.dst.push(self.src[self.j].clone()); self.j += 1}
+      Some(false) => {self.dst.push(self.src[self.i].clone()); self.i += 1}
+      None => {}
+    }
+    let n = self.src.len();
+    loop {
+      if self.width >= n {return None}
+      let mid = (self.lo + self.width).min(n);
+      let hi = (self.lo + 2 * self.width).min(n);
+      if self.i < mid && self.j < hi {
+        return Some((self.src[self.j].clone(), self.src[self.i].clone()))
+      }
+      self.dst.extend_from_slice(&self.src[self.i..mid]);
+      self.dst.extend_from_slice(&self.src[self.j..hi]);
+      self.lo = hi;
+      if self.lo >= n {
+        self.width *= 2;
+        self.lo = 0;
+        mem::swap(&mut self.src, &mut self.dst);
+        self.dst.clear();
+      }
+      self.i = self.lo;
+      self.j = (self.lo + self.width).min(n);
+    }
+  }
+}
+
 impl LispKind {
   fn as_ref_mut<T>(&self, f: impl FnOnce(&mut LispVal) -> T) -> Option<T> {
     match self {
@@ -139,37 +306,67 @@ impl LispKind {
     }
   }
 
-  fn make_map_mut<T>(&self, f: impl FnOnce(&mut HashMap<AtomID, LispVal>) -> T) -> (Option<T>, Option<LispVal>) {
+  fn make_container_mut<C: Container, T>(&self, f: impl FnOnce(&mut C) -> T) -> (Option<T>, Option<LispVal>) {
     match self {
-      LispKind::AtomMap(m) => {
-        let mut m = m.clone();
-        (Some(f(&mut m)), Some(LispVal::new(LispKind::AtomMap(m))))
-      }
-      LispKind::Annot(sp, e) => match e.make_map_mut(f) {
+      LispKind::Annot(sp, e) => match e.make_container_mut(f) {
         (r, None) => (r, None),
         (r, Some(e)) => (r, Some(LispVal::new(LispKind::Annot(sp.clone(), e)))),
       },
-      LispKind::Ref(m) => (m.get_mut(|e| e.as_map_mut(f)), None),
-      _ => (None, None)
+      LispKind::Ref(m) => (m.get_mut(|e| e.as_container_mut(f)), None),
+      e => match C::get(e) {
+        Some(m) => {
+          let mut m = m.clone();
+          (Some(f(&mut m)), Some(LispVal::new(m.into_lisp())))
+        }
+        None => (None, None)
+      }
     }
   }
 }
 impl LispVal {
-  fn as_map_mut<T>(&mut self, f: impl FnOnce(&mut HashMap<AtomID, LispVal>) -> T) -> Option<T> {
+  /// Get mutable access to the container (map or vector) in this value, cloning it first
+  /// if it is shared.
+  fn as_container_mut<C: Container, T>(&mut self, f: impl FnOnce(&mut C) -> T) -> Option<T> {
     match self.get_mut() {
       None => {
-        let (r, new) = self.make_map_mut(f);
+        let (r, new) = self.make_container_mut(f);
         if let Some(e) = new {*self = e}
         r
       }
-      Some(LispKind::AtomMap(m)) => Some(f(m)),
-      Some(LispKind::Annot(_, e)) => Self::as_map_mut(e, f),
-      Some(LispKind::Ref(m)) => m.get_mut(|e| Self::as_map_mut(e, f)),
-      Some(_) => None
+      Some(LispKind::Annot(_, e)) => Self::as_container_mut(e, f),
+      Some(LispKind::Ref(m)) => m.get_mut(|e| Self::as_container_mut(e, f)),
+      Some(e) => C::get_mut(e).map(f)
     }
   }
 }
 
+/// A lisp data structure which can be modified in place, by the `insert!` and `vec-set!`
+/// family of functions.
+trait Container: Clone {
+  fn get(e: &LispKind) -> Option<&Self>;
+  fn get_mut(e: &mut LispKind) -> Option<&mut Self>;
+  fn into_lisp(self) -> LispKind;
+}
+
+macro_rules! impl_container {
+  ($($ty:ty => $kind:ident),*) => {$(
+    impl Container for $ty {
+      fn get(e: &LispKind) -> Option<&Self> {
+        if let LispKind::$kind(m) = e {Some(m)} else {None}
+      }
+      fn get_mut(e: &mut LispKind) -> Option<&mut Self> {
+        if let LispKind::$kind(m) = e {Some(m)} else {None}
+      }
+      fn into_lisp(self) -> LispKind { LispKind::$kind(self) }
+    }
+  )*}
+}
+impl_container! {
+  HashMap<AtomID, LispVal> => AtomMap,
+  HashMap<LispKey, LispVal> => HashMap,
+  Vec<LispVal> => Vector
+}
+
 #[derive(Debug)]
 enum Dot<'a> { List(Option<usize>), DottedList(&'a Pattern) }
 #[derive(Debug)]
@@ -395,13 +592,42 @@ impl Elaborator {
     self.as_lref(e, |m| m.get_mut(f))
   }
 
-  fn as_map<T>(&self, e: &LispKind, f: impl FnOnce(&HashMap<AtomID, LispVal>) -> SResult<T>) -> SResult<T> {
+  fn as_list(&self, e: &LispVal) -> SResult<Vec<LispVal>> {
+    let mut u = Uncons::from(e.clone());
+    let es = (&mut u).collect();
+    if u.exactly(0) {Ok(es)} else {Err(format!("expected a list, got {}", self.print(e)))}
+  }
+
+  fn as_vec<T>(&self, e: &LispKind, f: impl FnOnce(&[LispVal]) -> SResult<T>) -> SResult<T> {
     e.unwrapped(|e| match e {
-      LispKind::AtomMap(m) => f(m),
-      _ => Err(format!("not an atom map: {}", self.print(e)))
+      LispKind::Vector(es) => f(es),
+      _ => Err(format!("not a vector: {}", self.print(e)))
     })
   }
 
+  fn as_key(&self, e: &LispVal) -> SResult<LispKey> {
+    e.as_key().ok_or_else(|| format!("expected a hashable value, got {}", self.print(e)))
+  }
+
+  /// Insert `v` at key `k` in the map `m`, or remove the key if `v` is `None`.
+  /// The map can be either an atom map or a hash map.
+  fn map_insert(&mut self, m: &mut LispVal, k: &LispVal, v: Option<LispVal>) -> SResult<()> {
+    if m.is_map() {
+      let k = self.as_string_atom(k)
+        .ok_or_else(|| format!("expected an atom, got {}", self.print(k)))?;
+      m.as_container_mut(|m: &mut HashMap<AtomID, LispVal>| match v {
+        Some(v) => {m.insert(k, v);}
+        None => {m.remove(&k);}
+      })
+    } else {
+      let k = self.as_key(k)?;
+      m.as_container_mut(|m: &mut HashMap<LispKey, LispVal>| match v {
+        Some(v) => {m.insert(k, v);}
+        None => {m.remove(&k);}
+      })
+    }.ok_or_else(|| "expected a map".into())
+  }
+
   fn to_string(&self, e: &LispKind) -> ArcString {
     match e {
       LispKind::Ref(m) => m.get(|e| self.to_string(e)),
@@ -413,6 +639,39 @@ impl Elaborator {
     }
   }
 
+  fn as_u64(&self, e: &LispVal) -> SResult<u64> {
+    self.with_int(e, |n| Ok(wrap_u64(n)))
+  }
+
+  /// Get the numerator and denominator of a rational number, which is either an integer
+  /// or a pair `(n . d)` of integers with `d` nonzero. The returned denominator is positive.
+  fn as_rat(&self, e: &LispVal) -> SResult<(BigInt, BigInt)> {
+    e.unwrapped(|k| match k {
+      LispKind::Number(n) => Ok((n.clone(), 1.into())),
+      LispKind::DottedList(es, d) if es.len() == 1 => {
+        let (n, d) = (self.as_int(&es[0])?, self.as_int(d)?);
+        match d.sign() {
+          Sign::NoSign => Err("division by zero".into()),
+          Sign::Minus => Ok((-n, -d)),
+          Sign::Plus => Ok((n, d)),
+        }
+      }
+      _ => Err(format!("expected a rational number, got {}", self.print(k)))
+    })
+  }
+
+  /// Compare a list of rational numbers pairwise, by comparing `n1 * d2` with `n2 * d1`.
+  fn rat_bool_binop(&self, mut f: impl FnMut(&BigInt, &BigInt) -> bool, args: &[LispVal]) -> SResult<bool> {
+    let mut it = args.iter();
+    let (mut n, mut d) = self.as_rat(it.next().expect("rat_bool_binop([])"))?;
+    for e in it {
+      let (n2, d2) = self.as_rat(e)?;
+      if !f(&(&n * &d2), &(&n2 * &d)) {return Ok(false)}
+      n = n2; d = d2;
+    }
+    Ok(true)
+  }
+
   fn int_bool_binop(&self, mut f: impl FnMut(&BigInt, &BigInt) -> bool, args: &[LispVal]) -> SResult<bool> {
     let mut it = args.iter();
     let mut last = self.as_int(it.next().expect("int_bool_binop([])"))?;
@@ -611,6 +870,62 @@ impl Elaborator {
       }
     }
   }
+
+  /// Read the data file `name`, which is resolved relative to the directory of the
+  /// current file and must be inside the project root.
+  fn read_data_file(&mut self, name: &str) -> StdResult<Arc<LinedString>, String> {
+    let path = self.path.path().parent().map_or_else(|| PathBuf::from(name), |p| p.join(name));
+    let path = path.canonicalize().map_err(|e| format!("{}: {}", name, e))?;
+    if !crate::get_project_root().and_then(|p| p.canonicalize().ok())
+        .map_or(false, |root| path.starts_with(root)) {
+      return Err(format!("'{}' is outside the project root", name))
+    }
+    let file = FileRef::from(path);
+    match &mut self.read_file {
+      Some(f) => (f.0)(file),
+      None => std::fs::read_to_string(file.path()).map(|s| Arc::new(s.into())).map_err(Into::into),
+    }.map_err(|e| format!("{}: {}", name, e))
+  }
+
+  /// Convert an s-expression in a data file with contents `src` to a lisp value,
+  /// as if it was quoted.
+  fn data_sexpr(&mut self, src: &LinedString, e: &SExpr) -> StdResult<LispVal, String> {
+    Ok(match &e.k {
+      &SExprKind::Atom(a) => LispVal::atom(self.get_atom(match a {
+        Atom::Ident => &src.as_bytes()[e.span.start..e.span.end],
+        Atom::Quote => b"quote",
+        Atom::Unquote => b"unquote",
+        Atom::Nfx => b":nfx",
+        Atom::Gen(_) => unreachable!("not produced by the parser"),
+      })),
+      SExprKind::List(es) => LispVal::list(
+        es.iter().map(|e| self.data_sexpr(src, e)).collect::<StdResult<Vec<_>, _>>()?),
+      SExprKind::DottedList(es, r) => LispVal::dotted_list(
+        es.iter().map(|e| self.data_sexpr(src, e)).collect::<StdResult<Vec<_>, _>>()?,
+        self.data_sexpr(src, r)?),
+      SExprKind::Number(n) => LispVal::number(n.clone().into()),
+      SExprKind::String(s) => LispVal::string(s.clone()),
+      &SExprKind::Bool(b) => LispVal::bool(b),
+      SExprKind::Undef => LispVal::undef(),
+      SExprKind::DocComment(_, e) => self.data_sexpr(src, e)?,
+      SExprKind::Formula(_) => {
+        let pos = src.to_pos(e.span.start);
+        return Err(format!("{}:{}: formulas are not supported in data files",
+          pos.line + 1, pos.character + 1))
+      }
+    })
+  }
+}
+
+/// Construct the list `(e1 e2 ... en . r)`, where `vec = [e1, ..., en]`.
+fn cons_all(mut vec: Vec<LispVal>, r: LispVal) -> LispVal {
+  if vec.is_empty() {return r}
+  match r.try_unwrap() {
+    Ok(LispKind::List(es)) => { vec.extend::<Vec<_>>(es.into()); LispVal::list(vec) }
+    Ok(LispKind::DottedList(es, e)) => { vec.extend::<Vec<_>>(es.into()); LispVal::dotted_list(vec, e) }
+    Ok(e) => LispVal::dotted_list(vec, LispVal::new(e)),
+    Err(r) => LispVal::dotted_list(vec, r),
+  }
 }
 
 fn set_report_mode(fe: FormatEnv<'_>, mode: &mut ReportMode, args: &[LispVal]) -> SResult<()> {
@@ -632,6 +947,61 @@ fn set_report_mode(fe: FormatEnv<'_>, mode: &mut ReportMode, args: &[LispVal]) -
   } else {Err("invalid arguments".into())}
 }
 
+/// Truncate an integer to its low 64 bits, as an unsigned number.
+fn wrap_u64(n: &BigInt) -> u64 {
+  let (sign, digits) = n.to_u64_digits();
+  let lo = digits.first().copied().unwrap_or(0);
+  if sign == Sign::Minus { lo.wrapping_neg() } else { lo }
+}
+
+/// Interpret a 64 bit word as a signed integer in two's complement.
+#[allow(clippy::cast_possible_wrap)]
+fn signed_u64(n: u64) -> BigInt { (n as i64).into() }
+
+/// Construct the rational number `n / d` in lowest terms, represented as the integer `n`
+/// if `d = 1` and as the pair `(n . d)` with `d > 1` otherwise.
+fn mk_rat(mut n: BigInt, mut d: BigInt) -> SResult<LispVal> {
+  if d.is_zero() { return Err("division by zero".into()) }
+  if d.is_negative() { n = -n; d = -d }
+  let g = n.gcd(&d);
+  if !g.is_one() { n /= &g; d /= &g }
+  Ok(if d.is_one() { LispVal::number(n) } else {
+    LispVal::dotted_list(vec![LispVal::number(n)], LispVal::number(d))
+  })
+}
+
+/// Compute `b ^ e mod m`, with a result in the range `[0, |m|)`.
+fn mod_pow(b: &BigInt, e: &BigInt, m: &BigInt) -> SResult<BigInt> {
+  if m.is_zero() { return Err("division by zero".into()) }
+  if e.is_negative() { return Err("negative exponent".into()) }
+  let m = m.abs();
+  Ok(b.mod_floor(&m).modpow(e, &m))
+}
+
+/// Test whether `n` is prime, using a Miller-Rabin test with the first 13 primes as bases.
+/// This is deterministic for `n < 3.3 * 10^24`, and a strong probable prime test above that.
+fn is_prime(n: &BigInt) -> bool {
+  const BASES: [u32; 13] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41];
+  if *n < BigInt::from(2) { return false }
+  for &p in &BASES {
+    if *n == BigInt::from(p) { return true }
+    if (n % p).is_zero() { return false }
+  }
+  let n1: BigInt = n - 1;
+  let s = n1.trailing_zeros().expect("n > 1");
+  let d = &n1 >> s;
+  'bases: for &p in &BASES {
+    let mut x = BigInt::from(p).modpow(&d, n);
+    if x.is_one() || x == n1 { continue }
+    for _ in 1..s {
+      x = x.modpow(&2.into(), n);
+      if x == n1 { continue 'bases }
+    }
+    return false
+  }
+  true
+}
+
 /// The lisp evaluation context, representing a lisp evaluation in progress.
 /// This is an explicitly unfolled state machine (rather than using recursive functions)
 /// so that we can explicitly manipulate the program stack for error reporting purposes.
@@ -861,13 +1231,21 @@ make_builtins! { self, sp1, sp2, args,
   Div: AtLeast(1) => {
     let mut it = args.into_iter();
     let mut n: BigInt = try1!(self.as_int(&it.next().unwrap()));
-    for e in it { n /= try1!(self.as_int(&e)) }
+    for e in it {
+      let d = try1!(self.as_int(&e));
+      if d.is_zero() { try1!(Err("division by zero")) }
+      n /= d
+    }
     LispVal::number(n)
   },
   Mod: AtLeast(1) => {
     let mut it = args.into_iter();
     let mut n: BigInt = try1!(self.as_int(&it.next().unwrap()));
-    for e in it { n %= try1!(self.as_int(&e)) }
+    for e in it {
+      let d = try1!(self.as_int(&e));
+      if d.is_zero() { try1!(Err("division by zero")) }
+      n %= d
+    }
     LispVal::number(n)
   },
   Lt: AtLeast(1) => LispVal::bool(try1!(self.int_bool_binop(|a, b| a < b, &args))),
@@ -934,6 +1312,141 @@ make_builtins! { self, sp1, sp2, args,
     };
     LispVal::number(!n)
   },
+  Gcd: AtLeast(0) => {
+    let mut n: BigInt = 0.into();
+    for e in args { n = n.gcd(&try1!(self.as_int(&e))) }
+    LispVal::number(n)
+  },
+  Lcm: AtLeast(0) => {
+    let mut n: BigInt = 1.into();
+    for e in args { n = n.lcm(&try1!(self.as_int(&e))) }
+    LispVal::number(n)
+  },
+  ModPow: Exact(3) => {
+    let (b, e, m) = (try1!(self.as_int(&args[0])), try1!(self.as_int(&args[1])), try1!(self.as_int(&args[2])));
+    LispVal::number(try1!(mod_pow(&b, &e, &m)))
+  },
+  ModInv: Exact(2) => {
+    let (a, m) = (try1!(self.as_int(&args[0])), try1!(self.as_int(&args[1])));
+    if m.is_zero() { try1!(Err("division by zero")) }
+    let m = m.abs();
+    let e = a.mod_floor(&m).extended_gcd(&m);
+    if !e.gcd.is_one() { try1!(Err(format!("{} is not invertible modulo {}", a, m))) }
+    LispVal::number(e.x.mod_floor(&m))
+  },
+  ISqrt: Exact(1) => {
+    let n = try1!(self.as_int(&args[0]));
+    if n.is_negative() { try1!(Err("square root of a negative number")) }
+    LispVal::number(n.sqrt())
+  },
+  ILog: Exact(2) => {
+    let (b, n) = (try1!(self.as_int(&args[0])), try1!(self.as_int(&args[1])));
+    if b < 2.into() { try1!(Err("logarithm base must be at least 2")) }
+    if !n.is_positive() { try1!(Err("logarithm of a non-positive number")) }
+    let (mut k, mut p) = (0_u64, b.clone());
+    while p <= n { p *= &b; k += 1 }
+    LispVal::number(k.into())
+  },
+  IsPrime: Exact(1) => LispVal::bool(try1!(self.with_int(&args[0], |n| Ok(is_prime(n))))),
+  Rat: AtLeast(1) => {
+    if args.len() > 2 { try1!(Err("expected 1 or 2 arguments")) }
+    let (n, d) = try1!(self.as_rat(&args[0]));
+    let (n2, d2) = match args.get(1) { None => (1.into(), 1.into()), Some(e) => try1!(self.as_rat(e)) };
+    try1!(mk_rat(n * d2, d * n2))
+  },
+  RatAdd: AtLeast(0) => {
+    let (mut n, mut d): (BigInt, BigInt) = (0.into(), 1.into());
+    for e in args {
+      let (n2, d2) = try1!(self.as_rat(&e));
+      n = n * &d2 + n2 * &d; d *= d2;
+    }
+    try1!(mk_rat(n, d))
+  },
+  RatSub: AtLeast(1) => {
+    let mut it = args.iter();
+    let (mut n, mut d) = try1!(self.as_rat(it.next().unwrap()));
+    if args.len() == 1 { n = -n }
+    for e in it {
+      let (n2, d2) = try1!(self.as_rat(e));
+      n = n * &d2 - n2 * &d; d *= d2;
+    }
+    try1!(mk_rat(n, d))
+  },
+  RatMul: AtLeast(0) => {
+    let (mut n, mut d): (BigInt, BigInt) = (1.into(), 1.into());
+    for e in args {
+      let (n2, d2) = try1!(self.as_rat(&e));
+      n *= n2; d *= d2;
+    }
+    try1!(mk_rat(n, d))
+  },
+  RatDiv: AtLeast(1) => {
+    let mut it = args.iter();
+    let (mut n, mut d) = try1!(self.as_rat(it.next().unwrap()));
+    if args.len() == 1 { std::mem::swap(&mut n, &mut d) }
+    for e in it {
+      let (n2, d2) = try1!(self.as_rat(e));
+      n *= d2; d *= n2;
+    }
+    try1!(mk_rat(n, d))
+  },
+  RatLt: AtLeast(1) => LispVal::bool(try1!(self.rat_bool_binop(|n1, n2| n1 < n2, &args))),
+  RatLe: AtLeast(1) => LispVal::bool(try1!(self.rat_bool_binop(|n1, n2| n1 <= n2, &args))),
+  Numer: Exact(1) => {
+    let (n, d) = try1!(self.as_rat(&args[0]));
+    LispVal::number(&n / n.gcd(&d))
+  },
+  Denom: Exact(1) => {
+    let (n, d) = try1!(self.as_rat(&args[0]));
+    LispVal::number(&d / n.gcd(&d))
+  },
+  U64: Exact(1) => LispVal::number(try1!(self.as_u64(&args[0])).into()),
+  I64: Exact(1) => LispVal::number(signed_u64(try1!(self.as_u64(&args[0])))),
+  U64Add: AtLeast(0) => {
+    let mut n = 0_u64;
+    for e in args { n = n.wrapping_add(try1!(self.as_u64(&e))) }
+    LispVal::number(n.into())
+  },
+  U64Sub: AtLeast(1) => {
+    let mut it = args.iter();
+    let mut n = try1!(self.as_u64(it.next().unwrap()));
+    if args.len() == 1 { n = n.wrapping_neg() }
+    for e in it { n = n.wrapping_sub(try1!(self.as_u64(e))) }
+    LispVal::number(n.into())
+  },
+  U64Mul: AtLeast(0) => {
+    let mut n = 1_u64;
+    for e in args { n = n.wrapping_mul(try1!(self.as_u64(&e))) }
+    LispVal::number(n.into())
+  },
+  I64Add: AtLeast(0) => {
+    let mut n = 0_u64;
+    for e in args { n = n.wrapping_add(try1!(self.as_u64(&e))) }
+    LispVal::number(signed_u64(n))
+  },
+  I64Sub: AtLeast(1) => {
+    let mut it = args.iter();
+    let mut n = try1!(self.as_u64(it.next().unwrap()));
+    if args.len() == 1 { n = n.wrapping_neg() }
+    for e in it { n = n.wrapping_sub(try1!(self.as_u64(e))) }
+    LispVal::number(signed_u64(n))
+  },
+  I64Mul: AtLeast(0) => {
+    let mut n = 1_u64;
+    for e in args { n = n.wrapping_mul(try1!(self.as_u64(&e))) }
+    LispVal::number(signed_u64(n))
+  },
+  Bits: Exact(3) => {
+    let n = try1!(self.as_int(&args[0]));
+    let lo: u64 = try1!(self.with_int(&args[1], |i| i.try_into().map_err(|_| "bit index out of range".into())));
+    let len: u64 = try1!(self.with_int(&args[2], |i| i.try_into().map_err(|_| "bit length out of range".into())));
+    let n = n >> lo;
+    // a nonnegative number that already fits in `len` bits doesn't need a mask
+    if !n.is_negative() && len >= n.bits() { LispVal::number(n) } else {
+      if n.is_negative() && len > MAX_BITS_LEN { try1!(Err("bit length out of range")) }
+      LispVal::number(n & ((BigInt::one() << len) - 1))
+    }
+  },
   Equal: AtLeast(1) => {
     let (e1, args) = args.split_first().unwrap();
     LispVal::bool(args.iter().all(|e2| e1 == e2))
@@ -1012,6 +1525,94 @@ make_builtins! { self, sp1, sp2, args,
     return Ok(State::MapProc(sp1, sp, proc,
       it.map(Uncons::from).collect(), vec![]))
   },
+  Filter: Exact(2) => {
+    let f = args.pop().unwrap();
+    let sp = f.fspan().map_or(sp2, |fsp| fsp.span);
+    let u = Uncons::from(args.pop().unwrap());
+    return Ok(State::ListProc(sp1, sp, f, ListProc::Filter(u, vec![], None)))
+  },
+  Foldl: Exact(3) => {
+    let f = args.pop().unwrap();
+    let sp = f.fspan().map_or(sp2, |fsp| fsp.span);
+    let z = args.pop().unwrap();
+    let u = Uncons::from(args.pop().unwrap());
+    return Ok(State::ListProc(sp1, sp, f, ListProc::Foldl(z, u)))
+  },
+  Foldr: Exact(3) => {
+    let f = args.pop().unwrap();
+    let sp = f.fspan().map_or(sp2, |fsp| fsp.span);
+    let z = args.pop().unwrap();
+    let es = try1!(self.as_list(&args[0]));
+    return Ok(State::ListProc(sp1, sp, f, ListProc::Foldr(z, es)))
+  },
+  Any: Exact(2) => {
+    let f = args.pop().unwrap();
+    let sp = f.fspan().map_or(sp2, |fsp| fsp.span);
+    let u = Uncons::from(args.pop().unwrap());
+    return Ok(State::ListProc(sp1, sp, f, ListProc::AnyAll(true, u)))
+  },
+  All: Exact(2) => {
+    let f = args.pop().unwrap();
+    let sp = f.fspan().map_or(sp2, |fsp| fsp.span);
+    let u = Uncons::from(args.pop().unwrap());
+    return Ok(State::ListProc(sp1, sp, f, ListProc::AnyAll(false, u)))
+  },
+  Sort: Exact(2) => {
+    let f = args.pop().unwrap();
+    let sp = f.fspan().map_or(sp2, |fsp| fsp.span);
+    let es = try1!(self.as_list(&args[0]));
+    return Ok(State::ListProc(sp1, sp, f, ListProc::Sort(MergeSort::new(es))))
+  },
+  Assoc: Exact(2) => {
+    let mut u = Uncons::from(args[0].clone());
+    let res = u.find(|e| Uncons::from(e.clone()).next().map_or(false, |h| h == args[1]));
+    if res.is_none() && !u.exactly(0) {
+      try1!(Err(format!("expected a list, got {}", self.print(&args[0]))))
+    }
+    res.unwrap_or_else(LispVal::undef)
+  },
+  Append: AtLeast(0) => match args.pop() {
+    None => LispVal::nil(),
+    Some(r) => {
+      let mut es = vec![];
+      for l in &args { es.extend(try1!(self.as_list(l))) }
+      cons_all(es, r)
+    }
+  },
+  Reverse: Exact(1) => {
+    let mut es = try1!(self.as_list(&args[0]));
+    es.reverse();
+    LispVal::list(es)
+  },
+  Range: AtLeast(1) => {
+    let (a, b, step) = match *args {
+      [ref b] => (BigInt::from(0), try1!(self.as_int(b)), BigInt::from(1)),
+      [ref a, ref b] => (try1!(self.as_int(a)), try1!(self.as_int(b)), BigInt::from(1)),
+      [ref a, ref b, ref step] => (try1!(self.as_int(a)), try1!(self.as_int(b)), try1!(self.as_int(step))),
+      _ => try1!(Err("expected 1 to 3 arguments")),
+    };
+    let mut es = vec![];
+    let mut n = a;
+    match step.sign() {
+      Sign::NoSign => try1!(Err("range: step must not be zero")),
+      Sign::Plus => while n < b { es.push(LispVal::number(n.clone())); n += &step },
+      Sign::Minus => while n > b { es.push(LispVal::number(n.clone())); n += &step },
+    }
+    LispVal::list(es)
+  },
+  Zip: AtLeast(1) => {
+    let ls = try1!(args.iter().map(|l| self.as_list(l)).collect::<SResult<Vec<_>>>());
+    let n = ls[0].len();
+    if ls.iter().any(|l| l.len() != n) {try1!(Err("mismatched input length"))}
+    let mut its: Vec<_> = ls.into_iter().map(Vec::into_iter).collect();
+    LispVal::list((0..n).map(|_|
+      LispVal::list(its.iter_mut().map(|it| it.next().unwrap()).collect::<Vec<_>>())
+    ).collect::<Vec<_>>())
+  },
+  Length: Exact(1) => {
+    if !args[0].is_list() {try1!(Err(format!("expected a list, got {}", self.print(&args[0]))))}
+    LispVal::number(args[0].len().into())
+  },
   IsBool: Exact(1) => LispVal::bool(args[0].is_bool()),
   IsAtom: Exact(1) => LispVal::bool(args[0].is_atom()),
   IsPair: Exact(1) => LispVal::bool(args[0].at_least(1)),
@@ -1065,50 +1666,99 @@ make_builtins! { self, sp1, sp2, args,
     }
     LispVal::new_ref(LispVal::new(LispKind::AtomMap(m)))
   },
+  IsHashMap: Exact(1) => LispVal::bool(args[0].is_hash_map()),
+  NewHashMap: AtLeast(0) => {
+    let mut m = HashMap::new();
+    for e in args {
+      let mut u = Uncons::from(e);
+      let e = try1!(u.next().ok_or("invalid arguments"));
+      let k = try1!(self.as_key(&e));
+      let ret = u.next();
+      if !u.exactly(0) {try1!(Err("invalid arguments"))}
+      if let Some(v) = ret {m.insert(k, v);} else {m.remove(&k);}
+    }
+    LispVal::new_ref(LispVal::new(LispKind::HashMap(m)))
+  },
+  MapToList: Exact(1) => {
+    let pair = |k, v: &LispVal| LispVal::list(vec![k, v.clone()]);
+    let es = try1!(args[0].unwrapped(|e| match e {
+      LispKind::AtomMap(m) => Ok(m.iter().map(|(&a, v)| pair(LispVal::atom(a), v)).collect::<Vec<_>>()),
+      LispKind::HashMap(m) => Ok(m.iter().map(|(k, v)| pair(k.to_lisp(), v)).collect()),
+      _ => Err(format!("not a map: {}", self.print(e)))
+    }));
+    LispVal::list(es)
+  },
   Lookup: AtLeast(2) => {
-    match self.as_string_atom(&args[1]) {
-      None => LispVal::undef(),
-      Some(k) => {
-        let e = try1!(self.as_map(&args[0], |m| Ok(m.get(&k).cloned())));
-        if let Some(e) = e {e} else {
-          let v = args.get(2).cloned().unwrap_or_else(LispVal::undef);
-          if v.is_proc() {
-            let sp = v.fspan().map_or(sp2, |fsp| fsp.span);
-            return Ok(State::App(sp1, sp, v, vec![], [].iter()))
-          } else {v}
-        }
+    let e = if args[0].is_hash_map() {
+      args[1].as_key().and_then(|k| args[0].unwrapped(|e| match e {
+        LispKind::HashMap(m) => m.get(&k).cloned(),
+        _ => None
+      }))
+    } else {
+      match self.as_string_atom(&args[1]) {
+        None => None,
+        Some(k) => try1!(args[0].unwrapped(|e| match e {
+          LispKind::AtomMap(m) => Ok(m.get(&k).cloned()),
+          _ => Err(format!("not a map: {}", self.print(e)))
+        }))
       }
+    };
+    if let Some(e) = e {e} else {
+      let v = args.get(2).cloned().unwrap_or_else(LispVal::undef);
+      if v.is_proc() {
+        let sp = v.fspan().map_or(sp2, |fsp| fsp.span);
+        return Ok(State::App(sp1, sp, v, vec![], [].iter()))
+      } else {v}
     }
   },
   Insert: AtLeast(2) => {
-    try1!(try1!(args[0].as_ref_mut(|r| {
-      r.as_map_mut(|m| -> SResult<_> {
-        let k = self.as_string_atom(&args[1])
-          .ok_or_else(|| format!("expected an atom, got {}", self.print(&args[1])))?;
-        match args.get(2) {
-          Some(v) => {m.insert(k, v.clone());}
-          None => {m.remove(&k);}
-        }
-        Ok(())
-      })
-    }).unwrap_or(None).ok_or("expected a mutable map")));
+    let v = args.get(2).cloned();
+    try1!(try1!(args[0].as_ref_mut(|r| self.map_insert(r, &args[1], v))
+      .ok_or("expected a mutable map")));
     LispVal::undef()
   },
   InsertNew: AtLeast(2) => {
     let mut it = args.into_iter();
     let mut m = it.next().unwrap();
     let k = it.next().unwrap();
-    let k = self.as_string_atom(&k)
-      .ok_or_else(|| format!("expected an atom, got {}", self.print(&k)));
-    try1!(try1!(m.as_map_mut(|m| -> SResult<_> {
-      match it.next() {
-        Some(v) => {m.insert(k?, v);}
-        None => {m.remove(&k?);}
-      }
-      Ok(())
-    }).ok_or("expected a map")));
+    try1!(self.map_insert(&mut m, &k, it.next()));
+    LispVal::undef()
+  },
+  IsVec: Exact(1) => LispVal::bool(args[0].is_vec()),
+  NewVec: AtLeast(0) => LispVal::new_ref(LispVal::new(LispKind::Vector(args))),
+  ListToVec: Exact(1) => {
+    let es = try1!(self.as_list(&args[0]));
+    LispVal::new_ref(LispVal::new(LispKind::Vector(es)))
+  },
+  VecToList: Exact(1) => try1!(self.as_vec(&args[0], |es| Ok(LispVal::list(es.to_vec())))),
+  VecLen: Exact(1) => try1!(self.as_vec(&args[0], |es| Ok(LispVal::number(es.len().into())))),
+  VecGet: Exact(2) => {
+    let n = try1!(args[1].as_int(|n| n.to_usize().unwrap_or(usize::MAX)).ok_or("expected a number"));
+    try1!(self.as_vec(&args[0], |es| Ok(es.get(n).cloned().unwrap_or_else(LispVal::undef))))
+  },
+  VecSet: Exact(3) => {
+    let n = try1!(args[1].as_int(BigInt::clone).ok_or("expected a number"));
+    let v = args[2].clone();
+    try1!(try1!(args[0].as_ref_mut(|r| r.as_container_mut(|es: &mut Vec<LispVal>| {
+      let len = es.len();
+      *n.to_usize().and_then(|i| es.get_mut(i))
+        .ok_or_else(|| format!("index {} out of range for vector of length {}", n, len))? = v;
+      Ok::<_, String>(())
+    })).unwrap_or(None).ok_or("expected a mutable vector")));
     LispVal::undef()
   },
+  VecPush: AtLeast(1) => {
+    let mut it = args.into_iter();
+    let v = it.next().unwrap();
+    try1!(v.as_ref_mut(|r| r.as_container_mut(|es: &mut Vec<LispVal>| es.extend(it)))
+      .unwrap_or(None).ok_or("expected a mutable vector"));
+    LispVal::undef()
+  },
+  VecPop: Exact(1) => {
+    let e = try1!(args[0].as_ref_mut(|r| r.as_container_mut(|es: &mut Vec<LispVal>| es.pop()))
+      .unwrap_or(None).ok_or("expected a mutable vector"));
+    e.unwrap_or_else(LispVal::undef)
+  },
   SetTimeout: Exact(1) => {
     match try1!(args[0].as_int(|n| n.to_u64()).ok_or("expected a number")) {
       None | Some(0) => {self.timeout = None; self.cur_timeout = None},
@@ -1259,6 +1909,134 @@ make_builtins! { self, sp1, sp2, args,
     let bytes = self.eval_string(&fsp, &args)?;
     LispVal::string(bytes.into())
   },
+  ReadFile: Exact(1) => {
+    let name = try1!(self.as_string(&args[0]));
+    let src = try1!(self.read_data_file(&String::from_utf8_lossy(&name)));
+    LispVal::string(ArcString::new(src.as_bytes().into()))
+  },
+  RegisterEq: AtLeast(3) => {
+    if args.len() > 4 { try1!(Err("expected 3 or 4 arguments")) }
+    let fsp = self.fspan_base(sp1);
+    try1!(self.register_eq(fsp, &args));
+    LispVal::undef()
+  },
+  RegisterCongr: AtLeast(0) => {
+    let fsp = self.fspan_base(sp1);
+    try1!(self.register_congr(&fsp, &args));
+    LispVal::undef()
+  },
+  Rewrite: AtLeast(2) => {
+    if args.len() > 3 { try1!(Err("expected 2 or 3 arguments")) }
+    let limit = match args.get(2) {
+      None => crate::elab::rewrite::DEFAULT_STEP_LIMIT,
+      Some(e) => try1!(self.with_int(e, |n| n.try_into().map_err(|_| "expected a step limit".into()))),
+    };
+    try1!(self.rewrite_expr(&args[0], &args[1], limit))
+  },
+  RewriteGoal: AtLeast(2) => {
+    if args.len() > 3 { try1!(Err("expected 2 or 3 arguments")) }
+    let p = args.get(2).cloned().unwrap_or_else(|| LispVal::atom(AtomID::UNDER));
+    try1!(self.rewrite_goal(&args[0], &args[1], p))
+  },
+  RegisterTauto: Exact(1) => {
+    let fsp = self.fspan_base(sp1);
+    try1!(self.register_tauto(fsp, &args[0]));
+    LispVal::undef()
+  },
+  Tauto: AtLeast(0) => {
+    if args.len() > 1 { try1!(Err("expected 0 or 1 arguments")) }
+    try1!(self.tauto(args.get(0)))
+  },
+  RegisterNormNum: Exact(1) => {
+    let fsp = self.fspan_base(sp1);
+    try1!(self.register_norm_num(fsp, &args[0]));
+    LispVal::undef()
+  },
+  NormNum: Exact(1) => try1!(self.norm_num(&args[0])),
+  Auto: AtLeast(1) => {
+    if args.len() > 3 { try1!(Err("expected 1 to 3 arguments")) }
+    let depth = match args.get(2) {
+      None => crate::elab::auto::DEFAULT_DEPTH,
+      Some(e) => try1!(self.with_int(e, |n| n.try_into().map_err(|_| "expected a depth".into()))),
+    };
+    try1!(self.auto(&args[0], args.get(1), depth))
+  },
+  RegisterTptp: Exact(1) => {
+    let fsp = self.fspan_base(sp1);
+    try1!(self.register_tptp(fsp, &args[0]));
+    LispVal::undef()
+  },
+  TptpExport: AtLeast(1) => {
+    if args.len() > 3 { try1!(Err("expected 1 to 3 arguments")) }
+    let lang = match args.get(2).map(|e| e.as_atom().map(|a| &*self.data[a].name)) {
+      None | Some(Some(b"fof")) => crate::elab::tptp::Lang::Fof,
+      Some(Some(b"thf")) => crate::elab::tptp::Lang::Thf,
+      _ => try1!(Err("expected 'fof or 'thf")),
+    };
+    let goal = args.get(1).filter(|e| !e.is_nil());
+    LispVal::string(try1!(self.tptp_export(&args[0], goal, lang)).as_bytes().into())
+  },
+  TptpReconstruct: AtLeast(1) => {
+    if args.len() > 3 { try1!(Err("expected 1 to 3 arguments")) }
+    let depth = match args.get(2) {
+      None => crate::elab::auto::DEFAULT_DEPTH,
+      Some(e) => try1!(self.with_int(e, |n| n.try_into().map_err(|_| "expected a depth".into()))),
+    };
+    try1!(self.tptp_reconstruct(&args[0], args.get(1), depth))
+  },
+  FindThms: Exact(1) => {
+    let thms = try1!(self.find_thms(&args[0]));
+    LispVal::list(thms.into_iter().map(|t| LispVal::atom(self.thms[t].atom)).collect::<Vec<_>>())
+  },
+  ReadSexprs: Exact(1) => {
+    let name = try1!(self.as_string(&args[0]));
+    let name = String::from_utf8_lossy(&name);
+    let src = try1!(self.read_data_file(&name));
+    let es = try1!(parse_sexprs(src.as_bytes()).map_err(|e| {
+      let pos = src.to_pos(e.pos.start);
+      format!("{}:{}:{}: {}", name, pos.line + 1, pos.character + 1, e.msg)
+    }));
+    let vs = try1!(es.iter().map(|e| self.data_sexpr(&src, e))
+      .collect::<StdResult<Vec<_>, _>>().map_err(|e| format!("{}:{}", name, e)));
+    LispVal::list(vs)
+  },
+  RegisterLrat: Exact(1) => {
+    let fsp = self.fspan_base(sp1);
+    try1!(self.register_lrat(fsp, &args[0]));
+    LispVal::undef()
+  },
+  Lrat: Exact(4) => {
+    let cnf = try1!(self.as_string(&args[0]));
+    let proof = try1!(self.as_string(&args[1]));
+    try1!(self.lrat(sp1, &String::from_utf8_lossy(&cnf), &String::from_utf8_lossy(&proof), &args[2], &args[3]))
+  },
+  ProofSteps: AtLeast(1) => {
+    if args.len() > 2 { try1!(Err("expected 1 or 2 arguments")) }
+    let order = match args.get(1).map(|e| e.as_atom().map(|a| &*self.data[a].name)) {
+      None | Some(Some(b"post")) => crate::elab::proof_layout::ProofOrder::Post,
+      Some(Some(b"pre")) => crate::elab::proof_layout::ProofOrder::Pre,
+      _ => try1!(Err("expected 'pre or 'post")),
+    };
+    let x = try1!(args[0].as_atom().ok_or("expected an atom"));
+    try1!(self.proof_steps(x, order))
+  },
+  MinimizeProof: AtLeast(0) => try1!(self.minimize_proof(&args)),
+  Unfold: AtLeast(1) => {
+    use crate::elab::unfold::Delta;
+    if args.len() > 2 { try1!(Err("expected 1 or 2 arguments")) }
+    let mode = match args.get(1) {
+      None => Delta::All,
+      Some(d) => {
+        let a = try1!(d.as_atom().ok_or("expected an atom"));
+        match self.term(a) {
+          Some(t) if matches!(self.terms[t].kind, TermKind::Def(_)) => Delta::Def(t),
+          _ => try1!(Err(format!("unknown definition '{}'", self.data[a].name))),
+        }
+      }
+    };
+    try1!(self.unfold_builtin(mode, &args[0]))
+  },
+  Whnf: Exact(1) => try1!(self.unfold_builtin(crate::elab::unfold::Delta::Head, &args[0])),
   MMCInit: Exact(0) => LispVal::proc(Proc::MMCCompiler(
     RefCell::new(crate::mmc::Compiler::new(self)))),
 }
@@ -1327,7 +2105,7 @@ impl<'a> Evaluator<'a> {
                 let s = name.clone();
                 let a = self.get_atom(&s);
                 let ret = LispVal::proc(Proc::Builtin(p));
-                self.data[a].lisp = Some(LispData {src: None, doc: None, val: ret.clone()});
+                self.data[a].lisp = Some(LispData {src: None, doc: None, local: false, val: ret.clone()});
                 ret
               }
             },
@@ -1343,10 +2121,10 @@ impl<'a> Evaluator<'a> {
             Some(Stack::Eval(e, it)) => push!(NoTailRec; Evals(e, it)),
             Some(s) => push!(s; State::Ret(LispVal::undef())),
           }
-          &IR::Focus(sp, ref irs) => {
+          &IR::Focus(sp, full, ref irs) => {
             if self.lc.goals.is_empty() {throw!(sp, "no goals")}
             let gs = self.lc.goals.drain(1..).collect();
-            push!(Focus(sp, true, gs); Refines(sp, irs.iter()))
+            push!(Focus(sp, true, gs); Refines(full, irs.iter()))
           }
           &IR::Def(n, ref x, ref val) => {
             assert!(self.ctx.len() == n);
@@ -1379,12 +2157,7 @@ impl<'a> Evaluator<'a> {
           Some(Stack::List(sp, mut vec, it)) => { vec.push(ret); State::List(sp, vec, it) }
           Some(Stack::DottedList(mut vec, it, e)) => { vec.push(ret); State::DottedList(vec, it, e) }
           Some(Stack::DottedList2(vec)) if vec.is_empty() => State::Ret(ret),
-          Some(Stack::DottedList2(mut vec)) => State::Ret(match ret.try_unwrap() {
-            Ok(LispKind::List(es)) => { vec.extend::<Vec<_>>(es.into()); LispVal::list(vec) }
-            Ok(LispKind::DottedList(es, e)) => { vec.extend::<Vec<_>>(es.into()); LispVal::dotted_list(vec, e) }
-            Ok(e) => LispVal::dotted_list(vec, LispVal::new(e)),
-            Err(ret) => LispVal::dotted_list(vec, ret),
-          }),
+          Some(Stack::DottedList2(vec)) => State::Ret(cons_all(vec, ret)),
           Some(Stack::App(sp1, sp2, es)) => State::App(sp1, sp2, ret, vec![], es.iter()),
           Some(Stack::App2(sp1, sp2, f, mut vec, it)) => { vec.push(ret); State::App(sp1, sp2, f, vec, it) }
           Some(Stack::AppHead(sp1, sp2, e)) => State::App(sp1, sp2, ret, vec![e], [].iter()),
@@ -1412,10 +2185,17 @@ impl<'a> Evaluator<'a> {
             if let Some(&Some((sp1, sp2, ref doc, a))) = x {
               let loc = (self.fspan(sp2), sp1);
               if ret.is_def_strict() {
+                let local = self.lisp_local;
                 let e = mem::replace(&mut self.data[a].lisp,
-                  Some(LispData {src: Some(loc), doc: doc.clone(), val: ret}));
-                if e.is_none() {
-                  self.stmts.push(StmtTrace::Global(a))
+                  Some(LispData {src: Some(loc), doc: doc.clone(), local, val: ret}));
+                match e {
+                  None => self.stmts.push(StmtTrace::Global(a)),
+                  Some(LispData {src: Some((fsp, _)), ..}) if fsp.file != self.path => {
+                    let msg = format!("redefining global '{}' from another file", self.data[a].name);
+                    self.report(ElabError::warn_with_info(sp2, msg.into(),
+                      vec![(fsp, "previously defined here".into())]))
+                  }
+                  Some(_) => {}
                 }
               } else if mem::take(&mut self.data[a].lisp).is_some() {
                 self.data[a].graveyard = Some(Box::new(loc));
@@ -1437,6 +2217,10 @@ impl<'a> Evaluator<'a> {
             vec.push(ret);
             State::MapProc(sp1, sp2, f, us, vec)
           }
+          Some(Stack::ListProc(sp1, sp2, f, mut lp)) => match lp.ret(ret) {
+            Some(ret) => State::Ret(ret),
+            None => State::ListProc(sp1, sp2, f, lp),
+          }
           Some(Stack::AddThmProc(fsp, ap)) => {
             ap.finish(self, &fsp, ret)?;
             State::Ret(LispVal::undef())
@@ -1546,6 +2330,7 @@ impl<'a> Evaluator<'a> {
                 let code: *const IR = &**code;
                 State::Eval(unsafe { &*code })
               },
+              Proc::Macro(f) => State::App(sp1, sp2, f.clone(), args, [].iter()),
               Proc::MatchCont(valid) => {
                 if !valid.get() {throw!(sp2, "continuation has expired")}
                 loop {
@@ -1647,10 +2432,21 @@ impl<'a> Evaluator<'a> {
             }
           }
         }
-        State::Refines(sp, mut it) => match it.next() {
-          None => State::Ret(LispVal::undef()),
-          Some(e) => push!(Refines(sp, Some(e.span().unwrap_or(sp)), it); Eval(e))
-        },
+        State::ListProc(sp1, sp2, f, mut lp) => match lp.next() {
+          Err(e) => throw!(sp1, e),
+          Ok(ListStep::Done(ret)) => State::Ret(ret),
+          Ok(ListStep::TailCall(args)) => State::App(sp1, sp2, f, args, [].iter()),
+          Ok(ListStep::Call(args)) =>
+            push!(ListProc(sp1, sp2, f.clone(), lp); App(sp1, sp2, f, args, [].iter())),
+        }
+        State::Refines(sp, mut it) => {
+          let next = it.next();
+          self.call_goal_listener_at(sp, next.map(|e| e.span().unwrap_or(sp).start));
+          match next {
+            None => State::Ret(LispVal::undef()),
+            Some(e) => push!(Refines(sp, Some(e.span().unwrap_or(sp)), it); Eval(e))
+          }
+        }
         State::Refine {sp, mut stack, state} => {
           let res = self.elab.run_refine(self.orig_span, &mut stack, state)
             .map_err(|e| self.err(Some((e.pos, true)), e.kind.msg()))?;
@@ -5,12 +5,12 @@
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 use std::collections::HashMap;
-use num::{BigInt, ToPrimitive};
+use num::{BigInt, ToPrimitive, bigint::Sign};
 use itertools::Itertools;
 use crate::parser::ast::{SExpr, SExprKind, Atom};
 use crate::util::{ArcString, OptionExt};
 use super::super::{AtomID, Span, DocComment, Elaborator, ElabError, ObjectKind};
-use super::{BuiltinProc, FileSpan, LispKind, LispVal, Proc, ProcSpec,
+use super::{BuiltinProc, FileSpan, LispKind, LispVal, Proc, ProcPos, ProcSpec,
   Remap, Remapper, Syntax};
 use super::super::math_parser::{QExpr, QExprKind};
 use super::print::{FormatEnv, EnvDisplay};
@@ -44,7 +44,8 @@ pub enum IR {
   If(Box<(IR, IR, IR)>),
   /// The `(focus es)` syntax form. This should be a regular function, but it does some
   /// preparation work before it starts executing the list of arguments.
-  Focus(Span, Box<[IR]>),
+  /// The spans are the `focus` keyword and the full `(focus es)` form.
+  Focus(Span, Span, Box<[IR]>),
   /// The `(def x e)` syntax form. Call the argument, and extend the context with the result.
   /// The `usize` argument indicates the number of the variable that was just declared,
   /// but it is only there for sanity checking - there is only one valid value for this field.
@@ -87,7 +88,7 @@ impl<'a> EnvDisplay for IR {
         Some(&**e).into_iter().chain(&**es).map(|ir| fe.to(ir)).format(" ")),
       IR::If(es) => write!(f, "(if {} {} {})",
         fe.to(&es.0), fe.to(&es.1), fe.to(&es.2)),
-      IR::Focus(_, es) => write!(f, "(focus {})", es.iter().map(|ir| fe.to(ir)).format(" ")),
+      IR::Focus(_, _, es) => write!(f, "(focus {})", es.iter().map(|ir| fe.to(ir)).format(" ")),
       IR::NoTailRec => write!(f, "(no-tail-rec)"),
       IR::Def(n, a, e) => write!(f, "(def {}:{} {})",
         n, fe.to(&a.as_ref().map_or(AtomID::UNDER, |&(_, _, _, a)| a)), fe.to(e)),
@@ -136,7 +137,7 @@ impl IR {
       &IR::Global(sp, _) |
       &IR::List(sp, _) |
       &IR::App(sp, _, _, _) |
-      &IR::Focus(sp, _) |
+      &IR::Focus(sp, _, _) |
       &IR::Lambda(sp, _, _, _) |
       &IR::Match(sp, _, _) => Some(sp),
       _ => None
@@ -277,14 +278,14 @@ impl Remap for IR {
   fn remap(&self, r: &mut Remapper) -> Self {
     match self {
       &IR::Local(i) => IR::Local(i),
-      &IR::Global(sp, a) => IR::Global(sp, a.remap(r)),
+      &IR::Global(sp, a) => IR::Global(sp, r.global.get(&a).copied().unwrap_or_else(|| a.remap(r))),
       IR::Const(v) => IR::Const(unsafe { v.freeze() }.remap(r)),
       IR::List(sp, v) => IR::List(*sp, v.remap(r)),
       IR::DottedList(v, e) => IR::DottedList(v.remap(r), e.remap(r)),
       &IR::App(s, t, ref e, ref es) => IR::App(s, t, e.remap(r), es.remap(r)),
       IR::If(e) => IR::If(e.remap(r)),
       IR::NoTailRec => IR::NoTailRec,
-      IR::Focus(sp, e) => IR::Focus(*sp, e.remap(r)),
+      IR::Focus(sp1, sp2, e) => IR::Focus(*sp1, *sp2, e.remap(r)),
       &IR::Def(n, ref a, ref e) => IR::Def(n,
         a.as_ref().map(|&(sp1, sp2, ref doc, a)| (sp1, sp2, doc.clone(), a.remap(r))),
         e.remap(r)),
@@ -421,9 +422,21 @@ impl LocalCtx {
   }
 }
 
+/// The maximum nesting depth of macro expansions, to catch runaway recursive macros.
+const MAX_MACRO_DEPTH: usize = 256;
+
+/// Records the source expression of each value passed to a macro, keyed by address, so that
+/// parts of the arguments that survive into the expansion keep their original span.
+/// The values are kept here to ensure that the addresses are not reused.
+type MacroSrc<'c> = HashMap<*const LispKind, (LispVal, &'c SExpr)>;
+
 struct LispParser<'a> {
   elab: &'a mut Elaborator,
   ctx: LocalCtx,
+  /// The values of the generated atoms [`Atom::Gen`] produced by macro expansion.
+  gen: Vec<LispVal>,
+  /// The current number of nested macro expansions.
+  depth: usize,
 }
 impl<'a> Deref for LispParser<'a> {
   type Target = Elaborator;
@@ -438,14 +451,18 @@ enum Item<'a> {
   DottedList(&'a [SExpr], &'a SExpr),
 }
 
-type Var<'a> = (Span, AtomID, Vec<Item<'a>>);
+type Var<'a> = (Span, AtomID, Vec<Item<'a>>, Atom);
 
 impl<'a> LispParser<'a> {
+  fn new(elab: &'a mut Elaborator) -> Self {
+    LispParser {elab, ctx: LocalCtx::new(), gen: vec![], depth: 0}
+  }
+
   fn def_var<'c>(&mut self, mut e: &'c SExpr) -> Result<Var<'c>, ElabError> {
     let mut stack = vec![];
     loop {
       match &e.k {
-        &SExprKind::Atom(a) => break Ok((e.span, self.parse_atom(e.span, a)?, stack)),
+        &SExprKind::Atom(a) => break Ok((e.span, self.parse_atom(e.span, a)?, stack, a)),
         SExprKind::List(xs) if !xs.is_empty() =>
           {stack.push(Item::List(&xs[1..])); e = &xs[0]}
         SExprKind::DottedList(xs, y) if !xs.is_empty() =>
@@ -489,9 +506,9 @@ impl<'a> LispParser<'a> {
   }
 
   fn def(&mut self, e: &SExpr, es: &[SExpr]) -> Result<(Span, AtomID, Vec<IR>), ElabError> {
-    let (sp, x, stack) = self.def_var(e)?;
+    let (sp, x, stack, a) = self.def_var(e)?;
     let ir = self.def_ir(sp, es, stack)?;
-    if self.ctx.len() == 0 {
+    if self.ctx.len() == 0 && !matches!(a, Atom::Gen(_)) {
       self.spans.insert(sp, ObjectKind::Global(x));
     }
     Ok((sp, x, ir))
@@ -499,7 +516,38 @@ impl<'a> LispParser<'a> {
 }
 
 impl<'a> LispParser<'a> {
+  /// Returns the value of a generated atom, if it is a constant rather than an
+  /// identifier or keyword.
+  fn gen_const(&self, a: Atom) -> Option<LispVal> {
+    if let Atom::Gen(i) = a {
+      let v = &self.gen[i];
+      if !matches!(**v, LispKind::Atom(_) | LispKind::Syntax(_)) { return Some(v.clone()) }
+    }
+    None
+  }
+
+  /// The text of an atom, for comparison against keywords. Generated constants have no text.
+  fn atom_text(&self, sp: Span, a: Atom) -> &[u8] {
+    match a {
+      Atom::Gen(i) => match *self.gen[i] {
+        LispKind::Atom(x) => &self.data[x].name,
+        LispKind::Syntax(s) => s.to_str().as_bytes(),
+        _ => b"",
+      },
+      _ => self.ast.span_atom(sp, a)
+    }
+  }
+
+  /// Parse an atom which is either an identifier or a keyword. Generated constants
+  /// must be handled by the caller (see [`gen_const`](Self::gen_const)).
   fn parse_ident_or_syntax(&mut self, sp: Span, a: Atom) -> Result<AtomID, Syntax> {
+    if let Atom::Gen(i) = a {
+      return match *self.gen[i] {
+        LispKind::Atom(x) => Syntax::from_bytes(&self.data[x].name).map_or(Ok(x), Err),
+        LispKind::Syntax(s) => Err(s),
+        _ => unreachable!("constant in identifier position"),
+      }
+    }
     match Syntax::parse(self.ast.clone().span(sp), a) {
       Ok(s) => Err(s),
       Err(s) => Ok(self.get_atom(s))
@@ -507,6 +555,9 @@ impl<'a> LispParser<'a> {
   }
 
   fn parse_atom(&mut self, sp: Span, a: Atom) -> Result<AtomID, ElabError> {
+    if self.gen_const(a).is_some() {
+      return Err(ElabError::new_e(sp, "expected an identifier"))
+    }
     self.parse_ident_or_syntax(sp, a).map_err(|_|
       ElabError::new_e(sp, "keyword in invalid position"))
   }
@@ -573,7 +624,7 @@ impl<'a> LispParser<'a> {
     if rec {
       let mut ds = Vec::with_capacity(ls.len());
       for l in ls {
-        let ((sp, x, stk), e2) = self.let_var(l)?;
+        let ((sp, x, stk, _), e2) = self.let_var(l)?;
         let n = self.ctx.push(x);
         let sps = if x == AtomID::UNDER {None} else {Some((l.span, sp, None, x))};
         cs.push(IR::Def(n, sps.clone(),
@@ -592,7 +643,7 @@ impl<'a> LispParser<'a> {
       cs.push(IR::NoTailRec);
     } else {
       for l in ls {
-        let ((sp, x, stk), e2) = self.let_var(l)?;
+        let ((sp, x, stk, _), e2) = self.let_var(l)?;
         let v = self.def_ir(sp, e2, stk)?;
         if x == AtomID::UNDER {
           cs.push(IR::Eval(false, v.into()))
@@ -612,12 +663,12 @@ impl<'a> LispParser<'a> {
       match es {
         [] => return Ok(Pattern::List(pfx.into(), None)),
         &[SExpr {span, k: SExprKind::Atom(a)}, ref e] if quote =>
-          if self.ast.span_atom(span, a) == b"unquote" {
+          if self.atom_text(span, a) == b"unquote" {
             break self.pattern(ctx, code, false, e)?
           },
         _ if quote => {},
         [head, args @ ..] => if let SExprKind::Atom(a) = head.k {
-          match self.ast.span_atom(head.span, a) {
+          match self.atom_text(head.span, a) {
             b"quote" => match args {
               [e] => break self.pattern(ctx, code, true, e)?,
               _ => return Err(ElabError::new_e(head.span, "expected one argument")),
@@ -625,7 +676,7 @@ impl<'a> LispParser<'a> {
             b"mvar" => match args {
               [] => break Pattern::MVar(MVarPattern::Unknown),
               &[SExpr {span, k: SExprKind::Atom(a)}]
-                if matches!(self.ast.span_atom(span, a), b"___" | b"...") =>
+                if matches!(self.atom_text(span, a), b"___" | b"...") =>
                 break Pattern::MVar(MVarPattern::Any),
               [bd, s] => {
                 let bd = self.pattern(ctx, code, quote, bd)?;
@@ -716,6 +767,23 @@ impl<'a> LispParser<'a> {
   fn pattern(&mut self, ctx: &mut LocalCtx, code: &mut Vec<IR>,
       quote: bool, e: &SExpr) -> Result<Pattern, ElabError> {
     match &e.k {
+      &SExprKind::Atom(a) if matches!(a, Atom::Gen(_)) => match self.gen_const(a) {
+        Some(v) => match &*v {
+          LispKind::Number(n) => Ok(Pattern::Number(n.clone())),
+          LispKind::String(s) => Ok(Pattern::String(s.clone())),
+          &LispKind::Bool(b) => Ok(Pattern::Bool(b)),
+          LispKind::Undef => Ok(Pattern::Undef),
+          _ => Err(ElabError::new_e(e.span, "invalid pattern")),
+        },
+        None if quote => {
+          let x = self.atom_text(e.span, a).to_owned();
+          Ok(Pattern::QuoteAtom(self.get_atom(&x)))
+        }
+        None => Ok(match self.parse_atom(e.span, a)? {
+          AtomID::UNDER => Pattern::Skip,
+          x => Pattern::Atom(ctx.get_or_push(x)),
+        }),
+      },
       &SExprKind::Atom(a) => Ok(
         if quote {
           Pattern::QuoteAtom(self.elab.env.get_atom(self.elab.ast.span_atom(e.span, a)))
@@ -750,7 +818,7 @@ impl<'a> LispParser<'a> {
     if let Some(e2) = es.get(0) {
       if let SExprKind::List(v) = &e2.k {
         if let [SExpr {span, k: SExprKind::Atom(a)}, ref x] = **v {
-          if let b"=>" = self.ast.span_atom(span, a) {
+          if let b"=>" = self.atom_text(span, a) {
             cont = self.parse_ident(x)?;
             es = &es[1..];
           }
@@ -782,16 +850,132 @@ impl<'a> LispParser<'a> {
     }
   }
 
-  fn eval_atom(&mut self, sp: Span, x: AtomID) -> IR {
+  fn eval_atom(&mut self, sp: Span, a: Atom, x: AtomID) -> IR {
     match self.ctx.get(x) {
       None => {
-        self.spans.insert(sp, ObjectKind::Global(x));
+        if !matches!(a, Atom::Gen(_)) { self.spans.insert(sp, ObjectKind::Global(x)); }
         IR::Global(sp, x)
       },
       Some(i) => IR::Local(i)
     }
   }
 
+  /// Returns the macro transformer if `x` refers to a global macro.
+  fn get_macro(&self, x: AtomID) -> Option<LispVal> {
+    if self.ctx.get(x).is_some() { return None }
+    self.data[x].lisp.as_ref()?.unwrapped(|e| match e {
+      LispKind::Proc(Proc::Macro(f)) => Some(f.clone()),
+      _ => None
+    })
+  }
+
+  /// Convert a macro argument to a lisp value, as if it was quoted, except that `unquote`
+  /// is not evaluated. Every subterm is recorded in `src`.
+  fn macro_arg<'c>(&mut self, e: &'c SExpr, src: &mut MacroSrc<'c>) -> Result<LispVal, ElabError> {
+    let v = match &e.k {
+      &SExprKind::Atom(a) => match self.gen_const(a) {
+        Some(v) => v,
+        None => match self.parse_ident_or_syntax(e.span, a) {
+          Ok(x) => LispVal::atom(x),
+          Err(s) => LispVal::syntax(s),
+        }
+      },
+      SExprKind::List(es) => {
+        let mut vs = vec![];
+        for e in es { vs.push(self.macro_arg(e, src)?) }
+        LispVal::list(vs)
+      }
+      SExprKind::DottedList(es, r) => {
+        let mut vs = vec![];
+        for e in es { vs.push(self.macro_arg(e, src)?) }
+        LispVal::dotted_list(vs, self.macro_arg(r, src)?)
+      }
+      SExprKind::Number(n) => LispVal::number(n.clone().into()),
+      SExprKind::String(s) => LispVal::string(s.clone()),
+      &SExprKind::Bool(b) => LispVal::bool(b),
+      SExprKind::Undef => LispVal::undef(),
+      SExprKind::DocComment(_, e) => self.macro_arg(e, src)?,
+      // Formulas with antiquotations can't be evaluated yet, so the macro gets `#undef`,
+      // but the formula is still restored if the value is passed through.
+      &SExprKind::Formula(f) => {
+        let q = self.parse_formula(f)?;
+        if let IR::Const(v) = self.qexpr(q)? {v} else {LispVal::undef()}
+      }
+    };
+    let v = v.span(self.fspan(e.span));
+    src.insert(std::ptr::addr_of!(*v), (v.clone(), e));
+    Ok(v)
+  }
+
+  /// Convert the result of a macro expansion back to an s-expression. Parts of the
+  /// macro arguments are replaced by their source expressions, and everything else
+  /// gets the span `sp` of the macro call.
+  fn macro_result(&mut self, sp: Span, v: &LispVal, src: &MacroSrc<'_>) -> SExpr {
+    if let Some((_, e)) = src.get(&std::ptr::addr_of!(**v)) { return (*e).clone() }
+    let k = match &**v {
+      LispKind::Annot(_, v) => return self.macro_result(sp, v, src),
+      LispKind::List(_) | LispKind::DottedList(..) => {
+        let mut es = vec![];
+        match self.macro_tail(sp, v, src, &mut es) {
+          None => SExprKind::List(es),
+          Some(r) => SExprKind::DottedList(es, Box::new(r)),
+        }
+      }
+      LispKind::Number(n) if n.sign() != Sign::Minus =>
+        SExprKind::Number(n.magnitude().clone()),
+      LispKind::String(s) => SExprKind::String(s.clone()),
+      &LispKind::Bool(b) => SExprKind::Bool(b),
+      LispKind::Undef => SExprKind::Undef,
+      _ => {
+        self.gen.push(v.clone());
+        SExprKind::Atom(Atom::Gen(self.gen.len() - 1))
+      }
+    };
+    SExpr {span: sp, k}
+  }
+
+  /// Convert a list in the result of a macro expansion, pushing the elements to `es` and
+  /// returning the tail of an improper list. Dotted lists whose tail is a list (as produced
+  /// by `cons`) are flattened, including lists from the macro arguments.
+  fn macro_tail(&mut self, sp: Span, v: &LispVal, src: &MacroSrc<'_>, es: &mut Vec<SExpr>) -> Option<SExpr> {
+    if let Some((_, e)) = src.get(&std::ptr::addr_of!(**v)) {
+      return match &e.k {
+        SExprKind::List(es2) => { es.extend_from_slice(es2); None }
+        SExprKind::DottedList(es2, r) => { es.extend_from_slice(es2); Some((**r).clone()) }
+        _ => Some((*e).clone())
+      }
+    }
+    match &**v {
+      LispKind::Annot(_, v) => self.macro_tail(sp, v, src, es),
+      LispKind::List(es2) => {
+        for e in &**es2 { es.push(self.macro_result(sp, e, src)) }
+        None
+      }
+      LispKind::DottedList(es2, r) => {
+        for e in &**es2 { es.push(self.macro_result(sp, e, src)) }
+        self.macro_tail(sp, r, src, es)
+      }
+      _ => Some(self.macro_result(sp, v, src))
+    }
+  }
+
+  /// Parse an application `(f es)` of a macro with transformer `f`, by expanding it
+  /// and parsing the result.
+  fn macro_app(&mut self, doc: String, sp: Span, f: LispVal, es: &[SExpr]) -> Result<IR, ElabError> {
+    if self.depth >= MAX_MACRO_DEPTH {
+      return Err(ElabError::new_e(sp, "macro expansion depth limit exceeded"))
+    }
+    let mut src = HashMap::new();
+    let mut args = vec![];
+    for e in es { args.push(self.macro_arg(e, &mut src)?) }
+    let v = self.elab.call_func(sp, f, args)?;
+    let e = self.macro_result(sp, &v, &src);
+    self.depth += 1;
+    let res = self.expr_doc(doc, false, &e);
+    self.depth -= 1;
+    res
+  }
+
   fn expr(&mut self, quote: bool, e: &SExpr) -> Result<IR, ElabError> {
     self.expr_doc(String::new(), quote, e)
   }
@@ -800,7 +984,9 @@ impl<'a> LispParser<'a> {
     macro_rules! span {($sp:expr, $e:expr) => {{$e.span(self.fspan($sp))}}}
     let mut restore = Some(self.ctx.len());
     let res = match &e.k {
-      &SExprKind::Atom(a) => if quote {
+      &SExprKind::Atom(a) => if let Some(v) = self.gen_const(a) {
+        Ok(IR::Const(v))
+      } else if quote {
         Ok(IR::Const(span!(e.span,
           match self.parse_ident_or_syntax(e.span, a) {
             Ok(x) => LispVal::atom(x),
@@ -810,7 +996,7 @@ impl<'a> LispParser<'a> {
       } else {
         Ok(match self.parse_atom(e.span, a)? {
           AtomID::UNDER => IR::Const(span!(e.span, LispVal::atom(AtomID::UNDER))),
-          x => self.eval_atom(e.span, x),
+          x => self.eval_atom(e.span, a, x),
         })
       },
       SExprKind::DottedList(es, e) => {
@@ -820,7 +1006,7 @@ impl<'a> LispParser<'a> {
         let mut cs = vec![];
         for e in es {
           if let SExprKind::Atom(a) = es[0].k {
-            if let Ok(Syntax::Unquote) = Syntax::parse(self.ast.span(e.span), a) {
+            if let Ok(Syntax::Unquote) = Syntax::parse(self.atom_text(e.span, a), a) {
               return Err(ElabError::new_e(e.span, "cannot evaluate an improper list"))
             }
           }
@@ -845,7 +1031,7 @@ impl<'a> LispParser<'a> {
         Ok(loop {
           if let Some(arg) = it.next() {
             if let SExprKind::Atom(a) = arg.k {
-              if let Ok(Syntax::Unquote) = Syntax::parse(self.ast.span(arg.span), a) {
+              if let Ok(Syntax::Unquote) = Syntax::parse(self.atom_text(arg.span, a), a) {
                 let r = it.next().ok_or_else(||
                   ElabError::new_e(arg.span, "expected at least one argument"))?;
                 break IR::dotted_list(e.span, cs, self.expr(false, r)?)
@@ -853,14 +1039,22 @@ impl<'a> LispParser<'a> {
             } else {cs.push(self.expr(true, arg)?)}
           } else {break IR::list(self.fspan(e.span), cs)}
         })
-      } else if let SExprKind::Atom(a) = es[0].k {
+      } else if let Some(a) = match es[0].k {
+        SExprKind::Atom(a) if self.gen_const(a).is_none() => Some(a),
+        _ => None
+      } {
+        let gen = matches!(a, Atom::Gen(_));
         match self.parse_ident_or_syntax(es[0].span, a) {
           Ok(AtomID::UNDER) => return Err(ElabError::new_e(es[0].span, "'_' is not a function")),
-          Ok(x) =>
+          Ok(x) => if let Some(f) = self.get_macro(x) {
+            if !gen { self.spans.insert(es[0].span, ObjectKind::Global(x)); }
+            return self.macro_app(doc, e.span, f, &es[1..])
+          } else {
             Ok(IR::App(e.span, es[0].span,
-              Box::new(self.eval_atom(es[0].span, x)), self.exprs(false, &es[1..])?.into())),
+              Box::new(self.eval_atom(es[0].span, a, x)), self.exprs(false, &es[1..])?.into()))
+          },
           Err(stx) => {
-            self.spans.insert_if(es[0].span, || ObjectKind::Syntax(stx));
+            if !gen { self.spans.insert_if(es[0].span, || ObjectKind::Syntax(stx)); }
             match stx {
               Syntax::Begin => Ok(IR::Eval(true, self.exprs(false, &es[1..])?.into())),
               Syntax::Define if es.len() < 2 => return Err(
@@ -912,7 +1106,7 @@ impl<'a> LispParser<'a> {
               )))),
               Syntax::If => return Err(
                 ElabError::new_e(es[0].span, "expected two or three arguments")),
-              Syntax::Focus => Ok(IR::Focus(es[0].span, self.exprs(false, &es[1..])?.into())),
+              Syntax::Focus => Ok(IR::Focus(es[0].span, e.span, self.exprs(false, &es[1..])?.into())),
               Syntax::Let => self.let_(false, &es[1..]),
               Syntax::Letrec => self.let_(true, &es[1..]),
               Syntax::Match if es.len() < 2 => return Err(
@@ -932,6 +1126,29 @@ impl<'a> LispParser<'a> {
                 Ok(IR::Lambda(es[0].span, i, ProcSpec::AtLeast(0),
                   Arc::new(self.match_(&es[1..], |m| IR::match_fn_body(es[0].span, i, m))?)))
               }
+              Syntax::DefMacro if es.len() < 2 => return Err(
+                ElabError::new_e(es[0].span, "expected at least one argument")),
+              Syntax::DefMacro if self.ctx.len() != 0 => return Err(
+                ElabError::new_e(es[0].span, "defmacro: macros must be defined at top level")),
+              Syntax::DefMacro => {
+                let (sp, x, mut cs) = self.def(&es[1], &es[2..])?;
+                match (x, cs.pop()) {
+                  (AtomID::UNDER, _) => return Err(
+                    ElabError::new_e(sp, "defmacro: expected a macro name")),
+                  (_, Some(IR::Lambda(_, 0, spec, code))) if cs.is_empty() => {
+                    restore = None;
+                    let doc = if doc.is_empty() {None} else {Some(doc.into())};
+                    let f = LispVal::proc(Proc::Lambda {
+                      pos: ProcPos::Named(self.fspan(sp), e.span, x),
+                      env: Box::new([]), spec, code
+                    });
+                    Ok(IR::Def(self.ctx.push(x), Some((e.span, sp, doc, x)),
+                      Box::new(IR::Const(LispVal::proc(Proc::Macro(f))))))
+                  }
+                  _ => return Err(ElabError::new_e(es[1].span,
+                    "defmacro: expected (defmacro (name args) body)")),
+                }
+              }
             }
           }
         }
@@ -955,13 +1172,13 @@ impl Elaborator {
   /// Parse a lisp `SExpr` from the surface syntax into an `IR` object suitable for evaluation.
   /// The `doc` argument is an additional doc string, if applicable.
   pub fn parse_lisp_doc(&mut self, e: &SExpr, doc: String) -> Result<IR, ElabError> {
-    LispParser {elab: &mut *self, ctx: LocalCtx::new()}.expr_doc(doc, false, e)
+    LispParser::new(self).expr_doc(doc, false, e)
   }
 
   /// Parse a `QExpr`, the result of parsing a math formula,
   /// into an `IR` object suitable for evaluation. (Usually this will be a `IR::Const`,
   /// but `QExpr`'s can contain antiquotations which require evaluation.)
   pub fn parse_qexpr(&mut self, e: QExpr) -> Result<IR, ElabError> {
-    LispParser {elab: &mut *self, ctx: LocalCtx::new()}.qexpr(e)
+    LispParser::new(self).qexpr(e)
   }
 }
\ No newline at end of file
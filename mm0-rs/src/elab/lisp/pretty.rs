@@ -78,7 +78,9 @@ impl LispKind {
       LispKind::List(es) => es.is_empty(),
       LispKind::DottedList(_, _) |
       LispKind::AtomMap(_) |
+      LispKind::HashMap(_) |
       LispKind::Goal(_) => false,
+      LispKind::Vector(es) => es.is_empty(),
       LispKind::Atom(_) |
       LispKind::MVar(_, _) |
       LispKind::Proc(_) |
@@ -14,7 +14,7 @@ use std::fmt::{self, Display};
 use itertools::Itertools;
 use super::super::{LinedString, Environment, Elaborator, TermID, ThmID, SortID,
   Sort, Term, Thm, DeclKey};
-use super::{AtomID, LispKind, LispVal, Uncons, InferTarget, Proc, ProcPos};
+use super::{AtomID, LispKey, LispKind, LispVal, Uncons, InferTarget, Proc, ProcPos};
 
 /// The side information required to print an object in the environment.
 #[repr(C)]
@@ -186,11 +186,22 @@ impl EnvDisplay for LispKind {
       LispKind::Proc(Proc::RefineCallback) => write!(f, "#[refine]"),
       LispKind::Proc(Proc::ProofThunk(x, _)) => write!(f, "#[proof of {}]", fe.to(x)),
       LispKind::Proc(Proc::MMCCompiler(_)) => write!(f, "#[mmc-compiler]"),
+      LispKind::Proc(Proc::Macro(m)) => write!(f, "#[macro {}]", fe.to(m)),
       LispKind::AtomMap(m) => {
         write!(f, "(atom-map!")?;
         for (a, v) in m {write!(f, " [{} {}]", fe.data[*a].name, fe.to(v))?}
         write!(f, ")")
       }
+      LispKind::HashMap(m) => {
+        write!(f, "(hash-map!")?;
+        for (k, v) in m {write!(f, " [{} {}]", fe.to(k), fe.to(v))?}
+        write!(f, ")")
+      }
+      LispKind::Vector(es) => {
+        write!(f, "(vec!")?;
+        for e in es {write!(f, " {}", fe.to(e))?}
+        write!(f, ")")
+      }
       LispKind::Ref(m) if m.too_many_readers() => write!(f, "#[ref]"),
       LispKind::Ref(m) => m.get(|e| e.fmt(fe, f)),
       &LispKind::MVar(n, _) => write!(f, "?{}", alphanumber(n)),
@@ -199,6 +210,12 @@ impl EnvDisplay for LispKind {
   }
 }
 
+impl EnvDisplay for LispKey {
+  fn fmt(&self, fe: FormatEnv<'_>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.to_lisp().fmt(fe, f)
+  }
+}
+
 impl EnvDisplay for Uncons {
   fn fmt(&self, fe: FormatEnv<'_>, f: &mut fmt::Formatter<'_>) -> fmt::Result {
     match self {
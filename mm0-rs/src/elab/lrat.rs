@@ -0,0 +1,530 @@
+//! Import of LRAT unsatisfiability certificates as MM0 proofs, used by the `lrat` builtin.
+//!
+//! The procedure is configured using `register-lrat` with the library's propositional
+//! connectives and a fixed set of lemmas (see [`LEMMAS`]). A CNF in DIMACS format is
+//! read relative to a list of formulas for its variables: the clause `l1 ... ln` stands for
+//! `l1 \/ (... \/ ln)`, where the literal `v` is the `v`th formula and `-v` is its negation,
+//! and the empty clause is the registered false term `F`.
+//!
+//! Each clause added by the LRAT proof is checked by reverse unit propagation along its
+//! hints. We work in the fixed context `G = T /\ ~l1 /\ ... /\ ~ln`, where `T` is `F -> F`
+//! and `l1 ... ln` is the new clause, and prove `G -> l` for each literal `l` that becomes
+//! unit, by disjunctive syllogism on the hint clause, until a hint clause is falsified
+//! and we get `G -> F`. The literals of the clause are then discharged one at a time
+//! to get `T -> l1 \/ ... \/ ln`, and finally the clause itself.
+//!
+//! The proofs are built in elaborated form and shared between clauses, so the result
+//! is wrapped in `(:verb p)` and is not elaborated again by `refine`. DRAT proofs should
+//! be converted to LRAT first, for example with `drat-trim -L`.
+
+use std::collections::HashMap;
+use crate::util::{FileSpan, Span};
+use super::{Elaborator, ElabError, Remap, Remapper};
+use super::environment::{AtomID, TermID, ThmID};
+use super::lisp::{LispVal, Uncons, eval::SResult};
+use super::expr_table::ExprTable;
+
+/// A lemma used by `lrat`, as an index into [`LEMMAS`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Lemma {
+  Id, Mp, Syl, A1i, AnL, AnR, NotNot,
+  OrElimL, OrElimR, Nor, Absurd, OrIntro, Contra,
+}
+
+/// The lemmas used by `lrat`, with their names in the `register-lrat` configuration
+/// and the required form of the statement, where `>` separates the hypotheses.
+const LEMMAS: [(Lemma, &str, &str); 13] = [
+  (Lemma::Id, "id", "a -> a"),
+  (Lemma::Mp, "mp", "a > a -> b > b"),
+  (Lemma::Syl, "syl", "a -> b > b -> c > a -> c"),
+  (Lemma::A1i, "a1i", "b > a -> b"),
+  (Lemma::AnL, "anl", "a /\\ b -> a"),
+  (Lemma::AnR, "anr", "a /\\ b -> b"),
+  (Lemma::NotNot, "notnot1", "a -> ~~a"),
+  (Lemma::OrElimL, "or-elim-l", "a -> b \\/ c > a -> ~b > a -> c"),
+  (Lemma::OrElimR, "or-elim-r", "a -> b \\/ c > a -> ~c > a -> b"),
+  (Lemma::Nor, "nor", "a -> ~b > a -> ~c > a -> ~(b \\/ c)"),
+  (Lemma::Absurd, "absurd", "a -> b > a -> ~b > a -> F"),
+  (Lemma::OrIntro, "or-intro", "a /\\ ~b -> c > a -> b \\/ c"),
+  (Lemma::Contra, "contra", "a /\\ ~b -> F > a -> b"),
+];
+
+/// The configuration of the `lrat` builtin, set by `register-lrat` and stored in the
+/// [`Environment`](super::Environment) so that it is available to importing files.
+#[derive(Clone, Debug, DeepSizeOf)]
+pub struct LratEnv {
+  /// The location of the registration.
+  pub span: FileSpan,
+  /// The implication term `a -> b`.
+  pub im: TermID,
+  /// The negation term `~a`.
+  pub not: TermID,
+  /// The conjunction term `a /\ b`.
+  pub an: TermID,
+  /// The disjunction term `a \/ b`.
+  pub or: TermID,
+  /// The false term `F`.
+  pub fal: TermID,
+  /// The lemmas, indexed by [`Lemma`].
+  pub lemmas: Box<[ThmID]>,
+}
+
+impl Remap for LratEnv {
+  type Target = Self;
+  fn remap(&self, r: &mut Remapper) -> Self {
+    LratEnv {
+      span: self.span.clone(),
+      im: self.im.remap(r),
+      not: self.not.remap(r),
+      an: self.an.remap(r),
+      or: self.or.remap(r),
+      fal: self.fal.remap(r),
+      lemmas: self.lemmas.iter().map(|t| t.remap(r)).collect(),
+    }
+  }
+}
+
+impl LratEnv {
+  /// Merge the configuration of an imported environment into this one.
+  pub fn merge(this: &mut Option<Self>, other: &Option<Self>, r: &mut Remapper,
+      errors: &mut Vec<ElabError>, sp: Span) {
+    if let Some(other) = other {
+      let other = other.remap(r);
+      match this {
+        None => *this = Some(other),
+        Some(old) => if old.im != other.im || old.fal != other.fal || old.lemmas != other.lemmas {
+          errors.push(ElabError::with_info(sp, "lrat configuration registered twice".into(),
+            vec![(other.span, "registered here".into()), (old.span.clone(), "registered here".into())]))
+        }
+      }
+    }
+  }
+}
+
+/// Parse a DIMACS CNF file, returning the number of variables and the clauses.
+fn parse_cnf(src: &str) -> SResult<(usize, Vec<Box<[i64]>>)> {
+  let mut header: Option<(usize, usize)> = None;
+  let mut clauses = vec![];
+  let mut clause = vec![];
+  for (n, line) in src.lines().enumerate() {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('c') || line.starts_with('%') { continue }
+    if line.starts_with('p') {
+      let mut it = line.split_whitespace().skip(1);
+      header = match (it.next(), it.next().map(str::parse), it.next().map(str::parse)) {
+        (Some("cnf"), Some(Ok(vars)), Some(Ok(num))) if header.is_none() => Some((vars, num)),
+        _ => return Err(format!("CNF line {}: bad header", n + 1))
+      };
+      continue
+    }
+    let (vars, _) = header.ok_or_else(|| format!("CNF line {}: missing header", n + 1))?;
+    for tk in line.split_whitespace() {
+      let lit = tk.parse::<i64>().map_err(|_| format!("CNF line {}: bad literal '{}'", n + 1, tk))?;
+      if lit == 0 {
+        clauses.push(std::mem::take(&mut clause).into())
+      } else if lit.unsigned_abs() > vars as u64 {
+        return Err(format!("CNF line {}: variable {} out of range", n + 1, lit.abs()))
+      } else if clause.contains(&lit) {
+        return Err(format!("CNF line {}: repeated literal {}", n + 1, lit))
+      } else {
+        clause.push(lit)
+      }
+    }
+  }
+  let (vars, num) = header.ok_or("CNF: missing header")?;
+  if !clause.is_empty() { return Err("CNF: unterminated clause".into()) }
+  if clauses.len() != num {
+    return Err(format!("CNF: expected {} clauses, found {}", num, clauses.len()))
+  }
+  Ok((vars, clauses))
+}
+
+/// The reason a variable has a value in the current context: the formula `e` which
+/// sets it, which is `~v`, `~~v` or `v`, and the proof of `G -> e`.
+#[derive(Clone)]
+enum Src {
+  /// The `i`th negated literal of the clause being checked.
+  Ctx(usize),
+  /// A unit literal derived from a hint clause, with its proof.
+  Unit(LispVal),
+}
+
+/// The state of an LRAT proof import.
+struct Lrat<'a> {
+  cfg: &'a LratEnv,
+  tbl: ExprTable<'a>,
+  /// The formula for each variable (indexed from 0).
+  vars: Vec<usize>,
+  /// The false formula `F`.
+  fal: usize,
+  /// The true formula `T = F -> F`, and its proof.
+  top: (usize, LispVal),
+  /// The clauses that are currently active, with their proofs.
+  clauses: HashMap<u64, (Box<[i64]>, LispVal)>,
+  /// The contexts `G_0 = T` and `G_{i+1} = G_i /\ lits[i]` for the current clause.
+  ctx: Vec<usize>,
+  /// The negated literals of the current clause.
+  lits: Vec<usize>,
+  /// The memoized proofs of `G_d -> lits[i]`.
+  proj: HashMap<(usize, usize), LispVal>,
+  /// The current assignment: the value of each assigned variable, the formula which
+  /// sets it, and the source of the proof.
+  assign: HashMap<u64, (bool, usize, Src)>,
+}
+
+impl Lrat<'_> {
+  fn im(&mut self, a: usize, b: usize) -> usize { self.tbl.app(self.cfg.im, &[a, b]) }
+  fn not(&mut self, a: usize) -> usize { self.tbl.app(self.cfg.not, &[a]) }
+  fn an(&mut self, a: usize, b: usize) -> usize { self.tbl.app(self.cfg.an, &[a, b]) }
+  fn or(&mut self, a: usize, b: usize) -> usize { self.tbl.app(self.cfg.or, &[a, b]) }
+
+  /// The formula for literal `l`.
+  #[allow(clippy::cast_possible_truncation)]
+  fn lit(&mut self, l: i64) -> usize {
+    let v = self.vars[l.unsigned_abs() as usize - 1];
+    if l > 0 { v } else { self.not(v) }
+  }
+
+  /// The formula for the clause `ls`.
+  fn clause(&mut self, ls: &[i64]) -> usize {
+    match *ls {
+      [] => self.fal,
+      [l] => self.lit(l),
+      [l, ref rest @ ..] => { let (a, b) = (self.lit(l), self.clause(rest)); self.or(a, b) }
+    }
+  }
+
+  /// Apply lemma `l` with conclusion `concl` and the given hypotheses.
+  fn lemma(&mut self, l: Lemma, concl: usize, hyps: Vec<(usize, LispVal)>) -> LispVal {
+    let thm = self.cfg.lemmas[l as usize];
+    self.tbl.apply_verb(thm, concl, hyps)
+  }
+
+  /// The current context `G`.
+  fn gc(&self) -> usize { *self.ctx.last().expect("nonempty") }
+
+  /// Prove `G_depth -> lits[idx]`, where `idx < depth`.
+  fn proj(&mut self, depth: usize, idx: usize) -> LispVal {
+    if let Some(pf) = self.proj.get(&(depth, idx)) { return pf.clone() }
+    let (gc, l) = (self.ctx[depth], self.lits[idx]);
+    let gl = self.im(gc, l);
+    let pf = if idx + 1 == depth { self.lemma(Lemma::AnR, gl, vec![]) } else {
+      let g2 = self.ctx[depth - 1];
+      let gg2 = self.im(gc, g2);
+      let pf = self.lemma(Lemma::AnL, gg2, vec![]);
+      let pf2 = self.proj(depth - 1, idx);
+      let g2l = self.im(g2, l);
+      self.lemma(Lemma::Syl, gl, vec![(gg2, pf), (g2l, pf2)])
+    };
+    self.proj.insert((depth, idx), pf.clone());
+    pf
+  }
+
+  /// Prove `G -> e`, where `e` is the formula set by `src`.
+  fn source(&mut self, src: Src) -> LispVal {
+    match src {
+      Src::Ctx(i) => self.proj(self.ctx.len() - 1, i),
+      Src::Unit(pf) => pf,
+    }
+  }
+
+  /// Given the formula `e` with proof `pf` of `G -> e`, which makes the literal `l` false,
+  /// prove `G -> ~l`.
+  fn falsify(&mut self, l: i64, e: usize, pf: LispVal) -> LispVal {
+    let nl = { let l = self.lit(l); self.not(l) };
+    if e == nl { return pf }
+    // Otherwise `l = -v` and `e = v`
+    let gc = self.gc();
+    let (ge, lem, gnl) = (self.im(gc, e), self.im(e, nl), self.im(gc, nl));
+    let pf2 = self.lemma(Lemma::NotNot, lem, vec![]);
+    self.lemma(Lemma::Syl, gnl, vec![(ge, pf), (lem, pf2)])
+  }
+
+  /// Prove `G -> ~l`, where `l` is false in the current assignment.
+  fn neg(&mut self, l: i64) -> LispVal {
+    let (_, e, src) = self.assign[&l.unsigned_abs()].clone();
+    let pf = self.source(src);
+    self.falsify(l, e, pf)
+  }
+
+  /// Prove `G -> ~(l1 \/ ... \/ ln)`, where all the literals are false.
+  fn neg_clause(&mut self, ls: &[i64]) -> LispVal {
+    match *ls {
+      [] => unreachable!(),
+      [l] => self.neg(l),
+      [l, ref rest @ ..] => {
+        let (pf1, pf2) = (self.neg(l), self.neg_clause(rest));
+        let gc = self.gc();
+        let (a, b) = (self.lit(l), self.clause(rest));
+        let (na, nb, ab) = (self.not(a), self.not(b), self.or(a, b));
+        let nab = self.not(ab);
+        let (h1, h2, concl) = (self.im(gc, na), self.im(gc, nb), self.im(gc, nab));
+        self.lemma(Lemma::Nor, concl, vec![(h1, pf1), (h2, pf2)])
+      }
+    }
+  }
+
+  /// Given proofs `pf1: G -> b` and `pf2: G -> ~b`, prove `G -> F`.
+  fn absurd(&mut self, b: usize, pf1: LispVal, pf2: LispVal) -> LispVal {
+    let gc = self.gc();
+    let nb = self.not(b);
+    let (h1, h2, concl) = (self.im(gc, b), self.im(gc, nb), self.im(gc, self.fal));
+    self.lemma(Lemma::Absurd, concl, vec![(h1, pf1), (h2, pf2)])
+  }
+
+  /// Assign the variable of literal `l` to make `l` true, where `e` is the formula which
+  /// sets it. Returns a proof of `G -> F` if this contradicts the current assignment.
+  fn set(&mut self, l: i64, e: usize, src: Src) -> Option<LispVal> {
+    let v = l.unsigned_abs();
+    match self.assign.get(&v).cloned() {
+      None => { self.assign.insert(v, (l > 0, e, src)); None }
+      Some((val, _, _)) if val == (l > 0) => None,
+      Some((_, e2, src2)) => {
+        // The old entry makes `l` false and the new one makes `-l` false
+        let (pf, pf2) = (self.source(src2), self.source(src));
+        let (pf, pf2) = (self.falsify(l, e2, pf), self.falsify(-l, e, pf2));
+        let nv = { let v = self.lit(l.abs()); self.not(v) };
+        // One of `pf, pf2` proves `G -> ~v`, and the other `G -> ~~v`
+        Some(if l > 0 { self.absurd(nv, pf, pf2) } else { self.absurd(nv, pf2, pf) })
+      }
+    }
+  }
+
+  /// Process the hint clause `id` in the current context. Returns a proof of `G -> F`
+  /// if the clause is falsified, and otherwise adds the unit literal it implies.
+  #[allow(clippy::many_single_char_names)]
+  fn hint(&mut self, line: usize, id: u64) -> SResult<Option<LispVal>> {
+    let (ls, pf) = self.clauses.get(&id)
+      .ok_or_else(|| format!("LRAT line {}: unknown clause {}", line, id))?.clone();
+    let mut unit = None;
+    for (j, &l) in ls.iter().enumerate() {
+      match self.assign.get(&l.unsigned_abs()) {
+        Some(&(val, _, _)) if val == (l > 0) =>
+          return Err(format!("LRAT line {}: hint clause {} is satisfied", line, id)),
+        Some(_) => {}
+        None if unit.is_none() => unit = Some(j),
+        None => return Err(format!("LRAT line {}: hint clause {} is not unit", line, id)),
+      }
+    }
+    let gc = self.gc();
+    let d = self.clause(&ls);
+    let gd = self.im(gc, d);
+    let mut pf = self.lemma(Lemma::A1i, gd, vec![(d, pf)]);
+    let j = match unit {
+      None if ls.is_empty() => return Ok(Some(pf)),
+      None => {
+        let pf2 = self.neg_clause(&ls);
+        return Ok(Some(self.absurd(d, pf, pf2)))
+      }
+      Some(j) => j,
+    };
+    for i in 0..j {
+      let pf2 = self.neg(ls[i]);
+      let (a, c) = (self.lit(ls[i]), self.clause(&ls[i+1..]));
+      let (ac, na) = (self.or(a, c), self.not(a));
+      let gbc = self.im(gc, ac);
+      let (gna, gc2) = (self.im(gc, na), self.im(gc, c));
+      pf = self.lemma(Lemma::OrElimL, gc2, vec![(gbc, pf), (gna, pf2)]);
+    }
+    let l = ls[j];
+    let b = self.lit(l);
+    if j + 1 < ls.len() {
+      let pf2 = self.neg_clause(&ls[j+1..]);
+      let c = self.clause(&ls[j+1..]);
+      let (bc, nc) = (self.or(b, c), self.not(c));
+      let (gbc, gnc, gb) = (self.im(gc, bc), self.im(gc, nc), self.im(gc, b));
+      pf = self.lemma(Lemma::OrElimR, gb, vec![(gbc, pf), (gnc, pf2)]);
+    }
+    Ok(self.set(l, b, Src::Unit(pf)))
+  }
+
+  /// Prove the clause `ls` by reverse unit propagation using the clauses `hints`.
+  fn step(&mut self, line: usize, ls: &[i64], hints: &[u64]) -> SResult<LispVal> {
+    self.ctx.clear();
+    self.ctx.push(self.top.0);
+    self.lits.clear();
+    self.proj.clear();
+    self.assign.clear();
+    for &l in ls {
+      let nl = { let l = self.lit(l); self.not(l) };
+      let gc = self.gc();
+      let g2 = self.an(gc, nl);
+      self.ctx.push(g2);
+      self.lits.push(nl);
+    }
+    let mut contra = None;
+    for (i, &l) in ls.iter().enumerate() {
+      if let Some(pf) = self.set(-l, self.lits[i], Src::Ctx(i)) { contra = Some(pf); break }
+    }
+    for &id in hints {
+      if contra.is_some() { break }
+      contra = self.hint(line, id)?;
+    }
+    let mut pf = contra.ok_or_else(|| format!("LRAT line {}: the hints do not refute the clause", line))?;
+    for i in (0..ls.len()).rev() {
+      let (g, g2) = (self.ctx[i], self.ctx[i + 1]);
+      let (r, c) = if i + 1 == ls.len() { (self.fal, self.lit(ls[i])) }
+        else { (self.clause(&ls[i+1..]), self.clause(&ls[i..])) };
+      let (h, concl) = (self.im(g2, r), self.im(g, c));
+      let lem = if i + 1 == ls.len() { Lemma::Contra } else { Lemma::OrIntro };
+      pf = self.lemma(lem, concl, vec![(h, pf)]);
+    }
+    let (top, c) = (self.top.clone(), self.clause(ls));
+    let tc = self.im(top.0, c);
+    Ok(self.lemma(Lemma::Mp, c, vec![top, (tc, pf)]))
+  }
+
+  /// Check the LRAT proof `src`, returning the proof of the empty clause.
+  #[allow(clippy::cast_possible_truncation)]
+  fn run(&mut self, src: &str) -> SResult<LispVal> {
+    let parse = |n: usize, tk: &str| tk.parse::<i64>()
+      .map_err(|_| format!("LRAT line {}: bad number '{}'", n, tk));
+    for (n, line) in src.lines().enumerate() {
+      let n = n + 1;
+      let mut it = line.split_whitespace();
+      let id = match it.next() {
+        None => continue,
+        Some(tk) if tk.starts_with('c') => continue,
+        Some(tk) => parse(n, tk)?,
+      };
+      let mut it = it.peekable();
+      if it.peek() == Some(&"d") {
+        it.next();
+        for tk in it {
+          match parse(n, tk)? {
+            0 => break,
+            id => { self.clauses.remove(&id.unsigned_abs()); }
+          }
+        }
+        continue
+      }
+      let mut ls = vec![];
+      loop {
+        match parse(n, it.next().ok_or_else(|| format!("LRAT line {}: unterminated clause", n))?)? {
+          0 => break,
+          l if l.unsigned_abs() as usize > self.vars.len() =>
+            return Err(format!("LRAT line {}: variable {} out of range", n, l.abs())),
+          l if ls.contains(&l) => return Err(format!("LRAT line {}: repeated literal {}", n, l)),
+          l => ls.push(l),
+        }
+      }
+      let mut hints = vec![];
+      loop {
+        match parse(n, it.next().ok_or_else(|| format!("LRAT line {}: unterminated hints", n))?)? {
+          0 => break,
+          h if h < 0 => return Err(format!("LRAT line {}: RAT steps are not supported", n)),
+          h => hints.push(h.unsigned_abs()),
+        }
+      }
+      let pf = self.step(n, &ls, &hints)?;
+      if ls.is_empty() { return Ok(pf) }
+      self.clauses.insert(id.unsigned_abs(), (ls.into(), pf));
+    }
+    Err("the LRAT proof does not derive the empty clause".into())
+  }
+}
+
+impl Elaborator {
+  /// Implementation of `(register-lrat cfg)`.
+  pub(crate) fn register_lrat(&mut self, span: FileSpan, cfg: &LispVal) -> SResult<()> {
+    let mut map = HashMap::new();
+    for entry in Uncons::from(cfg.clone()) {
+      let mut it = Uncons::from(entry.clone());
+      let (key, val) = match (it.next().and_then(|e| e.as_atom()), it.next(), it.as_lisp().as_atom()) {
+        (Some(key), Some(val), _) if it.exactly(0) => (key, val),
+        (Some(key), None, Some(val)) => (key, LispVal::atom(val)),
+        _ => return Err(format!("expected a pair (key value), got {}", self.print(&entry))),
+      };
+      let val = val.as_atom().ok_or_else(|| format!("expected an atom, got {}", self.print(&val)))?;
+      map.insert(self.data[key].name.clone(), val);
+    }
+    let mut get_term = |name: &str, nargs: usize| -> SResult<TermID> {
+      let at = map.remove(name.as_bytes()).ok_or_else(|| format!("missing term '{}'", name))?;
+      let t = self.term(at).ok_or_else(|| format!("unknown term '{}'", self.data[at].name))?;
+      if self.terms[t].args.len() != nargs {
+        return Err(format!("'{}' should have {} arguments", self.data[at].name, nargs))
+      }
+      Ok(t)
+    };
+    let (im, not, an) = (get_term("im", 2)?, get_term("not", 1)?, get_term("an", 2)?);
+    let (or, fal) = (get_term("or", 2)?, get_term("false", 0)?);
+    let mut tbl = ExprTable::new(self.format_env());
+    let (fa, fb, fc) = (tbl.fresh(), tbl.fresh(), tbl.fresh());
+    let ff = tbl.app(fal, &[]);
+    let im_ = |tbl: &mut ExprTable<'_>, lhs, rhs| tbl.app(im, &[lhs, rhs]);
+    let not_ = |tbl: &mut ExprTable<'_>, lhs| tbl.app(not, &[lhs]);
+    let mut lemmas = vec![];
+    for &(lem, name, form) in &LEMMAS {
+      let thm = match map.remove(name.as_bytes()) {
+        Some(at) => self.thm(at).ok_or_else(|| format!("unknown theorem '{}'", self.data[at].name))?,
+        None => return Err(format!("missing lemma '{}: {}'", name, form)),
+      };
+      let (na, nb, nc) = (not_(&mut tbl, fa), not_(&mut tbl, fb), not_(&mut tbl, fc));
+      let (ab, bc) = (im_(&mut tbl, fa, fb), tbl.app(or, &[fb, fc]));
+      let anb = tbl.app(an, &[fa, nb]);
+      let (concl, hyps) = match lem {
+        Lemma::Id => (im_(&mut tbl, fa, fa), vec![]),
+        Lemma::Mp => (fb, vec![fa, ab]),
+        Lemma::Syl => (im_(&mut tbl, fa, fc), vec![ab, im_(&mut tbl, fb, fc)]),
+        Lemma::A1i => (ab, vec![fb]),
+        Lemma::AnL | Lemma::AnR => {
+          let hyp = tbl.app(an, &[fa, fb]);
+          (im_(&mut tbl, hyp, if lem == Lemma::AnL { fa } else { fb }), vec![])
+        }
+        Lemma::NotNot => { let nna = not_(&mut tbl, na); (im_(&mut tbl, fa, nna), vec![]) }
+        Lemma::OrElimL => (im_(&mut tbl, fa, fc), vec![im_(&mut tbl, fa, bc), im_(&mut tbl, fa, nb)]),
+        Lemma::OrElimR => (ab, vec![im_(&mut tbl, fa, bc), im_(&mut tbl, fa, nc)]),
+        Lemma::Nor => {
+          let nbc = not_(&mut tbl, bc);
+          (im_(&mut tbl, fa, nbc), vec![im_(&mut tbl, fa, nb), im_(&mut tbl, fa, nc)])
+        }
+        Lemma::Absurd => (im_(&mut tbl, fa, ff), vec![ab, im_(&mut tbl, fa, nb)]),
+        Lemma::OrIntro => (im_(&mut tbl, fa, bc), vec![{ let anc = tbl.app(an, &[fa, nb]); im_(&mut tbl, anc, fc) }]),
+        Lemma::Contra => (ab, vec![im_(&mut tbl, anb, ff)]),
+      };
+      tbl.check_thm(thm, concl, &hyps, form)?;
+      lemmas.push(thm);
+    }
+    if let Some(key) = map.keys().next() {
+      return Err(format!("unknown key '{}'", key))
+    }
+    self.lrat = Some(LratEnv {span, im, not, an, or, fal, lemmas: lemmas.into()});
+    Ok(())
+  }
+
+  /// Implementation of `(lrat cnf proof vars hyps)`, which returns a proof of the
+  /// registered false term, given the DIMACS `cnf`, the LRAT `proof`, the formulas `vars`
+  /// for the variables and the proofs `hyps` of the clauses.
+  pub(crate) fn lrat(&self, sp: Span, cnf: &str, proof: &str, vars: &LispVal, hyps: &LispVal) -> SResult<LispVal> {
+    let cfg = self.lrat.as_ref().ok_or("lrat has not been configured, see 'register-lrat'")?;
+    let (nvars, cnf) = parse_cnf(cnf)?;
+    let mut tbl = ExprTable::new(self.format_env());
+    let vars = Uncons::from(vars.clone()).map(|e| tbl.node(&e)).collect::<SResult<Vec<_>>>()?;
+    if vars.len() < nvars {
+      return Err(format!("the CNF has {} variables, but only {} formulas were given", nvars, vars.len()))
+    }
+    let hyps = Uncons::from(hyps.clone()).collect::<Vec<_>>();
+    if hyps.len() != cnf.len() {
+      return Err(format!("the CNF has {} clauses, but {} proofs were given", cnf.len(), hyps.len()))
+    }
+    let fal = tbl.app(cfg.fal, &[]);
+    let top = tbl.app(cfg.im, &[fal, fal]);
+    let top = (top, tbl.apply_verb(cfg.lemmas[Lemma::Id as usize], top, vec![]));
+    let mut st = Lrat {
+      cfg, tbl, vars, fal, top, clauses: HashMap::new(),
+      ctx: vec![], lits: vec![], proj: HashMap::new(), assign: HashMap::new(),
+    };
+    for (i, (ls, pf)) in cnf.into_iter().zip(hyps).enumerate() {
+      let ty = self.infer_type(sp, &pf).map_err(|e| e.kind.msg())?;
+      let (c, ty) = (st.clause(&ls), st.tbl.node(&ty)?);
+      if c != ty {
+        let (c, ty) = (st.tbl.expr(c), st.tbl.expr(ty));
+        return Err(format!("clause {} should be {}, but the proof has type {}",
+          i + 1, self.format_env().to(&c), self.format_env().to(&ty)))
+      }
+      st.clauses.insert(i as u64 + 1, (ls, pf));
+    }
+    let pf = st.run(proof)?;
+    Ok(LispVal::list(vec![LispVal::atom(AtomID::VERB), pf]))
+  }
+}
@@ -97,6 +97,32 @@ impl Elaborator {
   }
 }
 
+/// Parse a [`Formula`] in `source` into a [`QExpr`] using the notations in `pe`.
+///
+/// Unlike [`Elaborator::parse_formula`], this does not need an elaborator, and it is used
+/// for inspecting formulas outside of elaboration (for example by the formatter).
+pub fn parse_math(pe: &ParserEnv, source: &[u8], f: Formula) -> Result<QExpr, ParseError> {
+  let mut spans = Spans::new();
+  let mut p = MathParser {
+    pe,
+    p: Parser {
+      source,
+      errors: vec![],
+      imports: vec![],
+      idx: f.0.start + 1,
+      restart_pos: Some(0), // skip command checks
+    },
+    spans: &mut spans,
+  };
+  p.ws();
+  let expr = p.expr(Prec::Prec(0))?;
+  if let Some(tk) = p.token() {
+    return Err(ParseError::new(tk, "expected '$'".into()))
+  }
+  if let Some(e) = p.p.errors.into_iter().next() { return Err(e) }
+  Ok(expr)
+}
+
 /// The precedence of application, `1024`. This determines whether
 /// `f x + y` is interpreted as `f (x + y)` or `(f x) + y`,
 /// by comparing the precedence of `+` to [`APP_PREC`].
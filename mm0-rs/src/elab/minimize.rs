@@ -0,0 +1,373 @@
+//! Proof minimization, used by the `minimize-proof` builtin and `mm0-rs compile --minimize`.
+//!
+//! A stored [`Proof`] is read back into a [`Dedup`], where it is hash-consed together
+//! with its expressions, and then rewritten bottom-up:
+//!
+//! * All subproofs of the same statement are replaced by the first one. This goes beyond
+//!   the sharing found during elaboration, which only merges structurally equal proofs.
+//! * A conversion `(:conv e c p)` where `p` already proves `e`, possibly under more
+//!   conversions (for example unfolding a definition and then folding it back up),
+//!   is replaced by the proof of `e`.
+//! * A theorem application is replaced by an application of an earlier theorem whose
+//!   hypotheses are proved by subproofs a few steps further down, skipping the steps
+//!   in between. Candidates are found using the [`ThmIndex`](super::disc_tree::ThmIndex).
+//!
+//! None of these steps can make the proof larger, because the replacement of a
+//! subproof only uses statements and subproofs that it already contains.
+
+use std::collections::{HashMap, HashSet};
+use crate::lined_string::LinedString;
+use super::{Elaborator, Environment, lisp::{LispVal, eval::SResult, print::FormatEnv}};
+use super::environment::{DeclKey, ExprNode, Proof, ProofNode, ThmID, ThmKind, Type};
+use super::proof::{Dedup, IDedup, ProofHash, build};
+use super::expr_table::{ExprTable, Node};
+
+/// The maximum depth below a theorem application at which we look for proofs of
+/// the hypotheses of a replacement theorem.
+const SEARCH_DEPTH: usize = 4;
+
+/// The maximum number of subproofs considered as proofs of the hypotheses
+/// of a replacement theorem.
+const SEARCH_WIDTH: usize = 64;
+
+/// The size of a proof, counted as the number of [`ProofNode`]s stored in it
+/// (not counting [`Ref`](ProofNode::Ref) nodes).
+#[must_use] pub fn proof_size(pf: &Proof) -> usize {
+  fn size(p: &ProofNode) -> usize {
+    let all = |ps: &[ProofNode]| ps.iter().map(size).sum::<usize>();
+    match p {
+      ProofNode::Ref(_) => 0,
+      ProofNode::Dummy(_, _) => 1,
+      ProofNode::Term {args, ..} |
+      ProofNode::Cong {args, ..} => 1 + all(args),
+      ProofNode::Hyp(_, e) |
+      ProofNode::Refl(e) |
+      ProofNode::Sym(e) => 1 + size(e),
+      ProofNode::Thm {args, res, ..} => 1 + all(args) + size(res),
+      ProofNode::Conv(p) => 1 + size(&p.0) + size(&p.1) + size(&p.2),
+      ProofNode::Unfold {args, res, ..} => 1 + all(args) + size(&res.0) + size(&res.1) + size(&res.2),
+    }
+  }
+  pf.heap.iter().chain(&*pf.hyps).map(size).sum::<usize>() + size(&pf.head)
+}
+
+/// The indexes that the node `h` refers to.
+fn children(h: &ProofHash) -> Vec<usize> {
+  match *h {
+    ProofHash::Ref(_, _) | ProofHash::Dummy(_, _) => vec![],
+    ProofHash::Term(_, ref ns) => ns.to_vec(),
+    ProofHash::Cong(_, ref ns) => ns.to_vec(),
+    ProofHash::Hyp(_, e) | ProofHash::Refl(e) | ProofHash::Sym(e) => vec![e],
+    ProofHash::Thm(_, ref ns, r) => ns.iter().copied().chain(Some(r)).collect(),
+    ProofHash::Conv(a, b, c) => vec![a, b, c],
+    ProofHash::Unfold(_, ref ns, a, b, c) => ns.iter().copied().chain(vec![a, b, c]).collect(),
+  }
+}
+
+struct Minimizer<'a> {
+  fe: FormatEnv<'a>,
+  /// The theorem being minimized. Only earlier theorems are used as replacements.
+  thm: ThmID,
+  de: Dedup<ProofHash>,
+  tbl: ExprTable<'a>,
+  /// The node of `tbl` for each expression in `de`.
+  to_tbl: HashMap<usize, usize>,
+  /// The expression in `de` for each node of `tbl`.
+  from_tbl: HashMap<usize, usize>,
+  /// The canonical proof of each statement.
+  canon: HashMap<usize, usize>,
+}
+
+impl<'a> Minimizer<'a> {
+  /// Read the proof nodes `ps` into the dedup.
+  fn import_all(&mut self, heap: &[ProofNode], ids: &mut [Option<usize>], ps: &[ProofNode]) -> Box<[usize]> {
+    ps.iter().map(|p| self.import(heap, ids, p)).collect()
+  }
+
+  /// Read the proof node `p` into the dedup, using `ids` to cache heap elements.
+  fn import(&mut self, heap: &[ProofNode], ids: &mut [Option<usize>], p: &ProofNode) -> usize {
+    let h = match *p {
+      ProofNode::Ref(i) => {
+        if let Some(n) = ids[i] { return n }
+        let n = self.import(heap, ids, &heap[i]);
+        ids[i] = Some(n);
+        return n
+      }
+      ProofNode::Dummy(a, s) => ProofHash::Dummy(a, s),
+      ProofNode::Term {term, ref args} => ProofHash::Term(term, self.import_all(heap, ids, args)),
+      ProofNode::Hyp(i, ref e) => ProofHash::Hyp(i, self.import(heap, ids, e)),
+      ProofNode::Thm {thm, ref args, ref res} => {
+        let ns = self.import_all(heap, ids, args);
+        ProofHash::Thm(thm, ns, self.import(heap, ids, res))
+      }
+      ProofNode::Conv(ref p) => ProofHash::Conv(
+        self.import(heap, ids, &p.0), self.import(heap, ids, &p.1), self.import(heap, ids, &p.2)),
+      ProofNode::Refl(ref e) => ProofHash::Refl(self.import(heap, ids, e)),
+      ProofNode::Sym(ref e) => ProofHash::Sym(self.import(heap, ids, e)),
+      ProofNode::Cong {term, ref args} => ProofHash::Cong(term, self.import_all(heap, ids, args).into()),
+      ProofNode::Unfold {term, ref args, ref res} => {
+        let ns = self.import_all(heap, ids, args);
+        ProofHash::Unfold(term, ns,
+          self.import(heap, ids, &res.0), self.import(heap, ids, &res.1), self.import(heap, ids, &res.2))
+      }
+    };
+    self.de.add_direct(h)
+  }
+
+  /// The statement proved by node `i`, if it is a proof.
+  fn stmt(&self, i: usize) -> Option<usize> {
+    match self.de[i] {
+      ProofHash::Hyp(_, e) | ProofHash::Thm(_, _, e) | ProofHash::Conv(e, _, _) => Some(e),
+      _ => None
+    }
+  }
+
+  /// The node of `tbl` corresponding to the expression `i`. Variables of the theorem
+  /// become opaque constants, and dummy variables become local variables.
+  fn tbl_node(&mut self, i: usize) -> usize {
+    if let Some(&n) = self.to_tbl.get(&i) { return n }
+    let n = match self.de[i] {
+      ProofHash::Ref(_, j) => self.tbl.intern(Node::MVar(j)),
+      ProofHash::Dummy(a, _) => self.tbl.intern(Node::Var(a)),
+      ProofHash::Term(t, ref ns) => {
+        let args = ns.clone().iter().map(|&j| self.tbl_node(j)).collect::<Vec<_>>();
+        self.tbl.app(t, &args)
+      }
+      _ => unreachable!("not an expression"),
+    };
+    self.to_tbl.insert(i, n);
+    self.from_tbl.insert(n, i);
+    n
+  }
+
+  /// The proof nodes used directly by the proof `i`.
+  fn subproofs(&self, i: usize) -> Vec<usize> {
+    match self.de[i] {
+      ProofHash::Thm(t, ref ns, _) => ns[self.fe.thms[t].args.len()..].to_vec(),
+      ProofHash::Conv(_, _, p) => vec![p],
+      _ => vec![],
+    }
+  }
+
+  /// Check that `args` is a valid substitution for the variables of `thm`, that is,
+  /// bound variables are mapped to bound variables and the disjointness conditions hold.
+  fn check_dv(&self, thm: ThmID, args: &[usize]) -> bool {
+    let deps = |i: usize| self.de.vec[i].2;
+    let mut bvars: Vec<u64> = vec![];
+    self.fe.thms[thm].args.iter().enumerate().all(|(i, (_, t))| match *t {
+      Type::Bound(_) => {
+        let d = deps(args[i]);
+        bvars.push(d);
+        let is_bound = match self.de[args[i]] {
+          ProofHash::Ref(_, j) => matches!(self.fe.thms[self.thm].args[j].1, Type::Bound(_)),
+          ProofHash::Dummy(_, _) => true,
+          _ => false,
+        };
+        is_bound && args[..i].iter().all(|&j| deps(j) & d == 0)
+      }
+      Type::Reg(_, mut d) => bvars.iter().all(|&bv| {
+        let old = d;
+        d /= 2;
+        old & 1 != 0 || bv & deps(args[i]) == 0
+      }),
+    })
+  }
+
+  /// Try to find an earlier theorem that proves the statement of `i` using proofs of
+  /// statements found below `i`, skipping at least one step.
+  fn replace(&mut self, i: usize, s: usize) -> Option<usize> {
+    // Collect the subproofs a few levels down, in breadth first order.
+    let direct = self.subproofs(i);
+    let mut cands: Vec<usize> = vec![];
+    let mut seen = HashSet::new();
+    let mut level = direct.clone();
+    for _ in 0..SEARCH_DEPTH {
+      let mut next = vec![];
+      for j in level {
+        if cands.len() < SEARCH_WIDTH && seen.insert(j) {
+          cands.push(j);
+          next.extend(self.subproofs(j));
+        }
+      }
+      level = next;
+    }
+    if cands.len() == direct.len() { return None }
+    let stmts = cands.iter().map(|&j| {
+      let e = self.stmt(j).expect("is a proof");
+      self.tbl_node(e)
+    }).collect::<Vec<_>>();
+    let concl = self.tbl_node(s);
+    let mut thms = vec![];
+    self.fe.thm_index.find(&self.tbl, &HashMap::new(), &mut vec![concl], &mut thms);
+    thms.sort();
+    thms.dedup();
+    for thm in thms {
+      if thm >= self.thm { break }
+      let td = &self.fe.thms[thm];
+      if matches!(td.kind, ThmKind::Thm(None)) ||
+        matches!(td.ret, ExprNode::Ref(j) if j < td.args.len()) { continue }
+      let mut subst = vec![None; td.args.len()];
+      if !self.tbl.matches(&td.heap, td.args.len(), &td.ret, concl, &mut subst) { continue }
+      let mut hyps = vec![];
+      if !self.match_hyps(thm, &stmts, &mut subst, &mut hyps) { continue }
+      // Skip the replacement if it only uses the direct subproofs.
+      if hyps.iter().all(|&k| direct.contains(&cands[k])) { continue }
+      let args = subst.iter().map(|&n| n.and_then(|n| self.from_tbl.get(&n).copied()))
+        .collect::<Option<Vec<_>>>();
+      let args = if let Some(args) = args { args } else { continue };
+      if !self.check_dv(thm, &args) { continue }
+      let ns = args.into_iter().chain(hyps.into_iter().map(|k| cands[k])).collect::<Box<[_]>>();
+      return Some(self.de.add_direct(ProofHash::Thm(thm, ns, s)))
+    }
+    None
+  }
+
+  /// Match the hypotheses of `thm` from `k` onwards against the statements `stmts`,
+  /// extending `subst` and recording the index of the statement used for each one.
+  fn match_hyps(&self, thm: ThmID, stmts: &[usize], subst: &mut Vec<Option<usize>>,
+      out: &mut Vec<usize>) -> bool {
+    let td = &self.fe.thms[thm];
+    let h = if let Some((_, h)) = td.hyps.get(out.len()) { h } else { return true };
+    for (j, &e) in stmts.iter().enumerate() {
+      let old = subst.clone();
+      if self.tbl.matches(&td.heap, td.args.len(), h, e, subst) {
+        out.push(j);
+        if self.match_hyps(thm, stmts, subst, out) { return true }
+        out.pop();
+      }
+      *subst = old;
+    }
+    false
+  }
+
+  /// Minimize the proof `i`, whose subproofs have already been minimized and are given
+  /// by `new`, returning the new proof.
+  fn minimize(&mut self, i: usize, new: &[usize]) -> usize {
+    let r = match self.de[i].clone() {
+      ProofHash::Thm(t, ns, res) => {
+        let nargs = self.fe.thms[t].args.len();
+        let ns2 = ns.iter().enumerate()
+          .map(|(k, &j)| if k < nargs {j} else {new[j]}).collect::<Box<[_]>>();
+        if ns2 == ns { i } else { self.de.add_direct(ProofHash::Thm(t, ns2, res)) }
+      }
+      ProofHash::Conv(tgt, c, p) => {
+        let mut q = new[p];
+        loop {
+          if self.stmt(q) == Some(tgt) { break q }
+          if let ProofHash::Conv(_, _, q2) = self.de[q] { q = q2 } else {
+            break if new[p] == p { i } else { self.de.add_direct(ProofHash::Conv(tgt, c, new[p])) }
+          }
+        }
+      }
+      _ => i
+    };
+    let s = self.stmt(r).expect("is a proof");
+    if let Some(&j) = self.canon.get(&s) { return j }
+    let r = if matches!(self.de[r], ProofHash::Thm(..)) {
+      self.replace(r, s).unwrap_or(r)
+    } else { r };
+    self.canon.insert(s, r);
+    r
+  }
+}
+
+/// Minimize the proof of theorem `thm`, returning the new proof if it is smaller.
+#[allow(clippy::many_single_char_names, clippy::similar_names)]
+fn minimize_thm(fe: FormatEnv<'_>, thm: ThmID) -> Option<Proof> {
+  let td = &fe.thms[thm];
+  let pf = if let ThmKind::Thm(Some(pf)) = &td.kind { pf } else { return None };
+  let nargs = td.args.len();
+  let mut m = Minimizer {
+    fe, thm,
+    de: Dedup::new(&td.args),
+    tbl: ExprTable::new(fe),
+    to_tbl: HashMap::new(),
+    from_tbl: HashMap::new(),
+    canon: HashMap::new(),
+  };
+  let mut ids = vec![None; pf.heap.len()];
+  for (i, id) in ids.iter_mut().enumerate().take(nargs) { *id = Some(i) }
+  let hyps = pf.hyps.iter().map(|h| m.import(&pf.heap, &mut ids, h)).collect::<Vec<_>>();
+  let head = m.import(&pf.heap, &mut ids, &pf.head);
+  let n = m.de.vec.len();
+  let mut new = Vec::with_capacity(n);
+  for i in 0..n {
+    let r = if m.stmt(i).is_some() { m.minimize(i, &new) } else { i };
+    new.push(r)
+  }
+  let head = new[head];
+
+  // Collect the reachable nodes and count the references to each one.
+  // The variables always stay at the start of the heap.
+  let mut reach = vec![false; m.de.vec.len()];
+  let mut stack: Vec<usize> = (0..nargs).chain(hyps.iter().copied()).chain(Some(head)).collect();
+  while let Some(i) = stack.pop() {
+    if !std::mem::replace(&mut reach[i], true) { stack.extend(children(&m.de[i])) }
+  }
+  let mut refs = vec![0_usize; reach.len()];
+  for &i in hyps.iter().chain(Some(&head)) { refs[i] += 1 }
+  for i in (0..reach.len()).filter(|&i| reach[i]) {
+    for j in children(&m.de[i]) { refs[j] += 1 }
+  }
+  let mut remap = vec![usize::MAX; refs.len()];
+  let mut nodes = vec![];
+  for (i, &r) in refs.iter().enumerate() {
+    if !reach[i] { continue }
+    remap[i] = nodes.len();
+    let h = match m.de[i].clone() {
+      ProofHash::Term(t, ns) => ProofHash::Term(t, ns.iter().map(|&j| remap[j]).collect()),
+      ProofHash::Cong(t, ns) => ProofHash::Cong(t, ns.iter().map(|&j| remap[j]).collect()),
+      ProofHash::Hyp(k, e) => ProofHash::Hyp(k, remap[e]),
+      ProofHash::Refl(e) => ProofHash::Refl(remap[e]),
+      ProofHash::Sym(e) => ProofHash::Sym(remap[e]),
+      ProofHash::Thm(t, ns, e) => ProofHash::Thm(t, ns.iter().map(|&j| remap[j]).collect(), remap[e]),
+      ProofHash::Conv(a, b, c) => ProofHash::Conv(remap[a], remap[b], remap[c]),
+      ProofHash::Unfold(t, ns, a, b, c) =>
+        ProofHash::Unfold(t, ns.iter().map(|&j| remap[j]).collect(), remap[a], remap[b], remap[c]),
+      h => h,
+    };
+    nodes.push((h, i < nargs || r > 1));
+  }
+  let (mut ids, heap) = build(nodes.iter().map(|(h, b)| (h, *b)));
+  let hyps = hyps.into_iter().map(|i| ids[remap[i]].take()).collect();
+  let head = ids[remap[head]].take();
+  let res = Proof {heap, hyps, head};
+  if proof_size(&res) < proof_size(pf) { Some(res) } else { None }
+}
+
+/// Minimize the proofs of the theorems `thms` in place, and return the old and new
+/// sizes (see [`proof_size`]) of the proofs that got smaller.
+pub fn minimize_proofs(env: &mut Environment, source: &LinedString,
+  thms: impl IntoIterator<Item=ThmID>
+) -> Vec<(ThmID, usize, usize)> {
+  let mut out = vec![];
+  for thm in thms {
+    if let Some(pf) = minimize_thm(FormatEnv {source, env}, thm) {
+      if let ThmKind::Thm(Some(old)) = &mut env.thms[thm].kind {
+        out.push((thm, proof_size(old), proof_size(&pf)));
+        *old = pf;
+      }
+    }
+  }
+  out
+}
+
+impl Elaborator {
+  /// Implementation of `(minimize-proof x ...)`, which minimizes the proofs of the given
+  /// theorems (or all theorems if none are given) and returns a list of `(x old new)`
+  /// for the proofs that got smaller.
+  pub(crate) fn minimize_proof(&mut self, args: &[LispVal]) -> SResult<LispVal> {
+    let thms = if args.is_empty() {
+      self.thms.enum_iter().map(|(t, _)| t).collect::<Vec<_>>()
+    } else {
+      args.iter().map(|e| match e.as_atom().and_then(|a| self.data[a].decl) {
+        Some(DeclKey::Thm(t)) => Ok(t),
+        _ => Err(format!("expected a theorem, got {}", self.print(e))),
+      }).collect::<SResult<_>>()?
+    };
+    let res = minimize_proofs(&mut self.env, &self.ast.source, thms);
+    Ok(LispVal::list(res.into_iter().map(|(t, old, new)| LispVal::list(vec![
+      LispVal::atom(self.thms[t].atom), LispVal::number(old.into()), LispVal::number(new.into())
+    ])).collect::<Vec<_>>()))
+  }
+}
@@ -0,0 +1,985 @@
+//! Certified evaluation of numeric expressions, used by the `norm-num` builtin.
+//!
+//! The numeral encoding is registered by the library using `register-norm-num`. Numerals in
+//! base `B` are built from `B` digit constants `x0, ..., x(B-1)`, a coercion `digit x` from
+//! digits to numbers, and a term `hex a x` denoting `a * B + x`. A numeral is in normal form
+//! if it is either `digit x`, or `hex a x` where `a` is a nonzero numeral in normal form;
+//! every natural number has exactly one normal form.
+//!
+//! Results are computed using arbitrary precision integers, and the proofs are built
+//! digit by digit from the addition, multiplication and comparison tables for single digits
+//! and a fixed set of structural lemmas (see [`LEMMAS`]), which the library proves once.
+//! The result is a proof term in the explicit `(! thm args hyps)` form accepted by `refine`.
+
+use std::collections::HashMap;
+use num::{BigInt, Integer, ToPrimitive, Zero};
+use crate::util::{FileSpan, Span};
+use super::{Elaborator, ElabError, Remap, Remapper};
+use super::environment::{AtomID, TermID, ThmID};
+use super::lisp::{LispVal, Uncons, eval::SResult};
+use super::expr_table::{ExprTable, Node};
+
+/// A structural lemma used by `norm-num`, as an index into [`LEMMAS`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Lemma {
+  SucX, SucXf,
+  AddXx0, AddXx1, AdcXx0, AdcXx1, Add0x0, Add0x1, Adc0x0, Adc0x1, AddX00, AddX01, AdcX00, AdcX01,
+  Mul0l, Mul0r, MulX1x, MulX10, MulX2x, MulX20,
+  EqRefl, EqSymm, EqTr4, SucEq, AddEq, MulEq, HexEq, HexZero,
+  LtX1, LtX2, Lt0x, LtEq, LtLe, LeRefl, LeEq, LtNe, LtNeR, NeEq, SubAdd, SubEq,
+}
+
+/// The lemmas used by `norm-num`. Each entry gives the name of the lemma in the
+/// `register-norm-num` configuration, the optional term which must be registered for the
+/// lemma to be needed, and the hypotheses and conclusion of the lemma.
+///
+/// The statements are written as s-expressions using the registered terms `eq`, `suc`, `add`,
+/// `mul`, `lt`, `le`, `ne`, `sub`, `digit` and `hex`, the digit constants `d0`, `d1` and `dm`
+/// (the largest digit), and the variables `a` through `g`.
+const LEMMAS: [(Lemma, &str, &str, &[&str], &str); 40] = [
+  (Lemma::SucX, "suc-x", "", &["(eq (suc (digit b)) (digit c))"],
+    "(eq (suc (hex a b)) (hex a c))"),
+  (Lemma::SucXf, "suc-xf", "", &["(eq (suc a) b)"], "(eq (suc (hex a dm)) (hex b d0))"),
+  (Lemma::AddXx0, "add-xx0", "", &["(eq (add a c) e)", "(eq (add (digit b) (digit d)) (digit f))"],
+    "(eq (add (hex a b) (hex c d)) (hex e f))"),
+  (Lemma::AddXx1, "add-xx1", "",
+    &["(eq (suc (add a c)) e)", "(eq (add (digit b) (digit d)) (hex (digit d1) f))"],
+    "(eq (add (hex a b) (hex c d)) (hex e f))"),
+  (Lemma::AdcXx0, "adc-xx0", "",
+    &["(eq (add a c) e)", "(eq (suc (add (digit b) (digit d))) (digit f))"],
+    "(eq (suc (add (hex a b) (hex c d))) (hex e f))"),
+  (Lemma::AdcXx1, "adc-xx1", "",
+    &["(eq (suc (add a c)) e)", "(eq (suc (add (digit b) (digit d))) (hex (digit d1) f))"],
+    "(eq (suc (add (hex a b) (hex c d))) (hex e f))"),
+  (Lemma::Add0x0, "add-0x0", "", &["(eq (add (digit a) (digit c)) (digit d))"],
+    "(eq (add (digit a) (hex b c)) (hex b d))"),
+  (Lemma::Add0x1, "add-0x1", "",
+    &["(eq (suc b) d)", "(eq (add (digit a) (digit c)) (hex (digit d1) e))"],
+    "(eq (add (digit a) (hex b c)) (hex d e))"),
+  (Lemma::Adc0x0, "adc-0x0", "", &["(eq (suc (add (digit a) (digit c))) (digit d))"],
+    "(eq (suc (add (digit a) (hex b c))) (hex b d))"),
+  (Lemma::Adc0x1, "adc-0x1", "",
+    &["(eq (suc b) d)", "(eq (suc (add (digit a) (digit c))) (hex (digit d1) e))"],
+    "(eq (suc (add (digit a) (hex b c))) (hex d e))"),
+  (Lemma::AddX00, "add-x00", "", &["(eq (add (digit b) (digit c)) (digit d))"],
+    "(eq (add (hex a b) (digit c)) (hex a d))"),
+  (Lemma::AddX01, "add-x01", "",
+    &["(eq (suc a) d)", "(eq (add (digit b) (digit c)) (hex (digit d1) e))"],
+    "(eq (add (hex a b) (digit c)) (hex d e))"),
+  (Lemma::AdcX00, "adc-x00", "", &["(eq (suc (add (digit b) (digit c))) (digit d))"],
+    "(eq (suc (add (hex a b) (digit c))) (hex a d))"),
+  (Lemma::AdcX01, "adc-x01", "",
+    &["(eq (suc a) d)", "(eq (suc (add (digit b) (digit c))) (hex (digit d1) e))"],
+    "(eq (suc (add (hex a b) (digit c))) (hex d e))"),
+  (Lemma::Mul0l, "mul-0l", "", &[], "(eq (mul (digit d0) a) (digit d0))"),
+  (Lemma::Mul0r, "mul-0r", "", &[], "(eq (mul a (digit d0)) (digit d0))"),
+  (Lemma::MulX1x, "mul-x1x", "",
+    &["(eq (mul a c) d)", "(eq (mul (digit b) c) (hex e f))", "(eq (add d e) g)"],
+    "(eq (mul (hex a b) c) (hex g f))"),
+  (Lemma::MulX10, "mul-x10", "", &["(eq (mul a c) d)", "(eq (mul (digit b) c) (digit e))"],
+    "(eq (mul (hex a b) c) (hex d e))"),
+  (Lemma::MulX2x, "mul-x2x", "",
+    &["(eq (mul a b) d)", "(eq (mul a (digit c)) (hex e f))", "(eq (add d e) g)"],
+    "(eq (mul a (hex b c)) (hex g f))"),
+  (Lemma::MulX20, "mul-x20", "", &["(eq (mul a b) d)", "(eq (mul a (digit c)) (digit e))"],
+    "(eq (mul a (hex b c)) (hex d e))"),
+  (Lemma::EqRefl, "eq-refl", "", &[], "(eq a a)"),
+  (Lemma::EqSymm, "eq-symm", "", &["(eq a b)"], "(eq b a)"),
+  (Lemma::EqTr4, "eq-tr4", "", &["(eq a b)", "(eq c b)"], "(eq a c)"),
+  (Lemma::SucEq, "suc-eq", "", &["(eq a b)", "(eq (suc b) c)"], "(eq (suc a) c)"),
+  (Lemma::AddEq, "add-eq", "", &["(eq a c)", "(eq b d)", "(eq (add c d) e)"], "(eq (add a b) e)"),
+  (Lemma::MulEq, "mul-eq", "", &["(eq a c)", "(eq b d)", "(eq (mul c d) e)"], "(eq (mul a b) e)"),
+  (Lemma::HexEq, "hex-eq", "", &["(eq a c)"], "(eq (hex a b) (hex c b))"),
+  (Lemma::HexZero, "hex-0", "", &[], "(eq (hex (digit d0) a) (digit a))"),
+  (Lemma::LtX1, "lt-x1", "lt", &["(lt a c)"], "(lt (hex a b) (hex c d))"),
+  (Lemma::LtX2, "lt-x2", "lt", &["(lt (digit b) (digit c))"], "(lt (hex a b) (hex a c))"),
+  (Lemma::Lt0x, "lt-0x", "lt", &["(lt (digit d0) b)"], "(lt (digit a) (hex b c))"),
+  (Lemma::LtEq, "lt-eq", "lt", &["(eq a c)", "(eq b d)", "(lt c d)"], "(lt a b)"),
+  (Lemma::LtLe, "lt-le", "le", &["(lt a b)"], "(le a b)"),
+  (Lemma::LeRefl, "le-refl", "le", &[], "(le a a)"),
+  (Lemma::LeEq, "le-eq", "le", &["(eq a c)", "(eq b d)", "(le c d)"], "(le a b)"),
+  (Lemma::LtNe, "lt-ne", "ne", &["(lt a b)"], "(ne a b)"),
+  (Lemma::LtNeR, "lt-ne-r", "ne", &["(lt a b)"], "(ne b a)"),
+  (Lemma::NeEq, "ne-eq", "ne", &["(eq a c)", "(eq b d)", "(ne c d)"], "(ne a b)"),
+  (Lemma::SubAdd, "sub-add", "sub", &["(eq (add c b) a)"], "(eq (sub a b) c)"),
+  (Lemma::SubEq, "sub-eq", "sub", &["(eq a c)", "(eq b d)", "(eq (sub c d) e)"], "(eq (sub a b) e)"),
+];
+
+/// The terms used in the numeral encoding and the arithmetic operations.
+#[derive(Copy, Clone, Debug, DeepSizeOf)]
+pub struct NumTerms {
+  /// The equality relation `a = b` on numbers.
+  pub eq: TermID,
+  /// The successor function `suc a`.
+  pub suc: TermID,
+  /// Addition `a + b`.
+  pub add: TermID,
+  /// Multiplication `a * b`.
+  pub mul: TermID,
+  /// The coercion `digit x` from digits to numbers.
+  pub digit: TermID,
+  /// The numeral `hex a x`, denoting `a * B + x`.
+  pub hex: TermID,
+  /// The strict order `a < b`, if any.
+  pub lt: Option<TermID>,
+  /// The order `a <= b`, if any.
+  pub le: Option<TermID>,
+  /// The inequality `a != b`, if any.
+  pub ne: Option<TermID>,
+  /// Truncated subtraction `a - b`, if any.
+  pub sub: Option<TermID>,
+}
+
+impl Remap for NumTerms {
+  type Target = Self;
+  fn remap(&self, r: &mut Remapper) -> Self {
+    NumTerms {
+      eq: self.eq.remap(r),
+      suc: self.suc.remap(r),
+      add: self.add.remap(r),
+      mul: self.mul.remap(r),
+      digit: self.digit.remap(r),
+      hex: self.hex.remap(r),
+      lt: self.lt.remap(r),
+      le: self.le.remap(r),
+      ne: self.ne.remap(r),
+      sub: self.sub.remap(r),
+    }
+  }
+}
+
+impl NumTerms {
+  fn get(&self, name: &str) -> Option<TermID> {
+    match name {
+      "eq" => Some(self.eq),
+      "suc" => Some(self.suc),
+      "add" => Some(self.add),
+      "mul" => Some(self.mul),
+      "digit" => Some(self.digit),
+      "hex" => Some(self.hex),
+      "lt" => self.lt,
+      "le" => self.le,
+      "ne" => self.ne,
+      "sub" => self.sub,
+      _ => None,
+    }
+  }
+}
+
+/// The configuration of the `norm-num` builtin, set by `register-norm-num` and stored in
+/// the [`Environment`](super::Environment) so that it is available to importing files.
+#[derive(Clone, Debug, DeepSizeOf)]
+pub struct NormNumEnv {
+  /// The location of the registration.
+  pub span: FileSpan,
+  /// The registered terms.
+  pub terms: NumTerms,
+  /// The digit constants, in order. The base is the number of digits.
+  pub digits: Box<[TermID]>,
+  /// The theorems `suc (digit x) = x + 1`, for each digit `x`.
+  pub suc_table: Box<[ThmID]>,
+  /// The theorems `digit x + digit y = x + y`, indexed by `x * B + y`.
+  pub add_table: Box<[ThmID]>,
+  /// The theorems `suc (digit x + digit y) = x + y + 1`, indexed by `x * B + y`.
+  pub adc_table: Box<[ThmID]>,
+  /// The theorems `digit x * digit y = x * y`, indexed by `x * B + y`.
+  pub mul_table: Box<[ThmID]>,
+  /// The theorems `digit x < digit y` for `x < y`, indexed by `x * B + y`.
+  pub lt_table: Box<[Option<ThmID>]>,
+  /// The structural lemmas, indexed by [`Lemma`].
+  pub lemmas: Box<[Option<ThmID>]>,
+  /// The argument and hypothesis order of each structural lemma, relative to its
+  /// statement in `LEMMAS`, computed once at registration.
+  pub orders: Box<[Option<LemmaOrder>]>,
+  /// The nullary terms `t` with theorems `t = n` where `n` is a numeral in normal form.
+  pub literals: Vec<(TermID, ThmID)>,
+}
+
+impl Remap for NormNumEnv {
+  type Target = Self;
+  fn remap(&self, r: &mut Remapper) -> Self {
+    NormNumEnv {
+      span: self.span.clone(),
+      terms: self.terms.remap(r),
+      digits: self.digits.remap(r),
+      suc_table: self.suc_table.remap(r),
+      add_table: self.add_table.remap(r),
+      adc_table: self.adc_table.remap(r),
+      mul_table: self.mul_table.remap(r),
+      lt_table: self.lt_table.remap(r),
+      lemmas: self.lemmas.remap(r),
+      orders: self.orders.clone(),
+      literals: self.literals.remap(r),
+    }
+  }
+}
+
+impl NormNumEnv {
+  /// Merge the configuration of an imported environment into this one.
+  pub fn merge(this: &mut Option<Self>, other: &Option<Self>, r: &mut Remapper,
+      errors: &mut Vec<ElabError>, sp: Span) {
+    if let Some(other) = other {
+      let other = other.remap(r);
+      match this {
+        None => *this = Some(other),
+        Some(old) => if old.digits != other.digits || old.lemmas != other.lemmas {
+          errors.push(ElabError::with_info(sp, "norm-num configuration registered twice".into(),
+            vec![(other.span, "registered here".into()), (old.span.clone(), "registered here".into())]))
+        } else {
+          for lit in other.literals {
+            if !old.literals.iter().any(|l| l.0 == lit.0) { old.literals.push(lit) }
+          }
+        }
+      }
+    }
+  }
+
+  fn lemma(&self, l: Lemma) -> ThmID { self.lemmas[l as usize].expect("checked") }
+}
+
+/// Instantiate the statement `pat` from [`LEMMAS`], using `digits` for the digit constants
+/// and `vars` for the variables `a`, `b`, ....
+fn inst(tbl: &mut ExprTable<'_>, terms: &NumTerms, digits: &[usize], pat: &str, vars: &[usize]) -> usize {
+  fn rec(tbl: &mut ExprTable<'_>, terms: &NumTerms, digits: &[usize],
+      s: &mut std::str::SplitWhitespace<'_>, vars: &[usize]) -> usize {
+    match s.next().expect("bad pattern") {
+      "(" => {
+        let t = terms.get(s.next().expect("bad pattern")).expect("unregistered term");
+        let mut args = vec![];
+        loop {
+          let mut s2 = s.clone();
+          if s2.next() == Some(")") { *s = s2; break }
+          args.push(rec(tbl, terms, digits, s, vars))
+        }
+        tbl.app(t, &args)
+      }
+      "d0" => digits[0],
+      "d1" => digits[1],
+      "dm" => digits[digits.len() - 1],
+      v => vars[usize::from(v.as_bytes()[0] - b'a')],
+    }
+  }
+  let pat = pat.replace('(', " ( ").replace(')', " ) ");
+  rec(tbl, terms, digits, &mut pat.split_whitespace(), vars)
+}
+
+/// The decomposition of a numeral in normal form.
+#[derive(Copy, Clone)]
+enum Num {
+  /// `digit x`, with the value of `x`.
+  Digit(usize),
+  /// `hex a x`, with the node `a` and the value of `x`.
+  Hex(usize, usize),
+}
+
+/// The state of a run of the `norm-num` procedure.
+struct NormNum<'a> {
+  cfg: &'a NormNumEnv,
+  tbl: ExprTable<'a>,
+  /// The nodes of the digit constants.
+  digits: Vec<usize>,
+  /// The value of each digit constant node.
+  digit_val: HashMap<usize, usize>,
+  /// The node `digit 0`.
+  zero: usize,
+  /// The registered literals.
+  literals: HashMap<TermID, ThmID>,
+  /// The proofs of the digit table entries and literals that have been used, so that
+  /// each one is built once and shared.
+  facts: HashMap<ThmID, LispVal>,
+  /// The results of the arithmetic operations that have been proved, so that repeated
+  /// subproblems (common when multiplying large numerals) share their proofs.
+  memo: HashMap<(Op, usize, usize), (usize, LispVal)>,
+}
+
+/// For a lemma, the variable of the statement in [`LEMMAS`] for each argument of the
+/// theorem, and the hypothesis of the statement for each hypothesis of the theorem.
+pub type LemmaOrder = (Box<[usize]>, Box<[usize]>);
+
+/// An arithmetic operation, as a key of [`NormNum::memo`].
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum Op { Suc, Add(bool), Mul }
+
+// The variable names follow the statements in `LEMMAS`.
+#[allow(clippy::many_single_char_names)]
+impl NormNum<'_> {
+  fn base(&self) -> usize { self.digits.len() }
+
+  /// The node `digit x`.
+  fn digit(&mut self, x: usize) -> usize { self.tbl.app(self.cfg.terms.digit, &[self.digits[x]]) }
+
+  /// Decompose a numeral in normal form. This only checks the outermost constructor,
+  /// so `e` should be known to be in normal form, see [`is_normal`](Self::is_normal).
+  fn num(&self, e: usize) -> Option<Num> {
+    let terms = &self.cfg.terms;
+    match self.tbl.nodes[e] {
+      Node::App(t, ref args) => match **args {
+        [x] if t == terms.digit => Some(Num::Digit(*self.digit_val.get(&x)?)),
+        [a, x] if t == terms.hex && a != self.zero => Some(Num::Hex(a, *self.digit_val.get(&x)?)),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  /// Returns true if `e` is a numeral in normal form.
+  fn is_normal(&self, mut e: usize) -> bool {
+    loop {
+      match self.num(e) {
+        None => return false,
+        Some(Num::Digit(_)) => return true,
+        Some(Num::Hex(a, _)) => e = a,
+      }
+    }
+  }
+
+  /// Decompose a numeral in normal form, or fail with an error.
+  fn nf(&mut self, e: usize) -> SResult<Num> {
+    self.num(e).ok_or_else(|| format!("not a numeral in normal form: {}", self.print(e)))
+  }
+
+  /// The value of a numeral in normal form, or an error.
+  fn nf_value(&mut self, e: usize) -> SResult<BigInt> {
+    self.value(e).ok_or_else(|| format!("not a numeral in normal form: {}", self.print(e)))
+  }
+
+  /// Insert the digit coercion where a bare digit is used as a number. Quoted
+  /// expressions like `$ x1 :x x0 $` have not been through the elaborator, so
+  /// the coercions are missing until we put them back.
+  fn coerce(&mut self, e: usize) -> usize {
+    let terms = &self.cfg.terms;
+    match self.tbl.nodes[e] {
+      Node::App(_, ref args) if args.is_empty() && self.digit_val.contains_key(&e) =>
+        self.tbl.app(terms.digit, &[e]),
+      Node::Var(a) => match self.tbl.fe.term(a) {
+        Some(t) if self.cfg.digits.contains(&t) => {
+          let x = self.tbl.app(t, &[]);
+          self.tbl.app(terms.digit, &[x])
+        }
+        _ => e,
+      },
+      Node::App(t, _) if t == terms.digit => e,
+      Node::App(t, ref args) => {
+        let (t, args) = (t, args.clone());
+        let hex = t == terms.hex;
+        let args2 = args.iter().enumerate()
+          .map(|(i, &a)| if hex && i == 1 { self.digit_arg(a) } else { self.coerce(a) })
+          .collect::<Vec<_>>();
+        if *args2 == *args { e } else { self.tbl.app(t, &args2) }
+      }
+      _ => e,
+    }
+  }
+
+  /// A bare digit in argument position, written as an atom in a quotation.
+  fn digit_arg(&mut self, e: usize) -> usize {
+    match self.tbl.nodes[e] {
+      Node::Var(a) => match self.tbl.fe.term(a) {
+        Some(t) if self.cfg.digits.contains(&t) => self.tbl.app(t, &[]),
+        _ => e,
+      },
+      _ => e,
+    }
+  }
+
+  /// The value of a numeral in normal form.
+  fn value(&self, e: usize) -> Option<BigInt> {
+    match self.num(e)? {
+      Num::Digit(x) => Some(x.into()),
+      Num::Hex(a, x) => Some(self.value(a)? * self.base() + x),
+    }
+  }
+
+  /// The normal form numeral with value `n`.
+  fn numeral(&mut self, n: &BigInt) -> usize {
+    let (q, r) = n.div_rem(&BigInt::from(self.base()));
+    let x = r.to_usize().expect("digit");
+    if q.is_zero() { return self.digit(x) }
+    let a = self.numeral(&q);
+    self.tbl.app(self.cfg.terms.hex, &[a, self.digits[x]])
+  }
+
+  /// Compute the argument and hypothesis order of theorem `thm` for lemma `l`
+  /// (see [`NormNumEnv::orders`]), by matching it against the statement with fresh variables.
+  fn lemma_order(&mut self, l: Lemma, thm: ThmID) -> LemmaOrder {
+    let (_, _, _, hs, concl) = LEMMAS[l as usize];
+    let cfg = self.cfg;
+    let vars = (0..7).map(|_| self.tbl.fresh()).collect::<Vec<_>>();
+    let concl = inst(&mut self.tbl, &cfg.terms, &self.digits, concl, &vars);
+    let hs = hs.iter().map(|h| inst(&mut self.tbl, &cfg.terms, &self.digits, h, &vars)).collect::<Vec<_>>();
+    let (subst, order) = self.tbl.unify_thm(thm, concl, &hs).expect("checked");
+    let args = subst.into_iter().map(|i| vars.iter().position(|&v| v == i).expect("checked"));
+    (args.collect(), order.into())
+  }
+
+  /// Apply lemma `l` with the given variable assignment and hypothesis proofs.
+  /// The statement is not instantiated, since the lemma was checked against it in
+  /// `register-norm-num`.
+  fn lemma(&mut self, l: Lemma, vars: &[usize], hyps: Vec<LispVal>) -> LispVal {
+    let Self {cfg, tbl, ..} = self;
+    let (args, order) = cfg.orders[l as usize].as_ref().expect("checked");
+    let mut out = Vec::with_capacity(1 + args.len() + order.len());
+    out.push(LispVal::atom(tbl.fe.env.thms[cfg.lemma(l)].atom));
+    for &i in &**args { out.push(tbl.expr(vars[i])) }
+    let mut hyps = hyps.into_iter().map(Some).collect::<Vec<_>>();
+    out.extend(order.iter().map(|&i| hyps[i].take().expect("distinct")));
+    LispVal::list(out)
+  }
+
+  /// Apply a theorem with no hypotheses and conclusion `eq lhs rhs`.
+  fn table(&mut self, thm: ThmID, lhs: usize, rhs: usize) -> LispVal {
+    let concl = self.tbl.app(self.cfg.terms.eq, &[lhs, rhs]);
+    self.fact(thm, concl)
+  }
+
+  /// Apply a theorem with no hypotheses and conclusion `concl`, reusing the proof
+  /// if the theorem has been used before.
+  fn fact(&mut self, thm: ThmID, concl: usize) -> LispVal {
+    if let Some(p) = self.facts.get(&thm) { return p.clone() }
+    let p = self.tbl.apply_verb(thm, concl, vec![]);
+    self.facts.insert(thm, p.clone());
+    p
+  }
+
+  /// The proof of `e = e2` given an optional proof, where `None` means `e` and `e2` are equal.
+  fn or_refl(&mut self, e: usize, p: Option<LispVal>) -> LispVal {
+    p.unwrap_or_else(|| self.lemma(Lemma::EqRefl, &[e], vec![]))
+  }
+
+  /// Prove `digit x + digit y = n` (or `suc (digit x + digit y) = n` if `carry` is true),
+  /// returning `n` and the proof.
+  fn add_digits(&mut self, x: usize, y: usize, carry: bool) -> (usize, LispVal) {
+    let (dx, dy) = (self.digit(x), self.digit(y));
+    let rhs = self.numeral(&BigInt::from(x + y + usize::from(carry)));
+    let mut lhs = self.tbl.app(self.cfg.terms.add, &[dx, dy]);
+    let thm = if carry {
+      lhs = self.tbl.app(self.cfg.terms.suc, &[lhs]);
+      self.cfg.adc_table[x * self.base() + y]
+    } else {
+      self.cfg.add_table[x * self.base() + y]
+    };
+    (rhs, self.table(thm, lhs, rhs))
+  }
+
+  /// Look up the result of `op` on `a` and `b` in the [`memo`](Self::memo) table,
+  /// or compute it using `f`.
+  fn memo(&mut self, op: Op, a: usize, b: usize,
+      f: impl FnOnce(&mut Self) -> SResult<(usize, LispVal)>) -> SResult<(usize, LispVal)> {
+    if let Some(res) = self.memo.get(&(op, a, b)) { return Ok(res.clone()) }
+    let res = f(self)?;
+    self.memo.insert((op, a, b), res.clone());
+    Ok(res)
+  }
+
+  /// Given a numeral `a` in normal form, prove `suc a = b`, returning `b` and the proof.
+  fn suc(&mut self, a: usize) -> SResult<(usize, LispVal)> {
+    self.memo(Op::Suc, a, a, |this| this.suc_core(a))
+  }
+
+  fn suc_core(&mut self, a: usize) -> SResult<(usize, LispVal)> {
+    Ok(match self.nf(a)? {
+      Num::Digit(x) => {
+        let rhs = self.numeral(&BigInt::from(x + 1));
+        let lhs = self.tbl.app(self.cfg.terms.suc, &[a]);
+        (rhs, self.table(self.cfg.suc_table[x], lhs, rhs))
+      }
+      Num::Hex(a1, x) if x + 1 < self.base() => {
+        let (dx, dx1) = (self.digit(x), self.digit(x + 1));
+        let lhs = self.tbl.app(self.cfg.terms.suc, &[dx]);
+        let p = self.table(self.cfg.suc_table[x], lhs, dx1);
+        let (bx, bx1) = (self.digits[x], self.digits[x + 1]);
+        (self.tbl.app(self.cfg.terms.hex, &[a1, bx1]), self.lemma(Lemma::SucX, &[a1, bx, bx1], vec![p]))
+      }
+      Num::Hex(a1, _) => {
+        let (b, p) = self.suc(a1)?;
+        (self.tbl.app(self.cfg.terms.hex, &[b, self.digits[0]]), self.lemma(Lemma::SucXf, &[a1, b], vec![p]))
+      }
+    })
+  }
+
+  /// Given numerals `a` and `b` in normal form, prove `a + b = c` (or `suc (a + b) = c`
+  /// if `carry` is true), returning `c` and the proof.
+  fn add(&mut self, a: usize, b: usize, carry: bool) -> SResult<(usize, LispVal)> {
+    self.memo(Op::Add(carry), a, b, |this| this.add_core(a, b, carry))
+  }
+
+  fn add_core(&mut self, a: usize, b: usize, carry: bool) -> SResult<(usize, LispVal)> {
+    let hex = self.cfg.terms.hex;
+    Ok(match (self.nf(a)?, self.nf(b)?) {
+      (Num::Digit(x), Num::Digit(y)) => self.add_digits(x, y, carry),
+      (Num::Hex(a1, x), Num::Hex(b1, y)) => {
+        let (n, pt) = self.add_digits(x, y, carry);
+        let (bx, by) = (self.digits[x], self.digits[y]);
+        match self.nf(n)? {
+          Num::Digit(z) => {
+            let (c, p1) = self.add(a1, b1, false)?;
+            let bz = self.digits[z];
+            let l = if carry { Lemma::AdcXx0 } else { Lemma::AddXx0 };
+            (self.tbl.app(hex, &[c, bz]), self.lemma(l, &[a1, bx, b1, by, c, bz], vec![p1, pt]))
+          }
+          Num::Hex(_, z) => {
+            let (c, p1) = self.add(a1, b1, true)?;
+            let bz = self.digits[z];
+            let l = if carry { Lemma::AdcXx1 } else { Lemma::AddXx1 };
+            (self.tbl.app(hex, &[c, bz]), self.lemma(l, &[a1, bx, b1, by, c, bz], vec![p1, pt]))
+          }
+        }
+      }
+      (Num::Digit(x), Num::Hex(b1, y)) => {
+        let (n, pt) = self.add_digits(x, y, carry);
+        let (bx, by) = (self.digits[x], self.digits[y]);
+        match self.nf(n)? {
+          Num::Digit(z) => {
+            let bz = self.digits[z];
+            let l = if carry { Lemma::Adc0x0 } else { Lemma::Add0x0 };
+            (self.tbl.app(hex, &[b1, bz]), self.lemma(l, &[bx, b1, by, bz], vec![pt]))
+          }
+          Num::Hex(_, z) => {
+            let (c, p1) = self.suc(b1)?;
+            let bz = self.digits[z];
+            let l = if carry { Lemma::Adc0x1 } else { Lemma::Add0x1 };
+            (self.tbl.app(hex, &[c, bz]), self.lemma(l, &[bx, b1, by, c, bz], vec![p1, pt]))
+          }
+        }
+      }
+      (Num::Hex(a1, x), Num::Digit(y)) => {
+        let (n, pt) = self.add_digits(x, y, carry);
+        let (bx, by) = (self.digits[x], self.digits[y]);
+        match self.nf(n)? {
+          Num::Digit(z) => {
+            let bz = self.digits[z];
+            let l = if carry { Lemma::AdcX00 } else { Lemma::AddX00 };
+            (self.tbl.app(hex, &[a1, bz]), self.lemma(l, &[a1, bx, by, bz], vec![pt]))
+          }
+          Num::Hex(_, z) => {
+            let (c, p1) = self.suc(a1)?;
+            let bz = self.digits[z];
+            let l = if carry { Lemma::AdcX01 } else { Lemma::AddX01 };
+            (self.tbl.app(hex, &[c, bz]), self.lemma(l, &[a1, bx, by, c, bz], vec![p1, pt]))
+          }
+        }
+      }
+     })
+  }
+
+  /// Given numerals `a` and `b` in normal form, prove `a * b = c`, returning `c` and the proof.
+  fn mul(&mut self, a: usize, b: usize) -> SResult<(usize, LispVal)> {
+    self.memo(Op::Mul, a, b, |this| this.mul_core(a, b))
+  }
+
+  fn mul_core(&mut self, a: usize, b: usize) -> SResult<(usize, LispVal)> {
+    let zero = self.zero;
+    if a == zero { return Ok((zero, self.lemma(Lemma::Mul0l, &[b], vec![]))) }
+    if b == zero { return Ok((zero, self.lemma(Lemma::Mul0r, &[a], vec![]))) }
+    let hex = self.cfg.terms.hex;
+    Ok(match (self.nf(a)?, self.nf(b)?) {
+      (Num::Digit(x), Num::Digit(y)) => {
+        let rhs = self.numeral(&BigInt::from(x * y));
+        let lhs = self.tbl.app(self.cfg.terms.mul, &[a, b]);
+        (rhs, self.table(self.cfg.mul_table[x * self.base() + y], lhs, rhs))
+      }
+      (_, Num::Hex(b1, y)) => {
+        let (d, p1) = self.mul(a, b1)?;
+        let dy = self.digit(y);
+        let (n, p2) = self.mul(a, dy)?;
+        let by = self.digits[y];
+        match self.nf(n)? {
+          Num::Hex(e, z) => {
+            let (g, p3) = self.add(d, e, false)?;
+            let bz = self.digits[z];
+            (self.tbl.app(hex, &[g, bz]), self.lemma(Lemma::MulX2x, &[a, b1, by, d, e, bz, g], vec![p1, p2, p3]))
+          }
+          Num::Digit(z) => {
+            let bz = self.digits[z];
+            (self.tbl.app(hex, &[d, bz]), self.lemma(Lemma::MulX20, &[a, b1, by, d, bz], vec![p1, p2]))
+          }
+        }
+      }
+      (Num::Hex(a1, x), Num::Digit(_)) => {
+        let (d, p1) = self.mul(a1, b)?;
+        let dx = self.digit(x);
+        let (n, p2) = self.mul(dx, b)?;
+        let bx = self.digits[x];
+        match self.nf(n)? {
+          Num::Hex(e, z) => {
+            let (g, p3) = self.add(d, e, false)?;
+            let bz = self.digits[z];
+            (self.tbl.app(hex, &[g, bz]), self.lemma(Lemma::MulX1x, &[a1, bx, b, d, e, bz, g], vec![p1, p2, p3]))
+          }
+          Num::Digit(z) => {
+            let bz = self.digits[z];
+            (self.tbl.app(hex, &[d, bz]), self.lemma(Lemma::MulX10, &[a1, bx, b, d, bz], vec![p1, p2]))
+          }
+        }
+      }
+    })
+  }
+
+  /// Given numerals `a < b` in normal form, prove `a < b`.
+  fn lt(&mut self, a: usize, b: usize) -> SResult<LispVal> {
+    Ok(match (self.nf(a)?, self.nf(b)?) {
+      (Num::Digit(x), Num::Digit(y)) => {
+        let concl = self.tbl.app(self.cfg.terms.lt.expect("checked"), &[a, b]);
+        let thm = self.cfg.lt_table[x * self.base() + y].expect("checked");
+        self.fact(thm, concl)
+      }
+      (Num::Digit(x), Num::Hex(b1, y)) => {
+        let p = self.lt(self.zero, b1)?;
+        let (bx, by) = (self.digits[x], self.digits[y]);
+        self.lemma(Lemma::Lt0x, &[bx, b1, by], vec![p])
+      }
+      (Num::Hex(a1, x), Num::Hex(b1, y)) => {
+        let (bx, by) = (self.digits[x], self.digits[y]);
+        if a1 == b1 {
+          let (dx, dy) = (self.digit(x), self.digit(y));
+          let p = self.lt(dx, dy)?;
+          self.lemma(Lemma::LtX2, &[a1, bx, by], vec![p])
+        } else {
+          let p = self.lt(a1, b1)?;
+          self.lemma(Lemma::LtX1, &[a1, bx, b1, by], vec![p])
+        }
+      }
+      (Num::Hex(..), Num::Digit(_)) =>
+        return Err(format!("statement is false: {} < {}", self.print(a), self.print(b))),
+    })
+  }
+
+  /// Evaluate a numeric expression `e` to a numeral `n` in normal form, and return `n`
+  /// and a proof of `e = n`, or `None` if `e` is already in normal form.
+  fn eval(&mut self, e: usize) -> SResult<(usize, Option<LispVal>)> {
+    if self.is_normal(e) { return Ok((e, None)) }
+    let terms = &self.cfg.terms;
+    let (t, args) = match &self.tbl.nodes[e] {
+      Node::App(t, args) => (*t, args.clone()),
+      _ => return Err(format!("not a numeral: {}", self.print(e))),
+    };
+    Ok(match *args {
+      [a] if t == terms.suc => {
+        let (a2, pa) = self.eval(a)?;
+        let (c, p) = self.suc(a2)?;
+        (c, Some(match pa { None => p, Some(pa) => self.lemma(Lemma::SucEq, &[a, a2, c], vec![pa, p]) }))
+      }
+      [a, b] if t == terms.add || t == terms.mul || Some(t) == terms.sub => {
+        let ((a2, pa), (b2, pb)) = (self.eval(a)?, self.eval(b)?);
+        let (c, p, l) = if t == terms.add {
+          let (c, p) = self.add(a2, b2, false)?;
+          (c, p, Lemma::AddEq)
+        } else if t == terms.mul {
+          let (c, p) = self.mul(a2, b2)?;
+          (c, p, Lemma::MulEq)
+        } else {
+          let (va, vb) = (self.nf_value(a2)?, self.nf_value(b2)?);
+          if va < vb {
+            return Err(format!("subtraction {} - {} is truncated", va, vb))
+          }
+          let c = self.numeral(&(va - vb));
+          let (_, p) = self.add(c, b2, false)?;
+          (c, self.lemma(Lemma::SubAdd, &[a2, b2, c], vec![p]), Lemma::SubEq)
+        };
+        if pa.is_none() && pb.is_none() { return Ok((c, Some(p))) }
+        let (pa, pb) = (self.or_refl(a, pa), self.or_refl(b, pb));
+        (c, Some(self.lemma(l, &[a, b, a2, b2, c], vec![pa, pb, p])))
+      }
+      [a, x] if t == terms.hex => {
+        if !self.digit_val.contains_key(&x) {
+          return Err(format!("not a numeral: {}", self.print(e)))
+        }
+        let (a2, pa) = self.eval(a)?;
+        if a2 == self.zero {
+          let c = self.tbl.app(terms.digit, &[x]);
+          let p = self.lemma(Lemma::HexZero, &[x], vec![]);
+          let pa = match pa { None => return Ok((c, Some(p))), Some(pa) => pa };
+          let e2 = self.tbl.app(terms.hex, &[a2, x]);
+          let p1 = self.lemma(Lemma::HexEq, &[a, x, a2], vec![pa]);
+          let p2 = self.lemma(Lemma::EqSymm, &[e2, c], vec![p]);
+          (c, Some(self.lemma(Lemma::EqTr4, &[e, e2, c], vec![p1, p2])))
+        } else if let Some(pa) = pa {
+          (self.tbl.app(terms.hex, &[a2, x]), Some(self.lemma(Lemma::HexEq, &[a, x, a2], vec![pa])))
+        } else {
+          // If `a` is already normal then so is `e`.
+          (e, None)
+        }
+      }
+      [] if self.literals.contains_key(&t) => {
+        let thm = self.literals[&t];
+        let td = &self.tbl.fe.env.thms[thm];
+        let concl = self.tbl.subst(&td.heap, 0, &td.ret, &[]);
+        let c = match self.tbl.nodes[concl] {
+          Node::App(_, ref args) if args.len() == 2 => args[1],
+          _ => return Err(format!("literal '{}' is not an equation", self.tbl.fe.data[td.atom].name)),
+        };
+        (c, Some(self.fact(thm, concl)))
+      }
+      _ => return Err(format!("not a numeral: {}", self.print(e))),
+    })
+  }
+
+  /// Prove a statement `a = b`, `a < b`, `a <= b` or `a != b` about numeric expressions.
+  fn prove(&mut self, e: usize) -> SResult<LispVal> {
+    let cfg = self.cfg;
+    let terms = &cfg.terms;
+    let (t, a, b) = match self.tbl.nodes[e] {
+      Node::App(t, ref args) if args.len() == 2 => (t, args[0], args[1]),
+      _ => return Err(format!("not a numeric comparison: {}", self.print(e))),
+    };
+    let ((a2, pa), (b2, pb)) = (self.eval(a)?, self.eval(b)?);
+    let (va, vb) = (self.nf_value(a2)?, self.nf_value(b2)?);
+    let false_stmt = |this: &mut Self| Err(format!("statement is false: {}", this.print(e)));
+    if t == terms.eq {
+      if a2 != b2 { return false_stmt(self) }
+      return Ok(match (pa, pb) {
+        (pa, None) => self.or_refl(a, pa),
+        (None, Some(pb)) => self.lemma(Lemma::EqSymm, &[b, a], vec![pb]),
+        (Some(pa), Some(pb)) => self.lemma(Lemma::EqTr4, &[a, a2, b], vec![pa, pb]),
+      })
+    }
+    let (p, l) = if Some(t) == terms.lt {
+      if va >= vb { return false_stmt(self) }
+      (self.lt(a2, b2)?, Lemma::LtEq)
+    } else if Some(t) == terms.le {
+      if va > vb { return false_stmt(self) }
+      (if a2 == b2 { self.lemma(Lemma::LeRefl, &[a2], vec![]) } else {
+        let p = self.lt(a2, b2)?;
+        self.lemma(Lemma::LtLe, &[a2, b2], vec![p])
+      }, Lemma::LeEq)
+    } else if Some(t) == terms.ne {
+      if va == vb { return false_stmt(self) }
+      (if va < vb {
+        let p = self.lt(a2, b2)?;
+        self.lemma(Lemma::LtNe, &[a2, b2], vec![p])
+      } else {
+        let p = self.lt(b2, a2)?;
+        self.lemma(Lemma::LtNeR, &[b2, a2], vec![p])
+      }, Lemma::NeEq)
+    } else {
+      return Err(format!("not a numeric comparison: {}", self.print(e)))
+    };
+    if pa.is_none() && pb.is_none() { return Ok(p) }
+    let (pa, pb) = (self.or_refl(a, pa), self.or_refl(b, pb));
+    Ok(self.lemma(l, &[a, b, a2, b2], vec![pa, pb, p]))
+  }
+
+  fn print(&mut self, e: usize) -> String {
+    let e = self.tbl.expr(e);
+    format!("{}", self.tbl.fe.to(&e))
+  }
+}
+
+impl Elaborator {
+  fn norm_num_atom(&self, e: &LispVal) -> SResult<AtomID> {
+    e.as_atom().ok_or_else(|| format!("expected an atom, got {}", self.print(e)))
+  }
+
+  fn norm_num_thm(&self, e: &LispVal) -> SResult<ThmID> {
+    let a = self.norm_num_atom(e)?;
+    self.thm(a).ok_or_else(|| format!("unknown theorem '{}'", self.data[a].name))
+  }
+
+  fn norm_num_table(&self, e: &LispVal, base: usize, name: &str) -> SResult<Vec<Option<ThmID>>> {
+    let mut out = vec![];
+    for row in Uncons::from(e.clone()) {
+      let row = Uncons::from(row).collect::<Vec<_>>();
+      if row.len() != base { return Err(format!("'{}' should have {} rows of {} entries", name, base, base)) }
+      for e in row {
+        out.push(if e.as_atom().is_some() { Some(self.norm_num_thm(&e)?) } else { None })
+      }
+    }
+    if out.len() != base * base {
+      return Err(format!("'{}' should have {} rows of {} entries", name, base, base))
+    }
+    Ok(out)
+  }
+
+  /// Implementation of `(register-norm-num cfg)`.
+  pub(crate) fn register_norm_num(&mut self, span: FileSpan, cfg: &LispVal) -> SResult<()> {
+    let mut map = HashMap::new();
+    for entry in Uncons::from(cfg.clone()) {
+      let mut it = Uncons::from(entry.clone());
+      let key = it.next().and_then(|e| e.as_atom())
+        .ok_or_else(|| format!("expected a list (key value ...), got {}", self.print(&entry)))?;
+      map.insert(self.data[key].name.clone(), it.as_lisp());
+    }
+    let mut get = |name: &str| map.remove(name.as_bytes());
+    // A key with a single value can be written `(key value)` or `(key . value)`.
+    let single = |e: LispVal| -> SResult<AtomID> {
+      if let Some(a) = e.as_atom() { return Ok(a) }
+      let mut it = Uncons::from(e.clone());
+      match it.next() {
+        Some(v) if it.exactly(0) => self.norm_num_atom(&v),
+        _ => Err(format!("expected a single value, got {}", self.print(&e))),
+      }
+    };
+    let mut get_term = |name: &str| -> SResult<Option<TermID>> {
+      let a = if let Some(e) = get(name) { single(e)? } else { return Ok(None) };
+      Ok(Some(self.term(a).ok_or_else(|| format!("unknown term '{}'", self.data[a].name))?))
+    };
+    let mut req_term = |name: &str| get_term(name)?.ok_or_else(|| format!("missing term '{}'", name));
+    let terms = NumTerms {
+      eq: req_term("eq")?, suc: req_term("suc")?, add: req_term("add")?, mul: req_term("mul")?,
+      digit: req_term("digit")?, hex: req_term("hex")?,
+      lt: get_term("lt")?, le: get_term("le")?, ne: get_term("ne")?, sub: get_term("sub")?,
+    };
+    if terms.lt.is_none() && (terms.le.is_some() || terms.ne.is_some()) {
+      return Err("'le' and 'ne' require 'lt'".into())
+    }
+    let digits = Uncons::from(get("digits").ok_or("missing key 'digits'")?)
+      .map(|e| {
+        let a = self.norm_num_atom(&e)?;
+        self.term(a).ok_or_else(|| format!("unknown term '{}'", self.data[a].name))
+      }).collect::<SResult<Box<[_]>>>()?;
+    let base = digits.len();
+    if base < 2 { return Err("there should be at least two digits".into()) }
+    let mut tbl = ExprTable::new(self.format_env());
+    let dnodes = digits.iter().map(|&t| tbl.app(t, &[])).collect::<Vec<_>>();
+    let vars = (0..7).map(|_| tbl.fresh()).collect::<Vec<_>>();
+    let cfg = NormNumEnv {
+      span, terms, digits, suc_table: Box::new([]), add_table: Box::new([]),
+      adc_table: Box::new([]), mul_table: Box::new([]), lt_table: Box::new([]),
+      lemmas: Box::new([]), orders: Box::new([]), literals: vec![],
+    };
+    let zero = tbl.app(cfg.terms.digit, &[dnodes[0]]);
+    let mut st = NormNum {
+      cfg: &cfg, tbl, digits: dnodes.clone(), zero, literals: HashMap::new(),
+      facts: HashMap::new(), memo: HashMap::new(),
+      digit_val: dnodes.iter().enumerate().map(|(i, &n)| (n, i)).collect(),
+    };
+    let terms = &cfg.terms;
+    let (mut lemmas, mut orders) = (vec![None; LEMMAS.len()], vec![None; LEMMAS.len()]);
+    for &(l, name, req, hyps, concl) in &LEMMAS {
+      if !req.is_empty() && terms.get(req).is_none() {
+        if get(name).is_some() {
+          return Err(format!("lemma '{}' given but the '{}' term is not", name, req))
+        }
+        continue
+      }
+      let mut form = String::new();
+      for h in hyps { form += h; form += " > " }
+      form += concl;
+      let a = single(get(name).ok_or_else(|| format!("missing lemma '{}: {}'", name, form))?)?;
+      let thm = self.thm(a).ok_or_else(|| format!("unknown theorem '{}'", self.data[a].name))?;
+      let concl = inst(&mut st.tbl, terms, &dnodes, concl, &vars);
+      let hyps = hyps.iter().map(|h| inst(&mut st.tbl, terms, &dnodes, h, &vars)).collect::<Vec<_>>();
+      st.tbl.check_thm(thm, concl, &hyps, &form)?;
+      lemmas[l as usize] = Some(thm);
+      orders[l as usize] = Some(st.lemma_order(l, thm));
+    }
+    let check_table = |st: &mut NormNum<'_>, name: &str, thms: &[Option<ThmID>],
+        f: &dyn Fn(&mut NormNum<'_>, usize, usize) -> Option<usize>| -> SResult<()> {
+      for (i, &thm) in thms.iter().enumerate() {
+        let (x, y) = i.div_rem(&base);
+        if let Some(concl) = f(st, x, y) {
+          let thm = thm.ok_or_else(|| format!("missing entry {} in '{}'", i, name))?;
+          let n = (x, y);
+          st.tbl.check_thm(thm, concl, &[], &format!("entry {:?} of '{}'", n, name))?;
+        }
+      }
+      Ok(())
+    };
+    let suc_table = Uncons::from(get("suc-table").ok_or("missing key 'suc-table'")?)
+      .map(|e| self.norm_num_thm(&e)).collect::<SResult<Vec<_>>>()?;
+    if suc_table.len() != base { return Err(format!("'suc-table' should have {} entries", base)) }
+    let mut tables = vec![];
+    for &name in &["add-table", "adc-table", "mul-table", "lt-table"] {
+      if name == "lt-table" && terms.lt.is_none() { tables.push(vec![]); continue }
+      let e = get(name).ok_or_else(|| format!("missing key '{}'", name))?;
+      tables.push(self.norm_num_table(&e, base, name)?);
+    }
+    let literals = match get("literals") {
+      None => vec![],
+      Some(e) => Uncons::from(e).map(|e| self.norm_num_thm(&e)).collect::<SResult<Vec<_>>>()?,
+    };
+    if let Some(k) = map.keys().next() {
+      return Err(format!("unknown key '{}'", k))
+    }
+    let suc_table2 = suc_table.iter().map(|&t| Some(t)).collect::<Vec<_>>();
+    let (eq, suc, add, mul, lt) = (terms.eq, terms.suc, terms.add, terms.mul, terms.lt);
+    let eq_stmt = |st: &mut NormNum<'_>, lhs, n: usize| {
+      let rhs = st.numeral(&BigInt::from(n));
+      st.tbl.app(eq, &[lhs, rhs])
+    };
+    check_table(&mut st, "suc-table", &suc_table2, &|st, _, x| {
+      let dx = st.digit(x);
+      let lhs = st.tbl.app(suc, &[dx]);
+      Some(eq_stmt(st, lhs, x + 1))
+    })?;
+    check_table(&mut st, "add-table", &tables[0], &|st, x, y| {
+      let (dx, dy) = (st.digit(x), st.digit(y));
+      let lhs = st.tbl.app(add, &[dx, dy]);
+      Some(eq_stmt(st, lhs, x + y))
+    })?;
+    check_table(&mut st, "adc-table", &tables[1], &|st, x, y| {
+      let (dx, dy) = (st.digit(x), st.digit(y));
+      let lhs = st.tbl.app(add, &[dx, dy]);
+      let lhs = st.tbl.app(suc, &[lhs]);
+      Some(eq_stmt(st, lhs, x + y + 1))
+    })?;
+    check_table(&mut st, "mul-table", &tables[2], &|st, x, y| {
+      let (dx, dy) = (st.digit(x), st.digit(y));
+      let lhs = st.tbl.app(mul, &[dx, dy]);
+      Some(eq_stmt(st, lhs, x * y))
+    })?;
+    if let Some(lt) = lt {
+      check_table(&mut st, "lt-table", &tables[3], &|st, x, y| {
+        if x >= y { return None }
+        let (dx, dy) = (st.digit(x), st.digit(y));
+        Some(st.tbl.app(lt, &[dx, dy]))
+      })?;
+    }
+    let mut lits = vec![];
+    for thm in literals {
+      let td = &self.thms[thm];
+      let ok = td.args.is_empty() && td.hyps.is_empty() && match td.ret {
+        super::environment::ExprNode::App(t, ref args) if t == eq => match args[..] {
+          [super::environment::ExprNode::App(c, ref cargs), _] if cargs.is_empty() => {
+            let concl = st.tbl.subst(&td.heap, 0, &td.ret, &[]);
+            let rhs = match st.tbl.nodes[concl] { Node::App(_, ref args) => args[1], _ => unreachable!() };
+            if st.is_normal(rhs) { lits.push((c, thm)); true } else { false }
+          }
+          _ => false,
+        },
+        _ => false,
+      };
+      if !ok {
+        return Err(format!("literal '{}' should have the form 'eq c n' where n is a numeral",
+          self.data[td.atom].name))
+      }
+    }
+    let (suc_table, mut tables) = (suc_table.into(), tables.into_iter());
+    let mut next = || tables.next().expect("four tables");
+    let full = |v: Vec<Option<ThmID>>| v.into_iter().map(|t| t.expect("checked")).collect();
+    drop(st);
+    let NormNumEnv {span, terms, digits, ..} = cfg;
+    self.norm_num = Some(NormNumEnv {
+      span, terms, digits, suc_table,
+      add_table: full(next()), adc_table: full(next()), mul_table: full(next()),
+      lt_table: next().into(), lemmas: lemmas.into(), orders: orders.into(), literals: lits,
+    });
+    Ok(())
+  }
+
+  /// Implementation of `(norm-num e)`, which evaluates the numeric expression `e` and returns
+  /// `(n p)` where `p` proves `e = n`, or proves `e` if it is a numeric comparison.
+  pub(crate) fn norm_num(&self, e: &LispVal) -> SResult<LispVal> {
+    let cfg = self.norm_num.as_ref().ok_or("norm-num has not been configured, see 'register-norm-num'")?;
+    let mut tbl = ExprTable::new(self.format_env());
+    let digits = cfg.digits.iter().map(|&t| tbl.app(t, &[])).collect::<Vec<_>>();
+    let zero = tbl.app(cfg.terms.digit, &[digits[0]]);
+    let mut st = NormNum {
+      cfg, tbl, zero, digit_val: digits.iter().enumerate().map(|(i, &n)| (n, i)).collect(), digits,
+      literals: cfg.literals.iter().copied().collect(), facts: HashMap::new(), memo: HashMap::new(),
+    };
+    let e = st.tbl.node(e)?;
+    let e = st.coerce(e);
+    let t = &cfg.terms;
+    let verb = |p| LispVal::list(vec![LispVal::atom(AtomID::VERB), p]);
+    match st.tbl.nodes[e] {
+      Node::App(h, _) if h == t.eq || Some(h) == t.lt || Some(h) == t.le || Some(h) == t.ne =>
+        Ok(verb(st.prove(e)?)),
+      _ => {
+        let (n, p) = st.eval(e)?;
+        let p = st.or_refl(e, p);
+        Ok(LispVal::list(vec![st.tbl.expr(n), verb(p)]))
+      }
+    }
+  }
+}
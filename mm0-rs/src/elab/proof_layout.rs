@@ -0,0 +1,231 @@
+//! Layout of proofs as a numbered list of steps, used for documentation pages,
+//! the `proof-steps` builtin and the `mm0-rs show-proof` subcommand.
+//!
+//! Each step is a hypothesis, a theorem application or a conversion, together with the
+//! steps it uses and the statement it proves. Subterms of the proof that are shared via
+//! the heap are only laid out once.
+
+use std::convert::{TryFrom, TryInto};
+use crate::util::SliceUninit;
+use super::{Elaborator, Environment, lisp::LispVal, environment::{AtomID, DeclKey, ExprNode,
+  Proof, ProofNode, TermID, ThmID, ThmKind, Type}};
+
+/// The order in which to display the steps of a proof.
+#[derive(Clone, Copy, Debug)]
+pub enum ProofOrder {
+  /// Preorder traversal: the conclusion comes first.
+  Pre,
+  /// Postorder traversal: the conclusion comes last.
+  Post,
+}
+
+/// The justification of a step in a proof.
+#[derive(Debug, Clone)]
+pub enum LineKind {
+  /// A hypothesis of the theorem, with its name.
+  Hyp(Option<AtomID>),
+  /// An application of a theorem. The hypotheses of the step are the subproofs
+  /// of the theorem's hypotheses, in order.
+  Thm(ThmID),
+  /// A conversion from the single hypothesis, by unfolding the given definitions.
+  Conv(Box<[TermID]>),
+}
+
+/// A step in a proof.
+#[derive(Debug, Clone)]
+pub struct Line {
+  /// The indexes of the steps that are used by this step.
+  pub hyps: Box<[u32]>,
+  /// The justification of the step.
+  pub kind: LineKind,
+  /// The statement proved by this step.
+  pub expr: LispVal,
+}
+
+impl Line {
+  /// Convert the step to a lisp value `(hyps step expr)`, where `step` is the theorem
+  /// name, `(:hyp h)` or `(:hyp)` for a hypothesis, or `(:conv d1 ... dn)` for a conversion.
+  pub fn to_lisp(&self, env: &mut Environment) -> LispVal {
+    let kind = match self.kind {
+      LineKind::Hyp(a) => LispVal::list(std::iter::once(env.get_atom(b":hyp"))
+        .chain(a).map(LispVal::atom).collect::<Vec<_>>()),
+      LineKind::Thm(tid) => LispVal::atom(env.thms[tid].atom),
+      LineKind::Conv(ref defs) => LispVal::list(std::iter::once(AtomID::CONV)
+        .chain(defs.iter().map(|&t| env.terms[t].atom)).map(LispVal::atom).collect::<Vec<_>>()),
+    };
+    let hyps = self.hyps.iter().map(|&i| LispVal::number(i.into())).collect::<Vec<_>>();
+    LispVal::list(vec![LispVal::list(hyps), kind, self.expr.clone()])
+  }
+}
+
+#[derive(Clone)]
+enum LayoutResult {
+  Expr(LispVal),
+  Proof(u32),
+  Conv(Box<[TermID]>),
+}
+
+impl LayoutResult {
+  fn into_expr(self) -> LispVal {
+    if let LayoutResult::Expr(e) = self {e} else {panic!("bad proof")}
+  }
+  fn into_proof(self) -> u32 {
+    if let LayoutResult::Proof(e) = self {e} else {panic!("bad proof")}
+  }
+  fn into_conv(self) -> Box<[TermID]> {
+    if let LayoutResult::Conv(e) = self {e} else {panic!("bad proof")}
+  }
+  fn as_conv(&self) -> &[TermID] {
+    if let LayoutResult::Conv(e) = self {e} else {panic!("bad proof")}
+  }
+}
+
+struct LayoutProof<'a> {
+  env: &'a mut Environment,
+  lines: Vec<Line>,
+  rev: bool,
+  args: &'a [(Option<AtomID>, Type)],
+  heap: &'a [ProofNode],
+  hyps: &'a [(Option<AtomID>, ExprNode)],
+  heap_lines: Box<[Option<LayoutResult>]>,
+}
+
+impl<'a> LayoutProof<'a> {
+  fn push_line(&mut self, hyps: Box<[u32]>, kind: LineKind, expr: LispVal) -> u32 {
+    let line = self.lines.len().try_into().expect("lines are u32");
+    self.lines.push(Line {hyps, kind, expr});
+    line
+  }
+
+  fn layout_conv(&mut self, defs: &mut Vec<TermID>, p: &ProofNode) {
+    match p {
+      &ProofNode::Ref(i) => {
+        for &d in
+          if let Some(h) = &self.heap_lines[i] {h} else {
+            let h = self.layout(&self.heap[i]);
+            self.heap_lines[i].get_or_insert(h)
+          }.as_conv()
+        { if !defs.contains(&d) {defs.push(d)} }
+      },
+      ProofNode::Dummy(_, _) |
+      ProofNode::Term {..} |
+      ProofNode::Hyp(_, _) |
+      ProofNode::Thm {..} |
+      ProofNode::Conv(_) => unreachable!(),
+      ProofNode::Refl(_) => {}
+      ProofNode::Sym(c) => self.layout_conv(defs, c),
+      ProofNode::Cong {args, ..} => for c in &**args { self.layout_conv(defs, c) },
+      &ProofNode::Unfold {term, ref res, ..} => {
+        if !defs.contains(&term) {defs.push(term)}
+        self.layout_conv(defs, &res.2)
+      }
+    }
+  }
+
+  fn layout(&mut self, p: &ProofNode) -> LayoutResult {
+    match *p {
+      ProofNode::Ref(i) =>
+        if let Some(n) = &self.heap_lines[i] {n.clone()}
+        else {
+          let res = if let Some((a, _)) = self.args.get(i) {
+            let a = a.unwrap_or_else(|| self.env.get_atom(format!("v{}", i+1).as_bytes()));
+            LayoutResult::Expr(LispVal::atom(a))
+          } else { self.layout(&self.heap[i]) };
+          self.heap_lines[i] = Some(res.clone());
+          res
+        },
+      ProofNode::Dummy(a, _) => LayoutResult::Expr(LispVal::atom(a)),
+      ProofNode::Term {term, ref args} => {
+        let mut out = Vec::with_capacity(args.len()+1);
+        out.push(LispVal::atom(self.env.terms[term].atom));
+        for e in &**args {
+          out.push(self.layout(e).into_expr());
+        }
+        LayoutResult::Expr(LispVal::list(out))
+      }
+      ProofNode::Hyp(i, ref e) => {
+        let e = self.layout(e).into_expr();
+        LayoutResult::Proof(self.push_line(Box::new([]), LineKind::Hyp(self.hyps[i].0), e))
+      }
+      ProofNode::Thm {thm, ref args, ref res} => {
+        let td = &self.env.thms[thm];
+        let mut hyps = SliceUninit::new(td.hyps.len());
+        if self.rev {
+          for (i, e) in args[td.args.len()..].iter().enumerate().rev() {
+            hyps.set(i, self.layout(e).into_proof())
+          }
+        } else {
+          for (i, e) in args[td.args.len()..].iter().enumerate() {
+            hyps.set(i, self.layout(e).into_proof())
+          }
+        }
+        let res = self.layout(res).into_expr();
+        LayoutResult::Proof(self.push_line(unsafe {hyps.assume_init()}, LineKind::Thm(thm), res))
+      }
+      ProofNode::Conv(ref p) => {
+        let n = self.layout(&p.2).into_proof();
+        let mut defs = self.layout(&p.1).into_conv();
+        let tgt = self.layout(&p.0).into_expr();
+        defs.sort_by_key(|&t| self.env.data[self.env.terms[t].atom].name.as_str());
+        LayoutResult::Proof(self.push_line(Box::new([n]), LineKind::Conv(defs), tgt))
+      }
+      ProofNode::Refl(_) |
+      ProofNode::Sym(_) |
+      ProofNode::Cong {..} |
+      ProofNode::Unfold {..} => {
+        let mut defs = vec![];
+        self.layout_conv(&mut defs, p);
+        LayoutResult::Conv(defs.into_boxed_slice())
+      }
+    }
+  }
+}
+
+/// Lay out the proof `pf` of a theorem with arguments `args` and hypotheses `hyps`
+/// as a list of steps, in the given order. The `hyps` of each step are indexes into the
+/// returned list, and the last step (or the first, in preorder) proves the conclusion.
+/// Unnamed variables are displayed as `v1`, `v2`, ..., which may add new atoms to `env`.
+pub fn layout_proof(env: &mut Environment, order: ProofOrder,
+  args: &[(Option<AtomID>, Type)], hyps: &[(Option<AtomID>, ExprNode)], pf: &Proof,
+) -> Vec<Line> {
+  let mut layout = LayoutProof {
+    env, args, heap: &pf.heap, hyps,
+    rev: matches!(order, ProofOrder::Pre), lines: vec![],
+    heap_lines: vec![None; pf.heap.len()].into_boxed_slice()
+  };
+  layout.layout(&pf.head).into_proof();
+  let mut lines = layout.lines;
+  if matches!(order, ProofOrder::Pre) {
+    let last = u32::try_from(lines.len()).expect("lines are u32") - 1;
+    lines.reverse();
+    for line in &mut lines {
+      for i in &mut *line.hyps { *i = last - *i }
+    }
+  }
+  lines
+}
+
+impl Elaborator {
+  /// Lay out the proof of the theorem `tid` using [`layout_proof`].
+  pub fn layout_thm(&mut self, tid: ThmID, order: ProofOrder) -> Result<Vec<Line>, String> {
+    let td = &self.thms[tid];
+    let pf = match &td.kind {
+      ThmKind::Thm(Some(pf)) => pf.clone(),
+      ThmKind::Thm(None) => return Err(format!("theorem '{}' has no proof", self.data[td.atom].name)),
+      ThmKind::Axiom => return Err(format!("'{}' is an axiom", self.data[td.atom].name)),
+    };
+    let (args, hyps) = (td.args.clone(), td.hyps.clone());
+    Ok(layout_proof(&mut self.env, order, &args, &hyps, &pf))
+  }
+
+  /// Lay out the proof of the theorem `x` as a list of steps in the format of
+  /// [`Line::to_lisp`], for the `proof-steps` builtin.
+  pub fn proof_steps(&mut self, x: AtomID, order: ProofOrder) -> Result<LispVal, String> {
+    let tid = match self.data[x].decl {
+      Some(DeclKey::Thm(tid)) => tid,
+      _ => return Err(format!("unknown theorem '{}'", self.data[x].name)),
+    };
+    let lines = self.layout_thm(tid, order)?;
+    Ok(LispVal::list(lines.iter().map(|l| l.to_lisp(&mut self.env)).collect::<Vec<_>>()))
+  }
+}
@@ -0,0 +1,124 @@
+//! Summary information about theorems and their proofs, used for documentation and code lenses.
+
+use std::collections::HashMap;
+use bit_set::BitSet;
+use super::environment::{Proof, ProofNode, Thm, ThmID, ThmKind, ThmVec};
+
+/// A cache of the (transitive) axiom dependencies of theorems.
+///
+/// Each theorem is mapped to a [`BitSet`] of axioms, where index `0` means the
+/// theorem depends on a `sorry` (a theorem with a missing proof), and index `i > 0`
+/// is the axiom `to_tid[i]`, where `to_tid` is the vector returned by [`AxiomUse::new`].
+#[derive(Debug)]
+pub struct AxiomUse(HashMap<ThmID, BitSet>);
+
+impl AxiomUse {
+  /// Construct a new [`AxiomUse`] for the given theorems, also returning the mapping
+  /// from axiom indexes to [`ThmID`]s. (Index 0, which means `sorry`, is mapped to a dummy.)
+  #[must_use] pub fn new(thms: &ThmVec<Thm>) -> (Vec<ThmID>, Self) {
+    let mut axuse = HashMap::new();
+    let mut to_tid = vec![ThmID(u32::MAX)];
+    for (tid, td) in thms.enum_iter() {
+      if let ThmKind::Axiom = td.kind {
+        let axid = to_tid.len();
+        to_tid.push(tid);
+        let mut bs = BitSet::new();
+        bs.insert(axid);
+        axuse.insert(tid, bs);
+      }
+    }
+    (to_tid, AxiomUse(axuse))
+  }
+
+  fn accumulate(&mut self, thms: &ThmVec<Thm>, bs: &mut BitSet, node: &ProofNode) {
+    match node {
+      ProofNode::Ref(_) |
+      ProofNode::Dummy(_, _) |
+      ProofNode::Term {..} |
+      ProofNode::Hyp(_, _) |
+      ProofNode::Refl(_) |
+      ProofNode::Sym(_) |
+      ProofNode::Cong {..} |
+      ProofNode::Unfold {..} => {}
+      ProofNode::Conv(p) => self.accumulate(thms, bs, &p.2),
+      &ProofNode::Thm {thm: tid, ref args, ..} => {
+        bs.union_with(self.get(thms, tid));
+        for p in &**args { self.accumulate(thms, bs, p) }
+      }
+    }
+  }
+
+  /// Get the set of axioms used by theorem `tid`, computing it if it is not already cached.
+  pub fn get<'a, 'b>(&'a mut self, thms: &'b ThmVec<Thm>, tid: ThmID) -> &'a BitSet {
+    if let Some(bs) = self.0.get(&tid) {
+      // Safety: This is the same issue that comes up in Spans::insert.
+      // We are performing a lifetime cast here because rust can't see that
+      // in the None case it is safe to drop the borrow of `self.axuse`.
+      #[allow(clippy::useless_transmute, clippy::transmute_ptr_to_ptr)]
+      return unsafe { std::mem::transmute(bs) }
+    }
+    let mut bs = BitSet::new();
+    let td = &thms[tid];
+    match &td.kind {
+      ThmKind::Axiom => unreachable!(),
+      ThmKind::Thm(None) => {bs.insert(0);}
+      ThmKind::Thm(Some(Proof {heap, head, ..})) => {
+        for p in &heap[td.args.len()..] { self.accumulate(thms, &mut bs, p) }
+        self.accumulate(thms, &mut bs, head)
+      }
+    }
+    self.0.entry(tid).or_insert(bs)
+  }
+}
+
+/// The size of a (deduplicated) proof, as well as the theorems it directly references.
+#[derive(Debug, Default)]
+pub struct ProofSize {
+  /// The number of proof steps, that is, theorem applications, hypotheses and
+  /// conversions. Subproofs that are shared via the proof heap are only counted once.
+  pub steps: usize,
+  /// The theorems that are referenced in the proof, in order of first use.
+  pub deps: Vec<ThmID>,
+}
+
+impl ProofSize {
+  /// Calculate the [`ProofSize`] of a proof. `nargs` is the number of arguments
+  /// of the theorem, which occupy the start of the proof heap.
+  #[must_use] pub fn new(nargs: usize, pf: &Proof) -> Self {
+    struct Counter<'a> {
+      heap: &'a [ProofNode],
+      visited: BitSet,
+      res: ProofSize,
+    }
+    impl Counter<'_> {
+      fn node(&mut self, p: &ProofNode) {
+        match p {
+          &ProofNode::Ref(i) => if self.visited.insert(i) {
+            if let Some(p) = self.heap.get(i) { self.node(p) }
+          },
+          ProofNode::Dummy(_, _) |
+          ProofNode::Term {..} |
+          ProofNode::Refl(_) |
+          ProofNode::Sym(_) |
+          ProofNode::Cong {..} |
+          ProofNode::Unfold {..} => {}
+          ProofNode::Hyp(_, _) => self.res.steps += 1,
+          ProofNode::Conv(p) => {
+            self.res.steps += 1;
+            self.node(&p.2)
+          }
+          &ProofNode::Thm {thm, ref args, ..} => {
+            self.res.steps += 1;
+            if !self.res.deps.contains(&thm) { self.res.deps.push(thm) }
+            for p in &**args { self.node(p) }
+          }
+        }
+      }
+    }
+    let mut visited = BitSet::with_capacity(pf.heap.len());
+    for i in 0..nargs { visited.insert(i); }
+    let mut c = Counter {heap: &pf.heap, visited, res: ProofSize::default()};
+    c.node(&pf.head);
+    c.res
+  }
+}
@@ -349,7 +349,7 @@ impl LispVal {
   fn conv(tgt: Self, u: Self, p: Self) -> Self {
     Self::list(vec![Self::atom(AtomID::CONV), tgt, u, p])
   }
-  fn unfold(t: AtomID, es: Vec<Self>, p: Self) -> Self {
+  pub(crate) fn unfold(t: AtomID, es: Vec<Self>, p: Self) -> Self {
     Self::list(vec![Self::atom(AtomID::UNFOLD), Self::atom(t), Self::list(es), p])
   }
   fn sym(p: Self) -> Self {
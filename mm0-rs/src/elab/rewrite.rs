@@ -0,0 +1,429 @@
+//! The native rewriting engine, used by the `rewrite` and `rewrite-goal` lisp builtins.
+//!
+//! Rewriting is performed with respect to equality relations which are registered by
+//! the user using `register-eq`, together with congruence lemmas for term constructors
+//! registered using `register-congr`. Given a list of rewrite rules (theorems with no
+//! hypotheses whose conclusion is a registered equality `lhs = rhs`), an expression is
+//! rewritten to normal form bottom-up: first the arguments of each term are normalized and
+//! the results are combined using the congruence lemma for the term, and then the rules
+//! are tried at the root, renormalizing the result whenever a rule applies.
+//!
+//! The result is an ordinary proof term built from the registered reflexivity,
+//! transitivity and congruence theorems, in the explicit `(! thm args hyps)` form
+//! accepted by `refine`, so nothing in the result needs to be trusted.
+
+use std::collections::{HashMap, HashSet};
+use crate::util::FileSpan;
+use super::{Elaborator, ElabError, Remap, Remapper};
+use super::environment::{Environment, ExprNode, SortID, TermID, ThmID};
+use super::lisp::{InferTarget, LispKind, LispVal, Uncons, print::FormatEnv, eval::SResult};
+use super::expr_table::{ExprTable, Node};
+use super::local_context::InferSort;
+
+/// The default maximum number of rule applications in a call to `rewrite`.
+pub const DEFAULT_STEP_LIMIT: usize = 10000;
+
+/// An equality relation registered for use by the rewriter, using `register-eq`.
+#[derive(Clone, Debug, DeepSizeOf)]
+pub struct EqRel {
+  /// The location of the registration.
+  pub span: FileSpan,
+  /// The binary term constructor `eq a b` for the relation.
+  pub term: TermID,
+  /// The reflexivity theorem `eq a a`.
+  pub refl: ThmID,
+  /// The transitivity theorem `eq a b > eq b c > eq a c`.
+  pub trans: ThmID,
+  /// The transport theorem `b > eq a b > a`, if this relation is on a provable sort.
+  pub mp: Option<ThmID>,
+}
+
+/// A congruence lemma registered for use by the rewriter, using `register-congr`.
+#[derive(Clone, Debug, DeepSizeOf)]
+pub struct Congr {
+  /// The location of the registration.
+  pub span: FileSpan,
+  /// The congruence theorem `eq a1 b1 > ... > eq an bn > eq (t a1 ... an) (t b1 ... bn)`.
+  pub thm: ThmID,
+}
+
+/// The registered equality relations and congruence lemmas, which are stored in the
+/// [`Environment`] so that they are available to importing files.
+#[derive(Default, Clone, Debug, DeepSizeOf)]
+pub struct RewriteEnv {
+  /// The equality relation for each sort.
+  pub eqs: HashMap<SortID, EqRel>,
+  /// The congruence lemma for each term constructor.
+  pub congrs: HashMap<TermID, Congr>,
+}
+
+impl Remap for EqRel {
+  type Target = Self;
+  fn remap(&self, r: &mut Remapper) -> Self {
+    EqRel {
+      span: self.span.clone(),
+      term: self.term.remap(r),
+      refl: self.refl.remap(r),
+      trans: self.trans.remap(r),
+      mp: self.mp.map(|t| t.remap(r)),
+    }
+  }
+}
+
+impl Remap for Congr {
+  type Target = Self;
+  fn remap(&self, r: &mut Remapper) -> Self {
+    Congr {span: self.span.clone(), thm: self.thm.remap(r)}
+  }
+}
+
+impl RewriteEnv {
+  /// Merge the registrations of an imported environment into this one.
+  pub fn merge(&mut self, other: &Self, r: &mut Remapper, errors: &mut Vec<ElabError>, sp: crate::util::Span) {
+    for (&s, eq) in &other.eqs {
+      let eq = eq.remap(r);
+      match self.eqs.get(&s.remap(r)) {
+        Some(old) if old.term != eq.term || old.refl != eq.refl || old.trans != eq.trans =>
+          errors.push(ElabError::with_info(sp, "equality relation registered twice".into(),
+            vec![(eq.span.clone(), "registered here".into()), (old.span.clone(), "registered here".into())])),
+        Some(_) => {}
+        None => { self.eqs.insert(s.remap(r), eq); }
+      }
+    }
+    for (&t, c) in &other.congrs {
+      let c = c.remap(r);
+      match self.congrs.get(&t.remap(r)) {
+        Some(old) if old.thm != c.thm =>
+          errors.push(ElabError::with_info(sp, "congruence lemma registered twice".into(),
+            vec![(c.span.clone(), "registered here".into()), (old.span.clone(), "registered here".into())])),
+        Some(_) => {}
+        None => { self.congrs.insert(t.remap(r), c); }
+      }
+    }
+  }
+}
+
+/// Follow references to the heap (but not to the variables) in a theorem statement.
+fn deref<'a>(heap: &'a [ExprNode], nargs: usize, mut e: &'a ExprNode) -> &'a ExprNode {
+  while let ExprNode::Ref(i) = *e {
+    if i < nargs { break }
+    e = &heap[i]
+  }
+  e
+}
+
+/// Mark the variables that occur in `e`.
+fn mark_vars(heap: &[ExprNode], nargs: usize, e: &ExprNode, occurs: &mut [bool]) {
+  match *e {
+    ExprNode::Ref(i) if i < nargs => occurs[i] = true,
+    ExprNode::Ref(i) => mark_vars(heap, nargs, &heap[i], occurs),
+    ExprNode::Dummy(_, _) => {}
+    ExprNode::App(_, ref es) => for e in &**es { mark_vars(heap, nargs, e, occurs) }
+  }
+}
+
+impl Environment {
+  /// Get the registered equality relation whose term constructor is `t`.
+  fn eq_rel_by_term(&self, t: TermID) -> Option<&EqRel> {
+    self.rewrite.eqs.values().find(|eq| eq.term == t)
+  }
+}
+
+/// The state of a rewriting run.
+struct Rewriter<'a> {
+  tbl: ExprTable<'a>,
+  /// The rewrite rules, indexed by the head of the left hand side.
+  rules: HashMap<TermID, Vec<ThmID>>,
+  /// The normal form and rewrite proof of each expression that has been normalized.
+  memo: HashMap<usize, (usize, Option<LispVal>)>,
+  /// The expressions that are currently being normalized, for loop detection.
+  active: HashSet<usize>,
+  /// The number of remaining rule applications.
+  fuel: usize,
+}
+
+impl<'a> Rewriter<'a> {
+  fn new(fe: FormatEnv<'a>, limit: usize) -> Self {
+    Rewriter {
+      tbl: ExprTable::new(fe),
+      rules: HashMap::new(), memo: HashMap::new(), active: HashSet::new(), fuel: limit
+    }
+  }
+
+  fn eq_rel(&self, s: SortID) -> SResult<&'a EqRel> {
+    let fe = self.tbl.fe;
+    fe.env.rewrite.eqs.get(&s).ok_or_else(||
+      format!("no equality relation registered for sort '{}'", fe.sorts[s].name))
+  }
+
+  fn mk_eq(&mut self, eq: &EqRel, a: usize, b: usize) -> usize {
+    self.tbl.app(eq.term, &[a, b])
+  }
+
+  /// Construct a proof of `eq a a`.
+  fn refl(&mut self, eq: &EqRel, a: usize) -> LispVal {
+    let concl = self.mk_eq(eq, a, a);
+    self.tbl.apply(eq.refl, concl, vec![])
+  }
+
+  /// Given proofs of `eq a b` and `eq b c`, where `None` denotes a reflexivity proof,
+  /// construct a proof of `eq a c`.
+  fn trans(&mut self, eq: &EqRel, a: usize, (b, p1): (usize, Option<LispVal>),
+      (c, p2): (usize, Option<LispVal>)) -> Option<LispVal> {
+    let (p1, p2) = match (p1, p2) {
+      (None, p) | (p, None) => return p,
+      (Some(p1), Some(p2)) => (p1, p2)
+    };
+    let concl = self.mk_eq(eq, a, c);
+    let hyps = vec![(self.mk_eq(eq, a, b), p1), (self.mk_eq(eq, b, c), p2)];
+    Some(self.tbl.apply(eq.trans, concl, hyps))
+  }
+
+  /// Normalize the arguments of `e`, using the congruence lemma for its head.
+  fn congr(&mut self, e: usize) -> SResult<(usize, Option<LispVal>)> {
+    let fe = self.tbl.fe;
+    let (t, args) = match &self.tbl.nodes[e] {
+      Node::App(t, args) => (*t, args.clone()),
+      _ => return Ok((e, None))
+    };
+    let c = match fe.env.rewrite.congrs.get(&t) { Some(c) => c, None => return Ok((e, None)) };
+    let tdef = &fe.terms[t];
+    let mut new_args = Vec::with_capacity(args.len());
+    let mut pfs = Vec::with_capacity(args.len());
+    for (&a, &(_, ty)) in args.iter().zip(tdef.args.iter()) {
+      if ty.bound() { new_args.push(a); pfs.push(None); continue }
+      let (a2, p) = self.norm(a)?;
+      new_args.push(a2);
+      pfs.push(p);
+    }
+    if pfs.iter().all(Option::is_none) { return Ok((e, None)) }
+    let e2 = self.tbl.app(t, &new_args);
+    let eq = self.eq_rel(tdef.ret.0)?;
+    let concl = self.mk_eq(eq, e, e2);
+    let mut hyps = Vec::with_capacity(args.len());
+    for (i, &(_, ty)) in tdef.args.iter().enumerate() {
+      if ty.bound() { continue }
+      let eq = self.eq_rel(ty.sort())?;
+      let p = match pfs[i].take() { Some(p) => p, None => self.refl(eq, args[i]) };
+      hyps.push((self.mk_eq(eq, args[i], new_args[i]), p));
+    }
+    Ok((e2, Some(self.tbl.apply(c.thm, concl, hyps))))
+  }
+
+  /// Try to rewrite `e` at the root using one of the rules.
+  fn rewrite_root(&mut self, e: usize) -> Option<(usize, LispVal)> {
+    let fe = self.tbl.fe;
+    let t = if let Node::App(t, _) = self.tbl.nodes[e] { t } else { return None };
+    for &thm in self.rules.get(&t)? {
+      let td = &fe.env.thms[thm];
+      let n = td.args.len();
+      if let ExprNode::App(eq, ref lr) = *deref(&td.heap, n, &td.ret) {
+        let mut subst = vec![None; n];
+        if self.tbl.matches(&td.heap, n, &lr[0], e, &mut subst) {
+          let rhs = self.tbl.subst(&td.heap, n, &lr[1], &subst);
+          let concl = self.tbl.app(eq, &[e, rhs]);
+          let p = self.tbl.apply(thm, concl, vec![]);
+          return Some((rhs, p))
+        }
+      }
+    }
+    None
+  }
+
+  /// Combine the steps `a ~> b` of a rewrite chain starting at `e`, given a proof `acc` of
+  /// `eq b nf` for the last step, into a proof of `eq e nf`, memoizing the intermediate results.
+  fn chain(&mut self, e: usize, steps: Vec<(usize, usize, Option<LispVal>, bool)>,
+      nf: usize, mut acc: Option<LispVal>) -> SResult<Option<LispVal>> {
+    let eq = if steps.len() > 1 {
+      let t = if let Node::App(t, _) = self.tbl.nodes[e] { t } else { unreachable!() };
+      Some(self.eq_rel(self.tbl.fe.terms[t].ret.0)?)
+    } else { None };
+    for (a, b, p, head) in steps.into_iter().rev() {
+      acc = match eq { Some(eq) => self.trans(eq, a, (b, p), (nf, acc)), None => p };
+      if head {
+        self.active.remove(&a);
+        self.memo.insert(a, (nf, acc.clone()));
+      }
+    }
+    Ok(acc)
+  }
+
+  /// Rewrite `e` to normal form, returning the normal form and a proof of `eq e e'`
+  /// (or `None` if `e` is already in normal form).
+  ///
+  /// The chain of root rewrites is followed in a loop rather than by recursion, so that
+  /// long rewrite sequences run out of fuel instead of stack.
+  fn norm(&mut self, e: usize) -> SResult<(usize, Option<LispVal>)> {
+    if let Some(res) = self.memo.get(&e) { return Ok(res.clone()) }
+    // The steps `a ~> b` of the rewrite chain, and whether `a` is a newly normalized term
+    // (as opposed to the result of a congruence step).
+    let mut steps: Vec<(usize, usize, Option<LispVal>, bool)> = vec![];
+    let mut cur = e;
+    let res = loop {
+      if !self.active.insert(cur) {
+        let e = self.tbl.expr(cur);
+        return Err(format!("rewrite loop detected at {}", self.tbl.fe.to(&e)))
+      }
+      let (e1, p1) = self.congr(cur)?;
+      steps.push((cur, e1, p1, true));
+      match self.rewrite_root(e1) {
+        None => break (e1, None),
+        Some((e2, q)) => {
+          self.fuel = self.fuel.checked_sub(1).ok_or("rewrite step limit exceeded")?;
+          steps.push((e1, e2, Some(q), false));
+          if let Some((e3, q2)) = self.memo.get(&e2) { break (*e3, q2.clone()) }
+          cur = e2;
+        }
+      }
+    };
+    let (nf, acc) = res;
+    let acc = self.chain(e, steps, nf, acc)?;
+    Ok((nf, acc))
+  }
+}
+
+impl Elaborator {
+  /// Get the sort of an expression (with only minimal type-checking).
+  fn rewrite_sort(&self, e: &LispVal) -> SResult<SortID> {
+    e.unwrapped(|k| match *k {
+      LispKind::Atom(a) => match self.lc.vars.get(&a) {
+        Some(&(_, InferSort::Bound(s) | InferSort::Reg(s, _))) => Some(s),
+        _ => None
+      },
+      LispKind::MVar(_, InferTarget::Bound(a) | InferTarget::Reg(a)) => self.data[a].sort,
+      LispKind::List(_) | LispKind::DottedList(_, _) =>
+        Uncons::from(e.clone()).next().and_then(|h| h.as_atom())
+          .and_then(|a| self.term(a)).map(|t| self.terms[t].ret.0),
+      _ => None
+    }).ok_or_else(|| format!("cannot determine the sort of {}", self.print(e)))
+  }
+
+  fn get_thm(&self, e: &LispVal) -> SResult<ThmID> {
+    let a = e.as_atom().ok_or_else(|| format!("expected a theorem name, got {}", self.print(e)))?;
+    self.thm(a).ok_or_else(|| format!("unknown theorem '{}'", self.data[a].name))
+  }
+
+  /// Implementation of `(register-eq eq refl trans [mp])`.
+  pub(crate) fn register_eq(&mut self, span: FileSpan, args: &[LispVal]) -> SResult<()> {
+    let a = args[0].as_atom().ok_or("expected a term name")?;
+    let term = self.term(a).ok_or_else(|| format!("unknown term '{}'", self.data[a].name))?;
+    let (refl, trans) = (self.get_thm(&args[1])?, self.get_thm(&args[2])?);
+    let mp = match args.get(3) { Some(e) => Some(self.get_thm(e)?), None => None };
+    let s = match *self.terms[term].args {
+      [(_, t1), (_, t2)] if !t1.bound() && !t2.bound() && t1.sort() == t2.sort() => t1.sort(),
+      _ => return Err(format!("'{}' is not a binary relation", self.data[a].name)),
+    };
+    let mut tbl = ExprTable::new(self.format_env());
+    let (x, y, z) = (tbl.fresh(), tbl.fresh(), tbl.fresh());
+    let (xx, xy, yz, xz) = (tbl.app(term, &[x, x]), tbl.app(term, &[x, y]),
+      tbl.app(term, &[y, z]), tbl.app(term, &[x, z]));
+    tbl.check_thm(refl, xx, &[], "eq a a")?;
+    tbl.check_thm(trans, xz, &[xy, yz], "eq a b > eq b c > eq a c")?;
+    if let Some(mp) = mp { tbl.check_thm(mp, x, &[y, xy], "b > eq a b > a")? }
+    if self.rewrite.eqs.get(&s).map_or(false, |old| old.term != term) {
+      return Err(format!("an equality relation is already registered for sort '{}'", self.sorts[s].name))
+    }
+    self.rewrite.eqs.insert(s, EqRel {span, term, refl, trans, mp});
+    Ok(())
+  }
+
+  /// Implementation of `(register-congr thm ...)`.
+  pub(crate) fn register_congr(&mut self, span: &FileSpan, args: &[LispVal]) -> SResult<()> {
+    for e in args {
+      let thm = self.get_thm(e)?;
+      let td = &self.thms[thm];
+      let nargs = td.args.len();
+      let form = "eq a1 b1 > ... > eq an bn > eq (t a1 ... an) (t b1 ... bn)";
+      let t = match deref(&td.heap, nargs, &td.ret) {
+        ExprNode::App(_, args) if args.len() == 2 => match deref(&td.heap, nargs, &args[0]) {
+          ExprNode::App(t, _) => *t,
+          _ => return Err(format!("'{}' does not have the form '{}'", self.data[td.atom].name, form))
+        },
+        _ => return Err(format!("'{}' does not have the form '{}'", self.data[td.atom].name, form))
+      };
+      let tdef = &self.terms[t];
+      let eq_rel = |s: SortID| self.rewrite.eqs.get(&s).ok_or_else(||
+        format!("no equality relation registered for sort '{}'", self.sorts[s].name));
+      let mut tbl = ExprTable::new(self.format_env());
+      let (mut lhs, mut rhs, mut hyps) = (vec![], vec![], vec![]);
+      for &(_, ty) in &*tdef.args {
+        let a = tbl.fresh();
+        lhs.push(a);
+        if ty.bound() { rhs.push(a); continue }
+        let b = tbl.fresh();
+        rhs.push(b);
+        hyps.push(tbl.app(eq_rel(ty.sort())?.term, &[a, b]));
+      }
+      let (lhs, rhs) = (tbl.app(t, &lhs), tbl.app(t, &rhs));
+      let concl = tbl.app(eq_rel(tdef.ret.0)?.term, &[lhs, rhs]);
+      tbl.check_thm(thm, concl, &hyps, form)?;
+      self.rewrite.congrs.insert(t, Congr {span: span.clone(), thm});
+    }
+    Ok(())
+  }
+
+  /// Rewrite the expression `e` to normal form using the list of rules `rules`.
+  /// Returns the rewriter state, the sort of `e`, the nodes for `e` and its normal form,
+  /// and a proof of equality (or `None` if the expression is already in normal form).
+  #[allow(clippy::type_complexity)]
+  fn rewrite_core(&self, e: &LispVal, rules: &LispVal, limit: usize
+  ) -> SResult<(Rewriter<'_>, SortID, usize, usize, Option<LispVal>)> {
+    let sort = self.rewrite_sort(e)?;
+    let mut rw = Rewriter::new(self.format_env(), limit);
+    for rule in Uncons::from(rules.clone()) {
+      let thm = self.get_thm(&rule)?;
+      let td = &self.thms[thm];
+      let nargs = td.args.len();
+      if !td.hyps.is_empty() {
+        return Err(format!("rewrite rule '{}' has hypotheses", self.data[td.atom].name))
+      }
+      let (eq, lhs) = match deref(&td.heap, nargs, &td.ret) {
+        ExprNode::App(eq, args) if args.len() == 2 && self.eq_rel_by_term(*eq).is_some() =>
+          (*eq, deref(&td.heap, nargs, &args[0])),
+        _ => return Err(format!("rewrite rule '{}' is not an equation", self.data[td.atom].name))
+      };
+      let t = match *lhs {
+        ExprNode::App(t, _) => t,
+        _ => return Err(format!("the left hand side of rewrite rule '{}' is a variable",
+          self.data[td.atom].name))
+      };
+      if self.rewrite.eqs.get(&self.terms[t].ret.0).map(|eq| eq.term) != Some(eq) {
+        return Err(format!("rewrite rule '{}' does not use the registered equality relation",
+          self.data[td.atom].name))
+      }
+      let mut occurs = vec![false; nargs];
+      mark_vars(&td.heap, nargs, lhs, &mut occurs);
+      if !occurs.iter().all(|&b| b) {
+        return Err(format!("rewrite rule '{}' has variables not in the left hand side",
+          self.data[td.atom].name))
+      }
+      rw.rules.entry(t).or_default().push(thm);
+    }
+    let e1 = rw.tbl.node(e)?;
+    let (e2, pf) = rw.norm(e1)?;
+    Ok((rw, sort, e1, e2, pf))
+  }
+
+  /// Implementation of `(rewrite e rules [limit])`, which returns `(e2 p)` where
+  /// `e2` is the normal form of `e` and `p` is a proof of `eq e e2`.
+  pub(crate) fn rewrite_expr(&self, e: &LispVal, rules: &LispVal, limit: usize) -> SResult<LispVal> {
+    let (mut rw, sort, e1, e2, pf) = self.rewrite_core(e, rules, limit)?;
+    let pf = if let Some(pf) = pf { pf } else {
+      let eq = rw.eq_rel(sort)?;
+      rw.refl(eq, e1)
+    };
+    Ok(LispVal::list(vec![rw.tbl.expr(e2), pf]))
+  }
+
+  /// Implementation of `(rewrite-goal t rules [p])`, which returns a proof of `t`
+  /// given a proof `p` of the normal form of `t`.
+  pub(crate) fn rewrite_goal(&self, t: &LispVal, rules: &LispVal, p: LispVal) -> SResult<LispVal> {
+    let (mut rw, sort, e1, e2, pf) = self.rewrite_core(t, rules, DEFAULT_STEP_LIMIT)?;
+    let pf = if let Some(pf) = pf { pf } else { return Ok(p) };
+    let eq = rw.eq_rel(sort)?;
+    let mp = eq.mp.ok_or_else(|| format!(
+      "no transport theorem registered for the equality on sort '{}'", self.sorts[sort].name))?;
+    let hyps = vec![(e2, p), (rw.mk_eq(eq, e1, e2), pf)];
+    Ok(rw.tbl.apply(mp, e1, hyps))
+  }
+}
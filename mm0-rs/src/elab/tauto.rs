@@ -0,0 +1,474 @@
+//! A decision procedure for propositional tautologies, used by the `tauto` builtin.
+//!
+//! The procedure is configured using `register-tauto` with the library's propositional
+//! connectives and a fixed set of lemmas (see [`LEMMAS`]). Any subterm of the goal whose
+//! head is not one of the registered connectives is treated as an atom.
+//!
+//! The goal is proved by case analysis on the atoms: at each point we have a context
+//! `G = l1 /\ ... /\ ln` of literals (atoms or negated atoms), and if the goal has a
+//! determined truth value under this partial assignment, we prove `G -> goal` by recursion
+//! on the formula, using a truth table lemma for each connective. Otherwise we split on
+//! another atom `p` and combine the proofs of `G /\ p -> goal` and `G /\ ~p -> goal`.
+//! The result is a proof term in the explicit `(! thm args hyps)` form accepted by `refine`.
+
+use std::collections::HashMap;
+use crate::util::{FileSpan, Span};
+use super::{Elaborator, ElabError, Remap, Remapper};
+use super::environment::{TermID, ThmID};
+use super::lisp::{LispVal, Uncons, eval::SResult};
+use super::expr_table::{ExprTable, Node};
+
+/// The maximum number of cases that `tauto` will consider before giving up.
+const MAX_CASES: usize = 1 << 16;
+
+/// A lemma used by `tauto`, as an index into [`LEMMAS`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Lemma {
+  Id, Syl, Jca, AnL, AnR, Cases, CasesD, NotNot,
+  ImFalseL, ImTrueR, ImFalse, AnFalseL, AnFalseR,
+  OrTrueL, OrTrueR, OrFalse, IffTrue, IffTrueN, IffFalseL, IffFalseR,
+}
+
+/// The lemmas used by `tauto`, with their names in the `register-tauto` configuration
+/// and the required form of the statement, where `>` separates the hypotheses from the
+/// conclusion.
+const LEMMAS: [(Lemma, &str, &str); 20] = [
+  (Lemma::Id, "id", "a -> a"),
+  (Lemma::Syl, "syl", "a -> b > b -> c > a -> c"),
+  (Lemma::Jca, "jca", "a -> b > a -> c > a -> b /\\ c"),
+  (Lemma::AnL, "anl", "a /\\ b -> a"),
+  (Lemma::AnR, "anr", "a /\\ b -> b"),
+  (Lemma::Cases, "cases", "a -> b > ~a -> b > b"),
+  (Lemma::CasesD, "casesd", "a /\\ b -> c > a /\\ ~b -> c > a -> c"),
+  (Lemma::NotNot, "notnot1", "a -> ~~a"),
+  (Lemma::ImFalseL, "im-false-l", "~a -> (a -> b)"),
+  (Lemma::ImTrueR, "im-true-r", "b -> (a -> b)"),
+  (Lemma::ImFalse, "im-false", "a /\\ ~b -> ~(a -> b)"),
+  (Lemma::AnFalseL, "an-false-l", "~a -> ~(a /\\ b)"),
+  (Lemma::AnFalseR, "an-false-r", "~b -> ~(a /\\ b)"),
+  (Lemma::OrTrueL, "or-true-l", "a -> a \\/ b"),
+  (Lemma::OrTrueR, "or-true-r", "b -> a \\/ b"),
+  (Lemma::OrFalse, "or-false", "~a /\\ ~b -> ~(a \\/ b)"),
+  (Lemma::IffTrue, "iff-true", "a /\\ b -> (a <-> b)"),
+  (Lemma::IffTrueN, "iff-true-n", "~a /\\ ~b -> (a <-> b)"),
+  (Lemma::IffFalseL, "iff-false-l", "a /\\ ~b -> ~(a <-> b)"),
+  (Lemma::IffFalseR, "iff-false-r", "~a /\\ b -> ~(a <-> b)"),
+];
+
+/// The configuration of the `tauto` tactic, set by `register-tauto` and stored in the
+/// [`Environment`](super::Environment) so that it is available to importing files.
+#[derive(Clone, Debug, DeepSizeOf)]
+pub struct TautoEnv {
+  /// The location of the registration.
+  pub span: FileSpan,
+  /// The implication term `a -> b`.
+  pub im: TermID,
+  /// The negation term `~a`.
+  pub not: TermID,
+  /// The conjunction term `a /\ b`.
+  pub an: TermID,
+  /// The disjunction term `a \/ b`, if any.
+  pub or: Option<TermID>,
+  /// The biconditional term `a <-> b`, if any.
+  pub iff: Option<TermID>,
+  /// The lemmas, indexed by [`Lemma`]. The lemmas for disjunction and the biconditional
+  /// are only present if the corresponding terms are.
+  pub lemmas: Box<[Option<ThmID>]>,
+}
+
+impl Remap for TautoEnv {
+  type Target = Self;
+  fn remap(&self, r: &mut Remapper) -> Self {
+    TautoEnv {
+      span: self.span.clone(),
+      im: self.im.remap(r),
+      not: self.not.remap(r),
+      an: self.an.remap(r),
+      or: self.or.map(|t| t.remap(r)),
+      iff: self.iff.map(|t| t.remap(r)),
+      lemmas: self.lemmas.iter().map(|t| t.map(|t| t.remap(r))).collect(),
+    }
+  }
+}
+
+impl TautoEnv {
+  /// Merge the configuration of an imported environment into this one.
+  pub fn merge(this: &mut Option<Self>, other: &Option<Self>, r: &mut Remapper,
+      errors: &mut Vec<ElabError>, sp: Span) {
+    if let Some(other) = other {
+      let other = other.remap(r);
+      match this {
+        None => *this = Some(other),
+        Some(old) => if old.im != other.im || old.not != other.not || old.an != other.an ||
+            old.or != other.or || old.iff != other.iff || old.lemmas != other.lemmas {
+          errors.push(ElabError::with_info(sp, "tauto configuration registered twice".into(),
+            vec![(other.span, "registered here".into()), (old.span.clone(), "registered here".into())]))
+        }
+      }
+    }
+  }
+
+  fn lemma(&self, l: Lemma) -> ThmID { self.lemmas[l as usize].expect("checked") }
+}
+
+/// The shape of a formula, with respect to the registered connectives.
+#[derive(Copy, Clone)]
+enum Shape {
+  Atom,
+  Not(usize),
+  Im(usize, usize),
+  An(usize, usize),
+  Or(usize, usize),
+  Iff(usize, usize),
+}
+
+/// The state of a run of the `tauto` procedure.
+struct Tauto<'a> {
+  cfg: &'a TautoEnv,
+  tbl: ExprTable<'a>,
+  /// The atoms of the goal, and their index in `atoms`.
+  atoms: HashMap<usize, usize>,
+  /// The atoms in order of first occurrence.
+  atom_list: Vec<usize>,
+  /// The current partial assignment of truth values to atoms.
+  assign: Vec<Option<bool>>,
+  /// The atoms which have been split on, in order, and the position in this list
+  /// for each atom in the current context.
+  order: Vec<usize>,
+  /// The context `G_k = l_0 /\ ... /\ l_k` after each literal `l_k` in `order`.
+  ctx: Vec<usize>,
+  /// The literal `l_k` for each element of `order`.
+  lits: Vec<usize>,
+  /// The number of cases that have been considered.
+  cases: usize,
+}
+
+impl Tauto<'_> {
+  fn shape(&self, e: usize) -> Shape {
+    let cfg = self.cfg;
+    match self.tbl.nodes[e] {
+      Node::App(t, ref args) => match **args {
+        [a] if t == cfg.not => Shape::Not(a),
+        [a, b] if t == cfg.im => Shape::Im(a, b),
+        [a, b] if t == cfg.an => Shape::An(a, b),
+        [a, b] if Some(t) == cfg.or => Shape::Or(a, b),
+        [a, b] if Some(t) == cfg.iff => Shape::Iff(a, b),
+        _ => Shape::Atom,
+      },
+      _ => Shape::Atom,
+    }
+  }
+
+  /// Collect the atoms of formula `e`.
+  fn collect_atoms(&mut self, e: usize) {
+    match self.shape(e) {
+      Shape::Atom => if !self.atoms.contains_key(&e) {
+        self.atoms.insert(e, self.atom_list.len());
+        self.atom_list.push(e);
+      },
+      Shape::Not(a) => self.collect_atoms(a),
+      Shape::Im(a, b) | Shape::An(a, b) | Shape::Or(a, b) | Shape::Iff(a, b) => {
+        self.collect_atoms(a);
+        self.collect_atoms(b)
+      }
+    }
+  }
+
+  /// Evaluate formula `e` under the current partial assignment.
+  fn eval(&self, e: usize) -> Option<bool> {
+    match self.shape(e) {
+      Shape::Atom => self.assign[self.atoms[&e]],
+      Shape::Not(a) => self.eval(a).map(|v| !v),
+      Shape::Im(a, b) => match (self.eval(a), self.eval(b)) {
+        (Some(false), _) | (_, Some(true)) => Some(true),
+        (Some(true), Some(false)) => Some(false),
+        _ => None,
+      },
+      Shape::An(a, b) => match (self.eval(a), self.eval(b)) {
+        (Some(false), _) | (_, Some(false)) => Some(false),
+        (Some(true), Some(true)) => Some(true),
+        _ => None,
+      },
+      Shape::Or(a, b) => match (self.eval(a), self.eval(b)) {
+        (Some(true), _) | (_, Some(true)) => Some(true),
+        (Some(false), Some(false)) => Some(false),
+        _ => None,
+      },
+      Shape::Iff(a, b) => Some(self.eval(a)? == self.eval(b)?),
+    }
+  }
+
+  fn im(&mut self, a: usize, b: usize) -> usize { self.tbl.app(self.cfg.im, &[a, b]) }
+  fn not(&mut self, a: usize) -> usize { self.tbl.app(self.cfg.not, &[a]) }
+  fn an(&mut self, a: usize, b: usize) -> usize { self.tbl.app(self.cfg.an, &[a, b]) }
+
+  /// The formula `e` if `v` is true, and `~e` otherwise.
+  fn lit(&mut self, e: usize, v: bool) -> usize { if v { e } else { self.not(e) } }
+
+  /// Apply lemma `l` with conclusion `concl` and the given hypotheses.
+  fn lemma(&mut self, l: Lemma, concl: usize, hyps: Vec<(usize, LispVal)>) -> LispVal {
+    let thm = self.cfg.lemma(l);
+    self.tbl.apply(thm, concl, hyps)
+  }
+
+  /// Given a proof of `gc -> lhs` and a lemma `lem: lhs -> rhs`, prove `gc -> rhs`.
+  fn syl(&mut self, gc: usize, lhs: usize, pf: LispVal, lem: Lemma, rhs: usize) -> LispVal {
+    let ab = self.im(lhs, rhs);
+    let pf2 = self.lemma(lem, ab, vec![]);
+    let (ga, gb) = (self.im(gc, lhs), self.im(gc, rhs));
+    self.lemma(Lemma::Syl, gb, vec![(ga, pf), (ab, pf2)])
+  }
+
+  /// Given proofs of `gc -> lhs` and `gc -> rhs`, prove `gc -> lhs /\ rhs`.
+  fn jca(&mut self, gc: usize, (lhs, pf): (usize, LispVal), (rhs, pf2): (usize, LispVal)) -> (usize, LispVal) {
+    let ab = self.an(lhs, rhs);
+    let (ga, gb, gab) = (self.im(gc, lhs), self.im(gc, rhs), self.im(gc, ab));
+    (ab, self.lemma(Lemma::Jca, gab, vec![(ga, pf), (gb, pf2)]))
+  }
+
+  /// Prove `G_depth -> l_idx`, where `l_idx` is the `idx`th literal in the context.
+  fn proj(&mut self, depth: usize, idx: usize) -> LispVal {
+    let (gc, lem) = (self.ctx[depth], self.lits[idx]);
+    let gl = self.im(gc, lem);
+    if depth == 0 { return self.lemma(Lemma::Id, gl, vec![]) }
+    if idx == depth { return self.lemma(Lemma::AnR, gl, vec![]) }
+    let g2 = self.ctx[depth - 1];
+    let gg2 = self.im(gc, g2);
+    let pf = self.lemma(Lemma::AnL, gg2, vec![]);
+    let pf2 = self.proj(depth - 1, idx);
+    let g2l = self.im(g2, lem);
+    self.lemma(Lemma::Syl, gl, vec![(gg2, pf), (g2l, pf2)])
+  }
+
+  /// Given that `fm` has a determined truth value `v` in context `G_depth`, return `v` and
+  /// a proof of `G_depth -> fm` if `v` is true, and `G_depth -> ~fm` otherwise.
+  fn prove(&mut self, depth: usize, fm: usize) -> (bool, LispVal) {
+    let gc = self.ctx[depth];
+    match self.shape(fm) {
+      Shape::Atom => {
+        let idx = self.order.iter().position(|&x| x == self.atoms[&fm]).expect("assigned");
+        (self.assign[self.atoms[&fm]].expect("assigned"), self.proj(depth, idx))
+      }
+      Shape::Not(lhs) => match self.prove(depth, lhs) {
+        (true, pf) => {
+          let ne = self.not(fm);
+          (false, self.syl(gc, lhs, pf, Lemma::NotNot, ne))
+        }
+        (false, pf) => (true, pf),
+      },
+      Shape::Im(lhs, rhs) => if self.eval(lhs) == Some(false) {
+        let (_, pf) = self.prove(depth, lhs);
+        let na = self.not(lhs);
+        (true, self.syl(gc, na, pf, Lemma::ImFalseL, fm))
+      } else if self.eval(rhs) == Some(true) {
+        let (_, pf) = self.prove(depth, rhs);
+        (true, self.syl(gc, rhs, pf, Lemma::ImTrueR, fm))
+      } else {
+        let ((_, pf), (_, pf2), nb, ne) =
+          (self.prove(depth, lhs), self.prove(depth, rhs), self.not(rhs), self.not(fm));
+        let (hyp, pf) = self.jca(gc, (lhs, pf), (nb, pf2));
+        (false, self.syl(gc, hyp, pf, Lemma::ImFalse, ne))
+      },
+      Shape::An(lhs, rhs) => if self.eval(lhs) == Some(false) {
+        let ((_, pf), na, ne) = (self.prove(depth, lhs), self.not(lhs), self.not(fm));
+        (false, self.syl(gc, na, pf, Lemma::AnFalseL, ne))
+      } else if self.eval(rhs) == Some(false) {
+        let ((_, pf), nb, ne) = (self.prove(depth, rhs), self.not(rhs), self.not(fm));
+        (false, self.syl(gc, nb, pf, Lemma::AnFalseR, ne))
+      } else {
+        let ((_, pf), (_, pf2)) = (self.prove(depth, lhs), self.prove(depth, rhs));
+        (true, self.jca(gc, (lhs, pf), (rhs, pf2)).1)
+      },
+      Shape::Or(lhs, rhs) => if self.eval(lhs) == Some(true) {
+        let (_, pf) = self.prove(depth, lhs);
+        (true, self.syl(gc, lhs, pf, Lemma::OrTrueL, fm))
+      } else if self.eval(rhs) == Some(true) {
+        let (_, pf) = self.prove(depth, rhs);
+        (true, self.syl(gc, rhs, pf, Lemma::OrTrueR, fm))
+      } else {
+        let ((_, pf), (_, pf2), na, nb, ne) =
+          (self.prove(depth, lhs), self.prove(depth, rhs), self.not(lhs), self.not(rhs), self.not(fm));
+        let (hyp, pf) = self.jca(gc, (na, pf), (nb, pf2));
+        (false, self.syl(gc, hyp, pf, Lemma::OrFalse, ne))
+      },
+      Shape::Iff(lhs, rhs) => {
+        let ((va, pf), (vb, pf2)) = (self.prove(depth, lhs), self.prove(depth, rhs));
+        let (la, lb) = (self.lit(lhs, va), self.lit(rhs, vb));
+        let (hyp, pf) = self.jca(gc, (la, pf), (lb, pf2));
+        let (lem, val) = match (va, vb) {
+          (true, true) => (Lemma::IffTrue, true),
+          (false, false) => (Lemma::IffTrueN, true),
+          (true, false) => (Lemma::IffFalseL, false),
+          (false, true) => (Lemma::IffFalseR, false),
+        };
+        let res = self.lit(fm, val);
+        (val, self.syl(gc, hyp, pf, lem, res))
+      }
+    }
+  }
+
+  /// Prove `goal` (if the context is empty) or `G_depth -> goal`,
+  /// where `depth + 1` is the length of the context.
+  fn split(&mut self, goal: usize) -> SResult<LispVal> {
+    let depth = self.ctx.len().checked_sub(1);
+    match (depth, self.eval(goal)) {
+      (Some(depth), Some(true)) => return Ok(self.prove(depth, goal).1),
+      (_, Some(false)) => {
+        let mut cex = vec![];
+        for &ix in &self.order {
+          let atom = self.tbl.expr(self.atom_list[ix]);
+          cex.push(format!("{} is {}", self.tbl.fe.to(&atom), self.assign[ix].expect("assigned")));
+        }
+        return Err(format!("not a tautology; it is false when {}", cex.join(", ")))
+      }
+      _ => {}
+    }
+    self.cases += 1;
+    if self.cases > MAX_CASES { return Err("too many cases".into()) }
+    let ix = self.assign.iter().position(Option::is_none).expect("goal is undetermined");
+    let atom = self.atom_list[ix];
+    self.order.push(ix);
+    let mut pfs = vec![];
+    for &val in &[true, false] {
+      self.assign[ix] = Some(val);
+      let lit = self.lit(atom, val);
+      let gc = match depth { None => lit, Some(depth) => self.an(self.ctx[depth], lit) };
+      self.ctx.push(gc);
+      self.lits.push(lit);
+      let pf = self.split(goal)?;
+      self.ctx.pop();
+      self.lits.pop();
+      let stmt = self.im(gc, goal);
+      pfs.push((stmt, pf));
+    }
+    self.assign[ix] = None;
+    self.order.pop();
+    Ok(match depth {
+      None => self.lemma(Lemma::Cases, goal, pfs),
+      Some(depth) => {
+        let concl = self.im(self.ctx[depth], goal);
+        self.lemma(Lemma::CasesD, concl, pfs)
+      }
+    })
+  }
+}
+
+impl Elaborator {
+  /// Implementation of `(register-tauto cfg)`.
+  pub(crate) fn register_tauto(&mut self, span: FileSpan, cfg: &LispVal) -> SResult<()> {
+    let mut map = HashMap::new();
+    for entry in Uncons::from(cfg.clone()) {
+      let mut it = Uncons::from(entry.clone());
+      let (key, val) = match (it.next().and_then(|e| e.as_atom()), it.next(), it.as_lisp().as_atom()) {
+        (Some(key), Some(val), _) if it.exactly(0) => (key, val),
+        (Some(key), None, Some(val)) => (key, LispVal::atom(val)),
+        _ => return Err(format!("expected a pair (key value), got {}", self.print(&entry))),
+      };
+      let val = val.as_atom().ok_or_else(|| format!("expected an atom, got {}", self.print(&val)))?;
+      map.insert(self.data[key].name.clone(), val);
+    }
+    let mut get_term = |name: &str| -> SResult<Option<TermID>> {
+      Ok(match map.remove(name.as_bytes()) {
+        None => None,
+        Some(at) => Some(self.term(at).ok_or_else(|| format!("unknown term '{}'", self.data[at].name))?)
+      })
+    };
+    let (im, not, an) = (get_term("im")?, get_term("not")?, get_term("an")?);
+    let (or, iff) = (get_term("or")?, get_term("iff")?);
+    let (im, not, an) = match (im, not, an) {
+      (Some(im), Some(not), Some(an)) => (im, not, an),
+      _ => return Err("the 'im', 'not' and 'an' terms are required".into())
+    };
+    let mut tbl = ExprTable::new(self.format_env());
+    let (fa, fb, fc) = (tbl.fresh(), tbl.fresh(), tbl.fresh());
+    let im_ = |tbl: &mut ExprTable<'_>, lhs, rhs| tbl.app(im, &[lhs, rhs]);
+    let not_ = |tbl: &mut ExprTable<'_>, lhs| tbl.app(not, &[lhs]);
+    let an_ = |tbl: &mut ExprTable<'_>, lhs, rhs| tbl.app(an, &[lhs, rhs]);
+    let mut lemmas = vec![None; LEMMAS.len()];
+    for &(lem, name, form) in &LEMMAS {
+      let (conn, binop) = match lem {
+        Lemma::OrTrueL | Lemma::OrTrueR | Lemma::OrFalse => (or, or),
+        Lemma::IffTrue | Lemma::IffTrueN | Lemma::IffFalseL | Lemma::IffFalseR => (iff, iff),
+        _ => (Some(im), None),
+      };
+      if conn.is_none() {
+        if map.remove(name.as_bytes()).is_some() {
+          return Err(format!("lemma '{}' given but the corresponding connective is not", name))
+        }
+        continue
+      }
+      let thm = match map.remove(name.as_bytes()) {
+        Some(at) => self.thm(at).ok_or_else(|| format!("unknown theorem '{}'", self.data[at].name))?,
+        None => return Err(format!("missing lemma '{}: {}'", name, form)),
+      };
+      let (na, nb) = (not_(&mut tbl, fa), not_(&mut tbl, fb));
+      let ab = an_(&mut tbl, fa, fb);
+      let op = binop.map(|op| tbl.app(op, &[fa, fb]));
+      let (concl, hyps) = match lem {
+        Lemma::Id => (im_(&mut tbl, fa, fa), vec![]),
+        Lemma::Syl => (im_(&mut tbl, fa, fc), vec![im_(&mut tbl, fa, fb), im_(&mut tbl, fb, fc)]),
+        Lemma::Jca => {
+          let bc = an_(&mut tbl, fb, fc);
+          (im_(&mut tbl, fa, bc), vec![im_(&mut tbl, fa, fb), im_(&mut tbl, fa, fc)])
+        }
+        Lemma::AnL => (im_(&mut tbl, ab, fa), vec![]),
+        Lemma::AnR => (im_(&mut tbl, ab, fb), vec![]),
+        Lemma::Cases => (fb, vec![im_(&mut tbl, fa, fb), im_(&mut tbl, na, fb)]),
+        Lemma::CasesD => {
+          let anb = an_(&mut tbl, fa, nb);
+          (im_(&mut tbl, fa, fc), vec![im_(&mut tbl, ab, fc), im_(&mut tbl, anb, fc)])
+        }
+        Lemma::NotNot => { let nna = not_(&mut tbl, na); (im_(&mut tbl, fa, nna), vec![]) }
+        Lemma::ImFalseL => { let ab = im_(&mut tbl, fa, fb); (im_(&mut tbl, na, ab), vec![]) }
+        Lemma::ImTrueR => { let ab = im_(&mut tbl, fa, fb); (im_(&mut tbl, fb, ab), vec![]) }
+        Lemma::ImFalse => {
+          let (anb, ab) = (an_(&mut tbl, fa, nb), im_(&mut tbl, fa, fb));
+          let nab = not_(&mut tbl, ab);
+          (im_(&mut tbl, anb, nab), vec![])
+        }
+        Lemma::AnFalseL => { let nab = not_(&mut tbl, ab); (im_(&mut tbl, na, nab), vec![]) }
+        Lemma::AnFalseR => { let nab = not_(&mut tbl, ab); (im_(&mut tbl, nb, nab), vec![]) }
+        Lemma::OrTrueL => (im_(&mut tbl, fa, op.expect("or")), vec![]),
+        Lemma::OrTrueR => (im_(&mut tbl, fb, op.expect("or")), vec![]),
+        Lemma::OrFalse | Lemma::IffTrueN => {
+          let nanb = an_(&mut tbl, na, nb);
+          let res = if lem == Lemma::OrFalse { not_(&mut tbl, op.expect("or")) } else { op.expect("iff") };
+          (im_(&mut tbl, nanb, res), vec![])
+        }
+        Lemma::IffTrue => (im_(&mut tbl, ab, op.expect("iff")), vec![]),
+        Lemma::IffFalseL | Lemma::IffFalseR => {
+          let hyp = if lem == Lemma::IffFalseL { an_(&mut tbl, fa, nb) } else { an_(&mut tbl, na, fb) };
+          let res = not_(&mut tbl, op.expect("iff"));
+          (im_(&mut tbl, hyp, res), vec![])
+        }
+      };
+      tbl.check_thm(thm, concl, &hyps, form)?;
+      lemmas[lem as usize] = Some(thm);
+    }
+    if let Some(key) = map.keys().next() {
+      return Err(format!("unknown key '{}'", key))
+    }
+    self.tauto = Some(TautoEnv {span, im, not, an, or, iff, lemmas: lemmas.into()});
+    Ok(())
+  }
+
+  /// Implementation of `(tauto [t])`, which returns a proof of the propositional
+  /// tautology `t`, or the type of the first goal if `t` is not given.
+  pub(crate) fn tauto(&self, t: Option<&LispVal>) -> SResult<LispVal> {
+    let cfg = self.tauto.as_ref().ok_or("tauto has not been configured, see 'register-tauto'")?;
+    let t = match t {
+      Some(t) => t.clone(),
+      None => self.lc.goals.first().and_then(|g| g.goal_type()).ok_or("no goals")?,
+    };
+    let mut tbl = ExprTable::new(self.format_env());
+    let goal = tbl.node(&t)?;
+    let mut st = Tauto {
+      cfg, tbl, atoms: HashMap::new(), atom_list: vec![], assign: vec![],
+      order: vec![], ctx: vec![], lits: vec![], cases: 0
+    };
+    st.collect_atoms(goal);
+    st.assign = vec![None; st.atom_list.len()];
+    st.split(goal)
+  }
+}
+
@@ -0,0 +1,612 @@
+//! Export of goals to the TPTP format for external automated theorem provers, used by the
+//! `tptp-export` and `tptp-reconstruct` builtins and the `mm0-rs tptp` subcommand.
+//!
+//! The translation is configured using `register-tptp`, which maps term constructors to
+//! the logical connectives, quantifiers and equality of TPTP. Terms returning a provable
+//! sort are formulas, and other terms are individuals (all sorts are identified). Any term
+//! that is not mapped becomes an uninterpreted predicate or function symbol `t_name`, and
+//! the variables of the local context become constants `c_name`, applied to the variables
+//! they depend on. Theorems are universally closed over their arguments, which is only
+//! possible in FOF when all the arguments are individuals; in THF, regular variables of
+//! a provable sort or with dependencies are quantified at the appropriate function type.
+//!
+//! The prover is not trusted: [`used_premises`] extracts the premises used in a proof from
+//! the prover output, and `tptp-reconstruct` replays them through the `auto` search.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use crate::util::{FileSpan, Span};
+use super::{Elaborator, ElabError, Remap, Remapper};
+use super::environment::{AtomID, Modifiers, SortID, TermID, ThmID, Type};
+use super::lisp::{LispKind, LispVal, Uncons, print::FormatEnv, eval::SResult};
+use super::local_context::InferSort;
+use super::expr_table::{ExprTable, Node};
+
+/// A logical constant that a term constructor can be mapped to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Conn {
+  /// The true proposition `$true`.
+  True,
+  /// The false proposition `$false`.
+  False,
+  /// Negation `~ a`.
+  Not,
+  /// Conjunction `a & b`.
+  And,
+  /// Disjunction `a | b`.
+  Or,
+  /// Implication `a => b`.
+  Imp,
+  /// The biconditional `a <=> b`.
+  Iff,
+  /// Universal quantification `! [X] : a`.
+  All,
+  /// Existential quantification `? [X] : a`.
+  Ex,
+  /// Equality of individuals `a = b`.
+  Eq,
+  /// Disequality of individuals `a != b`.
+  Ne,
+}
+crate::deep_size_0!(Conn);
+
+/// The logical constants, with their names in the `register-tptp` configuration.
+const CONNS: [(Conn, &str); 11] = [
+  (Conn::True, "true"), (Conn::False, "false"), (Conn::Not, "not"),
+  (Conn::And, "and"), (Conn::Or, "or"), (Conn::Imp, "imp"), (Conn::Iff, "iff"),
+  (Conn::All, "all"), (Conn::Ex, "ex"), (Conn::Eq, "eq"), (Conn::Ne, "ne"),
+];
+
+/// The mapping of terms to logical constants, set by `register-tptp` and stored in the
+/// [`Environment`](super::Environment) so that it is available to importing files.
+#[derive(Clone, Debug, DeepSizeOf)]
+pub struct TptpEnv {
+  /// The location of the (first) registration.
+  pub span: FileSpan,
+  /// The logical constant for each mapped term.
+  pub conns: HashMap<TermID, Conn>,
+}
+
+impl Remap for TptpEnv {
+  type Target = Self;
+  fn remap(&self, r: &mut Remapper) -> Self {
+    TptpEnv {
+      span: self.span.clone(),
+      conns: self.conns.iter().map(|(t, &c)| (t.remap(r), c)).collect(),
+    }
+  }
+}
+
+impl TptpEnv {
+  /// Merge the configuration of an imported environment into this one.
+  pub fn merge(this: &mut Option<Self>, other: &Option<Self>, r: &mut Remapper,
+      errors: &mut Vec<ElabError>, sp: Span) {
+    if let Some(other) = other {
+      let other = other.remap(r);
+      match this {
+        None => *this = Some(other),
+        Some(old) => for (t, c) in other.conns {
+          if *old.conns.entry(t).or_insert(c) != c {
+            errors.push(ElabError::with_info(sp, "term mapped to two TPTP connectives".into(),
+              vec![(other.span, "registered here".into()), (old.span.clone(), "registered here".into())]));
+            break
+          }
+        }
+      }
+    }
+  }
+}
+
+/// The TPTP language to export to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Lang {
+  /// First-order form.
+  Fof,
+  /// Typed higher-order form.
+  Thf,
+}
+
+impl Lang {
+  fn name(self) -> &'static str {
+    match self { Lang::Fof => "fof", Lang::Thf => "thf" }
+  }
+}
+
+/// The name of the conjecture in an exported problem.
+pub const GOAL_NAME: &str = "mm0_goal";
+
+/// Encode a name as a TPTP formula name: lower words are left alone, and any other name
+/// is single quoted.
+#[must_use] pub fn formula_name(s: &[u8]) -> String {
+  if s.first().map_or(false, u8::is_ascii_lowercase) &&
+    s.iter().all(|&c| c.is_ascii_alphanumeric() || c == b'_') {
+    return String::from_utf8_lossy(s).into()
+  }
+  let mut out = String::from("'");
+  for &c in s {
+    if c == b'\'' || c == b'\\' { out.push('\\') }
+    out.push(c as char)
+  }
+  out.push('\'');
+  out
+}
+
+/// Encode a name as a TPTP symbol with the given prefix. Alphanumeric characters are
+/// kept, `_` is doubled and other characters are written as `_` followed by two hex digits,
+/// so that distinct names map to distinct symbols.
+fn symbol(prefix: &str, s: &[u8]) -> String {
+  let mut out = String::from(prefix);
+  for &c in s {
+    if c.is_ascii_alphanumeric() { out.push(c as char) }
+    else if c == b'_' { out.push_str("__") }
+    else { write!(out, "_{:02x}", c).expect("impossible") }
+  }
+  out
+}
+
+/// Parse a TPTP name (a lower word or a single quoted string) at the start of `s`,
+/// after skipping whitespace, returning the name and the rest of the input.
+fn parse_name(s: &str) -> Option<(String, &str)> {
+  let s = s.trim_start();
+  if let Some(s) = s.strip_prefix('\'') {
+    let mut out = String::new();
+    let mut it = s.char_indices();
+    while let Some((i, c)) = it.next() {
+      match c {
+        '\'' => return Some((out, &s[i + 1..])),
+        '\\' => out.push(it.next()?.1),
+        c => out.push(c),
+      }
+    }
+    None
+  } else {
+    let n = s.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(s.len());
+    if n == 0 { return None }
+    Some((s[..n].into(), &s[n..]))
+  }
+}
+
+/// Extract the names of the premises used in a proof from the output of a prover.
+/// The output must contain an SZS status line reporting `Theorem` or `Unsatisfiable`.
+/// The premises are the formulas of role `axiom` or `hypothesis` that appear in the
+/// output, as well as the names in `file(...)` source annotations, in order of appearance.
+pub fn used_premises(output: &str) -> Result<Vec<String>, String> {
+  let status = output.split("SZS status").nth(1).and_then(|s| s.split_whitespace().next())
+    .ok_or("no SZS status in the prover output")?;
+  if status != "Theorem" && status != "Unsatisfiable" {
+    return Err(format!("the prover did not find a proof (SZS status {})", status))
+  }
+  let mut out = vec![];
+  let mut push = |name: String| if name != GOAL_NAME && !out.contains(&name) { out.push(name) };
+  let mut s = output;
+  while let Some(i) = s.find('(') {
+    let head = s[..i].rsplit(|c: char| !c.is_ascii_alphanumeric()).next().unwrap_or("");
+    s = &s[i + 1..];
+    match head {
+      "fof" | "thf" | "tff" | "cnf" => if let Some((name, rest)) = parse_name(s) {
+        if let Some(rest) = rest.trim_start().strip_prefix(',') {
+          let rest = rest.trim_start();
+          if rest.starts_with("axiom") || rest.starts_with("hypothesis") { push(name) }
+        }
+      },
+      "file" => {
+        // skip the file name, which is a quoted string
+        if let Some((_, rest)) = parse_name(s) {
+          if let Some((name, rest)) = rest.trim_start().strip_prefix(',').and_then(parse_name) {
+            if rest.trim_start().starts_with(')') { push(name) }
+          }
+        }
+      }
+      _ => {}
+    }
+  }
+  Ok(out)
+}
+
+/// A variable in scope during the translation: its TPTP name, and the nodes of the
+/// variables it depends on (for theorem arguments in THF).
+struct ScopeVar {
+  node: usize,
+  name: String,
+  deps: Vec<usize>,
+}
+
+/// The sort of each variable of the local context, and the dependencies of the
+/// regular variables (`None` for bound variables).
+pub(crate) type Locals = HashMap<AtomID, (SortID, Option<Box<[AtomID]>>)>;
+
+/// The state of the translation of a problem to TPTP.
+pub(crate) struct Export<'a> {
+  /// The expression table for the statements. Theorem arguments are represented as holes.
+  pub(crate) tbl: ExprTable<'a>,
+  conns: HashMap<TermID, Conn>,
+  lang: Lang,
+  /// The sorts of the variables of the local context, and the dependencies of the
+  /// regular variables (`None` for bound variables).
+  locals: Locals,
+  /// The quantified variables in scope, innermost last.
+  scope: Vec<ScopeVar>,
+  /// The number of quantified variables created so far.
+  next_var: usize,
+  /// The uninterpreted symbols used, with their THF types, in order of first use.
+  symbols: Vec<(String, String)>,
+  seen: HashSet<String>,
+  /// The formulas of the problem.
+  body: String,
+}
+
+impl std::fmt::Debug for Export<'_> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Export {{ lang: {:?}, body: {:?} }}", self.lang, self.body)
+  }
+}
+
+impl<'a> Export<'a> {
+  /// Create a new problem, given the sorts and dependencies of the variables of the local
+  /// context. Regular variables become constants, and bound variables are universally
+  /// quantified in each formula.
+  #[must_use] pub(crate) fn new(fe: FormatEnv<'a>, lang: Lang, locals: Locals) -> Self {
+    let conns = fe.tptp.as_ref().map(|cfg| cfg.conns.clone()).unwrap_or_default();
+    Export {
+      tbl: ExprTable::new(fe), conns, lang, locals, scope: vec![], next_var: 0,
+      symbols: vec![], seen: HashSet::new(), body: String::new(),
+    }
+  }
+
+  fn is_prop(&self, s: SortID) -> bool {
+    self.tbl.fe.sorts[s].mods.contains(Modifiers::PROVABLE)
+  }
+
+  fn ty(&self, s: SortID) -> &'static str { if self.is_prop(s) { "$o" } else { "$i" } }
+
+  fn sort_name(&self, s: SortID) -> String {
+    String::from_utf8_lossy(&self.tbl.fe.sorts[s].name).into()
+  }
+
+  fn new_var(&mut self) -> String {
+    self.next_var += 1;
+    format!("X{}", self.next_var)
+  }
+
+  /// The binder for a quantified variable of sort `s`, which is a function of `ndeps`
+  /// individuals (only possible in THF).
+  fn binder(&self, name: &str, ndeps: usize, s: SortID) -> String {
+    match self.lang {
+      Lang::Fof => name.into(),
+      Lang::Thf => format!("{}: {}{}", name, "$i > ".repeat(ndeps), self.ty(s)),
+    }
+  }
+
+  /// Returns true if the variable node `v` occurs in `i`, directly or as a dependency.
+  fn mentions(&self, i: usize, v: usize) -> bool {
+    if i == v { return true }
+    match self.tbl.nodes[i] {
+      Node::App(_, ref es) => es.iter().any(|&e| self.mentions(e, v)),
+      Node::Var(a) => matches!((self.locals.get(&a), &self.tbl.nodes[v]),
+        (Some((_, Some(ds))), &Node::Var(b)) if ds.contains(&b)),
+      Node::Hole(_) => self.scope.iter().rev().find(|sv| sv.node == i).map_or(false, |sv| sv.deps.contains(&v)),
+      Node::MVar(_) => false,
+    }
+  }
+
+  /// Translate the formula `i`, universally closed over the bound variables in `bound`
+  /// (given as nodes with their sorts) that occur in it.
+  fn closed(&mut self, i: usize, bound: &[(usize, SortID)], args: &[(Option<AtomID>, Type)]) -> SResult<String> {
+    let n = self.scope.len();
+    let mut binders = vec![];
+    for &(node, s) in bound {
+      if self.mentions(i, node) {
+        let name = self.new_var();
+        binders.push(self.binder(&name, 0, s));
+        self.scope.push(ScopeVar {node, name, deps: vec![]});
+      }
+    }
+    let f = self.expr(i, true, args);
+    self.scope.truncate(n);
+    let f = f?;
+    Ok(if binders.is_empty() { f } else { format!("(! [{}] : {})", binders.join(", "), f) })
+  }
+
+  /// Record the use of an uninterpreted symbol with the given THF type.
+  fn use_symbol(&mut self, name: &str, ty: impl FnOnce() -> String) {
+    if self.seen.insert(name.into()) { self.symbols.push((name.into(), ty())) }
+  }
+
+  /// Write the application of `f` to the already translated `args`.
+  fn apply(&self, f: String, args: &[String]) -> String {
+    if args.is_empty() { return f }
+    match self.lang {
+      Lang::Fof => format!("{}({})", f, args.join(",")),
+      Lang::Thf => format!("({} @ {})", f, args.join(" @ ")),
+    }
+  }
+
+  /// The sort of a node, where the hole `j` is the argument `j` of `args`.
+  fn sort(&self, i: usize, args: &[(Option<AtomID>, Type)]) -> SResult<SortID> {
+    Ok(match self.tbl.nodes[i] {
+      Node::Var(a) => match self.locals.get(&a) {
+        Some(&(s, _)) => s,
+        None => match self.tbl.fe.term(a) {
+          Some(t) if self.tbl.fe.terms[t].args.is_empty() => self.tbl.fe.terms[t].ret.0,
+          _ => return Err(format!("unknown variable '{}'", self.tbl.fe.data[a].name)),
+        }
+      },
+      Node::Hole(j) => match args[j].1 { Type::Bound(s) | Type::Reg(s, _) => s },
+      Node::App(t, _) => self.tbl.fe.terms[t].ret.0,
+      Node::MVar(_) => return Err("cannot export a goal containing metavariables".into()),
+    })
+  }
+
+  /// Translate the expression `i` in a formula position if `prop` is true, and in a
+  /// term position otherwise.
+  #[allow(clippy::many_single_char_names)]
+  fn expr(&mut self, i: usize, prop: bool, args: &[(Option<AtomID>, Type)]) -> SResult<String> {
+    let s = self.sort(i, args)?;
+    if prop != self.is_prop(s) && (prop || self.lang == Lang::Fof) {
+      return Err(format!("expected {}, got an expression of sort '{}'",
+        if prop {"a formula"} else {"a first-order term"}, self.sort_name(s)))
+    }
+    if let Some(v) = self.scope.iter().rev().find(|v| v.node == i) {
+      let (name, deps) = (v.name.clone(), v.deps.clone());
+      let deps = deps.into_iter().map(|d| self.expr(d, false, args)).collect::<SResult<Vec<_>>>()?;
+      return Ok(self.apply(name, &deps))
+    }
+    match self.tbl.nodes[i].clone() {
+      Node::Var(a) => {
+        let (name, deps) = match self.locals.get(&a) {
+          Some((_, Some(deps))) => (symbol("c_", &self.tbl.fe.data[a].name), deps.clone()),
+          Some((_, None)) => return Err(format!("bound variable '{}' is not in scope", self.tbl.fe.data[a].name)),
+          None => {
+            // A nullary term, left as an atom by an unelaborated math quotation
+            let t = self.tbl.fe.term(a).expect("checked in sort");
+            let n = self.tbl.app(t, &[]);
+            return self.expr(n, prop, args)
+          }
+        };
+        let ty = deps.iter().map(|d| Ok(self.ty(self.locals.get(d)
+          .ok_or_else(|| format!("unknown variable '{}'", self.tbl.fe.data[*d].name))?.0)))
+          .collect::<SResult<Vec<_>>>()?;
+        let ret = self.ty(s);
+        self.use_symbol(&name, || {
+          let mut out = String::new();
+          for t in &ty { write!(out, "{} > ", t).expect("impossible") }
+          out.push_str(ret);
+          out
+        });
+        let deps = deps.iter().map(|&d| {
+          let d = self.tbl.intern(Node::Var(d));
+          self.expr(d, false, args)
+        }).collect::<SResult<Vec<_>>>()?;
+        Ok(self.apply(name, &deps))
+      }
+      Node::Hole(j) => Err(format!("variable {} is not in scope", j)),
+      Node::MVar(_) => unreachable!("checked in sort"),
+      Node::App(t, es) => {
+        let fe = self.tbl.fe;
+        let td = &fe.terms[t];
+        if let Some(&c) = self.conns.get(&t) {
+          let mut arg = |k: usize, prop| self.expr(es[k], prop, args);
+          return Ok(match c {
+            Conn::True => "$true".into(),
+            Conn::False => "$false".into(),
+            Conn::Not => format!("~ {}", arg(0, true)?),
+            Conn::And => format!("({} & {})", arg(0, true)?, arg(1, true)?),
+            Conn::Or => format!("({} | {})", arg(0, true)?, arg(1, true)?),
+            Conn::Imp => format!("({} => {})", arg(0, true)?, arg(1, true)?),
+            Conn::Iff => format!("({} <=> {})", arg(0, true)?, arg(1, true)?),
+            Conn::Eq => format!("({} = {})", arg(0, false)?, arg(1, false)?),
+            Conn::Ne => format!("({} != {})", arg(0, false)?, arg(1, false)?),
+            Conn::All | Conn::Ex => {
+              let bs = match td.args[0].1 { Type::Bound(s) | Type::Reg(s, _) => s };
+              let name = self.new_var();
+              let binder = self.binder(&name, 0, bs);
+              self.scope.push(ScopeVar {node: es[0], name, deps: vec![]});
+              let body = self.expr(es[1], true, args);
+              self.scope.pop();
+              format!("({} [{}] : {})", if c == Conn::All {"!"} else {"?"}, binder, body?)
+            }
+          })
+        }
+        let name = symbol("t_", &self.tbl.fe.data[td.atom].name);
+        if td.args.iter().any(|(_, ty)| matches!(ty, Type::Bound(_))) {
+          return Err(format!("term '{}' binds a variable but is not mapped to a quantifier",
+            self.tbl.fe.data[td.atom].name))
+        }
+        let tys = td.args.iter().map(|(_, ty)| match *ty { Type::Bound(s) | Type::Reg(s, _) => s })
+          .collect::<Vec<_>>();
+        let mut ty = String::new();
+        for &s in &tys { write!(ty, "{} > ", self.ty(s)).expect("impossible") }
+        ty.push_str(self.ty(s));
+        self.use_symbol(&name, || ty);
+        let es = es.iter().zip(tys).map(|(&e, s)| {
+          let p = self.is_prop(s);
+          self.expr(e, p, args)
+        }).collect::<SResult<Vec<_>>>()?;
+        Ok(self.apply(name, &es))
+      }
+    }
+  }
+
+  /// Run `f`, discarding the symbols it records if it fails.
+  fn transaction(&mut self, f: impl FnOnce(&mut Self) -> SResult<String>) -> SResult<String> {
+    let n = self.symbols.len();
+    let res = f(self);
+    if res.is_err() {
+      for (s, _) in self.symbols.drain(n..) { self.seen.remove(&s); }
+      self.scope.clear();
+    }
+    res
+  }
+
+  fn push_formula(&mut self, name: &str, role: &str, f: &str) {
+    writeln!(self.body, "{}({}, {}, {}).", self.lang.name(), name, role, f).expect("impossible")
+  }
+
+  /// Add a formula about the local context, with the given name and role.
+  pub(crate) fn local(&mut self, name: &str, role: &str, i: usize) -> SResult<()> {
+    let mut bound = self.locals.iter().filter(|(_, (_, deps))| deps.is_none())
+      .map(|(&a, &(s, _))| (a, s)).collect::<Vec<_>>();
+    bound.sort_by_key(|&(a, _)| a);
+    let bound = bound.into_iter().map(|(a, s)| (self.tbl.intern(Node::Var(a)), s)).collect::<Vec<_>>();
+    let f = self.transaction(|this| this.closed(i, &bound, &[]))?;
+    self.push_formula(name, role, &f);
+    Ok(())
+  }
+
+  /// Add a theorem as an axiom, universally closed over its arguments.
+  pub(crate) fn thm(&mut self, thm: ThmID) -> SResult<()> {
+    let td = &self.tbl.fe.env.thms[thm];
+    let name = formula_name(&self.tbl.fe.data[td.atom].name);
+    let f = self.transaction(|this| {
+      let td = &this.tbl.fe.env.thms[thm];
+      let nargs = td.args.len();
+      let hyps = td.hyps.iter().map(|(_, h)| this.tbl.inst_holes(&td.heap, nargs, h, 0)).collect::<Vec<_>>();
+      let td = &this.tbl.fe.env.thms[thm];
+      let ret = this.tbl.inst_holes(&td.heap, nargs, &td.ret, 0);
+      let td = &this.tbl.fe.env.thms[thm];
+      // The regular variables are quantified outside, and each hypothesis and the
+      // conclusion are separately closed over the bound variables.
+      let mut binders = vec![];
+      let mut bound = vec![];
+      for (j, &(_, ty)) in td.args.iter().enumerate() {
+        let node = this.tbl.intern(Node::Hole(j));
+        match ty {
+          Type::Bound(s) => bound.push((node, s)),
+          Type::Reg(s, deps) => {
+            let deps = bound.iter().enumerate()
+              .filter(|&(i, _)| deps & (1 << i) != 0).map(|(_, &(n, _))| n).collect::<Vec<_>>();
+            if this.lang == Lang::Fof && (!deps.is_empty() || this.is_prop(s)) {
+              return Err(format!("'{}' is not first-order; use THF",
+                this.tbl.fe.data[this.tbl.fe.env.thms[thm].atom].name))
+            }
+            let name = this.new_var();
+            binders.push(this.binder(&name, deps.len(), s));
+            this.scope.push(ScopeVar {node, name, deps});
+          }
+        }
+      }
+      let args = &this.tbl.fe.env.thms[thm].args;
+      let mut f = this.closed(ret, &bound, args)?;
+      if !hyps.is_empty() {
+        let hyps = hyps.into_iter().map(|h| this.closed(h, &bound, args)).collect::<SResult<Vec<_>>>()?;
+        f = if hyps.len() == 1 { format!("({} => {})", hyps[0], f) }
+          else { format!("(({}) => {})", hyps.join(" & "), f) };
+      }
+      this.scope.clear();
+      Ok(if binders.is_empty() { f } else { format!("(! [{}] : {})", binders.join(", "), f) })
+    })?;
+    self.push_formula(&name, "axiom", &f);
+    Ok(())
+  }
+
+  /// Finish the problem, adding the type declarations (in THF).
+  #[must_use] pub(crate) fn finish(self) -> String {
+    let mut out = String::from("% TPTP problem exported by mm0-rs\n");
+    if self.lang == Lang::Thf {
+      for (name, ty) in &self.symbols {
+        writeln!(out, "thf({}_type, type, {}: {}).", name, name, ty).expect("impossible")
+      }
+    }
+    out.push_str(&self.body);
+    out
+  }
+}
+
+impl Elaborator {
+  /// Implementation of `(register-tptp cfg)`.
+  pub(crate) fn register_tptp(&mut self, span: FileSpan, cfg: &LispVal) -> SResult<()> {
+    let mut conns = HashMap::new();
+    for entry in Uncons::from(cfg.clone()) {
+      let mut it = Uncons::from(entry.clone());
+      let key = it.next().and_then(|e| e.as_atom())
+        .ok_or_else(|| format!("expected a list (key terms...), got {}", self.print(&entry)))?;
+      let name = &self.data[key].name;
+      let c = CONNS.iter().find(|&&(_, s)| s.as_bytes() == &**name).map(|&(c, _)| c)
+        .ok_or_else(|| format!("unknown connective '{}'", name))?;
+      for e in it {
+        let t = e.as_atom().and_then(|a| self.term(a))
+          .ok_or_else(|| format!("expected a term, got {}", self.print(&e)))?;
+        self.check_conn(t, c)?;
+        if conns.insert(t, c).is_some() {
+          return Err(format!("term '{}' mapped twice", self.print(&e)))
+        }
+      }
+    }
+    match &mut self.tptp {
+      None => self.tptp = Some(TptpEnv {span, conns}),
+      Some(old) => for (t, c) in conns {
+        if *old.conns.entry(t).or_insert(c) != c {
+          return Err(format!("term '{}' is already mapped to a different connective",
+            self.data[self.terms[t].atom].name))
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// Check that the term `t` has the right type to be mapped to `c`.
+  fn check_conn(&self, t: TermID, c: Conn) -> SResult<()> {
+    let td = &self.terms[t];
+    let prop = |s: SortID| self.sorts[s].mods.contains(Modifiers::PROVABLE);
+    let reg = |i: usize, p: bool| matches!(td.args[i].1, Type::Reg(s, 0) if prop(s) == p);
+    let ok = prop(td.ret.0) && match c {
+      Conn::True | Conn::False => td.args.is_empty(),
+      Conn::Not => td.args.len() == 1 && reg(0, true),
+      Conn::And | Conn::Or | Conn::Imp | Conn::Iff => td.args.len() == 2 && reg(0, true) && reg(1, true),
+      Conn::Eq | Conn::Ne => td.args.len() == 2 && reg(0, false) && reg(1, false),
+      Conn::All | Conn::Ex => td.args.len() == 2 &&
+        matches!(td.args[0].1, Type::Bound(s) if !prop(s)) &&
+        matches!(td.args[1].1, Type::Reg(s, 1) if prop(s)),
+    };
+    if ok { Ok(()) } else {
+      let name = CONNS.iter().find(|p| p.0 == c).expect("impossible").1;
+      Err(format!("term '{}' does not have the right type for '{}'",
+        self.data[td.atom].name, name))
+    }
+  }
+
+  /// Implementation of `(tptp-export premises [goal] [lang])`, which returns the TPTP
+  /// problem of proving `goal` (by default the first goal) from the list of hypotheses
+  /// and theorems `premises`.
+  pub(crate) fn tptp_export(&self, premises: &LispVal, goal: Option<&LispVal>, lang: Lang) -> SResult<String> {
+    let goal = match goal {
+      Some(t) => t.clone(),
+      None => self.lc.goals.first().and_then(|g| g.goal_type()).ok_or("no goals")?,
+    };
+    let locals = self.lc.vars.iter().map(|(&a, (_, is))| match is {
+      InferSort::Bound(s) => Ok((a, (*s, None))),
+      InferSort::Reg(s, deps) => Ok((a, (*s, Some(deps.clone())))),
+      InferSort::Unknown {..} => Err(format!("variable '{}' has unknown sort", self.data[a].name)),
+    }).collect::<SResult<_>>()?;
+    let mut exp = Export::new(self.format_env(), lang, locals);
+    for e in Uncons::from(premises.clone()) {
+      let a = e.as_atom().ok_or_else(|| format!("expected a premise name, got {}", self.print(&e)))?;
+      let res = if let Some((_, ty, _)) = self.lc.get_proof(a) {
+        let i = exp.tbl.node(ty)?;
+        exp.local(&formula_name(&self.data[a].name), "hypothesis", i)
+      } else {
+        exp.thm(self.thm(a).ok_or_else(|| format!("unknown premise '{}'", self.data[a].name))?)
+      };
+      res.map_err(|e| format!("premise '{}': {}", self.data[a].name, e))?
+    }
+    let i = exp.tbl.node(&goal)?;
+    exp.local(GOAL_NAME, "conjecture", i)?;
+    Ok(exp.finish())
+  }
+
+  /// Implementation of `(tptp-reconstruct used [goal] [depth])`, which proves `goal`
+  /// (by default the first goal) using `auto` with the theorems in `used`, which is either
+  /// a list of premise names or the output of a prover on a problem from `tptp-export`.
+  pub(crate) fn tptp_reconstruct(&self, used: &LispVal, goal: Option<&LispVal>, depth: usize) -> SResult<LispVal> {
+    let thms = match used.unwrapped(|e| if let LispKind::String(s) = e { Some(s.clone()) } else { None }) {
+      Some(s) => used_premises(&String::from_utf8_lossy(&s))?.into_iter()
+        .filter_map(|name| self.atoms.get(name.as_bytes()).copied()).collect(),
+      None => Uncons::from(used.clone()).map(|e| e.as_atom()
+        .ok_or_else(|| format!("expected a premise name, got {}", self.print(&e))))
+        .collect::<SResult<Vec<_>>>()?,
+    };
+    // Hypotheses are always available to `auto`
+    let thms = thms.into_iter().filter(|&a| self.thm(a).is_some()).map(LispVal::atom).collect::<Vec<_>>();
+    self.auto(&LispVal::list(thms), goal, depth)
+  }
+}
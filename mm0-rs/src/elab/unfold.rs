@@ -0,0 +1,105 @@
+//! Definition unfolding, used by the `unfold` and `whnf` builtins.
+//!
+//! Unfolding an expression `e` produces the unfolded expression `e'` together with a
+//! conversion proof `c: e = e'`, so that a proof `p` of `e'` can be turned into a proof
+//! of `e` with `(:conv e c p)`. The conversion is built from `:unfold` steps at each
+//! unfolded definition and congruence steps `(t c1 ... cn)` above them, and uses the
+//! expression itself as the reflexivity proof for unchanged subterms, in the same way as
+//! the conversions produced by `refine`.
+//!
+//! Dummy variables in the body of a definition are replaced by fresh dummy variables
+//! `_1`, `_2`, ... of the local context, as if they were created by `dummy!`.
+
+use super::{Elaborator, lisp::{LispVal, Uncons, InferTarget, eval::SResult},
+  environment::{AtomID, TermID, TermKind}, local_context::InferSort};
+use super::proof::Subst;
+
+/// Which definitions to unfold.
+#[derive(Clone, Copy, Debug)]
+pub enum Delta {
+  /// Unfold all occurrences of this definition.
+  Def(TermID),
+  /// Unfold all definitions, producing the delta-normal form.
+  All,
+  /// Unfold only the head of the expression, until it is not a definition.
+  Head,
+}
+
+impl Elaborator {
+  /// Unfold the expression `e`, returning `None` if nothing was unfolded, or the
+  /// unfolded expression `e'` and a conversion proof `c: e = e'`.
+  #[allow(clippy::many_single_char_names)]
+  pub fn delta(&mut self, mode: Delta, e: &LispVal) -> SResult<Option<(LispVal, LispVal)>> {
+    if !e.is_list() {return Ok(None)}
+    let mut u = Uncons::from(e.clone());
+    let head = u.next().and_then(|a| a.as_atom()).ok_or_else(||
+      format!("bad term: {}", self.print(e)))?;
+    let t = self.term(head).ok_or_else(|| format!("unknown term '{}'", self.data[head].name))?;
+    let tdata = &self.env.terms[t];
+    let nargs = tdata.args.len();
+    let mut args = Vec::with_capacity(nargs);
+    if !u.exactly(nargs) || !u.extend_into(nargs, &mut args) {
+      return Err(format!("bad term: {}", self.print(e)))
+    }
+    let unfold = match mode { Delta::Def(t2) => t == t2, Delta::All | Delta::Head => true };
+    match (&tdata.kind, mode) {
+      (TermKind::Def(Some(val)), _) if unfold => {
+        let n = self.lc.mvars.len();
+        let e2 = Subst::new(&self.env, &val.heap, args.clone()).subst_mut(&mut self.lc, &val.head);
+        for i in n..self.lc.mvars.len() {
+          let m = self.lc.mvars[i].clone();
+          if let Some(sort) = m.mvar_target().and_then(|tgt| match tgt {
+            InferTarget::Bound(s) => self.data[s].sort,
+            _ => None,
+          }) {
+            let x = self.fresh_dummy();
+            self.lc.vars.insert(x, (true, InferSort::Bound(sort)));
+            m.as_ref_(|e| *e = LispVal::atom(x));
+          }
+        }
+        let (e2, c) = match self.delta(mode, &e2)? {
+          Some((e3, c)) => (e3, c),
+          None => (e2.clone(), e2),
+        };
+        Ok(Some((e2, LispVal::unfold(head, args, c))))
+      }
+      (_, Delta::Head) => Ok(None),
+      _ => {
+        let mut es = vec![LispVal::atom(head)];
+        let mut cs = vec![LispVal::atom(head)];
+        let mut changed = false;
+        for x in args {
+          if let Some((x2, c)) = self.delta(mode, &x)? {
+            es.push(x2);
+            cs.push(c);
+            changed = true;
+          } else {
+            es.push(x.clone());
+            cs.push(x);
+          }
+        }
+        Ok(if changed {Some((LispVal::list(es), LispVal::list(cs)))} else {None})
+      }
+    }
+  }
+
+  /// Get the first of `_1`, `_2`, ... which is not a variable of the local context.
+  fn fresh_dummy(&mut self) -> AtomID {
+    let mut i = 1;
+    loop {
+      let a = self.get_atom(format!("_{}", i).as_bytes());
+      if !self.lc.vars.contains_key(&a) {return a}
+      i += 1;
+    }
+  }
+
+  /// Implementation of the `unfold` and `whnf` builtins: unfold `e` and return the list
+  /// `(e' c)`, where `c: e = e'` is a conversion proof (which is `e` itself if nothing
+  /// was unfolded).
+  pub(crate) fn unfold_builtin(&mut self, mode: Delta, e: &LispVal) -> SResult<LispVal> {
+    Ok(match self.delta(mode, e)? {
+      Some((e2, c)) => LispVal::list(vec![e2, c]),
+      None => LispVal::list(vec![e.clone(), e.clone()]),
+    })
+  }
+}
@@ -0,0 +1,685 @@
+//! A source formatter for MM1 and MM0 files.
+//!
+//! The formatter works statement by statement, and only touches the parts of the file
+//! it understands, leaving everything else (in particular comments) in place:
+//!
+//! * Whitespace between statements is normalized: trailing whitespace is removed, and runs
+//!   of blank lines are collapsed into one.
+//! * Declaration headers are laid out from their components, merging adjacent binder groups
+//!   with the same type, so that `(a: wff) (b: wff)` becomes `(a b: wff)`. A header is put on
+//!   one line if it fits, and otherwise the line breaks of the original are kept, with
+//!   continuation lines indented by two spaces. Headers that contain comments are not
+//!   reflowed.
+//! * Math formulas keep their text, including any parentheses added for readability,
+//!   and only their whitespace is normalized. With `mm0-rs fmt --reprint`, they are instead
+//!   pretty printed using the notations of the elaborated environment. A formula is
+//!   only reprinted if the result parses back to the same expression, and it only
+//!   uses notations that are declared before the formula.
+//! * Lisp s-expressions are re-indented according to their bracket structure: each line
+//!   that leaves brackets open increases the indentation of the following lines by two.
+//!   Line breaks, comments and doc comments inside s-expressions are preserved.
+//!
+//! The result is checked by parsing it again and comparing the two parse trees, ignoring
+//! source positions, and the formatter refuses to make changes that would alter the parse.
+
+use std::{fs, io, sync::Arc};
+use clap::ArgMatches;
+use crate::elab::{FrozenEnv, environment::{DeclKey, ParserEnv}, lisp::LispVal,
+  math_parser::{parse_math, QExpr, QExprKind}};
+use crate::lined_string::LinedString;
+use crate::parser::{parse, ast::{AST, Atom, Binder, Const, Decl, DeclKind, Delimiter, DepType,
+  Formula, GenNota, Literal, LocalKind, SExpr, SExprKind, SimpleNota, SimpleNotaKind, Stmt,
+  StmtKind, Type}};
+use crate::util::{FileRef, Span};
+
+/// The maximum line width that the formatter aims for.
+pub const WIDTH: usize = 100;
+
+/// A formatting context for a single file.
+#[derive(Debug)]
+pub struct Formatter<'a> {
+  /// The file being formatted
+  path: &'a FileRef,
+  /// The parsed file
+  ast: &'a AST,
+  /// The elaborated environment, used to reprint formulas if provided
+  env: Option<&'a FrozenEnv>,
+}
+
+/// An element of a declaration header, such as a binder group.
+#[derive(Debug)]
+enum Piece {
+  /// A piece of text without line breaks
+  Text(String),
+  /// A math formula
+  Fmla(Formula),
+}
+
+/// A fragment of a declaration header, which is laid out as a unit.
+#[derive(Debug, Default)]
+struct Item {
+  pieces: Vec<Piece>,
+  /// True if there was a line break before this item in the original source
+  brk: bool,
+}
+
+impl Item {
+  fn text(&mut self, s: impl Into<String>) { self.pieces.push(Piece::Text(s.into())) }
+}
+
+fn trim_end(s: &str) -> &str { s.trim_end_matches(&[' ', '\n'][..]) }
+
+/// The column at the end of `s`, if it is printed starting at column `col`.
+fn end_col(s: &str, col: usize) -> usize {
+  match s.rfind('\n') { Some(n) => s.len() - n - 1, None => col + s.len() }
+}
+
+fn ty_start(ty: &Type) -> usize {
+  match ty {
+    Type::DepType(d) => d.sort.start,
+    Type::Formula(f) => f.0.start,
+  }
+}
+
+/// Returns true if `text` contains a line comment (`--`) outside of a math formula.
+fn has_comment(text: &[u8]) -> bool {
+  let mut in_fmla = false;
+  text.iter().enumerate().any(|(i, &c)| {
+    if c == b'$' { in_fmla = !in_fmla }
+    !in_fmla && c == b'-' && text.get(i + 1) == Some(&b'-')
+  })
+}
+
+/// Re-indent an s-expression (or any statement consisting of bracketed groups).
+///
+/// The first line is kept as is, and is assumed to be at indentation `base`. A line
+/// inside an open bracket is indented two spaces past the line that opened the bracket,
+/// and a line starting with a closing bracket is aligned with the line that opened it.
+/// Inside an `@` (which has no closing bracket), and outside all brackets, the original
+/// indentation relative to the enclosing line is kept, but no less than `base + cont`
+/// at the top level. Lines that start inside a string or formula are kept unchanged.
+#[must_use] pub fn reindent(text: &str, base: usize, cont: usize) -> String {
+  /// An open bracket or `@`, with the new and original indentation of its line.
+  struct Open { at: bool, indent: usize, orig: usize }
+  let mut stack: Vec<Open> = vec![];
+  let (mut in_str, mut in_fmla) = (false, false);
+  let mut out = String::with_capacity(text.len());
+  for (i, line) in text.split('\n').enumerate() {
+    let (indent, orig, line) = if i == 0 {
+      (base, base, line)
+    } else {
+      out.push('\n');
+      if in_str || in_fmla { (0, 0, line) } else {
+        let trimmed = line.trim_start_matches(' ');
+        let orig = line.len() - trimmed.len();
+        let indent = if matches!(trimmed.as_bytes().first(), Some(b')' | b']' | b'}')) {
+          stack.iter().rfind(|o| !o.at).map_or(base + cont, |o| o.indent)
+        } else {
+          // Skip `@`s from lines that are indented further than this one
+          let n = stack.iter().rposition(|o| !o.at || o.orig <= orig);
+          match n.map(|n| &stack[n]) {
+            Some(o) if o.at => o.indent + orig - o.orig,
+            Some(o) => o.indent + 2,
+            None => orig.max(base + cont),
+          }
+        };
+        if !trimmed.is_empty() { for _ in 0..indent { out.push(' ') } }
+        (indent, orig, trimmed)
+      }
+    };
+    let bytes = line.as_bytes();
+    let mut j = 0;
+    while j < bytes.len() {
+      let c = bytes[j];
+      if in_str {
+        match c {
+          b'\\' => j += 1,
+          b'"' => in_str = false,
+          _ => {}
+        }
+      } else if in_fmla {
+        if c == b'$' { in_fmla = false }
+      } else {
+        match c {
+          b'"' => in_str = true,
+          b'$' => in_fmla = true,
+          b'-' if bytes.get(j + 1) == Some(&b'-') &&
+            (j == 0 || matches!(bytes[j - 1], b' ' | b'(' | b')' | b'[' | b']' | b'{' | b'}')) =>
+            break,
+          b'@' => stack.push(Open {at: true, indent, orig}),
+          b'(' | b'[' | b'{' => stack.push(Open {at: false, indent, orig}),
+          b')' | b']' | b'}' => while let Some(o) = stack.pop() { if !o.at { break } },
+          _ => {}
+        }
+      }
+      j += 1;
+    }
+    if in_str || in_fmla { out.push_str(line) } else { out.push_str(trim_end(line)) }
+  }
+  out
+}
+
+/// Normalize the whitespace and comments between two statements. `first` and `last` are
+/// true if this is the text before the first statement or after the last one, respectively.
+fn format_gap(text: &str, first: bool, last: bool) -> String {
+  if !text.contains('\n') { return if first || last {String::new()} else {text.into()} }
+  let mut lines: Vec<&str> = text.split('\n').map(trim_end).collect();
+  let mut out = String::new();
+  if !first {
+    // the rest of the line of the previous statement
+    out.push_str(lines.remove(0));
+    out.push('\n');
+  }
+  if last {
+    while lines.last() == Some(&"") { lines.pop(); }
+  } else {
+    // the indentation of the next statement
+    lines.pop();
+  }
+  let mut blank = first;
+  for line in lines {
+    if line.is_empty() {
+      if blank { continue }
+      blank = true;
+    } else { blank = false }
+    out.push_str(line);
+    out.push('\n');
+  }
+  out
+}
+
+impl<'a> Formatter<'a> {
+  /// Construct a new formatter for the parsed file `ast`. If `env` is provided,
+  /// it should be the result of elaborating this file, and formulas are reprinted using it
+  /// instead of keeping their original text.
+  #[must_use] pub fn new(path: &'a FileRef, ast: &'a AST, env: Option<&'a FrozenEnv>) -> Self {
+    Formatter {path, ast, env}
+  }
+
+  fn source(&self) -> &'a LinedString { &self.ast.source }
+
+  fn str(&self, sp: Span) -> &'a str {
+    // Safety: the source is ASCII
+    unsafe { std::str::from_utf8_unchecked(&self.source()[sp]) }
+  }
+
+  /// Compute the list of edits needed to format the statements that overlap `range`
+  /// (or the whole file if it is `None`). The edits are sorted and non-overlapping.
+  fn raw_edits(&self, range: Option<Span>) -> Vec<(Span, String)> {
+    let overlaps = |sp: Span| range.map_or(true, |r| sp.start <= r.end && r.start <= sp.end);
+    let mut edits = vec![];
+    let mut push = |sp: Span, s: String| if s.as_bytes() != &self.source()[sp] { edits.push((sp, s)) };
+    let mut last = 0;
+    for s in &self.ast.stmts {
+      let gap = (last..s.span.start).into();
+      if overlaps(gap) { push(gap, format_gap(self.str(gap), last == 0, false)) }
+      if overlaps(s.span) { push(s.span, self.stmt(s)) }
+      last = s.span.end;
+    }
+    let gap = (last..self.source().len()).into();
+    if overlaps(gap) {
+      let mut s = format_gap(self.str(gap), last == 0, true);
+      if last != 0 && !s.ends_with('\n') { s.push('\n') }
+      push(gap, s)
+    }
+    edits
+  }
+
+  /// Compute the formatting edits and the resulting text, and check that the result
+  /// parses the same way as the original.
+  fn run(&self, range: Option<Span>) -> Result<(Vec<(Span, String)>, String), String> {
+    let edits = self.raw_edits(range);
+    let src = &***self.source();
+    let mut out = String::with_capacity(src.len());
+    let mut last = 0;
+    for (sp, s) in &edits {
+      out.push_str(&src[last..sp.start]);
+      out.push_str(s);
+      last = sp.end;
+    }
+    out.push_str(&src[last..]);
+    let (_, ast) = parse(Arc::new(out.clone().into()), None);
+    let same = SameAst {fmt: self, a: src.as_bytes(), b: out.as_bytes()};
+    if ast.errors.len() > self.ast.errors.len() ||
+        !same.list(&self.ast.stmts, &ast.stmts, SameAst::stmt) {
+      return Err("formatting would change the meaning of the file".into())
+    }
+    Ok((edits, out))
+  }
+
+  /// Compute the list of edits needed to format the statements that overlap `range`
+  /// (or the whole file if it is `None`). The edits are sorted and non-overlapping.
+  pub fn edits(&self, range: Option<Span>) -> Result<Vec<(Span, String)>, String> {
+    Ok(self.run(range)?.0)
+  }
+
+  /// Format the statements that overlap `range` (or the whole file if it is `None`),
+  /// returning the new text of the file.
+  pub fn format(&self, range: Option<Span>) -> Result<String, String> {
+    Ok(self.run(range)?.1)
+  }
+
+  fn stmt(&self, s: &Stmt) -> String {
+    match &s.k {
+      StmtKind::DocComment(_, inner) => {
+        let mut out = String::new();
+        for line in trim_end(self.str((s.span.start..inner.span.start).into())).split('\n') {
+          out.push_str(line.trim());
+          out.push('\n');
+        }
+        out.push_str(&self.stmt(inner));
+        out
+      }
+      StmtKind::Annot(e, inner) => {
+        let mut out = format!("@{}", reindent(self.str(e.span), 0, 2));
+        let sep = self.str((e.span.end..inner.span.start).into());
+        out.push(if sep.contains('\n') {'\n'} else {' '});
+        out.push_str(&self.stmt(inner));
+        out
+      }
+      &StmtKind::Sort(id, mods) => format!("{}sort {};", mods, self.str(id)),
+      StmtKind::Decl(d) if !has_comment(&self.source()[
+        (s.span.start..d.val.as_ref().map_or(s.span.end, |e| e.span.start)).into()
+      ]) => self.decl(s.span, d),
+      _ => reindent(self.str(s.span), 0, 2),
+    }
+  }
+
+  fn binder_key(&self, bi: &Binder) -> Option<(u8, String)> {
+    let open = self.source().as_bytes()[bi.span.start];
+    match &bi.ty {
+      None => Some((open, String::new())),
+      Some(Type::DepType(d)) => Some((open, self.dep_type(d))),
+      Some(Type::Formula(_)) => None,
+    }
+  }
+
+  fn dep_type(&self, d: &crate::parser::ast::DepType) -> String {
+    let mut s = self.str(d.sort).to_owned();
+    for &x in &*d.deps { s.push(' '); s.push_str(self.str(x)) }
+    s
+  }
+
+  fn ty_item(&self, it: &mut Item, ty: &Type) {
+    match ty {
+      Type::DepType(d) => it.text(self.dep_type(d)),
+      &Type::Formula(f) => it.pieces.push(Piece::Fmla(f)),
+    }
+  }
+
+  fn decl(&self, span: Span, d: &Decl) -> String {
+    let kw = match d.k {
+      DeclKind::Term => "term",
+      DeclKind::Axiom => "axiom",
+      DeclKind::Thm => "theorem",
+      DeclKind::Def => "def",
+    };
+    let mut items = vec![];
+    let mut head = Item::default();
+    head.text(format!("{}{} {}", d.mods, kw, self.str(d.id)));
+    items.push(head);
+    let mut prev_end = d.id.end;
+    let mut arrows = vec![];
+    let mut i = 0;
+    while let Some(bi) = d.bis.get(i) {
+      if bi.local.is_none() { arrows.push(bi); i += 1; continue }
+      let key = self.binder_key(bi);
+      let mut j = i + 1;
+      while let Some(bi2) = d.bis.get(j) {
+        if bi2.local.is_none() { break }
+        if !(bi2.span == bi.span || key.is_some() && self.binder_key(bi2) == key) { break }
+        j += 1;
+      }
+      let group = &d.bis[i..j];
+      let open = self.source().as_bytes()[bi.span.start];
+      let mut it = Item {
+        brk: self.source()[(prev_end..bi.span.start).into()].contains(&b'\n'),
+        ..Item::default()
+      };
+      let mut s = String::new();
+      s.push(open as char);
+      for (k, bi) in group.iter().enumerate() {
+        if k != 0 { s.push(' ') }
+        if let LocalKind::Dummy = bi.kind { s.push('.') }
+        s.push_str(self.str(bi.local.expect("not an arrow binder")));
+      }
+      if let Some(ty) = &bi.ty {
+        s.push_str(": ");
+        it.text(s);
+        self.ty_item(&mut it, ty);
+        s = String::new();
+      }
+      s.push(if open == b'{' {'}'} else {')'});
+      it.text(s);
+      items.push(it);
+      prev_end = group.last().expect("nonempty").span.end;
+      i = j;
+    }
+    if let Some(ty) = &d.ty {
+      items.last_mut().expect("nonempty").text(":");
+      let start = arrows.first().map_or_else(|| ty_start(ty), |bi| bi.span.start);
+      let mut it = Item {
+        brk: self.source()[(prev_end..start).into()].contains(&b'\n'),
+        ..Item::default()
+      };
+      for bi in arrows {
+        self.ty_item(&mut it, bi.ty.as_ref().expect("arrow binders have types"));
+        it.text(" >");
+        items.push(std::mem::take(&mut it));
+      }
+      self.ty_item(&mut it, ty);
+      items.push(it);
+    }
+
+    // Lay out the header
+    let single: Vec<String> = items.iter().map(|it| self.render(it, 0, 0, true)).collect();
+    // the length of the header on one line, including the following ` =` or `;`
+    let total = single.iter().map(|s| s.len() + 1).sum::<usize>() + 1;
+    // If the header had line breaks, only join it if the value also fits on the line
+    let one_line = total <= WIDTH && (!items.iter().any(|it| it.brk) ||
+      d.val.as_ref().map_or(true, |val| match val.k {
+        SExprKind::Formula(f) => total + 2 + self.formula(f, 0, 0, true).len() <= WIDTH,
+        _ => total + 2 + val.span.len() <= WIDTH && !self.source()[val.span].contains(&b'\n'),
+      }));
+    let mut out = String::new();
+    let (mut col, mut ind) = (0, 0);
+    for (k, (it, s)) in items.iter().zip(&single).enumerate() {
+      if k != 0 {
+        if one_line || !(it.brk || col + 1 + s.len() > WIDTH) {
+          out.push(' ');
+          col += 1;
+        } else {
+          out.push_str("\n  ");
+          col = 2;
+          ind = 2;
+        }
+      }
+      let s = self.render(it, col, ind, false);
+      col = end_col(&s, col);
+      out.push_str(&s);
+    }
+
+    // Lay out the value
+    if let Some(val) = &d.val {
+      let v = match val.k {
+        SExprKind::Formula(f) => {
+          let s = self.formula(f, col + 3, ind, true);
+          if col + 4 + s.len() <= WIDTH { s } else { self.formula(f, 0, 0, false) }
+        }
+        _ => reindent(self.str(val.span), 0, 0),
+      };
+      if !v.contains('\n') && col + 4 + v.len() <= WIDTH {
+        out.push_str(" = ");
+      } else {
+        out.push_str(" =\n");
+      }
+      out.push_str(&v);
+    }
+    debug_assert!(self.source().as_bytes()[span.end - 1] == b';');
+    out.push(';');
+    out
+  }
+
+  /// Render an [`Item`] starting at column `col`, on a line with indentation `ind`.
+  /// If `single` is true, formulas are rendered on a single line.
+  fn render(&self, it: &Item, mut col: usize, ind: usize, single: bool) -> String {
+    let mut out = String::new();
+    for p in &it.pieces {
+      match *p {
+        Piece::Text(ref s) => { col += s.len(); out.push_str(s) }
+        Piece::Fmla(f) => {
+          let s = self.formula(f, col, ind, single);
+          col = end_col(&s, col);
+          out.push_str(&s)
+        }
+      }
+    }
+    out
+  }
+
+  /// Format a math formula starting at column `col`, on a line with indentation `ind`.
+  /// If the formula does not fit on the line (and `single` is false), it is broken
+  /// into several lines, indented two spaces past `ind`.
+  fn formula(&self, f: Formula, col: usize, ind: usize, single: bool) -> String {
+    let orig = self.str(f.0);
+    let normalized = || if orig.contains('\n') { orig.to_owned() } else {
+      let inner = self.str(f.inner()).split(' ').filter(|s| !s.is_empty()).collect::<Vec<_>>();
+      if inner.is_empty() { "$$".into() } else { format!("$ {} $", inner.join(" ")) }
+    };
+    let env = if let Some(env) = self.env {env} else {return normalized()};
+    let pe = env.pe();
+    let e = match parse_math(pe, self.source().as_bytes(), f).ok()
+      .and_then(|q| self.to_lisp(self.source().as_bytes(), &q)) {
+      Some(e) => e,
+      None => return normalized(),
+    };
+    let fe = unsafe { env.format_env(self.source()) };
+    let line = format!("{}", fe.pp(&e, usize::MAX >> 1));
+    let text = if single || col + line.len() + 4 <= WIDTH { line } else {
+      let s = format!("{}", fe.pp(&e, WIDTH.saturating_sub(col.max(ind + 2) + 4).max(40)));
+      let mut indent = "\n".to_owned();
+      for _ in 0..ind + 2 { indent.push(' ') }
+      s.replace('\n', &indent)
+    };
+    // Check that the result parses to the same expression, using only earlier notations
+    let buf = format!("$ {} $", text);
+    let ok = parse_math(pe, buf.as_bytes(), Formula((0..buf.len()).into())).ok()
+      .filter(|q| self.notations_before(pe, buf.as_bytes(), q, f.0.start))
+      .and_then(|q| self.to_lisp(buf.as_bytes(), &q))
+      .map_or(false, |e2| format!("{}", fe.to(&e)) == format!("{}", fe.to(&e2)));
+    if ok { buf } else { normalized() }
+  }
+
+  /// The expression that the formula `f` in `src` parses to, printed as an s-expression,
+  /// if there is an environment to parse it in.
+  fn fmla_expr(&self, src: &[u8], f: Formula) -> Option<String> {
+    let env = self.env?;
+    let e = self.to_lisp(src, &parse_math(env.pe(), src, f).ok()?)?;
+    let fe = unsafe { env.format_env(self.source()) };
+    Some(format!("{}", fe.to(&e)))
+  }
+
+  /// Convert a [`QExpr`] to a lisp expression, if it does not contain antiquotations.
+  fn to_lisp(&self, src: &[u8], q: &QExpr) -> Option<LispVal> {
+    let env = self.env?;
+    match &q.k {
+      QExprKind::IdentApp(sp, args) => {
+        let a = env.get_atom(&src[sp.start..sp.end])?;
+        if args.is_empty() && !matches!(env.data()[a].decl(), Some(DeclKey::Term(_))) {
+          return Some(LispVal::atom(a))
+        }
+        let mut es = vec![LispVal::atom(a)];
+        for e in &**args { es.push(self.to_lisp(src, e)?) }
+        Some(LispVal::list(es))
+      }
+      QExprKind::App(_, t, args) => {
+        let mut es = vec![LispVal::atom(env.term(*t).atom)];
+        for e in &**args { es.push(self.to_lisp(src, e)?) }
+        Some(LispVal::list(es))
+      }
+      QExprKind::Unquote(_) => None,
+    }
+  }
+
+  /// Returns true if all notations used in `q` are declared before position `pos`
+  /// (or in another file).
+  fn notations_before(&self, pe: &ParserEnv, src: &[u8], q: &QExpr, pos: usize) -> bool {
+    match &q.k {
+      QExprKind::IdentApp(_, args) => args.iter().all(|e| self.notations_before(pe, src, e, pos)),
+      QExprKind::App(tk, _, args) => {
+        let tk = &src[tk.start..tk.end];
+        pe.prefixes.get(tk).or_else(|| pe.infixes.get(tk)).map_or(false, |info|
+          info.span.file != *self.path || info.span.span.end <= pos) &&
+        args.iter().all(|e| self.notations_before(pe, src, e, pos))
+      }
+      QExprKind::Unquote(_) => false,
+    }
+  }
+}
+
+/// Structural equality of the parse trees of a file before and after formatting.
+/// Spans are ignored, but the text they point to is compared, using the source `a` for
+/// the first tree and `b` for the second. Formulas are equal if they have the same tokens,
+/// or (when reprinting) if they parse to the same expression.
+struct SameAst<'a> {
+  fmt: &'a Formatter<'a>,
+  a: &'a [u8],
+  b: &'a [u8],
+}
+
+impl SameAst<'_> {
+  fn list<T>(&self, xs: &[T], ys: &[T], f: impl Fn(&Self, &T, &T) -> bool) -> bool {
+    xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| f(self, x, y))
+  }
+
+  fn opt<T>(&self, x: Option<&T>, y: Option<&T>, f: impl Fn(&Self, &T, &T) -> bool) -> bool {
+    match (x, y) {
+      (Some(x), Some(y)) => f(self, x, y),
+      (None, None) => true,
+      _ => false,
+    }
+  }
+
+  fn span(&self, x: &Span, y: &Span) -> bool { self.a[x.start..x.end] == self.b[y.start..y.end] }
+
+  fn fmla(&self, x: &Formula, y: &Formula) -> bool {
+    fn tokens<'a>(src: &'a [u8], f: &Formula) -> Vec<&'a [u8]> {
+      let sp = f.inner();
+      src[sp.start..sp.end].split(u8::is_ascii_whitespace).filter(|s| !s.is_empty()).collect()
+    }
+    tokens(self.a, x) == tokens(self.b, y) ||
+      match (self.fmt.fmla_expr(self.a, *x), self.fmt.fmla_expr(self.b, *y)) {
+        (Some(e1), Some(e2)) => e1 == e2,
+        _ => false,
+      }
+  }
+
+  fn cnst(&self, x: &Const, y: &Const) -> bool { self.span(&x.trim, &y.trim) }
+
+  fn ty(&self, x: &Type, y: &Type) -> bool {
+    match (x, y) {
+      (Type::DepType(DepType {sort: s1, deps: d1}), Type::DepType(DepType {sort: s2, deps: d2})) =>
+        self.span(s1, s2) && self.list(d1, d2, Self::span),
+      (Type::Formula(f1), Type::Formula(f2)) => self.fmla(f1, f2),
+      _ => false,
+    }
+  }
+
+  fn binder(&self, x: &Binder, y: &Binder) -> bool {
+    x.kind == y.kind && self.opt(x.local.as_ref(), y.local.as_ref(), Self::span) &&
+    self.opt(x.ty.as_ref(), y.ty.as_ref(), Self::ty)
+  }
+
+  fn sexpr(&self, x: &SExpr, y: &SExpr) -> bool {
+    match (&x.k, &y.k) {
+      (SExprKind::Atom(Atom::Ident), SExprKind::Atom(Atom::Ident)) => self.span(&x.span, &y.span),
+      (SExprKind::Atom(Atom::Quote), SExprKind::Atom(Atom::Quote)) |
+      (SExprKind::Atom(Atom::Unquote), SExprKind::Atom(Atom::Unquote)) |
+      (SExprKind::Atom(Atom::Nfx), SExprKind::Atom(Atom::Nfx)) |
+      (SExprKind::Undef, SExprKind::Undef) => true,
+      (SExprKind::Atom(Atom::Gen(i)), SExprKind::Atom(Atom::Gen(j))) => i == j,
+      (SExprKind::List(es1), SExprKind::List(es2)) => self.list(es1, es2, Self::sexpr),
+      (SExprKind::DottedList(es1, e1), SExprKind::DottedList(es2, e2)) =>
+        self.list(es1, es2, Self::sexpr) && self.sexpr(e1, e2),
+      (SExprKind::Number(n1), SExprKind::Number(n2)) => n1 == n2,
+      (SExprKind::String(s1), SExprKind::String(s2)) => s1 == s2,
+      (SExprKind::Bool(b1), SExprKind::Bool(b2)) => b1 == b2,
+      (SExprKind::DocComment(d1, e1), SExprKind::DocComment(d2, e2)) =>
+        d1 == d2 && self.sexpr(e1, e2),
+      (SExprKind::Formula(f1), SExprKind::Formula(f2)) => self.fmla(f1, f2),
+      _ => false,
+    }
+  }
+
+  fn decl(&self, x: &Decl, y: &Decl) -> bool {
+    x.mods == y.mods && x.k == y.k && self.span(&x.id, &y.id) &&
+    self.list(&x.bis, &y.bis, Self::binder) && self.opt(x.ty.as_ref(), y.ty.as_ref(), Self::ty) &&
+    self.opt(x.val.as_ref(), y.val.as_ref(), Self::sexpr)
+  }
+
+  fn literal(&self, x: &Literal, y: &Literal) -> bool {
+    match (x, y) {
+      (Literal::Const(c1, p1), Literal::Const(c2, p2)) => self.cnst(c1, c2) && p1 == p2,
+      (Literal::Var(v1), Literal::Var(v2)) => self.span(v1, v2),
+      _ => false,
+    }
+  }
+
+  fn stmt(&self, x: &Stmt, y: &Stmt) -> bool {
+    match (&x.k, &y.k) {
+      (StmtKind::Sort(id1, m1), StmtKind::Sort(id2, m2)) => m1 == m2 && self.span(id1, id2),
+      (StmtKind::Decl(d1), StmtKind::Decl(d2)) => self.decl(d1, d2),
+      (StmtKind::Delimiter(Delimiter::Both(l1)),
+       StmtKind::Delimiter(Delimiter::Both(l2))) => l1 == l2,
+      (StmtKind::Delimiter(Delimiter::LeftRight(l1, r1)),
+       StmtKind::Delimiter(Delimiter::LeftRight(l2, r2))) => l1 == l2 && r1 == r2,
+      (StmtKind::SimpleNota(n1), StmtKind::SimpleNota(n2)) => {
+        let SimpleNota {k: k1, id: id1, c: c1, prec: p1} = n1;
+        let SimpleNota {k: k2, id: id2, c: c2, prec: p2} = n2;
+        let same_k = match (k1, k2) {
+          (SimpleNotaKind::Prefix, SimpleNotaKind::Prefix) => true,
+          (SimpleNotaKind::Infix {right: r1}, SimpleNotaKind::Infix {right: r2}) => r1 == r2,
+          _ => false,
+        };
+        same_k && self.span(id1, id2) && self.cnst(c1, c2) && p1 == p2
+      }
+      (StmtKind::Coercion {id: id1, from: f1, to: t1},
+       StmtKind::Coercion {id: id2, from: f2, to: t2}) =>
+        self.span(id1, id2) && self.span(f1, f2) && self.span(t1, t2),
+      (StmtKind::Notation(n1), StmtKind::Notation(n2)) => {
+        let GenNota {id: id1, bis: bis1, ty: ty1, lits: lits1, prec: p1} = n1;
+        let GenNota {id: id2, bis: bis2, ty: ty2, lits: lits2, prec: p2} = n2;
+        self.span(id1, id2) && self.list(bis1, bis2, Self::binder) &&
+        self.opt(ty1.as_ref(), ty2.as_ref(), Self::ty) &&
+        self.list(lits1, lits2, Self::literal) && p1 == p2
+      }
+      (StmtKind::Inout {out: o1, k: k1, hs: hs1}, StmtKind::Inout {out: o2, k: k2, hs: hs2}) =>
+        o1 == o2 && self.span(k1, k2) && self.list(hs1, hs2, Self::sexpr),
+      (StmtKind::Annot(e1, s1), StmtKind::Annot(e2, s2)) => self.sexpr(e1, e2) && self.stmt(s1, s2),
+      (StmtKind::DocComment(d1, s1), StmtKind::DocComment(d2, s2)) => d1 == d2 && self.stmt(s1, s2),
+      (StmtKind::Do(m1, es1), StmtKind::Do(m2, es2)) =>
+        m1 == m2 && self.list(es1, es2, Self::sexpr),
+      (StmtKind::Import(_, f1), StmtKind::Import(_, f2)) => f1 == f2,
+      _ => false,
+    }
+  }
+}
+
+/// Main entry point for `mm0-rs fmt` subcommand.
+///
+/// # Arguments
+///
+/// `mm0-rs fmt <in.mm1> [--write] [--check] [--reprint]`, where:
+///
+/// - `in.mm1` is the MM1 (or MM0) file to format.
+/// - `--write`: Overwrite the input file, instead of printing the result to stdout.
+/// - `--check`: Don't output anything, but exit with an error if the file is not formatted.
+/// - `--reprint`: Elaborate the file first, and reprint formulas using its notations.
+pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
+  let path = args.value_of("INPUT").expect("required arg");
+  let path: FileRef = fs::canonicalize(path)?.into();
+  let (source, env) = if args.is_present("reprint") {
+    crate::compiler::log_to_stderr();
+    let (file, env) = crate::compiler::elab_for_result(path.clone())?;
+    (file.try_ascii().cloned(), env)
+  } else {
+    let text = fs::read_to_string(path.path())?;
+    (if text.is_ascii() {Some(Arc::new(text.into()))} else {None}, None)
+  };
+  let source = source.ok_or_else(||
+    io::Error::new(io::ErrorKind::InvalidData, "cannot format a non-ASCII file"))?;
+  let (_, ast) = parse(source.clone(), None);
+  let out = Formatter::new(&path, &ast, env.as_ref()).format(None)
+    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+  if args.is_present("check") {
+    if out != ***source {
+      eprintln!("{} is not formatted", path);
+      std::process::exit(1)
+    }
+  } else if args.is_present("write") {
+    if out != ***source { fs::write(path.path(), out)? }
+  } else {
+    print!("{}", out)
+  }
+  Ok(())
+}
@@ -88,7 +88,10 @@ impl<W: Write> Joiner<W> {
   }
 }
 
-fn join_with_header(comments: bool, header: bool, mut w: impl Write, file: FileRef) -> io::Result<()> {
+/// Join `file` and its imports, writing the result to `w`. If `comments` is false,
+/// comments are stripped, and if `header` is true, a comment listing the joined files
+/// is written at the start.
+pub(crate) fn join_with_header(comments: bool, header: bool, mut w: impl Write, file: FileRef) -> io::Result<()> {
   let mut buf = vec![];
   if comments && header {
     let mut joiner = Joiner::new(comments, &mut buf);
@@ -9,10 +9,15 @@
 //!     -V, --version    Prints version information
 //!
 //! SUBCOMMANDS:
-//!     compile    Compile MM1 files into MMB
-//!     help       Prints this message or the help of the given subcommand(s)
-//!     join       Join MM1/MM0 files with imports by concatenation
-//!     server     MM1 LSP server
+//!     compile       Compile MM1 files into MMB
+//!     doc           Build documentation pages
+//!     fmt           Format MM1/MM0 source files
+//!     help          Prints this message or the help of the given subcommand(s)
+//!     join          Join MM1/MM0 files with imports by concatenation
+//!     repl          Interactive MM1 read-eval-print loop
+//!     server        MM1 LSP server
+//!     show-proof    Print the steps of a proof as text or JSON
+//!     tptp          Export a theorem statement to TPTP for external provers
 //! ```
 //!
 //! [`mm0-rs/README.md`]: https://github.com/digama0/mm0/blob/master/mm0-rs/README.md
@@ -50,6 +55,10 @@ pub mod parser;
 #[macro_use] pub mod server;
 pub mod compiler;
 pub mod joiner;
+pub mod formatter;
+pub mod repl;
+pub mod tptp;
+pub mod show_proof;
 pub mod elab;
 #[cfg(feature = "doc")]
 pub mod doc;
@@ -62,7 +71,8 @@ pub mod mmb;
 pub mod mmu { pub mod import; pub mod export; }
 pub mod mmc;
 
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::PathBuf;
+use std::sync::{Mutex, atomic::{AtomicBool, Ordering}};
 
 static CHECK_PROOFS: AtomicBool = AtomicBool::new(true);
 pub(crate) fn get_check_proofs() -> bool { CHECK_PROOFS.load(Ordering::Relaxed) }
@@ -70,3 +80,22 @@ pub(crate) fn get_check_proofs() -> bool { CHECK_PROOFS.load(Ordering::Relaxed)
 /// Set the initial proof checking behavior at the start of an MM1 file
 /// before a `(check-proofs)` command is found.
 pub fn set_check_proofs(b: bool) { CHECK_PROOFS.store(b, Ordering::Relaxed) }
+
+lazy_static! {
+  static ref PROJECT_ROOT: Mutex<Option<PathBuf>> = Mutex::new(None);
+}
+
+/// Get the project root, the directory that lisp code is allowed to read data files from
+/// (see `read-file`). If it has not been set using [`set_project_root`] or
+/// [`set_default_project_root`], then lisp code cannot read any files.
+pub(crate) fn get_project_root() -> Option<PathBuf> { PROJECT_ROOT.lock().expect("poisoned").clone() }
+
+/// Set the project root, the directory that lisp code is allowed to read data files from.
+pub fn set_project_root(path: PathBuf) { *PROJECT_ROOT.lock().expect("poisoned") = Some(path) }
+
+/// Set the project root to the directory containing the main input file `file`,
+/// unless it has already been set.
+pub(crate) fn set_default_project_root(file: &std::path::Path) {
+  let mut root = PROJECT_ROOT.lock().expect("poisoned");
+  if root.is_none() { *root = file.parent().map(Into::into) }
+}
@@ -13,6 +13,7 @@ fn main() -> std::io::Result<()> {
     (@subcommand compile =>
       (about: "Compile MM1 files into MMB")
       (@arg no_proofs: -n --("no-proofs") "Disable proof checking until (check-proofs #t)")
+      (@arg minimize: --minimize "Minimize proofs before output, reporting the savings")
       (@arg output: -o --output [FILE] "Print 'output' commands to a file (use '-' to print to stdout)")
       (@arg INPUT: +required "Sets the input file (.mm1 or .mm0)")
       (@arg OUTPUT: "Sets the output file (.mmb or .mmu)"))
@@ -22,6 +23,34 @@ fn main() -> std::io::Result<()> {
       (@arg bare: -b --("bare") "Don't add any comments")
       (@arg INPUT: +required "Sets the input file (.mm1 or .mm0)")
       (@arg OUTPUT: "Sets the output file (.mm1 or .mm0), or stdin if omitted"))
+    (@subcommand fmt =>
+      (about: "Format MM1/MM0 source files")
+      (@arg write: -w --write "Overwrite the input file instead of printing to stdout")
+      (@arg check: --check "Exit with an error if the file is not already formatted")
+      (@arg reprint: --reprint "Reprint formulas using the notations of the elaborated file")
+      (@arg INPUT: +required "Sets the input file (.mm1 or .mm0)"))
+    (@subcommand repl =>
+      (about: "Interactive MM1 read-eval-print loop")
+      (@arg INPUT: "Sets a file (.mm1 or .mm0) to load before starting"))
+    (@subcommand tptp =>
+      (about: "Export a theorem statement to TPTP for external provers")
+      (@arg thf: --thf "Export to THF (higher-order) instead of FOF")
+      (@arg premises: -p --premises [THMS] "Use only the premises THMS (a comma separated list)")
+      (@arg output: -o --output [FILE] "Write the problem to FILE instead of stdout")
+      (@arg prover: --prover [CMD] "Run CMD on the problem file and report the premises used")
+      (@arg INPUT: +required "Sets the input file (.mm1 or .mm0)")
+      (@arg THM: +required "Sets the theorem to export"))
+    (@subcommand show_proof =>
+      (name: "show-proof")
+      (about: "Print the steps of a proof as text or JSON")
+      (@arg format: --format <FORMAT>
+         possible_values(&["text", "json"]) default_value("text")
+         "Output format")
+      (@arg order: --("order") <ORDER>
+         possible_values(&["pre", "post"]) default_value("post")
+         "Proof tree traversal order")
+      (@arg INPUT: +required "Sets the input file (.mm1 or .mm0)")
+      (@arg THM: +required "Sets the theorem to show"))
     (@subcommand doc =>
       (about: "Build documentation pages")
       (@arg INPUT: +required "Sets the input file (.mm1 or .mm0)")
@@ -48,6 +77,10 @@ fn main() -> std::io::Result<()> {
       mm0_rs::compiler::main(m)?
     }
     ("join", Some(m)) => mm0_rs::joiner::main(m)?,
+    ("fmt", Some(m)) => mm0_rs::formatter::main(m)?,
+    ("repl", Some(m)) => mm0_rs::repl::main(m)?,
+    ("tptp", Some(m)) => mm0_rs::tptp::main(m)?,
+    ("show-proof", Some(m)) => mm0_rs::show_proof::main(m)?,
     #[cfg(feature = "doc")]
     ("doc", Some(m)) => mm0_rs::doc::main(m)?,
     #[cfg(feature = "server")]
@@ -103,10 +103,12 @@ impl ParseError {
       range: file.to_range(self.pos),
       severity: Some(self.level.to_diag_severity()),
       code: None,
+      code_description: None,
       source: Some("mm0-rs".to_owned()),
       message: format!("{}", self.msg),
       related_information: None,
       tags: None,
+      data: None,
     }
   }
 }
@@ -904,7 +906,10 @@ impl<'a> Parser<'a> {
               GenNota {id, bis, ty, lits, prec}))))
           }
           Some(CommandKeyword::Do) => {
-            self.modifiers_empty(m, id, "do blocks do not take modifiers");
+            if !Modifiers::LOCAL.contains(m) {
+              self.push_err(self.err_str("do blocks only take the 'local' modifier"));
+              m &= Modifiers::LOCAL;
+            }
             let mut es = Vec::new();
             if self.chr(b'{').is_some() {
               while self.chr(b'}').is_none() {es.push(self.sexpr()?)}
@@ -912,7 +917,7 @@ impl<'a> Parser<'a> {
               es.push(self.sexpr()?)
             }
             let end = self.chr_err(b';')?;
-            Ok(Some(Stmt::new((start..end).into(), StmtKind::Do(es))))
+            Ok(Some(Stmt::new((start..end).into(), StmtKind::Do(m, es))))
           }
           Some(CommandKeyword::Import) => {
             self.modifiers_empty(m, id, "import statements do not take modifiers");
@@ -985,6 +990,20 @@ impl<'a> Parser<'a> {
   }
 }
 
+/// Parse a sequence of s-expressions, such as the contents of a data file read by
+/// `read-sexprs`. Unlike [`parse`], this does not parse statements, and it stops at the
+/// first error.
+pub fn parse_sexprs(source: &[u8]) -> Result<Vec<SExpr>> {
+  let mut p = Parser {source, errors: vec![], imports: vec![], idx: 0, restart_pos: None};
+  p.ws();
+  let mut es = vec![];
+  while p.idx < source.len() { es.push(p.sexpr()?) }
+  match p.errors.into_iter().next() {
+    None => Ok(es),
+    Some(e) => Err(e),
+  }
+}
+
 /// Main entry-point. Creates a [`Parser`] and parses a passed file.
 /// `old` contains the last successful parse of the same file, in order to reuse
 /// previous parsing work. The [`Position`] denotes the first byte where the
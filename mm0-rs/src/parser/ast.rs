@@ -288,6 +288,10 @@ pub enum Atom {
   /// This is an atom with the text `:nfx` that was generated by a malformed curly list
   /// (see [`curly_transform`]).
   Nfx,
+  /// This is an atom that was generated by lisp macro expansion, so it has no text in the
+  /// source. The number is an index into a table maintained by the lisp parser, which
+  /// holds the atom, keyword or constant value that was produced by the macro.
+  Gen(usize),
 }
 crate::deep_size_0!(Atom);
 
@@ -614,7 +618,9 @@ pub enum StmtKind {
   DocComment(DocComment, Box<Stmt>),
   /// A `do` block like `do { (print 1) };`. This allows the evaluation of lisp
   /// code, and definitions made in a `do` block populate the global context.
-  Do(Vec<SExpr>),
+  /// The modifiers are either empty or [`local`](Modifiers::LOCAL), in which case
+  /// the definitions are not exported to files that import this one.
+  Do(Modifiers, Vec<SExpr>),
   /// An `import` statement like `import "file.mm1";`. The span gives
   /// the string literal `"file.mm1"`, and the string is the result of parsing
   /// (after interpreting string escapes).
@@ -665,6 +671,7 @@ impl LinedString {
       Atom::Quote => b"quote",
       Atom::Unquote => b"unquote",
       Atom::Nfx => b":nfx",
+      Atom::Gen(_) => b":gen",
     }
   }
 }
@@ -0,0 +1,248 @@
+//! An interactive read-eval-print loop for MM1, the `mm0-rs repl` subcommand.
+//!
+//! The REPL reads MM1 statements and lisp expressions from stdin, and elaborates them
+//! one at a time into the same [`Elaborator`], which optionally starts from the
+//! environment of a file. An input that starts with a statement keyword (like `theorem`)
+//! is read up to the terminating `;`, and anything else is treated as a lisp expression,
+//! as if it was in a `do` block, and the result is printed. Lines starting with `:` are
+//! commands; see `:help` for a list.
+
+use std::{fs, io::{self, BufRead, Write}, sync::Arc};
+use clap::ArgMatches;
+use crate::elab::{ElabError, Elaborator, Environment, FrozenEnv,
+  environment::DeclKey, lisp::InferTarget};
+use crate::lined_string::LinedString;
+use crate::parser::{parse, ErrorLevel, ast::{AST, StmtKind}};
+use crate::util::FileRef;
+
+/// The keywords (including modifiers) that can start a statement.
+const STMT_KEYWORDS: &[&str] = &[
+  "sort", "term", "axiom", "theorem", "def", "delimiter", "notation", "infixl", "infixr",
+  "prefix", "coercion", "input", "output", "import", "do", "pure", "strict", "provable",
+  "free", "local", "abstract", "pub"];
+
+const HELP: &str = "\
+Enter an MM1 statement (terminated by ';') or a lisp expression to evaluate it.
+Commands:
+  :goals       show the current goals and hypotheses
+  :type e      show the sort of the expression or the statement of the proof e
+  :decl x      show the declaration or lisp definition x
+  :reset       discard everything entered so far
+  :help        show this message
+  :quit        exit the REPL";
+
+/// The REPL state.
+struct Repl {
+  /// The file that was loaded, or a dummy file in the current directory
+  path: FileRef,
+  /// The environment of the loaded file, used as the starting point of `:reset`
+  base: FrozenEnv,
+  /// The elaborator, which accumulates the state of all statements so far
+  elab: Elaborator,
+}
+
+/// Returns true if `text` is a complete input, that is, all brackets, strings and formulas
+/// are closed, and (for statements) the last token is the statement terminator `;`.
+fn is_complete(text: &str, stmt: bool) -> bool {
+  let (mut depth, mut in_str, mut in_fmla, mut last) = (0_i32, false, false, b' ');
+  for line in text.lines() {
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while let Some(&c) = bytes.get(i) {
+      if in_str {
+        match c {
+          b'\\' => i += 1,
+          b'"' => in_str = false,
+          _ => {}
+        }
+      } else if in_fmla {
+        if c == b'$' { in_fmla = false }
+      } else {
+        match c {
+          b'-' if bytes.get(i + 1) == Some(&b'-') => break,
+          b'"' => in_str = true,
+          b'$' => in_fmla = true,
+          b'(' | b'[' | b'{' => depth += 1,
+          b')' | b']' | b'}' => depth -= 1,
+          _ => {}
+        }
+      }
+      if !c.is_ascii_whitespace() { last = c }
+      i += 1;
+    }
+  }
+  !in_str && !in_fmla && depth <= 0 && (!stmt || last == b';')
+}
+
+/// Returns true if `text` should be read as a statement, rather than a lisp expression.
+fn is_stmt(text: &str) -> bool {
+  let text = text.trim_start();
+  if text.starts_with("--|") || text.starts_with('@') { return true }
+  let word = text.split(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).next();
+  word.map_or(false, |w| STMT_KEYWORDS.contains(&w))
+}
+
+fn empty_ast() -> Arc<AST> { Arc::new(parse(Arc::new(String::new().into()), None).1) }
+
+impl Repl {
+  fn new(path: FileRef, base: FrozenEnv) -> Self {
+    let elab = Elaborator::from_env(empty_ast(), path.clone(), &base);
+    Repl {path, base, elab}
+  }
+
+  fn reset(&mut self) {
+    self.elab = Elaborator::from_env(empty_ast(), self.path.clone(), &self.base);
+  }
+
+  /// Print the errors and messages produced by an input. Positions are relative to
+  /// `source`, and the first `skip` lines of `source` are not part of the user input.
+  fn report(errors: Vec<ElabError>, source: &LinedString, skip: u32) {
+    for e in errors {
+      let msg = e.kind.msg();
+      if matches!(e.level, ErrorLevel::Info) { println!("{}", msg); continue }
+      let pos = source.to_pos(e.pos.start);
+      println!("{}: {}:{}: {}", e.level, pos.line.saturating_sub(skip) + 1, pos.character + 1, msg);
+    }
+  }
+
+  /// Elaborate a statement, or evaluate a lisp expression if `stmt` is false.
+  fn input(&mut self, text: &str, stmt: bool) {
+    let (text, skip) = if stmt {(text.to_owned(), 0)} else {(format!("do {{\n{}\n}};", text), 1)};
+    let (_, ast) = parse(Arc::new(text.into()), None);
+    let ast = Arc::new(ast);
+    let mut errors: Vec<ElabError> = ast.errors.iter().map(|e| e.clone().into()).collect();
+    errors.extend(self.elab.elab_more(ast.clone()));
+    Self::report(errors, &ast.source, skip)
+  }
+
+  /// Show the sort of an expression or the type of a proof.
+  fn show_type(&mut self, text: &str) {
+    if let Some(&a) = self.elab.atoms.get(text.as_bytes()) {
+      if self.elab.data[a].decl.is_some() { return self.show_decl(text) }
+    }
+    let (_, ast) = parse(Arc::new(format!("do {{\n{}\n}};", text).into()), None);
+    let ast = Arc::new(ast);
+    let e = match ast.stmts.first().map(|s| &s.k) {
+      Some(StmtKind::Do(_, es)) if es.len() == 1 && ast.errors.is_empty() => es[0].clone(),
+      _ => return println!("error: expected a single expression"),
+    };
+    let v = match self.elab.eval_more(ast.clone(), &e) {
+      Ok(v) => v,
+      Err(err) => return Self::report(vec![err], &ast.source, 1),
+    };
+    if let Some(a) = v.as_atom() {
+      if let Some(DeclKey::Thm(_)) = self.elab.data[a].decl {
+        return self.show_decl(&String::from_utf8_lossy(&self.elab.data[a].name.clone()))
+      }
+    }
+    let target = self.elab.infer_target(e.span, &v).ok().or_else(|| {
+      // nullary terms are represented as atoms, which `infer_target` reads as variables
+      match self.elab.data[v.as_atom()?].decl? {
+        DeclKey::Term(t) => Some(InferTarget::Reg(self.elab.sorts[self.elab.terms[t].ret.0].atom)),
+        DeclKey::Thm(_) => None,
+      }
+    });
+    if let Some(InferTarget::Bound(s) | InferTarget::Reg(s)) = target {
+      return println!("$ {} $: {}", self.elab.format_env().pp(&v, 80), self.elab.data[s].name)
+    }
+    // proof terms are elaborated, so that implicit arguments can be omitted
+    let ty = if v.is_list() {
+      self.elab.infer_proof_type(e.span, v)
+    } else {
+      self.elab.infer_type(e.span, &v)
+    };
+    match ty {
+      Ok(ty) => println!("$ {} $", self.elab.format_env().pp(&ty, 80)),
+      Err(err) => Self::report(vec![err], &ast.source, 1),
+    }
+  }
+
+  /// Show a declaration or a lisp definition.
+  fn show_decl(&self, name: &str) {
+    let a = if let Some(&a) = self.elab.atoms.get(name.as_bytes()) {a} else {
+      return println!("unknown declaration '{}'", name)
+    };
+    let ad = &self.elab.data[a];
+    let found = if let Some(s) = ad.sort { println!("{}", self.elab.sorts[s]); true } else { false };
+    match ad.decl {
+      Some(DeclKey::Term(t)) => println!("{}", self.elab.print(&self.elab.terms[t])),
+      Some(DeclKey::Thm(t)) => println!("{}", self.elab.print(&self.elab.thms[t])),
+      None => if !found {
+        match &ad.lisp {
+          Some(l) => println!("{} := {}", name, self.elab.print(&l.val)),
+          None => println!("unknown declaration '{}'", name),
+        }
+      }
+    }
+  }
+
+  /// Run a REPL command (without the leading `:`). Returns false if the REPL should exit.
+  fn command(&mut self, cmd: &str) -> bool {
+    let (cmd, arg) = match cmd.find(char::is_whitespace) {
+      Some(n) => (&cmd[..n], cmd[n..].trim()),
+      None => (cmd, ""),
+    };
+    match cmd {
+      "q" | "quit" => return false,
+      "h" | "help" => println!("{}", HELP),
+      "goals" => {
+        let stat = self.elab.stat();
+        if stat.is_empty() { println!("no goals") } else { print!("{}", stat) }
+      }
+      "t" | "type" => self.show_type(arg),
+      "decl" => for name in arg.split_whitespace() { self.show_decl(name) },
+      "reset" => self.reset(),
+      _ => println!("unknown command ':{}', try ':help'", cmd),
+    }
+    true
+  }
+}
+
+/// Main entry point for `mm0-rs repl` subcommand.
+///
+/// # Arguments
+///
+/// `mm0-rs repl [in.mm1]`, where:
+///
+/// - `in.mm1` is an optional MM1 (or MM0) file to elaborate. If provided, the REPL starts
+///   in the environment at the end of the file. If the file cannot be elaborated at all
+///   (for example because of an import cycle), the REPL exits with an error.
+pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
+  let (path, base) = match args.value_of("INPUT") {
+    Some(path) => {
+      let path: FileRef = fs::canonicalize(path)?.into();
+      let (_, env) = crate::compiler::elab_for_result(path.clone())?;
+      let env = env.unwrap_or_else(|| {
+        eprintln!("error: failed to elaborate {}", path);
+        std::process::exit(1)
+      });
+      (path, env)
+    }
+    None => (std::env::current_dir()?.join("repl.mm1").into(), FrozenEnv::new(Environment::new())),
+  };
+  let mut repl = Repl::new(path, base);
+  let stdin = io::stdin();
+  let mut stdin = stdin.lock();
+  let mut buf = String::new();
+  loop {
+    print!("{}", if buf.is_empty() {"> "} else {"| "});
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if stdin.read_line(&mut line)? == 0 { break }
+    if buf.is_empty() {
+      let cmd = line.trim();
+      if cmd.is_empty() || cmd.starts_with("--") && !cmd.starts_with("--|") { continue }
+      if let Some(cmd) = cmd.strip_prefix(':') {
+        if !repl.command(cmd) { break }
+        continue
+      }
+    }
+    buf.push_str(&line);
+    let stmt = is_stmt(&buf);
+    if is_complete(&buf, stmt) {
+      repl.input(&buf, stmt);
+      buf.clear();
+    }
+  }
+  Ok(())
+}
@@ -1,36 +1,39 @@
 //! Implements the bridge between mm0-rs and an editor via an lsp [`Connection`]
 
 use std::{fs, io};
-use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}, Condvar};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering}, Condvar};
 use std::collections::{VecDeque, HashMap, HashSet, hash_map::{Entry, DefaultHasher}};
 use std::hash::{Hash, Hasher};
 use std::result::Result as StdResult;
 use std::thread::{ThreadId, self};
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use futures::{FutureExt, future::BoxFuture};
 use futures::channel::oneshot::{Sender as FSender, channel};
 use futures::executor::ThreadPool;
 use futures::lock::Mutex as FMutex;
 use lsp_server::{Connection, ErrorCode, Message, Notification, ProtocolError,
   Request, RequestId, Response, ResponseError};
-use serde::ser::Serialize;
+use serde::{Serialize, Deserialize};
 use serde_json::{from_value, to_value};
 use serde_repr::{Serialize_repr, Deserialize_repr};
-use serde::Deserialize;
 #[allow(clippy::wildcard_imports)] use lsp_types::*;
 use crossbeam::channel::{SendError, RecvError};
 use clap::ArgMatches;
 use crate::util::{ArcList, ArcString, BoxError, FileRef, FileSpan, Span,
   MutexExt, CondvarExt};
 use crate::lined_string::LinedString;
-use crate::parser::{AST, parse};
+use crate::parser::{AST, parse,
+  ast::{Binder, Decl, Formula, SExpr, SExprKind, Stmt, StmtKind, Type as AstType}};
 use crate::mmb::import::elab as mmb_elab;
 use crate::mmu::import::elab as mmu_elab;
 use crate::compiler::FileContents;
-use crate::elab::{ElabResult, ElaborateBuilder, FrozenEnv, GoalListener,
-  environment::{ObjectKind, DeclKey, StmtTrace, AtomID, SortID, TermID, ThmID},
+use crate::formatter::Formatter;
+use crate::elab::{ElabError, ElabResult, ElaborateBuilder, Elaborator, ErrorLevel, FileReader,
+  FrozenEnv, GoalListener, ProgressListener, Environment,
+  environment::{ObjectKind, DeclKey, StmtTrace, AtomID, SortID, TermID, ThmID,
+    Expr, ExprNode, Proof, ProofNode, TermKind, ThmKind},
   FrozenLispKind, FrozenAtomData,
-  local_context::InferSort, proof::Subst,
+  local_context::InferSort, proof::Subst, proof_stats::{AxiomUse, ProofSize},
   lisp::{print::FormatEnv, pretty::Pretty, LispKind, Proc, BuiltinProc},
   spans::Spans};
 
@@ -149,6 +152,142 @@ macro_rules! log {
   ($($es:tt)*) => {crate::server::log(format!($($es)*))}
 }
 
+/// The time an elaboration has to run before we start reporting progress
+/// (to avoid flickering progress bars for quick elaborations).
+const PROGRESS_DELAY: Duration = Duration::from_millis(500);
+/// The minimum time between consecutive progress reports for one elaboration.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The state of a `$/progress` token. The token can only be used once the client
+/// has answered the `window/workDoneProgress/create` request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TokenState {
+  /// The token has been requested, but the client has not answered yet.
+  Pending,
+  /// The client created the token, so we can send progress notifications.
+  Created,
+  /// The client refused to create the token, so we don't report progress.
+  Failed,
+}
+
+/// A `$/progress` token of an elaboration in progress.
+struct ProgressToken {
+  /// The cancellation flag of the elaboration.
+  cancel: Arc<AtomicBool>,
+  /// Whether the client has created the token, set when the response arrives.
+  state: Arc<Mutex<TokenState>>,
+}
+
+/// The `$/progress` reporting state for a single elaboration. The token is created lazily
+/// once the elaboration has been running for [`PROGRESS_DELAY`], and the client can
+/// cancel the elaboration using `window/workDoneProgress/cancel` on this token.
+struct ElabProgress {
+  path: FileRef,
+  cancel: Arc<AtomicBool>,
+  start: Instant,
+  last: Instant,
+  token: Option<(String, Arc<Mutex<TokenState>>)>,
+  /// True if the `begin` notification has been sent.
+  begun: bool,
+}
+
+impl ElabProgress {
+  fn new(path: FileRef, cancel: Arc<AtomicBool>) -> Self {
+    let start = Instant::now();
+    ElabProgress {path, cancel, start, last: start, token: None, begun: false}
+  }
+
+  /// Send a `$/progress` notification. If this fails then the client has most likely
+  /// disconnected, so we log the error and stop reporting progress on this token.
+  fn send(token: &str, state: &Mutex<TokenState>, value: WorkDoneProgress) {
+    if let Err(e) = send_message(Notification {
+      method: "$/progress".to_owned(),
+      params: to_value(ProgressParams {
+        token: NumberOrString::String(token.to_owned()),
+        value: ProgressParamsValue::WorkDone(value),
+      }).expect("serialization failure")
+    }) {
+      log::warn!("failed to send progress on {}: {:?}", token, e);
+      *state.ulock() = TokenState::Failed;
+    }
+  }
+
+  fn report(&mut self, ast: &AST, idx: usize) {
+    let now = Instant::now();
+    if now.duration_since(self.last) < PROGRESS_INTERVAL ||
+      now.duration_since(self.start) < PROGRESS_DELAY { return }
+    self.last = now;
+    let (token, state) = match &self.token {
+      Some((token, state)) => match *state.ulock() {
+        TokenState::Created => (token, state),
+        TokenState::Pending | TokenState::Failed => return,
+      },
+      None => {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let token = format!("mm0-rs/elab/{}", NEXT_ID.fetch_add(1, Ordering::Relaxed));
+        let state = Arc::new(Mutex::new(TokenState::Pending));
+        SERVER.progress.ulock().insert(token.clone(),
+          ProgressToken {cancel: self.cancel.clone(), state: state.clone()});
+        // Progress notifications are sent once the client has created the token
+        if let Err(e) = send_message(Request {
+          id: token.clone().into(),
+          method: "window/workDoneProgress/create".to_owned(),
+          params: to_value(WorkDoneProgressCreateParams {
+            token: NumberOrString::String(token.clone())
+          }).expect("serialization failure")
+        }) {
+          log::warn!("failed to create progress token {}: {:?}", token, e);
+          *state.ulock() = TokenState::Failed;
+        }
+        self.token = Some((token, state));
+        return
+      }
+    };
+    #[allow(clippy::cast_possible_truncation, clippy::integer_division)]
+    let percentage = Some((idx * 100 / ast.stmts.len().max(1)) as u32);
+    let message = ast.stmts.get(idx).map(|s| stmt_description(ast, s));
+    if self.begun {
+      Self::send(token, state, WorkDoneProgress::Report(WorkDoneProgressReport {
+        cancellable: Some(true), message, percentage
+      }));
+    } else {
+      self.begun = true;
+      let title = format!("Elaborating {}", self.path.path().file_name()
+        .map_or_else(|| self.path.rel().into(), |s| s.to_string_lossy()));
+      Self::send(token, state, WorkDoneProgress::Begin(WorkDoneProgressBegin {
+        title, cancellable: Some(true), message, percentage
+      }));
+    }
+  }
+
+  fn end(&mut self) {
+    if let Some((token, state)) = self.token.take() {
+      if self.begun && *state.ulock() == TokenState::Created {
+        Self::send(&token, &state, WorkDoneProgress::End(WorkDoneProgressEnd {message: None}));
+      }
+      SERVER.progress.ulock().remove(&token);
+    }
+  }
+}
+
+/// A short description of a statement for progress reports, usually the name of the declaration.
+fn stmt_description(ast: &AST, s: &Stmt) -> String {
+  match &s.k {
+    StmtKind::Annot(_, s) |
+    StmtKind::DocComment(_, s) => stmt_description(ast, s),
+    &StmtKind::Sort(id, _) |
+    &StmtKind::Decl(Decl {id, ..}) => String::from_utf8_lossy(ast.span(id)).into(),
+    StmtKind::Delimiter(_) => "delimiter".into(),
+    StmtKind::SimpleNota(_) |
+    StmtKind::Coercion {..} |
+    StmtKind::Notation(_) => "notation".into(),
+    StmtKind::Inout {out: false, ..} => "input".into(),
+    StmtKind::Inout {out: true, ..} => "output".into(),
+    StmtKind::Do(..) => "do".into(),
+    StmtKind::Import(..) => "import".into(),
+  }
+}
+
 async fn elaborate(path: FileRef, start: Option<Position>,
     cancel: Arc<AtomicBool>, rd: ArcList<FileRef>) -> Result<ElabResult<u64>> {
   let vfs = &SERVER.vfs;
@@ -211,6 +350,7 @@ async fn elaborate(path: FileRef, start: Option<Position>,
   let source = text.clone();
 
   let mut deps = Vec::new();
+  let data_deps = Arc::new(Mutex::new(Vec::new()));
   let (ast, (cyc, toks, errors, env)) = if path.has_extension("mmb") {
     let (error, env) = mmb_elab(&path, &text);
     let errors = if let Err(e) = error {vec![e]} else {vec![]};
@@ -219,10 +359,16 @@ async fn elaborate(path: FileRef, start: Option<Position>,
     let (error, env) = mmu_elab(&path, &text);
     let errors = if let Err(e) = error {vec![e]} else {vec![]};
     (None, (None, vec![], errors, FrozenEnv::new(env)))
+  } else if is_data_file(&path) {
+    text.ascii().as_str().hash(&mut hasher);
+    (None, (None, vec![], vec![], FrozenEnv::new(Environment::new())))
   } else {
     let (idx, ast) = parse(text.ascii().clone(), old_ast);
     let ast = Arc::new(ast);
     let rd = rd.push(path.clone());
+    let progress = if SERVER.caps.ulock().work_done_progress {
+      Some(Arc::new(Mutex::new(ElabProgress::new(path.clone(), cancel.clone()))))
+    } else { None };
     let elab = ElaborateBuilder {
       ast: &ast,
       path: path.clone(),
@@ -249,16 +395,30 @@ async fn elaborate(path: FileRef, start: Option<Position>,
         .and_then(|start| ast.source.to_idx(start))
         .filter(|&pos| pos != 0)
         .map(|pos| {
-          GoalListener::new(move |elab: &crate::elab::Elaborator, stat| {
+          GoalListener::at(pos, move |elab: &crate::elab::Elaborator, stat| {
             if elab.spans.stmt().contains(&pos) {
               log(format!("\n{}", stat));
             }
           })
         }),
+      recv_progress: progress.clone().map(|progress| ProgressListener::new(move |ast, idx|
+        progress.ulock().report(ast, idx))),
+      read_file: Some(read_data_file(data_deps.clone())),
     }.elab();
-    (Some(ast.clone()), elab.await)
+    let res = elab.await;
+    if let Some(progress) = progress { progress.ulock().end() }
+    (Some(ast.clone()), res)
   };
   for tok in toks {tok.hash(&mut hasher)}
+  for p in data_deps.ulock().drain(..) {
+    if deps.contains(&p) { continue }
+    if let Some(file) = vfs.get(&p) {
+      if let Some(g) = file.parsed.try_lock() {
+        if let Some(FileCache::Ready {hash, ..}) = *g { hash.hash(&mut hasher) }
+      }
+    }
+    deps.push(p)
+  }
   let hash = hasher.finish();
   log!("elabbed {:?}", path);
   let is_canceled = cancel.load(Ordering::SeqCst);
@@ -328,7 +488,7 @@ async fn elaborate(path: FileRef, start: Option<Position>,
     }
   }
   if !is_canceled {
-    *g = Some(FileCache::Ready {hash, source, ast, res: res.clone(), deps});
+    *g = Some(FileCache::Ready {hash, source, ast, res: res.clone(), deps, prefix: None});
     drop(g);
     for d in file.downstream.ulock().iter() {
       log!("{:?} affects {:?}", path, d);
@@ -338,6 +498,36 @@ async fn elaborate(path: FileRef, start: Option<Position>,
   Ok(res)
 }
 
+/// True if `path` is a data file read by lisp code (see `read-file`), rather than
+/// a file that can be elaborated.
+fn is_data_file(path: &FileRef) -> bool {
+  !["mm0", "mm1", "mmb", "mmu"].iter().any(|ext| path.has_extension(ext))
+}
+
+/// Creates a [`FileReader`] that reads data files through the VFS, so that the contents
+/// of open editor buffers are used, and pushes them to `deps`. Data files have a trivial
+/// elaboration (with an empty environment) which is used to track changes to them, so that
+/// they can be used as dependencies just like imported files.
+fn read_data_file(deps: Arc<Mutex<Vec<FileRef>>>) -> FileReader {
+  FileReader::new(move |p| {
+    let (p, file) = SERVER.vfs.get_or_insert(p)?;
+    let (version, text) = file.text.ulock().clone();
+    let src = text.try_ascii().ok_or("not a text file")?.clone();
+    if let Some(mut g) = file.parsed.try_lock() {
+      if g.is_none() {
+        let mut hasher = DefaultHasher::new();
+        version.hash(&mut hasher);
+        src.as_str().hash(&mut hasher);
+        let hash = hasher.finish();
+        let res = ElabResult::Ok(hash, None, FrozenEnv::new(Environment::new()));
+        *g = Some(FileCache::Ready {hash, source: text, ast: None, res, deps: vec![], prefix: None});
+      }
+    }
+    deps.ulock().push(p);
+    Ok(src)
+  })
+}
+
 async fn elaborate_and_report(path: FileRef, start: Option<Position>, cancel: Arc<AtomicBool>) {
   if let Err(e) =
     std::panic::AssertUnwindSafe(elaborate(path, start, cancel, Default::default()))
@@ -377,6 +567,9 @@ enum FileCache {
     ast: Option<Arc<AST>>,
     res: ElabResult<u64>,
     deps: Vec<FileRef>,
+    /// The environment after the first `n` statements, if it has been requested
+    /// by `mm0-rs/evalLisp`
+    prefix: Option<(usize, FrozenEnv)>,
   }
 }
 
@@ -428,6 +621,10 @@ impl VFS {
     self.0.ulock().get(file).unwrap().text.ulock().1.ascii().clone()
   }
 
+  fn try_source(&self, file: &FileRef) -> Option<Arc<LinedString>> {
+    self.0.ulock().get(file)?.text.ulock().1.try_ascii().cloned()
+  }
+
   fn open_virt(&self, path: FileRef, version: i32, text: String) -> Arc<VirtualFile> {
     let file = Arc::new(VirtualFile::new(Some(version), FileContents::new(text)));
     let file = match self.0.ulock().entry(path.clone()) {
@@ -485,6 +682,17 @@ enum RequestType {
   DocumentSymbol(DocumentSymbolParams),
   References(ReferenceParams),
   DocumentHighlight(DocumentHighlightParams),
+  CallHierarchyPrepare(CallHierarchyPrepareParams),
+  CallHierarchyIncoming(CallHierarchyIncomingCallsParams),
+  CallHierarchyOutgoing(CallHierarchyOutgoingCallsParams),
+  FoldingRange(FoldingRangeParams),
+  SelectionRange(SelectionRangeParams),
+  CodeLens(CodeLensParams),
+  Formatting(DocumentFormattingParams),
+  EvalLisp(EvalLispParams),
+  FindTheorems(FindTheoremsParams),
+  RangeFormatting(DocumentRangeFormattingParams),
+  ExecuteCommand(ExecuteCommandParams),
 }
 
 fn parse_request(Request {id, method, params}: Request) -> Result<Option<(RequestId, RequestType)>> {
@@ -496,6 +704,17 @@ fn parse_request(Request {id, method, params}: Request) -> Result<Option<(Reques
     "textDocument/documentSymbol"    => Some((id, RequestType::DocumentSymbol(from_value(params)?))),
     "textDocument/references"        => Some((id, RequestType::References(from_value(params)?))),
     "textDocument/documentHighlight" => Some((id, RequestType::DocumentHighlight(from_value(params)?))),
+    "textDocument/prepareCallHierarchy" => Some((id, RequestType::CallHierarchyPrepare(from_value(params)?))),
+    "callHierarchy/incomingCalls"    => Some((id, RequestType::CallHierarchyIncoming(from_value(params)?))),
+    "callHierarchy/outgoingCalls"    => Some((id, RequestType::CallHierarchyOutgoing(from_value(params)?))),
+    "textDocument/foldingRange"      => Some((id, RequestType::FoldingRange(from_value(params)?))),
+    "textDocument/selectionRange"    => Some((id, RequestType::SelectionRange(from_value(params)?))),
+    "textDocument/codeLens"          => Some((id, RequestType::CodeLens(from_value(params)?))),
+    "textDocument/formatting"        => Some((id, RequestType::Formatting(from_value(params)?))),
+    "textDocument/rangeFormatting"   => Some((id, RequestType::RangeFormatting(from_value(params)?))),
+    "mm0-rs/evalLisp"                => Some((id, RequestType::EvalLisp(from_value(params)?))),
+    "mm0-rs/findTheorems"            => Some((id, RequestType::FindTheorems(from_value(params)?))),
+    "workspace/executeCommand"       => Some((id, RequestType::ExecuteCommand(from_value(params)?))),
     _ => None
   })
 }
@@ -504,7 +723,6 @@ fn send_message<T: Into<Message>>(t: T) -> Result<()> {
   Ok(SERVER.conn.sender.send(t.into())?)
 }
 
-#[allow(unused)]
 fn show_message(typ: MessageType, message: String) -> Result<()> {
   send_message(Notification {
     method: "window/showMessage".to_owned(),
@@ -583,6 +801,28 @@ impl RequestHandler {
         self.finish(references(file.clone(), doc.position, true,
           |range| DocumentHighlight { range, kind: None }).await)
       }
+      RequestType::CallHierarchyPrepare(CallHierarchyPrepareParams {text_document_position_params: doc, ..}) =>
+        self.finish(call_hierarchy_prepare(doc.text_document.uri.into(), doc.position).await),
+      RequestType::CallHierarchyIncoming(CallHierarchyIncomingCallsParams {item, ..}) =>
+        self.finish(call_hierarchy_incoming(item).await),
+      RequestType::CallHierarchyOutgoing(CallHierarchyOutgoingCallsParams {item, ..}) =>
+        self.finish(call_hierarchy_outgoing(item).await),
+      RequestType::FoldingRange(FoldingRangeParams {text_document: doc, ..}) =>
+        self.finish(folding_range(doc.uri.into()).await),
+      RequestType::SelectionRange(SelectionRangeParams {text_document: doc, positions, ..}) =>
+        self.finish(selection_range(doc.uri.into(), positions).await),
+      RequestType::CodeLens(CodeLensParams {text_document: doc, ..}) =>
+        self.finish(code_lens(doc.uri.into()).await),
+      RequestType::Formatting(DocumentFormattingParams {text_document: doc, ..}) =>
+        self.finish(formatting(doc.uri.into(), None).await),
+      RequestType::RangeFormatting(DocumentRangeFormattingParams {text_document: doc, range, ..}) =>
+        self.finish(formatting(doc.uri.into(), Some(range)).await),
+      RequestType::EvalLisp(EvalLispParams {text_document: doc, position, expr}) =>
+        self.finish(eval_lisp(doc.uri.into(), position, expr).await),
+      RequestType::FindTheorems(FindTheoremsParams {text_document: doc, position, expr}) =>
+        self.finish(find_theorems(doc.uri.into(), position, expr).await),
+      RequestType::ExecuteCommand(ExecuteCommandParams {command, arguments, ..}) =>
+        self.finish(execute_command(command, arguments).await),
     }
   }
 
@@ -895,6 +1135,7 @@ async fn document_symbol(path: FileRef) -> StdResult<DocumentSymbolResponse, Res
     name: String::from_utf8_lossy(name).into(),
     detail: Some(desc),
     kind,
+    tags: None,
     #[allow(deprecated)] deprecated: None,
     range: text.to_range(full),
     selection_range: text.to_range(sp),
@@ -938,6 +1179,7 @@ async fn document_symbol(path: FileRef) -> StdResult<DocumentSymbolResponse, Res
                 r @ FrozenLispKind::List(_) |
                 r @ FrozenLispKind::DottedList(_, _) =>
                   if r.is_list() {SymbolKind::Array} else {SymbolKind::Object},
+                FrozenLispKind::Vector(_) => SymbolKind::Array,
                 FrozenLispKind::Number(_) => SymbolKind::Number,
                 FrozenLispKind::String(_) => SymbolKind::String,
                 FrozenLispKind::Bool(_) => SymbolKind::Boolean,
@@ -945,6 +1187,7 @@ async fn document_symbol(path: FileRef) -> StdResult<DocumentSymbolResponse, Res
                 FrozenLispKind::Undef => return None,
                 FrozenLispKind::Proc(_) => SymbolKind::Function,
                 FrozenLispKind::AtomMap(_) |
+                FrozenLispKind::HashMap(_) |
                 FrozenLispKind::Annot(_, _) |
                 FrozenLispKind::Ref(_) => SymbolKind::Object,
               }))() {
@@ -1003,6 +1246,8 @@ fn make_completion_item(path: &FileRef, fe: FormatEnv<'_>, ad: &FrozenAtomData,
         FrozenLispKind::String(_) |
         FrozenLispKind::Bool(_) |
         FrozenLispKind::AtomMap(_) |
+        FrozenLispKind::HashMap(_) |
+        FrozenLispKind::Vector(_) |
         FrozenLispKind::Annot(_, _) |
         FrozenLispKind::Ref(_) => CompletionItemKind::Value,
         FrozenLispKind::Syntax(_) => CompletionItemKind::Event,
@@ -1165,6 +1410,953 @@ async fn references<T>(
   Ok(res)
 }
 
+/// Get the declaration (term/def or axiom/theorem) that an [`ObjectKind`] refers to, if any.
+fn object_decl(env: &FrozenEnv, k: &ObjectKind) -> Option<DeclKey> {
+  match *k {
+    ObjectKind::Term(t, _) => Some(DeclKey::Term(t)),
+    ObjectKind::Thm(t) => Some(DeclKey::Thm(t)),
+    ObjectKind::Expr(ref e) => match env.data()[e.uncons().next().unwrap_or(e).as_atom()?].decl() {
+      dk @ Some(DeclKey::Term(_)) => dk,
+      _ => None,
+    },
+    ObjectKind::Proof(ref p) => match env.data()[p.uncons().next().unwrap_or(p).as_atom()?].decl() {
+      dk @ Some(DeclKey::Thm(_)) => dk,
+      _ => None,
+    },
+    ObjectKind::Global(a) => env.data()[a].decl(),
+    _ => None,
+  }
+}
+
+/// Get the name of a declaration, the span of the name, and the span of the whole declaration.
+fn decl_span(env: &FrozenEnv, dk: DeclKey) -> (AtomID, &FileSpan, Span) {
+  match dk {
+    DeclKey::Term(t) => { let td = env.term(t); (td.atom, &td.span, td.full) }
+    DeclKey::Thm(t) => { let td = env.thm(t); (td.atom, &td.span, td.full) }
+  }
+}
+
+/// Collect the declarations used by `dk`, in order of first use. For a theorem, these are
+/// the terms in the statement and the terms and theorems appearing in the proof,
+/// and for a definition, the terms appearing in the definition body.
+fn decl_uses(env: &FrozenEnv, dk: DeclKey) -> Vec<DeclKey> {
+  #[derive(Default)]
+  struct Uses(Vec<DeclKey>, HashSet<DeclKey>);
+  impl Uses {
+    fn push(&mut self, dk: DeclKey) {
+      if self.1.insert(dk) { self.0.push(dk) }
+    }
+    fn expr(&mut self, e: &ExprNode) {
+      if let ExprNode::App(t, ref es) = *e {
+        self.push(DeclKey::Term(t));
+        for e in &**es { self.expr(e) }
+      }
+    }
+    fn proof(&mut self, p: &ProofNode) {
+      match p {
+        ProofNode::Ref(_) |
+        ProofNode::Dummy(_, _) => {}
+        &ProofNode::Term {term, ref args} => {
+          self.push(DeclKey::Term(term));
+          for p in &**args { self.proof(p) }
+        }
+        ProofNode::Hyp(_, p) |
+        ProofNode::Refl(p) |
+        ProofNode::Sym(p) => self.proof(p),
+        &ProofNode::Thm {thm, ref args, ref res} => {
+          self.push(DeclKey::Thm(thm));
+          for p in &**args { self.proof(p) }
+          self.proof(res)
+        }
+        ProofNode::Conv(p) => {
+          self.proof(&p.0);
+          self.proof(&p.1);
+          self.proof(&p.2)
+        }
+        ProofNode::Cong {args, ..} => for p in &**args { self.proof(p) },
+        &ProofNode::Unfold {term, ref args, ref res} => {
+          self.push(DeclKey::Term(term));
+          for p in &**args { self.proof(p) }
+          self.proof(&res.0);
+          self.proof(&res.1);
+          self.proof(&res.2)
+        }
+      }
+    }
+  }
+
+  let mut uses = Uses::default();
+  match dk {
+    DeclKey::Term(t) => {
+      if let TermKind::Def(Some(Expr {heap, head})) = &env.term(t).kind {
+        let nargs = env.term(t).args.len();
+        for e in &heap[nargs..] { uses.expr(e) }
+        uses.expr(head)
+      }
+    }
+    DeclKey::Thm(t) => {
+      let td = env.thm(t);
+      for e in &td.heap[td.args.len()..] { uses.expr(e) }
+      for (_, e) in &*td.hyps { uses.expr(e) }
+      uses.expr(&td.ret);
+      if let ThmKind::Thm(Some(Proof {heap, hyps, head})) = &td.kind {
+        for p in &heap[td.args.len()..] { uses.proof(p) }
+        for p in &**hyps { uses.proof(p) }
+        uses.proof(head)
+      }
+    }
+  }
+  uses.0
+}
+
+/// Find the ranges inside the declaration `from` which refer to `to`,
+/// falling back on the name of `from` if there are none (for example because
+/// `from` is declared in another file, for which we do not have span information).
+fn decl_ref_ranges(env: &FrozenEnv, path: &FileRef, from: DeclKey, to: DeclKey) -> Vec<Range> {
+  let (_, fsp, full) = decl_span(env, from);
+  let text = match SERVER.vfs.try_source(&fsp.file) {
+    Some(text) => text,
+    None => return vec![Range::default()],
+  };
+  let mut res = vec![];
+  if fsp.file == *path {
+    if let Some(spans) = env.find(full.start) {
+      for &(sp, ref k) in spans {
+        if object_decl(env, k) == Some(to) {
+          let sp = if let ObjectKind::Term(_, sp2) = *k {sp2} else {sp};
+          res.push(text.to_range(sp))
+        }
+      }
+    }
+  }
+  if res.is_empty() { res.push(text.to_range(fsp.span)) }
+  res
+}
+
+/// Construct a [`CallHierarchyItem`] for the declaration `dk` in the environment of file `path`.
+/// The file and the name of the declaration are stored in the `data` field, so that
+/// they can be recovered by [`call_hierarchy_resolve`].
+fn call_hierarchy_item(env: &FrozenEnv, fe: FormatEnv<'_>, path: &FileRef, dk: DeclKey) -> CallHierarchyItem {
+  let (a, fsp, full) = decl_span(env, dk);
+  let name: String = String::from_utf8_lossy(env.data()[a].name()).into();
+  let (detail, kind) = match dk {
+    DeclKey::Term(t) => (format!("{}", fe.to(env.term(t))), SymbolKind::Constructor),
+    DeclKey::Thm(t) => (format!("{}", fe.to(env.thm(t))), SymbolKind::Method),
+  };
+  let (range, selection_range) = match SERVER.vfs.try_source(&fsp.file) {
+    Some(text) => (text.to_range(full), text.to_range(fsp.span)),
+    None => Default::default(),
+  };
+  CallHierarchyItem {
+    data: Some(to_value((path.url(), &name)).expect("serialization failed")),
+    name, kind,
+    tags: None,
+    detail: Some(detail),
+    uri: fsp.file.url().clone(),
+    range, selection_range,
+  }
+}
+
+/// Get the file, environment, source text, and declaration from a [`CallHierarchyItem`]
+/// constructed by [`call_hierarchy_item`].
+async fn call_hierarchy_resolve(item: CallHierarchyItem) ->
+    StdResult<Option<(FileRef, FrozenEnv, Arc<LinedString>, DeclKey)>, ResponseError> {
+  let data = item.data.ok_or_else(|| response_err(ErrorCode::InvalidRequest, "missing data"))?;
+  let (uri, name): (Url, String) = from_value(data).map_err(|e|
+    response_err(ErrorCode::InvalidRequest, format!("bad JSON {:?}", e)))?;
+  let path: FileRef = uri.into();
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "call hierarchy: nonexistent file"))?;
+  let text = file.text.ulock().1.ascii().clone();
+  let env = elaborate(path.clone(), Some(Position::default()), Default::default(), Default::default())
+    .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{:?}", e)))?;
+  let env = match env.into_response_error()? { Some((_, env)) => env, None => return Ok(None) };
+  let dk = env.get_atom(name.as_bytes()).and_then(|a| env.data()[a].decl())
+    .ok_or_else(|| response_err(ErrorCode::ContentModified, "declaration missing"))?;
+  Ok(Some((path, env, text, dk)))
+}
+
+async fn call_hierarchy_prepare(path: FileRef, pos: Position) ->
+    StdResult<Option<Vec<CallHierarchyItem>>, ResponseError> {
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "call hierarchy: nonexistent file"))?;
+  let text = file.text.ulock().1.ascii().clone();
+  let idx = if let Some(idx) = text.to_idx(pos) {idx} else {return Ok(None)};
+  let env = elaborate(path.clone(), Some(Position::default()), Default::default(), Default::default())
+    .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{:?}", e)))?;
+  let env = if let Some((_, env)) = env.into_response_error()? {env} else {return Ok(None)};
+  let spans = if let Some(spans) = env.find(idx) {spans} else {return Ok(None)};
+  let fe = unsafe { env.format_env(&text) };
+  let res = spans.find_pos(idx).find_map(|(_, k)| object_decl(&env, k))
+    .map(|dk| vec![call_hierarchy_item(&env, fe, &path, dk)]);
+  Ok(res)
+}
+
+async fn call_hierarchy_outgoing(item: CallHierarchyItem) ->
+    StdResult<Option<Vec<CallHierarchyOutgoingCall>>, ResponseError> {
+  let (path, env, text, dk) = match call_hierarchy_resolve(item).await? {
+    Some(res) => res,
+    None => return Ok(None)
+  };
+  let fe = unsafe { env.format_env(&text) };
+  Ok(Some(decl_uses(&env, dk).into_iter().map(|callee| CallHierarchyOutgoingCall {
+    to: call_hierarchy_item(&env, fe, &path, callee),
+    from_ranges: decl_ref_ranges(&env, &path, dk, callee),
+  }).collect()))
+}
+
+async fn call_hierarchy_incoming(item: CallHierarchyItem) ->
+    StdResult<Option<Vec<CallHierarchyIncomingCall>>, ResponseError> {
+  let (_, env, _, dk) = match call_hierarchy_resolve(item).await? {
+    Some(res) => res,
+    None => return Ok(None)
+  };
+  let (a, fsp, _) = decl_span(&env, dk);
+  let (name, fsp) = (env.data()[a].name().clone(), fsp.clone());
+  // The callers of a declaration can be in any file which (transitively) imports it,
+  // so we look through all files that have been elaborated, but only consider
+  // declarations in each environment that originate from that file to avoid duplicates.
+  let files: Vec<_> = SERVER.vfs.0.ulock().iter().map(|(p, f)| (p.clone(), f.clone())).collect();
+  let mut res = vec![];
+  for (path, file) in files {
+    let (env, text) = match file.parsed.try_lock().as_deref() {
+      Some(Some(FileCache::Ready {res: ElabResult::Ok(_, _, env), source, ..})) =>
+        match source.try_ascii() {
+          Some(text) => (env.clone(), text.clone()),
+          None => continue,
+        },
+      _ => continue
+    };
+    let target = match env.get_atom(&name).and_then(|a| env.data()[a].decl()) {
+      Some(dk) if *decl_span(&env, dk).1 == fsp => dk,
+      _ => continue
+    };
+    let fe = unsafe { env.format_env(&text) };
+    for s in env.stmts() {
+      if let StmtTrace::Decl(a) = *s {
+        let caller = env.data()[a].decl().expect("env well formed");
+        if decl_span(&env, caller).1.file == path && decl_uses(&env, caller).contains(&target) {
+          res.push(CallHierarchyIncomingCall {
+            from: call_hierarchy_item(&env, fe, &path, caller),
+            from_ranges: decl_ref_ranges(&env, &path, caller, target),
+          })
+        }
+      }
+    }
+  }
+  Ok(Some(res))
+}
+
+/// Get the parsed [`AST`] for the current contents of a file. This reuses the last parse
+/// if the file has not changed since, and otherwise parses the file again
+/// (without elaborating it). Returns `None` for binary files.
+async fn parse_current(file: &VirtualFile) -> Option<Arc<AST>> {
+  let text = file.text.ulock().1.clone();
+  if let Some(FileCache::Ready {source, ast: Some(ast), ..}) = &*file.parsed.lock().await {
+    if source.ptr_eq(&text) { return Some(ast.clone()) }
+  }
+  Some(Arc::new(parse(text.try_ascii()?.clone(), None).1))
+}
+
+async fn folding_range(path: FileRef) -> StdResult<Vec<FoldingRange>, ResponseError> {
+  struct Folder<'a> {
+    text: &'a LinedString,
+    res: Vec<FoldingRange>,
+  }
+  impl Folder<'_> {
+    fn push(&mut self, sp: Span, kind: Option<FoldingRangeKind>) {
+      let Range {start, end} = self.text.to_range(sp);
+      // A range ending at the start of a line (like a doc comment) does not include that line
+      let (end_line, end_character) = match end.character {
+        0 => (end.line.saturating_sub(1), None),
+        c => (end.line, Some(c)),
+      };
+      if start.line < end_line && !self.res.last().map_or(false, |r|
+          r.start_line == start.line && r.end_line == end_line) {
+        self.res.push(FoldingRange {
+          start_line: start.line,
+          start_character: Some(start.character),
+          end_line,
+          end_character,
+          kind,
+        })
+      }
+    }
+
+    fn stmt(&mut self, s: &Stmt) {
+      match &s.k {
+        StmtKind::DocComment(_, s2) => {
+          self.push((s.span.start..s2.span.start).into(), Some(FoldingRangeKind::Comment));
+          self.stmt(s2)
+        }
+        StmtKind::Annot(e, s2) => {
+          self.push(s.span, None);
+          self.sexpr(e);
+          self.stmt(s2)
+        }
+        _ => {
+          self.push(s.span, Some(FoldingRangeKind::Region));
+          match &s.k {
+            StmtKind::Decl(d) => if let Some(e) = &d.val { self.sexpr(e) },
+            StmtKind::Do(_, es) |
+            StmtKind::Inout {hs: es, ..} => for e in es { self.sexpr(e) },
+            _ => {}
+          }
+        }
+      }
+    }
+
+    fn sexpr(&mut self, e: &SExpr) {
+      match &e.k {
+        SExprKind::List(es) => {
+          self.push(e.span, None);
+          for e in es { self.sexpr(e) }
+        }
+        SExprKind::DottedList(es, r) => {
+          self.push(e.span, None);
+          for e in es { self.sexpr(e) }
+          self.sexpr(r)
+        }
+        SExprKind::DocComment(_, e2) => {
+          self.push((e.span.start..e2.span.start).into(), Some(FoldingRangeKind::Comment));
+          self.sexpr(e2)
+        }
+        SExprKind::Formula(_) => self.push(e.span, None),
+        _ => {}
+      }
+    }
+  }
+
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "folding range nonexistent file"))?;
+  let ast = if let Some(ast) = parse_current(&file).await {ast} else {return Ok(vec![])};
+  let mut folder = Folder {text: &ast.source, res: vec![]};
+  for s in &ast.stmts { folder.stmt(s) }
+  Ok(folder.res)
+}
+
+/// Collect the spans of all syntactic elements of `ast` which contain `pos`,
+/// from the outside in. Inside math formulas, this uses parenthesized groups and tokens.
+fn ast_nesting(ast: &AST, pos: usize) -> Vec<Span> {
+  struct Nesting<'a> {
+    source: &'a LinedString,
+    pos: usize,
+    res: Vec<Span>,
+  }
+  impl Nesting<'_> {
+    fn push(&mut self, sp: Span) -> bool {
+      let ok = sp.start <= self.pos && self.pos <= sp.end;
+      if ok && self.res.last() != Some(&sp) { self.res.push(sp) }
+      ok
+    }
+
+    fn stmt(&mut self, s: &Stmt) {
+      if !self.push(s.span) {return}
+      match &s.k {
+        &StmtKind::Sort(id, _) => {self.push(id);}
+        StmtKind::Decl(Decl {id, bis, ty, val, ..}) => {
+          self.push(*id);
+          for bi in bis { self.binder(bi) }
+          if let Some(ty) = ty { self.ty(ty) }
+          if let Some(e) = val { self.sexpr(e) }
+        }
+        StmtKind::Delimiter(_) => {}
+        StmtKind::SimpleNota(n) => {
+          self.push(n.id);
+          self.formula(n.c.fmla)
+        }
+        &StmtKind::Coercion {id, from, to} => for &sp in &[id, from, to] {self.push(sp);},
+        StmtKind::Notation(n) => {
+          self.push(n.id);
+          for bi in &n.bis { self.binder(bi) }
+          if let Some(ty) = &n.ty { self.ty(ty) }
+          for lit in &n.lits {
+            match *lit {
+              crate::parser::ast::Literal::Const(c, _) => self.formula(c.fmla),
+              crate::parser::ast::Literal::Var(sp) => {self.push(sp);}
+            }
+          }
+        }
+        StmtKind::Inout {k, hs, ..} => {
+          self.push(*k);
+          for e in hs { self.sexpr(e) }
+        }
+        StmtKind::Annot(e, s) => {
+          self.sexpr(e);
+          self.stmt(s)
+        }
+        StmtKind::DocComment(_, s) => self.stmt(s),
+        StmtKind::Do(_, es) => for e in es { self.sexpr(e) },
+        &StmtKind::Import(sp, _) => {self.push(sp);}
+      }
+    }
+
+    fn binder(&mut self, bi: &Binder) {
+      if !self.push(bi.span) {return}
+      if let Some(sp) = bi.local { self.push(sp); }
+      if let Some(ty) = &bi.ty { self.ty(ty) }
+    }
+
+    fn ty(&mut self, ty: &AstType) {
+      match ty {
+        AstType::DepType(d) => if self.push(d.span()) {
+          self.push(d.sort);
+          for &sp in &*d.deps { self.push(sp); }
+        },
+        &AstType::Formula(f) => self.formula(f),
+      }
+    }
+
+    fn sexpr(&mut self, e: &SExpr) {
+      if !self.push(e.span) {return}
+      match &e.k {
+        SExprKind::List(es) => for e in es { self.sexpr(e) },
+        SExprKind::DottedList(es, r) => {
+          for e in es { self.sexpr(e) }
+          self.sexpr(r)
+        }
+        SExprKind::DocComment(_, e) => self.sexpr(e),
+        &SExprKind::Formula(f) => self.formula(f),
+        _ => {}
+      }
+    }
+
+    fn formula(&mut self, f: Formula) {
+      if !self.push(f.0) {return}
+      let inner = f.inner();
+      let s = &self.source[inner];
+      let trim_start = s.iter().position(|c| !c.is_ascii_whitespace()).unwrap_or(s.len());
+      let trim_end = s.iter().rposition(|c| !c.is_ascii_whitespace()).map_or(trim_start, |i| i + 1);
+      self.push((inner.start + trim_start .. inner.start + trim_end).into());
+      // The formula is not parsed at this point, so we use parentheses for the nesting
+      let mut stack = vec![];
+      let mut groups = vec![];
+      let mut tk_start = None;
+      for (i, &c) in s.iter().enumerate() {
+        let i = inner.start + i;
+        if c == b'(' { stack.push(i) }
+        if c == b')' {
+          if let Some(start) = stack.pop() {
+            if start <= self.pos && self.pos <= i + 1 { groups.push(Span::from(start..=i)) }
+          }
+        }
+        match (c.is_ascii_whitespace() || c == b'(' || c == b')', tk_start) {
+          (false, None) => tk_start = Some(i),
+          (true, Some(start)) => {
+            if start <= self.pos && self.pos <= i { groups.push((start..i).into()) }
+            tk_start = None
+          }
+          _ => {}
+        }
+      }
+      if let Some(start) = tk_start {
+        if start <= self.pos && self.pos <= inner.end { groups.push((start..inner.end).into()) }
+      }
+      groups.sort_by_key(|sp| std::cmp::Reverse(sp.end - sp.start));
+      for sp in groups { self.push(sp); }
+    }
+  }
+
+  let mut nesting = Nesting {source: &ast.source, pos, res: vec![]};
+  let i = ast.stmts.partition_point(|s| s.span.end < pos);
+  if let Some(s) = ast.stmts.get(i) { nesting.stmt(s) }
+  nesting.res
+}
+
+async fn selection_range(path: FileRef, positions: Vec<Position>) ->
+    StdResult<Vec<SelectionRange>, ResponseError> {
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "selection range nonexistent file"))?;
+  let ast = if let Some(ast) = parse_current(&file).await {ast} else {return Ok(vec![])};
+  Ok(positions.into_iter().map(|pos| {
+    // The spans are ordered from the outside in, so each range is the parent of the next
+    let mut res = None;
+    if let Some(idx) = ast.source.to_idx(pos) {
+      for sp in ast_nesting(&ast, idx) {
+        let range = ast.source.to_range(sp);
+        res = Some(SelectionRange {range, parent: res.map(Box::new)});
+      }
+    }
+    res.unwrap_or(SelectionRange {range: Range {start: pos, end: pos}, parent: None})
+  }).collect())
+}
+
+async fn formatting(path: FileRef, range: Option<Range>) ->
+    StdResult<Vec<TextEdit>, ResponseError> {
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "formatting nonexistent file"))?;
+  let ast = if let Some(ast) = parse_current(&file).await {ast} else {return Ok(vec![])};
+  let range = match range {
+    None => None,
+    Some(Range {start, end}) => match (ast.source.to_idx(start), ast.source.to_idx(end)) {
+      (Some(start), Some(end)) => Some((start..end).into()),
+      _ => return Ok(vec![]),
+    }
+  };
+  // Formulas keep their text, so we don't need the elaborated environment
+  let edits = Formatter::new(&path, &ast, None).edits(range)
+    .map_err(|e| response_err(ErrorCode::InternalError, e))?;
+  Ok(edits.into_iter().map(|(sp, new_text)|
+    TextEdit {range: ast.source.to_range(sp), new_text}).collect())
+}
+
+/// The parameters of the `mm0-rs/evalLisp` request, which evaluates lisp expressions
+/// in the context of a file.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EvalLispParams {
+  /// The file providing the environment
+  text_document: TextDocumentIdentifier,
+  /// If provided, only the statements of the file before this position are in scope.
+  /// Otherwise the final environment of the file is used.
+  position: Option<Position>,
+  /// The lisp expressions to evaluate, as they would appear in a `do` block
+  expr: String,
+}
+
+/// The response to the `mm0-rs/evalLisp` request.
+#[derive(Debug, Serialize)]
+struct EvalLispResult {
+  /// The printed results of the expressions, and anything printed by them, one per line
+  output: String,
+  /// The errors and warnings produced by evaluation. The ranges are relative to the
+  /// expression string in the request, and so are the ranges of related information that
+  /// points into the expression (which has the URI of the file).
+  errors: Vec<Diagnostic>,
+}
+
+/// Evaluate `expr` against a copy of the environment `env` of the file at `path`.
+fn eval_lisp_in(path: &FileRef, env: &FrozenEnv, expr: &str) -> EvalLispResult {
+  // The expression is wrapped in a `do` block on separate lines,
+  // so that the line numbers are only off by one
+  let (_, ast) = parse(Arc::new(format!("do {{\n{}\n}};", expr).into()), None);
+  let ast = Arc::new(ast);
+  // The expression gets its own `FileRef`, so that spans in the expression can be told apart
+  // (by pointer) from spans in the file itself
+  let expr_path = FileRef::from(path.url().clone());
+  let mut elab = Elaborator::from_env(ast.clone(), expr_path.clone(), env);
+  let mut errors: Vec<ElabError> = ast.errors.iter().map(|e| e.clone().into()).collect();
+  errors.extend(elab.elab_more(ast.clone()));
+  let mut res = EvalLispResult {output: String::new(), errors: vec![]};
+  let to_expr_range = |sp: Span| {
+    let mut range = ast.source.to_range(sp);
+    for p in &mut [&mut range.start, &mut range.end] {
+      p.line = p.line.saturating_sub(1)
+    }
+    range
+  };
+  let mut srcs = HashMap::new();
+  let mut to_loc = |fsp: &FileSpan| -> Location {
+    if fsp.file.ptr_eq(&expr_path) {
+      return Location {uri: path.url().clone(), range: to_expr_range(fsp.span)}
+    }
+    // Files that are not open are read from disk
+    let src = srcs.entry(fsp.file.ptr()).or_insert_with(||
+      SERVER.vfs.try_source(&fsp.file).or_else(||
+        fs::read_to_string(fsp.file.path()).ok().map(|s| Arc::new(s.into()))));
+    match src {
+      Some(src) => src.to_loc(fsp),
+      None => Location {uri: fsp.file.url().clone(), range: Range::default()},
+    }
+  };
+  for e in errors {
+    if let ErrorLevel::Info = e.level {
+      if !res.output.is_empty() { res.output.push('\n') }
+      res.output.push_str(&e.kind.msg());
+    } else {
+      let mut diag = e.to_diag(&ast.source, &mut to_loc);
+      diag.range = to_expr_range(e.pos);
+      res.errors.push(diag)
+    }
+  }
+  res
+}
+
+/// Get the current [`AST`] of `file`, or an error if it is a binary file.
+async fn current_ast(file: &VirtualFile) -> StdResult<Arc<AST>, ResponseError> {
+  parse_current(file).await.ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "cannot evaluate in a binary file"))
+}
+
+/// The number of statements in `ast` which end before `pos`, plus one for the statement
+/// containing `pos` if `inclusive` is set.
+fn stmts_before(ast: &AST, pos: Position, inclusive: bool) -> StdResult<usize, ResponseError> {
+  let idx = ast.source.to_idx(pos).ok_or_else(||
+    response_err(ErrorCode::InvalidParams, "position out of range"))?;
+  let n = ast.stmts.iter().position(|s| s.span.end > idx).unwrap_or(ast.stmts.len());
+  Ok(if inclusive { ast.stmts.len().min(n + 1) } else { n })
+}
+
+/// Elaborate the first `n` statements of the file at `path`, whose current [`AST`] is `ast`.
+/// Imports are resolved as in [`elaborate`], so the cached elaborations of the imported
+/// files are reused.
+async fn elab_before(path: &FileRef, ast: &AST, n: usize,
+    recv_goal: impl FnOnce() -> Option<GoalListener> + Send) -> StdResult<FrozenEnv, ResponseError> {
+  let end = ast.stmts[..n].last().map_or(0, |s| s.span.end);
+  let ast = Arc::new(AST {
+    source: ast.source.clone(),
+    imports: ast.imports.iter().filter(|(sp, _)| sp.end <= end).cloned().collect(),
+    stmts: ast.stmts[..n].to_vec(),
+    errors: vec![],
+  });
+  let rd = ArcList::default().push(path.clone());
+  let (cyc, _, _, env) = ElaborateBuilder {
+    ast: &ast,
+    path: path.clone(),
+    mm0_mode: path.has_extension("mm0"),
+    check_proofs: crate::get_check_proofs(),
+    report_upstream_errors: false,
+    cancel: Arc::default(),
+    old: None,
+    recv_dep: |p| {
+      let (p, dep) = SERVER.vfs.get_or_insert(p)?;
+      let (send, recv) = channel();
+      if rd.contains(&p) {
+        send.send(ElabResult::ImportCycle(rd.clone())).expect("failed to send");
+      } else if let Some(Some(FileCache::Ready {res, ..})) = dep.parsed.try_lock().as_deref() {
+        send.send(res.clone()).expect("failed to send");
+      } else {
+        SERVER.pool.spawn_ok(elaborate_and_send(p, Arc::default(), send, rd.clone()));
+      }
+      Ok(recv)
+    },
+    recv_goal: recv_goal(),
+    recv_progress: None,
+    read_file: Some(read_data_file(Arc::default())),
+  }.elab().await;
+  if cyc.is_some() { return Err(response_err(ErrorCode::InvalidRequest, "import cycle")) }
+  Ok(env)
+}
+
+/// Get the environment of the file at `path` before `pos`, or the final environment
+/// if `pos` is not given.
+///
+/// This uses the cached elaboration of the file if `pos` is after the last statement.
+/// Otherwise the statements before `pos` are elaborated again, and the result is cached
+/// along with the elaboration of the file, so that further requests at the same place
+/// can reuse it until the file or its imports change.
+async fn env_at(path: &FileRef, pos: Option<Position>) -> StdResult<FrozenEnv, ResponseError> {
+  let file = SERVER.vfs.get(path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "eval nonexistent file"))?;
+  let env = elaborate(path.clone(), Some(Position::default()), Default::default(), Default::default())
+    .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{:?}", e)))?;
+  let env = match env.into_response_error()? {
+    None => return Err(response_err(ErrorCode::InvalidRequest, "import cycle")),
+    Some((_, env)) => env
+  };
+  if let Some(pos) = pos {
+    let ast = current_ast(&file).await?;
+    let n = stmts_before(&ast, pos, false)?;
+    let is_current = |ast2: &Option<Arc<AST>>| matches!(ast2, Some(a) if Arc::ptr_eq(a, &ast));
+    match &*file.parsed.lock().await {
+      Some(FileCache::Ready {ast: ast2, prefix, ..}) if is_current(ast2) => {
+        if n == ast.stmts.len() { return Ok(env) }
+        if let Some((m, env)) = prefix {
+          if *m == n { return Ok(env.clone()) }
+        }
+      }
+      _ => {}
+    }
+    let env = elab_before(path, &ast, n, || None).await?;
+    if let Some(FileCache::Ready {ast: ast2, prefix, ..}) = &mut *file.parsed.lock().await {
+      if is_current(ast2) { *prefix = Some((n, env.clone())) }
+    }
+    return Ok(env)
+  }
+  Ok(env)
+}
+
+async fn eval_lisp(path: FileRef, pos: Option<Position>, expr: String) ->
+    StdResult<EvalLispResult, ResponseError> {
+  let env = env_at(&path, pos).await?;
+  Ok(eval_lisp_in(&path, &env, &expr))
+}
+
+/// The parameters of the `mm0-rs/findTheorems` request, which searches for the theorems
+/// whose conclusion unifies with the goal at a position, or with a given expression.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FindTheoremsParams {
+  /// The file providing the environment
+  text_document: TextDocumentIdentifier,
+  /// The position of the goal, in the middle of a proof: the first goal before the next
+  /// tactic in a tactic script, or else the first goal left unsolved at the end of the
+  /// proof. If `expr` is provided, only the statements of the file before this position
+  /// are in scope, as in `mm0-rs/evalLisp`.
+  position: Option<Position>,
+  /// A lisp expression for the statement to search for, like `$ _ + 0 = _ $`,
+  /// which is used instead of the goal at `position` if provided
+  expr: Option<String>,
+}
+
+/// A theorem in the response to the `mm0-rs/findTheorems` request.
+#[derive(Debug, Serialize)]
+struct FoundTheorem {
+  /// The name of the theorem
+  name: String,
+  /// The pretty printed statement of the theorem
+  statement: String,
+  /// The location of the theorem's name
+  location: Location,
+}
+
+/// Describe the theorems `thms` for the response to `mm0-rs/findTheorems`.
+fn found_theorems(elab: &Elaborator, thms: Vec<ThmID>) -> Vec<FoundTheorem> {
+  let mut srcs = HashMap::new();
+  thms.into_iter().map(|t| {
+    let td = &elab.thms[t];
+    // Files that are not open are read from disk
+    let src = srcs.entry(td.span.file.ptr()).or_insert_with(||
+      SERVER.vfs.try_source(&td.span.file).or_else(||
+        fs::read_to_string(td.span.file.path()).ok().map(|s| Arc::new(s.into()))));
+    FoundTheorem {
+      name: String::from_utf8_lossy(&elab.data[td.atom].name).into(),
+      statement: format!("{}", elab.print(td)),
+      location: match src {
+        Some(src) => src.to_loc(&td.span),
+        None => Location {uri: td.span.file.url().clone(), range: Range::default()},
+      },
+    }
+  }).collect()
+}
+
+async fn find_theorems(path: FileRef, pos: Option<Position>, expr: Option<String>) ->
+    StdResult<Vec<FoundTheorem>, ResponseError> {
+  if let Some(expr) = expr {
+    let env = env_at(&path, pos).await?;
+    let (_, ast) = parse(Arc::new(format!("do {{\n{}\n}};", expr).into()), None);
+    let ast = Arc::new(ast);
+    let e = match ast.stmts.first().map(|s| &s.k) {
+      Some(StmtKind::Do(_, es)) if es.len() == 1 && ast.errors.is_empty() => es[0].clone(),
+      _ => return Err(response_err(ErrorCode::InvalidParams, "expected a single expression")),
+    };
+    let mut elab = Elaborator::from_env(ast.clone(), path, &env);
+    let e = elab.eval_more(ast, &e)
+      .map_err(|e| response_err(ErrorCode::InvalidParams, e.kind.msg()))?;
+    let thms = elab.find_thms(&e).map_err(|e| response_err(ErrorCode::InvalidParams, e))?;
+    return Ok(found_theorems(&elab, thms))
+  }
+  let pos = pos.ok_or_else(||
+    response_err(ErrorCode::InvalidParams, "expected a position or an expression"))?;
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "find theorems in nonexistent file"))?;
+  let ast = current_ast(&file).await?;
+  let idx = ast.source.to_idx(pos).ok_or_else(||
+    response_err(ErrorCode::InvalidParams, "position out of range"))?;
+  // The goal listener is called with the goals at the cursor if it is in a tactic script,
+  // and otherwise with the unsolved goals at the end of each proof, so we elaborate up to
+  // the statement containing the position and record the first goal it reports there.
+  let found = Arc::new(Mutex::new(None));
+  let found2 = found.clone();
+  let recv_goal = move || Some(GoalListener::at(idx, move |elab: &Elaborator, _| {
+    let mut found = found2.ulock();
+    if found.is_none() && elab.spans.stmt().contains(&idx) {
+      if let Some(res) = elab.find_thms_goal() {
+        *found = Some(res.map(|thms| found_theorems(elab, thms)));
+      }
+    }
+  }));
+  elab_before(&path, &ast, stmts_before(&ast, pos, true)?, recv_goal).await?;
+  let res = found.ulock().take();
+  match res {
+    None => Err(response_err(ErrorCode::InvalidParams, "no goal at this position")),
+    Some(res) => res.map_err(|e| response_err(ErrorCode::InvalidParams, e)),
+  }
+}
+
+async fn code_lens(path: FileRef) -> StdResult<Vec<CodeLens>, ResponseError> {
+  let file = SERVER.vfs.get(&path).ok_or_else(||
+    response_err(ErrorCode::InvalidRequest, "code lens nonexistent file"))?;
+  let maybe_old = if SERVER.elab_on().unwrap_or_default() == ElabOn::Save { try_old(&file) } else { None };
+  let (text, env) = if let Some((contents, frozen)) = maybe_old {
+    (contents.ascii().clone(), frozen)
+  } else {
+    let env = elaborate(path.clone(), Some(Position::default()), Default::default(), Default::default())
+      .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{:?}", e)))?;
+    match env.into_response_error()? {
+      None => return Ok(vec![]),
+      Some((_, env)) => (file.text.ulock().1.ascii().clone(), env)
+    }
+  };
+  let (to_tid, mut axuse) = AxiomUse::new(env.thms());
+  let mut sizes = HashMap::new();
+  let mut size = |tid: ThmID| -> usize {
+    *sizes.entry(tid).or_insert_with(|| {
+      let td = env.thm(tid);
+      if let ThmKind::Thm(Some(pf)) = &td.kind { ProofSize::new(td.args.len(), pf).steps } else {0}
+    })
+  };
+  let mut res = vec![];
+  for s in env.stmts() {
+    let tid = if let StmtTrace::Decl(a) = *s {
+      if let Some(DeclKey::Thm(tid)) = env.data()[a].decl() {tid} else {continue}
+    } else {continue};
+    let td = env.thm(tid);
+    let pf = match &td.kind {
+      ThmKind::Thm(pf) if td.span.file == path => pf,
+      _ => continue,
+    };
+    let range = text.to_range(td.span.span);
+    let lens = |title: String, command: &str, arguments| CodeLens {
+      range,
+      command: Some(Command {title, command: command.into(), arguments}),
+      data: None,
+    };
+    let mut axioms = String::new();
+    for i in axuse.get(env.thms(), tid) {
+      if !axioms.is_empty() { axioms.push_str(", ") }
+      if i == 0 { axioms.push_str("sorry") }
+      else { axioms.push_str(env.data()[env.thm(to_tid[i]).atom].name().as_str()) }
+    }
+    if axioms.is_empty() { axioms.push_str("none") }
+    let name = env.data()[td.atom].name();
+    let info = |msg: String| Some(vec![to_value(msg).expect("serialization failure")]);
+    res.push(lens(format!("axioms: {}", axioms), "mm0-rs.showInfo",
+      info(format!("{} depends on the axioms: {}", name, axioms))));
+    let pf = if let Some(pf) = pf {pf} else {continue};
+    let ps = ProofSize::new(td.args.len(), pf);
+    res.push(lens(format!("{} steps", ps.steps), "mm0-rs.showInfo",
+      info(format!("the proof of {} has {} steps and uses {} theorems", name, ps.steps, ps.deps.len()))));
+    let heaviest = ps.deps.iter().map(|&t| (t, size(t))).filter(|p| p.1 != 0)
+      .fold(None, |acc: Option<(ThmID, usize)>, p| match acc {
+        Some(q) if q.1 >= p.1 => Some(q),
+        _ => Some(p)
+      });
+    if let Some((t, n)) = heaviest {
+      let td2 = env.thm(t);
+      let target = if td2.span.file == path { Some(text.clone()) }
+        else { SERVER.vfs.try_source(&td2.span.file) };
+      if let Some(src) = target {
+        let loc = Location {uri: td2.span.file.url().clone(), range: src.to_range(td2.span.span)};
+        res.push(lens(
+          format!("heaviest dependency: {} ({} steps)", env.data()[td2.atom].name(), n),
+          "metamath-zero.goto", Some(vec![to_value(loc).expect("serialization failure")])));
+      }
+    }
+  }
+  Ok(res)
+}
+
+/// The commands supported by `workspace/executeCommand`. All commands take the URI of an
+/// MM1 file as the first argument; the output paths are resolved relative to this file,
+/// and must be inside the workspace root (or the file's directory if there is none).
+///
+/// * `mm0-rs.runOutput <uri> <out>`: Run the `output` commands in the file, writing to `out`.
+/// * `mm0-rs.export <uri> <out>`: Export the file to `out`, which should be a `.mmb`
+///   or `.mmu` file.
+/// * `mm0-rs.join <uri> <out>`: Join the file with its imports, writing to `out`.
+/// * `mm0-rs.docTheorem <uri> <pos> [dir]`: Generate documentation for the theorem
+///   at position `pos` in the file, in `dir` (default `doc`). Returns the generated file.
+///
+/// The exception is `mm0-rs.showInfo <msg>`, which takes no file and shows `msg` as an
+/// information message. It is the command of the informational code lenses.
+const COMMANDS: &[&str] = &[
+  "mm0-rs.showInfo",
+  "mm0-rs.runOutput",
+  "mm0-rs.export",
+  "mm0-rs.join",
+  #[cfg(feature = "doc")] "mm0-rs.docTheorem",
+];
+
+async fn execute_command(command: String, args: Vec<serde_json::Value>) ->
+    StdResult<Option<serde_json::Value>, ResponseError> {
+  fn arg<T: serde::de::DeserializeOwned>(args: &[serde_json::Value], i: usize) ->
+      StdResult<T, ResponseError> {
+    let v = args.get(i).cloned().unwrap_or(serde_json::Value::Null);
+    from_value(v).map_err(|e| response_err(ErrorCode::InvalidParams, format!("argument {}: {}", i + 1, e)))
+  }
+  fn io_err(e: impl std::fmt::Display) -> ResponseError {
+    response_err(ErrorCode::InternalError, format!("{}", e))
+  }
+
+  if command == "mm0-rs.showInfo" {
+    show_message(MessageType::Info, arg(&args, 0)?).map_err(|e| io_err(e.0))?;
+    return Ok(None)
+  }
+  let path: FileRef = arg::<Url>(&args, 0)?.into();
+  crate::set_default_project_root(path.path());
+  let resolve = |out: String| -> StdResult<std::path::PathBuf, ResponseError> {
+    let out = match path.path().parent() {
+      Some(dir) => dir.join(out),
+      None => out.into(),
+    };
+    let outside = || response_err(ErrorCode::InvalidParams,
+      format!("{}: output path is outside the workspace", out.display()));
+    let dir = out.parent().ok_or_else(outside)?;
+    let name = out.file_name().ok_or_else(outside)?;
+    let dir = dir.canonicalize().map_err(|e| io_err(format!("{}: {}", dir.display(), e)))?;
+    let file = dir.join(name);
+    // The file itself may not exist yet, but if it does it may be a link to somewhere else
+    let file = if file.symlink_metadata().is_ok() {
+      file.canonicalize().map_err(|e| io_err(format!("{}: {}", file.display(), e)))?
+    } else { file };
+    let root = crate::get_project_root().and_then(|p| p.canonicalize().ok()).ok_or_else(outside)?;
+    if !file.starts_with(root) { return Err(outside()) }
+    Ok(file)
+  };
+  let elab = || async {
+    let env = elaborate(path.clone(), Some(Position::default()), Default::default(), Default::default())
+      .await.map_err(|e| response_err(ErrorCode::InternalError, format!("{:?}", e)))?;
+    let file = SERVER.vfs.get(&path).ok_or_else(||
+      response_err(ErrorCode::InvalidRequest, "execute command: nonexistent file"))?;
+    let text = file.text.ulock().1.clone();
+    match env.into_response_error()? {
+      Some((_, env)) => Ok((text, env)),
+      None => Err(response_err(ErrorCode::InvalidRequest, "import cycle")),
+    }
+  };
+  match &*command {
+    "mm0-rs.runOutput" => {
+      let out = resolve(arg(&args, 1)?)?;
+      let (_, env) = elab().await?;
+      let w = io::BufWriter::new(fs::File::create(&out).map_err(io_err)?);
+      env.run_output(w).map_err(|(fsp, e)| {
+        let e: BoxError = e.into();
+        match SERVER.vfs.try_source(&fsp.file) {
+          Some(src) => {
+            let pos = src.to_pos(fsp.span.start);
+            io_err(format!("{}:{}:{}: {}", fsp.file, pos.line + 1, pos.character + 1, e))
+          }
+          None => io_err(format!("{}: {}", fsp.file, e)),
+        }
+      })?;
+      Ok(Some(to_value(out).expect("serialization failure")))
+    }
+    "mm0-rs.export" => {
+      let out = resolve(arg(&args, 1)?)?;
+      let (text, env) = elab().await?;
+      crate::compiler::export(path.clone(), &text, &env, &out.to_string_lossy()).map_err(io_err)?;
+      Ok(Some(to_value(out).expect("serialization failure")))
+    }
+    "mm0-rs.join" => {
+      let out = resolve(arg(&args, 1)?)?;
+      let w = io::BufWriter::new(fs::File::create(&out).map_err(io_err)?);
+      crate::joiner::join_with_header(true, true, w, path.clone()).map_err(io_err)?;
+      Ok(Some(to_value(out).expect("serialization failure")))
+    }
+    #[cfg(feature = "doc")]
+    "mm0-rs.docTheorem" => {
+      use crate::{doc::build_doc, elab::proof_layout::ProofOrder};
+      let pos: Position = arg(&args, 1)?;
+      let dir = resolve(arg::<Option<String>>(&args, 2)?.unwrap_or_else(|| "doc".into()))?;
+      let (text, env) = elab().await?;
+      let text = text.ascii();
+      let thm = text.to_idx(pos).and_then(|idx| env.find(idx))
+        .and_then(|spans| spans.find_pos(text.to_idx(pos)?).find_map(|(_, k)| match object_decl(&env, k) {
+          Some(DeclKey::Thm(t)) => Some(t),
+          _ => None,
+        }))
+        .ok_or_else(|| response_err(ErrorCode::InvalidParams, "no theorem at this position"))?;
+      let name = env.data()[env.thm(thm).atom].name().clone();
+      build_doc(&path, text, &env, dir.clone(), ProofOrder::Post, Some(name.as_str()), None)
+        .map_err(io_err)?;
+      // Only one theorem is generated, so the file name is not mangled
+      Ok(Some(to_value(dir.join("thms").join(format!("{}.html", name))).expect("serialization failure")))
+    }
+    _ => Err(response_err(ErrorCode::MethodNotFound, format!("unknown command {}", command)))
+  }
+}
+
 struct Server {
   conn: Connection,
   #[allow(unused)]
@@ -1175,6 +2367,8 @@ struct Server {
   #[allow(clippy::type_complexity)]
   threads: Arc<(Mutex<VecDeque<(Job, Arc<AtomicBool>)>>, Condvar)>,
   options: Mutex<ServerOptions>,
+  /// The `$/progress` tokens of elaborations in progress
+  progress: Mutex<HashMap<String, ProgressToken>>,
 }
 
 
@@ -1200,6 +2394,7 @@ struct ClientCapabilities {
   reg_id: Option<RequestId>,
   definition_location_links: Option<bool>,
   goal_view: bool,
+  work_done_progress: bool,
 }
 
 impl ClientCapabilities {
@@ -1210,10 +2405,12 @@ impl ClientCapabilities {
       Some(GotoCapability {dynamic_registration: Some(true), ..}) => Some(true),
       _ => Some(false)
     };
+    let work_done_progress = params.capabilities.window.as_ref()
+      .and_then(|w| w.work_done_progress).unwrap_or(false);
     let goal_view = params.initialization_options
       .and_then(|o| from_value(o).ok()).and_then(|o: InitOptions| o.extra_capabilities)
       .and_then(|c| c.goal_view).unwrap_or(false);
-    ClientCapabilities { reg_id: None, definition_location_links: dll, goal_view }
+    ClientCapabilities { reg_id: None, definition_location_links: dll, goal_view, work_done_progress }
   }
 
   fn register(&mut self) -> Result<()> {
@@ -1412,7 +2609,7 @@ fn send_config_request() -> Result<()> {
 impl Server {
   fn new() -> Result<Server> {
     let (conn, _iot) = Connection::stdio();
-    let params = from_value(conn.initialize(
+    let params: InitializeParams = from_value(conn.initialize(
       to_value(ServerCapabilities {
         text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::Incremental)),
         hover_provider: Some(true.into()),
@@ -1424,9 +2621,22 @@ impl Server {
         document_symbol_provider: Some(OneOf::Left(true)),
         references_provider: Some(OneOf::Left(true)),
         document_highlight_provider: Some(OneOf::Left(true)),
+        call_hierarchy_provider: Some(true.into()),
+        folding_range_provider: Some(true.into()),
+        selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+        code_lens_provider: Some(CodeLensOptions {resolve_provider: Some(false)}),
+        document_formatting_provider: Some(OneOf::Left(true)),
+        document_range_formatting_provider: Some(OneOf::Left(true)),
+        execute_command_provider: Some(ExecuteCommandOptions {
+          commands: COMMANDS.iter().map(|&c| c.into()).collect(),
+          work_done_progress_options: Default::default()
+        }),
         ..Default::default()
       })?
     )?)?;
+    if let Some(root) = params.root_uri.as_ref().and_then(|url| url.to_file_path().ok()) {
+      crate::set_project_root(root)
+    }
     Ok(Server {
       caps: Mutex::new(ClientCapabilities::new(params)),
       conn,
@@ -1435,6 +2645,7 @@ impl Server {
       pool: ThreadPool::new()?,
       threads: Default::default(),
       options: Mutex::new(ServerOptions::default()),
+      progress: Mutex::new(HashMap::new()),
     })
   }
 
@@ -1477,6 +2688,11 @@ impl Server {
               let mut caps = caps.ulock();
               if caps.reg_id.as_ref().map_or(false, |rid| rid == &resp.id) {
                 caps.finish_register(&resp);
+              } else if let Some(tk) = SERVER.progress.ulock().iter()
+                .find(|(tk, _)| RequestId::from((*tk).clone()) == resp.id) {
+                // response to window/workDoneProgress/create
+                *tk.1.state.ulock() =
+                  if resp.error.is_none() {TokenState::Created} else {TokenState::Failed};
               } else {
                 log!("response to unknown request {}", resp.id)
               }
@@ -1491,10 +2707,20 @@ impl Server {
                   cancel.store(true, Ordering::Relaxed);
                 }
               }
+              WorkDoneProgressCancel::METHOD => {
+                let WorkDoneProgressCancelParams {token} = from_value(notif.params)?;
+                if let NumberOrString::String(token) = token {
+                  if let Some(tk) = SERVER.progress.ulock().get(&token) {
+                    tk.cancel.store(true, Ordering::Relaxed);
+                  }
+                }
+              }
               DidOpenTextDocument::METHOD => {
                 let DidOpenTextDocumentParams {text_document: doc} = from_value(notif.params)?;
-                let path = doc.uri.into();
+                let path: FileRef = doc.uri.into();
                 log!("open {:?}", path);
+                // without a `rootUri`, the first opened file determines the project root
+                crate::set_default_project_root(path.path());
                 vfs.open_virt(path, doc.version, doc.text);
               }
               DidChangeTextDocument::METHOD => {
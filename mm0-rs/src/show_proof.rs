@@ -0,0 +1,115 @@
+//! Implementation of the `mm0-rs show-proof` subcommand, which prints the proof of a
+//! theorem as a numbered list of steps, in the layout used by the documentation pages
+//! (see [`elab::proof_layout`](crate::elab::proof_layout)).
+
+use std::{fs, io};
+use std::sync::Arc;
+use clap::ArgMatches;
+use serde_json::json;
+use crate::elab::{Elaborator, environment::DeclKey};
+use crate::elab::proof_layout::{Line, LineKind, ProofOrder};
+use crate::parser::parse;
+use crate::util::FileRef;
+
+fn fail(msg: impl std::fmt::Display) -> ! {
+  eprintln!("error: {}", msg);
+  std::process::exit(1)
+}
+
+/// The justification of a step, as displayed in the text format.
+fn step_name(elab: &Elaborator, kind: &LineKind) -> String {
+  match *kind {
+    LineKind::Hyp(None) => "hyp".into(),
+    LineKind::Hyp(Some(a)) => format!("hyp {}", elab.data[a].name),
+    LineKind::Thm(tid) => elab.data[elab.thms[tid].atom].name.to_string(),
+    LineKind::Conv(ref defs) => {
+      let mut s = String::from("conv");
+      for (i, &t) in defs.iter().enumerate() {
+        s.push_str(if i == 0 {" "} else {", "});
+        s.push_str(elab.data[elab.terms[t].atom].name.as_str());
+      }
+      s
+    }
+  }
+}
+
+/// Print the steps as a table with columns for the step number, the steps it uses,
+/// the justification and the statement.
+fn print_text(elab: &Elaborator, lines: &[Line]) {
+  let fe = elab.format_env();
+  let rows = lines.iter().enumerate().map(|(i, line)| {
+    let hyps = line.hyps.iter().map(|h| (h + 1).to_string()).collect::<Vec<_>>().join(", ");
+    ((i + 1).to_string(), hyps, step_name(elab, &line.kind))
+  }).collect::<Vec<_>>();
+  let w0 = rows.iter().map(|r| r.0.len()).max().unwrap_or(0);
+  let w1 = rows.iter().map(|r| r.1.len()).max().unwrap_or(0);
+  let w2 = rows.iter().map(|r| r.2.len()).max().unwrap_or(0);
+  for ((n, hyps, step), line) in rows.iter().zip(lines) {
+    println!("{:>w0$}  {:w1$}  {:w2$}  $ {} $", n, hyps, step,
+      fe.pp(&line.expr, usize::MAX >> 1), w0 = w0, w1 = w1, w2 = w2);
+  }
+}
+
+/// Print the steps as a JSON array. Each step is an object with fields `line` (the step
+/// number), `hyps` (the numbers of the steps it uses), `kind` (`"hyp"`, `"thm"` or `"conv"`),
+/// `name` (the hypothesis or theorem name, or `null`), `defs` (the unfolded definitions
+/// of a conversion, otherwise empty) and `expr` (the pretty printed statement).
+fn print_json(elab: &Elaborator, lines: &[Line]) {
+  let fe = elab.format_env();
+  let name = |a| elab.data[a].name.to_string();
+  let steps = lines.iter().enumerate().map(|(i, line)| {
+    let (kind, n, defs) = match line.kind {
+      LineKind::Hyp(a) => ("hyp", a.map(name), vec![]),
+      LineKind::Thm(tid) => ("thm", Some(name(elab.thms[tid].atom)), vec![]),
+      LineKind::Conv(ref defs) => ("conv", None,
+        defs.iter().map(|&t| name(elab.terms[t].atom)).collect()),
+    };
+    json!({
+      "line": i + 1,
+      "hyps": line.hyps.iter().map(|h| h + 1).collect::<Vec<_>>(),
+      "kind": kind,
+      "name": n,
+      "defs": defs,
+      "expr": fe.pp(&line.expr, usize::MAX >> 1).to_string(),
+    })
+  }).collect::<Vec<_>>();
+  println!("{}", serde_json::to_string_pretty(&steps).expect("serialization failure"));
+}
+
+/// Main entry point for `mm0-rs show-proof` subcommand.
+///
+/// # Arguments
+///
+/// `mm0-rs show-proof <in.mm1> <thm> [--format text|json] [--order pre|post]`, where:
+///
+/// - `in.mm1` is the MM1 (or MM0) file to elaborate.
+/// - `thm` is the theorem whose proof is shown.
+/// - `--format`: Print a table of steps (`text`, the default) or a JSON array (`json`).
+/// - `--order`: Put the conclusion last (`post`, the default) or first (`pre`).
+pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
+  let path = args.value_of("INPUT").expect("required arg");
+  let path: FileRef = fs::canonicalize(path)?.into();
+  crate::compiler::log_to_stderr();
+  let (_, env) = crate::compiler::elab_for_result(path.clone())?;
+  let env = env.unwrap_or_else(|| fail("elaboration failed"));
+  let (_, ast) = parse(Arc::new(String::new().into()), None);
+  let mut elab = Elaborator::from_env(Arc::new(ast), path, &env);
+  let name = args.value_of("THM").expect("required arg");
+  let thm = match elab.atoms.get(name.as_bytes()).and_then(|&a| elab.data[a].decl) {
+    Some(DeclKey::Thm(t)) => t,
+    Some(DeclKey::Term(_)) => fail(format!("'{}' is not a theorem", name)),
+    None => fail(format!("unknown theorem '{}'", name)),
+  };
+  let order = match args.value_of("order") {
+    Some("pre") => ProofOrder::Pre,
+    Some("post") => ProofOrder::Post,
+    _ => unreachable!(),
+  };
+  let lines = elab.layout_thm(thm, order).unwrap_or_else(|e| fail(e));
+  match args.value_of("format") {
+    Some("text") => print_text(&elab, &lines),
+    Some("json") => print_json(&elab, &lines),
+    _ => unreachable!(),
+  }
+  Ok(())
+}
@@ -0,0 +1,130 @@
+//! Implementation of the `mm0-rs tptp` subcommand, which exports the statement of a
+//! theorem to TPTP and optionally runs an external prover on it.
+//!
+//! The translation is described in [`elab::tptp`](crate::elab::tptp). The arguments of
+//! the theorem become the constants of the problem, its hypotheses are hypotheses, and the
+//! premises are either given explicitly or are all the theorems declared before it that
+//! can be expressed in the chosen language.
+
+use std::{fs, io};
+use std::collections::HashMap;
+use std::process::Command;
+use std::sync::Arc;
+use clap::ArgMatches;
+use crate::elab::{Elaborator, environment::{DeclKey, ThmID, Type}};
+use crate::elab::expr_table::Node;
+use crate::elab::tptp::{Export, Lang, GOAL_NAME, formula_name, used_premises};
+use crate::parser::parse;
+use crate::util::FileRef;
+
+fn fail(msg: impl std::fmt::Display) -> ! {
+  eprintln!("error: {}", msg);
+  std::process::exit(1)
+}
+
+/// Build the TPTP problem of proving the statement of `thm` from `premises`
+/// (or all previous theorems if `None`).
+fn problem(elab: &mut Elaborator, thm: ThmID, premises: Option<Vec<ThmID>>, lang: Lang) -> String {
+  let nargs = elab.thms[thm].args.len();
+  let mut atoms = vec![];
+  let mut bvs = vec![];
+  let mut locals = HashMap::new();
+  for j in 0..nargs {
+    let (a, ty) = elab.thms[thm].args[j];
+    let a = a.unwrap_or_else(|| elab.get_atom(format!("_{}", j).as_bytes()));
+    let (s, deps) = match ty {
+      Type::Bound(s) => { bvs.push(a); (s, None) }
+      Type::Reg(s, deps) => (s, Some(bvs.iter().enumerate()
+        .filter(|&(i, _)| deps & (1 << i) != 0).map(|(_, &a)| a).collect())),
+    };
+    locals.insert(a, (s, deps));
+    atoms.push(a);
+  }
+  let mut exp = Export::new(elab.format_env(), lang, locals);
+  let subst = atoms.iter().map(|&a| Some(exp.tbl.intern(Node::Var(a)))).collect::<Vec<_>>();
+  let name = |t: ThmID| String::from_utf8_lossy(&elab.data[elab.thms[t].atom].name).into_owned();
+  if let Some(ps) = premises {
+    for t in ps {
+      if let Err(e) = exp.thm(t) { fail(format!("premise '{}': {}", name(t), e)) }
+    }
+  } else {
+    let mut skipped = 0;
+    for i in 0..thm.0 { if exp.thm(ThmID(i)).is_err() { skipped += 1 } }
+    if skipped != 0 { eprintln!("skipped {} premises that cannot be expressed", skipped) }
+  }
+  let td = &elab.thms[thm];
+  for (i, (h, e)) in td.hyps.iter().enumerate() {
+    let hname = match h {
+      Some(h) => formula_name(&elab.data[*h].name),
+      None => format!("h{}", i + 1),
+    };
+    let n = exp.tbl.subst(&td.heap, nargs, e, &subst);
+    if let Err(e) = exp.local(&hname, "hypothesis", n) { fail(format!("hypothesis {}: {}", hname, e)) }
+  }
+  let n = exp.tbl.subst(&td.heap, nargs, &td.ret, &subst);
+  if let Err(e) = exp.local(GOAL_NAME, "conjecture", n) { fail(format!("goal: {}", e)) }
+  exp.finish()
+}
+
+/// Main entry point for `mm0-rs tptp` subcommand.
+///
+/// # Arguments
+///
+/// `mm0-rs tptp <in.mm1> <thm> [--thf] [--premises THMS] [-o out.p] [--prover CMD]`, where:
+///
+/// - `in.mm1` is the MM1 (or MM0) file to elaborate. It should use `register-tptp`
+///   to set up the translation of its connectives.
+/// - `thm` is the theorem whose statement is exported.
+/// - `--thf`: Export to THF instead of FOF.
+/// - `--premises THMS`: Use the comma separated list of theorems `THMS` as premises,
+///   instead of all theorems before `thm` that can be expressed.
+/// - `-o out.p`: Write the problem to `out.p` instead of stdout.
+/// - `--prover CMD`: Run `CMD out.p` (using a temporary file if `-o` is not given), and
+///   report the SZS status and the premises used in the proof.
+pub fn main(args: &ArgMatches<'_>) -> io::Result<()> {
+  let path = args.value_of("INPUT").expect("required arg");
+  let path: FileRef = fs::canonicalize(path)?.into();
+  crate::compiler::log_to_stderr();
+  let (_, env) = crate::compiler::elab_for_result(path.clone())?;
+  let env = env.unwrap_or_else(|| fail("elaboration failed"));
+  let (_, ast) = parse(Arc::new(String::new().into()), None);
+  let mut elab = Elaborator::from_env(Arc::new(ast), path, &env);
+  let thm_id = |elab: &Elaborator, name: &str| match elab.atoms.get(name.as_bytes()) {
+    Some(&a) => match elab.data[a].decl {
+      Some(DeclKey::Thm(t)) => t,
+      _ => fail(format!("'{}' is not a theorem", name)),
+    },
+    None => fail(format!("unknown theorem '{}'", name)),
+  };
+  let thm = thm_id(&elab, args.value_of("THM").expect("required arg"));
+  let premises = args.value_of("premises").map(|ps|
+    ps.split(',').map(|p| thm_id(&elab, p.trim())).collect());
+  let lang = if args.is_present("thf") { Lang::Thf } else { Lang::Fof };
+  let out = problem(&mut elab, thm, premises, lang);
+  let file = match (args.value_of("output"), args.value_of("prover")) {
+    (Some(file), _) => { fs::write(file, &out)?; file.into() }
+    (None, Some(_)) => {
+      let file = std::env::temp_dir().join(format!("mm0-rs-{}.p", std::process::id()));
+      fs::write(&file, &out)?;
+      file
+    }
+    (None, None) => { print!("{}", out); return Ok(()) }
+  };
+  if let Some(cmd) = args.value_of("prover") {
+    let mut words = cmd.split_whitespace();
+    let prog = words.next().unwrap_or_else(|| fail("empty prover command"));
+    let res = Command::new(prog).args(words).arg(&file).output();
+    if args.value_of("output").is_none() { fs::remove_file(&file)? }
+    let output = String::from_utf8_lossy(&res?.stdout).into_owned();
+    match used_premises(&output) {
+      Ok(used) => {
+        let used = used.into_iter().filter(|p| matches!(elab.atoms.get(p.as_bytes()),
+          Some(&a) if matches!(elab.data[a].decl, Some(DeclKey::Thm(_))))).collect::<Vec<_>>();
+        println!("proof found, using: {}", used.join(" "));
+        println!("reconstruct with: (tptp-reconstruct '({}))", used.join(" "));
+      }
+      Err(e) => fail(e),
+    }
+  }
+  Ok(())
+}
@@ -1,14 +1,17 @@
-import { commands, window, workspace, ExtensionContext, TextDocument, EndOfLine } from 'vscode';
+import { commands, window, workspace, ExtensionContext, TextDocument, EndOfLine, OutputChannel } from 'vscode';
 
 import {
 	LanguageClient,
 	LanguageClientOptions,
 	ServerOptions,
 	ErrorAction,
-	CloseAction
+	CloseAction,
+	Location,
+	Diagnostic
 } from 'vscode-languageclient';
 
 let client: LanguageClient;
+let lispOutput: OutputChannel | undefined;
 
 function startClient() {
 	let config = workspace.getConfiguration('metamath-zero');
@@ -56,10 +59,55 @@ export function activate(context: ExtensionContext) {
 		commands.registerCommand('metamath-zero.shutdownServer',
 		  () => client.stop().then(() => {}, () => {})),
 		commands.registerCommand('metamath-zero.restartServer',
-			() => client.stop().then(startClient, startClient))
+			() => client.stop().then(startClient, startClient)),
+		commands.registerCommand('metamath-zero.goto', (loc: Location) => {
+			let { uri, range } = client.protocol2CodeConverter.asLocation(loc);
+			window.showTextDocument(uri, { selection: range });
+		}),
+		commands.registerCommand('metamath-zero.evalLisp', evalLisp),
+		commands.registerCommand('metamath-zero.findTheorems', findTheorems)
 	);
 }
 
+// Evaluate a lisp expression in the context of the current file, at the cursor position
+async function evalLisp() {
+	let editor = window.activeTextEditor;
+	if (!editor || editor.document.languageId !== 'metamath-zero') { return; }
+	let expr = await window.showInputBox({ prompt: 'Lisp expression to evaluate' });
+	if (!expr) { return; }
+	let res: { output: string, errors: Diagnostic[] } = await client.sendRequest('mm0-rs/evalLisp', {
+		textDocument: { uri: editor.document.uri.toString() },
+		position: client.code2ProtocolConverter.asPosition(editor.selection.active),
+		expr
+	});
+	if (!lispOutput) { lispOutput = window.createOutputChannel('MM0 Lisp'); }
+	lispOutput.appendLine('> ' + expr);
+	if (res.output) { lispOutput.appendLine(res.output); }
+	for (let e of res.errors) {
+		lispOutput.appendLine(`${e.range.start.line + 1}:${e.range.start.character + 1}: ${e.message}`);
+	}
+	lispOutput.show(true);
+}
+
+// Search for theorems whose conclusion matches the goal at the cursor position
+async function findTheorems() {
+	let editor = window.activeTextEditor;
+	if (!editor || editor.document.languageId !== 'metamath-zero') { return; }
+	let res: { name: string, statement: string, location: Location }[];
+	try {
+		res = await client.sendRequest('mm0-rs/findTheorems', {
+			textDocument: { uri: editor.document.uri.toString() },
+			position: client.code2ProtocolConverter.asPosition(editor.selection.active)
+		});
+	} catch (e) {
+		window.showErrorMessage(e.message);
+		return;
+	}
+	let item = await window.showQuickPick(res.map(t => ({ label: t.name, detail: t.statement, location: t.location })),
+		{ placeHolder: res.length ? 'Theorems matching the current goal' : 'No theorems match the current goal' });
+	if (item) { commands.executeCommand('metamath-zero.goto', item.location); }
+}
+
 export function deactivate(): Thenable<void> | undefined {
 	if (!client) {
 		return undefined;